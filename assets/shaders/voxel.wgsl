@@ -0,0 +1,97 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+#import bevy_pbr::shadows
+
+@group(1) @binding(0)
+var voxel_texture: texture_2d_array<f32>;
+@group(1) @binding(1)
+var voxel_sampler: sampler;
+
+let MAX_LIGHT_LEVEL: f32 = 15.0;
+let VOXEL_PI: f32 = 3.141592653589793;
+let SUBVOXELS: f32 = 16.0;
+
+// Mirrors `ATTRIBUTE_PACKED_VOXEL` in mesh.rs
+struct Vertex {
+    @location(0) packed: vec2<u32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) ao: f32,
+    @location(4) light: f32,
+    @location(5) @interpolate(flat) layer: u32,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let position = vec3<f32>(
+        f32(vertex.packed.x & 1023u),
+        f32((vertex.packed.x >> 10u) & 1023u),
+        f32((vertex.packed.x >> 20u) & 1023u)
+    ) / SUBVOXELS;
+    let ao = (vertex.packed.x >> 30u) & 3u;
+    let layer = vertex.packed.y & 4095u;
+    let light = (vertex.packed.y >> 12u) & 15u;
+    let face = (vertex.packed.y >> 16u) & 7u;
+    let uv = vec2<f32>(
+        f32((vertex.packed.y >> 19u) & 31u),
+        f32((vertex.packed.y >> 24u) & 31u)
+    ) / SUBVOXELS;
+
+    // Same order as FACE_CHECKS
+    var normals = array<vec3<f32>, 6>(
+        vec3<f32>(-1.0, 0.0, 0.0),
+        vec3<f32>(1.0, 0.0, 0.0),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(0.0, -1.0, 0.0),
+        vec3<f32>(0.0, 0.0, 1.0),
+        vec3<f32>(0.0, 0.0, -1.0)
+    );
+    var out: VertexOutput;
+    out.world_position = mesh_position_local_to_world(mesh.model, vec4<f32>(position, 1.0));
+    out.clip_position = mesh_position_world_to_clip(out.world_position);
+    out.world_normal = mesh_normal_local_to_world(normals[face]);
+    out.uv = uv;
+    out.ao = f32(ao);
+    out.light = f32(light) / MAX_LIGHT_LEVEL;
+    out.layer = layer;
+    return out;
+}
+
+fn tone_map(color: vec3<f32>) -> vec3<f32> {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    return color / (1.0 + luminance);
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let base_color = textureSample(voxel_texture, voxel_sampler, in.uv, i32(in.layer));
+    // Cut out the transparent parts of plants and torches
+    if (base_color.a < 0.5) {
+        discard;
+    }
+    let normal = normalize(in.world_normal);
+
+    var light = lights.ambient_color.rgb;
+    for (var i: u32 = 0u; i < lights.n_directional_lights; i = i + 1u) {
+        let directional = lights.directional_lights[i];
+        let n_dot_l = max(dot(normal, directional.direction_to_light), 0.0);
+        var shadow: f32 = 1.0;
+        if ((directional.flags & DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+            shadow = fetch_directional_shadow(i, in.world_position, normal);
+        }
+        light = light + directional.color.rgb * n_dot_l * shadow / VOXEL_PI;
+    }
+
+    // AO of 3 means no occlusion
+    let occlusion = 0.4 + 0.2 * in.ao;
+    let color = base_color.rgb * light * occlusion * in.light;
+    return vec4<f32>(tone_map(color), base_color.a);
+}
@@ -0,0 +1,35 @@
+#import bevy_pbr::mesh_view_types
+#import bevy_pbr::mesh_types
+
+@group(0) @binding(0)
+var<uniform> view: View;
+
+@group(1) @binding(0)
+var<uniform> mesh: Mesh;
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+
+let SUBVOXELS: f32 = 16.0;
+
+// Mirrors `ATTRIBUTE_PACKED_VOXEL` in mesh.rs, only the position is read
+struct Vertex {
+    @location(0) packed: vec2<u32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let position = vec3<f32>(
+        f32(vertex.packed.x & 1023u),
+        f32((vertex.packed.x >> 10u) & 1023u),
+        f32((vertex.packed.x >> 20u) & 1023u)
+    ) / SUBVOXELS;
+
+    var out: VertexOutput;
+    out.clip_position = mesh_position_local_to_clip(mesh.model, vec4<f32>(position, 1.0));
+    return out;
+}
@@ -0,0 +1,724 @@
+//! Importing worlds of Minecraft Java Edition, saved in its Anvil format since 1.2.
+//!
+//! Minecraft keeps `r.<x>.<z>.mca` files of 32 by 32 chunk columns in the world's `region`
+//! directory. They start with a table of where in the file each chunk is, in 4 KiB sectors, and
+//! a table of when it was saved. Each chunk is its length as a big endian u32, how it is
+//! compressed as a u8 and its NBT, see `nbt`. Chunks are 16 by 16 blocks wide and split into
+//! sections of 16 blocks high, each holding a palette of block states and the index into it of
+//! every block, packed into longs. Worlds saved before 1.13, which numbered blocks instead, are
+//! not read.
+//!
+//! Blocks keep their x and z, and move up by `ImportOptions::y_offset`. What lies outside our
+//! world is left out. Every chunk of a column Minecraft has blocks in is saved, so the terrain
+//! generator only fills columns that were not imported.
+
+use crate::block_mapping::BlockMapping;
+use crate::block_types::BlockState;
+use crate::level::{self, Level};
+use crate::nbt::{self, Tag};
+use crate::player::EYE_HEIGHT;
+use crate::protocol::CHUNK_VOLUME;
+use crate::region::{self, WorldRegions};
+use crate::voxel_data::{CHUNK_SIZE, WORLD_HEIGHT_IN_CHUNKS};
+use crate::voxel_map::block_chunk;
+use crate::world::{ChunkCoord, WORLD_HEIGHT, WORLD_SIZE};
+use bevy::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const ANVIL_REGION_DIR: &str = "region";
+pub const LEVEL_DAT: &str = "level.dat";
+const SECTOR_SIZE: usize = 4096;
+// Chunk columns along each side of a region file
+const REGION_CHUNKS: i32 = 32;
+const SECTION_SIZE: i32 = 16;
+const SECTION_VOLUME: usize = 4096;
+// Chunk compression types, types above `EXTERNAL` keep the chunk in a file of its own
+const GZIP: u8 = 1;
+const ZLIB: u8 = 2;
+const UNCOMPRESSED: u8 = 3;
+const EXTERNAL: u8 = 128;
+// Data version of 20w17a, before which block indices could span two longs
+const NON_SPANNING_DATA_VERSION: i64 = 2529;
+// Chunk statuses of fully generated chunks, with or without the namespace
+const FULL_STATUSES: [&str; 3] = ["full", "postprocessed", "fullchunk"];
+
+/// How to import a world, read from the command line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportOptions {
+    /// Block mapping file, see `BlockMapping::load`.
+    pub mapping: Option<PathBuf>,
+    /// Blocks added to Minecraft's heights. Minecraft worlds reach from -64 to 320, ours from 0
+    /// to `WORLD_HEIGHT`.
+    pub y_offset: i32,
+}
+
+/// What was imported, to tell the player.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub chunks: usize,
+    pub skipped: usize,
+    /// Minecraft blocks replaced by the placeholder, and in how many sections.
+    pub unknown: BTreeMap<String, usize>,
+    /// What was ignored and why, such as the chunks skipped.
+    pub warnings: Vec<String>,
+}
+
+// A section of a Minecraft chunk as our block states
+struct Section {
+    y: i32,
+    palette: Vec<BlockState>,
+    indices: Vec<usize>,
+}
+
+/// Imports the Minecraft world in `source` as a new world in `saves`, named after it. The
+/// world is removed again if the import fails.
+pub fn import_world(
+    saves: &Path,
+    source: &Path,
+    options: &ImportOptions,
+) -> Result<(PathBuf, ImportSummary), String> {
+    let mapping = match &options.mapping {
+        Some(path) => BlockMapping::load(path)?,
+        None => BlockMapping::default(),
+    };
+    let region_files = region_files(&source.join(ANVIL_REGION_DIR))?;
+    if region_files.is_empty() {
+        return Err(format!(
+            "There is no Minecraft world in {}",
+            source.display()
+        ));
+    }
+    let mut summary = ImportSummary::default();
+    let level_dat = read_level_dat(source, &mut summary);
+    let name = level_dat
+        .as_ref()
+        .and_then(|data| data.get("LevelName")?.as_str().map(str::to_string))
+        .or_else(|| Some(source.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "Imported world".to_string());
+
+    let dir = level::create_world(saves, &name, level::random_seed())?;
+    let result = import_regions(
+        &dir,
+        &region_files,
+        &mapping,
+        options.y_offset,
+        &mut summary,
+    )
+    .and_then(|first_column| {
+        let minecraft_spawn = level_dat.as_ref().and_then(|data| {
+            let coordinate = |name| data.get(name)?.as_i64().map(|value| value as i32);
+            Some((coordinate("SpawnX")?, coordinate("SpawnZ")?))
+        });
+        let mut level = Level::load(&dir)?;
+        level.spawn = [minecraft_spawn, first_column]
+            .into_iter()
+            .flatten()
+            .find_map(|(x, z)| find_spawn(&dir, x, z))
+            .map(|spawn| spawn.to_array());
+        level.save(&dir)?;
+        Ok(())
+    });
+    match result {
+        Ok(()) => Ok((dir, summary)),
+        Err(error) => {
+            let _ = level::delete_world(&dir);
+            Err(error)
+        }
+    }
+}
+
+// A region file and the region it is of
+type RegionFile = (PathBuf, (i32, i32));
+
+// The region files in `dir`
+fn region_files(dir: &Path) -> Result<Vec<RegionFile>, String> {
+    let entries = fs::read_dir(dir).map_err(|error| format!("{}: {}", dir.display(), error))?;
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?;
+            let mut parts = name.strip_prefix("r.")?.strip_suffix(".mca")?.split('.');
+            let x = parts.next()?.parse().ok()?;
+            let z = parts.next()?.parse().ok()?;
+            Some((path, (x, z)))
+        })
+        .collect();
+    files.sort_by_key(|(_, region)| *region);
+    Ok(files)
+}
+
+// The `Data` compound of the world's level.dat, if it can be read
+fn read_level_dat(source: &Path, summary: &mut ImportSummary) -> Option<Tag> {
+    let path = source.join(LEVEL_DAT);
+    let bytes = fs::read(&path).ok()?;
+    match nbt::read(&bytes) {
+        Ok(root) => root.get("Data").cloned(),
+        Err(error) => {
+            summary
+                .warnings
+                .push(format!("Ignoring invalid {}: {}", path.display(), error));
+            None
+        }
+    }
+}
+
+// Converts and saves every region file, adding what was imported to the summary, and returns
+// the middle of the first column with blocks
+fn import_regions(
+    world_dir: &Path,
+    region_files: &[(PathBuf, (i32, i32))],
+    mapping: &BlockMapping,
+    y_offset: i32,
+    summary: &mut ImportSummary,
+) -> Result<Option<(i32, i32)>, String> {
+    let mut first_column = None;
+    for (path, region) in region_files {
+        let chunks = read_region(path, *region, mapping, y_offset, summary)?;
+        if first_column.is_none() {
+            first_column = chunks
+                .keys()
+                .min_by_key(|chunk_pos| (chunk_pos.x, chunk_pos.z))
+                .map(|chunk_pos| {
+                    let middle = CHUNK_SIZE as i32 / 2;
+                    (
+                        chunk_pos.x * CHUNK_SIZE as i32 + middle,
+                        chunk_pos.z * CHUNK_SIZE as i32 + middle,
+                    )
+                });
+        }
+        let chunks: Vec<_> = chunks.into_iter().collect();
+        region::save_chunks(world_dir, &chunks)?;
+    }
+    Ok(first_column)
+}
+
+// Our chunks holding the blocks of a Minecraft region file. Chunks that cannot be read are
+// skipped
+fn read_region(
+    path: &Path,
+    (region_x, region_z): (i32, i32),
+    mapping: &BlockMapping,
+    y_offset: i32,
+    summary: &mut ImportSummary,
+) -> Result<HashMap<ChunkCoord, Vec<BlockState>>, String> {
+    let bytes = fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+    let mut chunks = HashMap::new();
+    // Minecraft leaves empty files for regions it never saved a chunk in
+    if bytes.is_empty() {
+        return Ok(chunks);
+    }
+    if bytes.len() < 2 * SECTOR_SIZE {
+        return Err(format!("{}: not a region file", path.display()));
+    }
+
+    for index in 0..(REGION_CHUNKS * REGION_CHUNKS) as usize {
+        let entry = &bytes[index * 4..index * 4 + 4];
+        let sector = u32::from_be_bytes([0, entry[0], entry[1], entry[2]]) as usize;
+        if sector == 0 {
+            continue;
+        }
+        let chunk_x = region_x * REGION_CHUNKS + index as i32 % REGION_CHUNKS;
+        let chunk_z = region_z * REGION_CHUNKS + index as i32 / REGION_CHUNKS;
+        let sections = chunk_nbt(path, &bytes, sector, (chunk_x, chunk_z))
+            .and_then(|chunk| chunk_sections(&chunk, mapping, summary));
+        match sections {
+            Ok(Some(sections)) => {
+                place_sections(&mut chunks, (chunk_x, chunk_z), &sections, y_offset);
+                summary.chunks += 1;
+            }
+            Ok(None) => (),
+            Err(error) => {
+                summary.warnings.push(format!(
+                    "Skipped chunk ({}, {}) of {}: {}",
+                    chunk_x,
+                    chunk_z,
+                    path.display(),
+                    error
+                ));
+                summary.skipped += 1;
+            }
+        }
+    }
+    Ok(chunks)
+}
+
+// The NBT of the chunk starting at `sector` of a region file
+fn chunk_nbt(
+    path: &Path,
+    bytes: &[u8],
+    sector: usize,
+    (chunk_x, chunk_z): (i32, i32),
+) -> Result<Tag, String> {
+    let start = sector * SECTOR_SIZE;
+    let header = bytes
+        .get(start..start + 5)
+        .ok_or_else(|| "outside the file".to_string())?;
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let compression = header[4];
+    let data = if compression & EXTERNAL != 0 {
+        let external = path.with_file_name(format!("c.{}.{}.mcc", chunk_x, chunk_z));
+        fs::read(&external).map_err(|error| format!("{}: {}", external.display(), error))?
+    } else {
+        bytes
+            .get(start + 5..start + 4 + length.max(1))
+            .ok_or_else(|| "longer than the file".to_string())?
+            .to_vec()
+    };
+    // The NBT reader tells the compression from the data itself
+    match compression & !EXTERNAL {
+        GZIP | ZLIB | UNCOMPRESSED => nbt::read(&data),
+        other => Err(format!("unsupported compression {}", other)),
+    }
+}
+
+// The sections of a chunk, `None` if Minecraft has not finished generating it
+fn chunk_sections(
+    chunk: &Tag,
+    mapping: &BlockMapping,
+    summary: &mut ImportSummary,
+) -> Result<Option<Vec<Section>>, String> {
+    let data_version = chunk.get("DataVersion").and_then(Tag::as_i64).unwrap_or(0);
+    // Chunks kept everything in a `Level` compound before 21w43a
+    let level = chunk.get("Level").unwrap_or(chunk);
+    if let Some(status) = level.get("Status").and_then(Tag::as_str) {
+        let status = status.strip_prefix("minecraft:").unwrap_or(status);
+        if !FULL_STATUSES.contains(&status) {
+            return Ok(None);
+        }
+    }
+    let section_tags = level
+        .get("sections")
+        .or_else(|| level.get("Sections"))
+        .and_then(Tag::as_list)
+        .unwrap_or(&[]);
+
+    let mut sections = Vec::new();
+    for section in section_tags {
+        let y = section
+            .get("Y")
+            .and_then(Tag::as_i64)
+            .ok_or_else(|| "section without a height".to_string())? as i32;
+        let (palette, data) = match section.get("block_states") {
+            Some(states) => (states.get("palette"), states.get("data")),
+            None => (section.get("Palette"), section.get("BlockStates")),
+        };
+        let palette = match palette.and_then(Tag::as_list) {
+            Some(palette) => palette,
+            None if section.get("Blocks").is_some() => {
+                return Err("saved before Minecraft 1.13".to_string());
+            }
+            // Sections of only light
+            None => continue,
+        };
+        if palette.is_empty() || palette.len() > SECTION_VOLUME {
+            return Err(format!("palette of {} block states", palette.len()));
+        }
+        let palette: Vec<_> = palette
+            .iter()
+            .map(|entry| block_state(entry, mapping, summary))
+            .collect::<Result<_, _>>()?;
+
+        let indices = match data.and_then(Tag::as_long_array) {
+            Some(data) => {
+                let bits = (usize::BITS - (palette.len() - 1).leading_zeros()).max(4) as usize;
+                unpack(data, bits, data_version < NON_SPANNING_DATA_VERSION)?
+            }
+            // A single block state fills the section without any data
+            None if palette.len() == 1 => vec![0; SECTION_VOLUME],
+            None => return Err("section without block data".to_string()),
+        };
+        if indices.iter().any(|index| *index >= palette.len()) {
+            return Err("block outside the palette".to_string());
+        }
+        sections.push(Section {
+            y,
+            palette,
+            indices,
+        });
+    }
+    Ok(Some(sections))
+}
+
+// Our block state for a palette entry, counting blocks that are not mapped
+fn block_state(
+    entry: &Tag,
+    mapping: &BlockMapping,
+    summary: &mut ImportSummary,
+) -> Result<BlockState, String> {
+    let name = entry
+        .get("Name")
+        .and_then(Tag::as_str)
+        .ok_or_else(|| "palette entry without a name".to_string())?;
+    let properties: Vec<_> = entry
+        .get("Properties")
+        .and_then(Tag::as_compound)
+        .map(|properties| {
+            properties
+                .iter()
+                .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.to_string())))
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(match mapping.block(name, &properties) {
+        Some(state) => state,
+        None => {
+            *summary.unknown.entry(name.to_string()).or_insert(0) += 1;
+            mapping.placeholder()
+        }
+    })
+}
+
+// Palette indices packed `bits` to an entry into longs, starting at the lowest bits. Entries
+// either run on into the next long or, since 20w17a, leave the bits left over unused
+fn unpack(data: &[i64], bits: usize, spanning: bool) -> Result<Vec<usize>, String> {
+    let mask = (1u64 << bits) - 1;
+    let per_long = 64 / bits;
+    let needed = if spanning {
+        (SECTION_VOLUME * bits).div_ceil(64)
+    } else {
+        SECTION_VOLUME.div_ceil(per_long)
+    };
+    if data.len() < needed {
+        return Err(format!(
+            "{} longs of block data, not {}",
+            data.len(),
+            needed
+        ));
+    }
+
+    let indices = (0..SECTION_VOLUME).map(|index| {
+        let value = if spanning {
+            let (long, offset) = (index * bits / 64, index * bits % 64);
+            let mut value = data[long] as u64 >> offset;
+            if offset + bits > 64 {
+                value |= (data[long + 1] as u64) << (64 - offset);
+            }
+            value
+        } else {
+            data[index / per_long] as u64 >> (index % per_long * bits)
+        };
+        (value & mask) as usize
+    });
+    Ok(indices.collect())
+}
+
+// Writes the sections of a Minecraft chunk into our chunks, adding every chunk of the column
+fn place_sections(
+    chunks: &mut HashMap<ChunkCoord, Vec<BlockState>>,
+    (chunk_x, chunk_z): (i32, i32),
+    sections: &[Section],
+    y_offset: i32,
+) {
+    let half = (WORLD_SIZE / 2) as i32;
+    let size = CHUNK_SIZE as i32;
+    let (origin_x, origin_z) = (chunk_x * SECTION_SIZE, chunk_z * SECTION_SIZE);
+    // Minecraft's chunks lie in one of our columns, since ours are twice as wide
+    if !(-half..half).contains(&origin_x) || !(-half..half).contains(&origin_z) {
+        return;
+    }
+    let column = block_chunk(IVec3::new(origin_x, 0, origin_z));
+    for y in 0..WORLD_HEIGHT_IN_CHUNKS as i32 {
+        chunks
+            .entry(ChunkCoord { y, ..column })
+            .or_insert_with(|| vec![BlockState::AIR; CHUNK_VOLUME]);
+    }
+
+    for section in sections {
+        for (index, palette_index) in section.indices.iter().enumerate() {
+            let index = index as i32;
+            let block = IVec3::new(
+                origin_x + index % SECTION_SIZE,
+                section.y * SECTION_SIZE + index / (SECTION_SIZE * SECTION_SIZE) + y_offset,
+                origin_z + index / SECTION_SIZE % SECTION_SIZE,
+            );
+            if !(0..WORLD_HEIGHT as i32).contains(&block.y) {
+                continue;
+            }
+            let local = IVec3::new(
+                block.x.rem_euclid(size),
+                block.y.rem_euclid(size),
+                block.z.rem_euclid(size),
+            );
+            if let Some(blocks) = chunks.get_mut(&block_chunk(block)) {
+                // x major and z minor, like `VoxelMap::chunk_blocks`
+                blocks[((local.x * size + local.y) * size + local.z) as usize] =
+                    section.palette[*palette_index];
+            }
+        }
+    }
+}
+
+// Camera position above the highest collidable block of the imported column at `x`, `z`, `None`
+// if nothing to stand on was imported there
+fn find_spawn(world_dir: &Path, x: i32, z: i32) -> Option<Vec3> {
+    let mut regions = WorldRegions::new(world_dir);
+    let size = CHUNK_SIZE as i32;
+    let (local_x, local_z) = (x.rem_euclid(size), z.rem_euclid(size));
+    for chunk_y in (0..WORLD_HEIGHT_IN_CHUNKS as i32).rev() {
+        let chunk_pos = block_chunk(IVec3::new(x, chunk_y * size, z));
+        let blocks = regions.load_chunk(chunk_pos)?;
+        for local_y in (0..size).rev() {
+            let block = blocks[((local_x * size + local_y) * size + local_z) as usize];
+            if block.block_type().collidable {
+                let y = chunk_y * size + local_y;
+                return Some(Vec3::new(
+                    x as f32 + 0.5,
+                    y as f32 + 1.0 + EYE_HEIGHT,
+                    z as f32 + 0.5,
+                ));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_types::{block_by_name, Property, AXIS_X};
+
+    fn compound(entries: Vec<(&str, Tag)>) -> Tag {
+        Tag::Compound(
+            entries
+                .into_iter()
+                .map(|(name, tag)| (name.to_string(), tag))
+                .collect(),
+        )
+    }
+
+    fn palette_entry(name: &str, properties: &[(&str, &str)]) -> Tag {
+        let mut entry = vec![("Name", Tag::String(name.to_string()))];
+        if !properties.is_empty() {
+            let properties = properties
+                .iter()
+                .map(|(name, value)| (*name, Tag::String(value.to_string())))
+                .collect();
+            entry.push(("Properties", compound(properties)));
+        }
+        compound(entry)
+    }
+
+    // A chunk as Minecraft saves it, read back from NBT
+    fn chunk(entries: Vec<(&str, Tag)>) -> Tag {
+        match compound(entries) {
+            Tag::Compound(root) => nbt::read(&nbt::write("", &root)).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    // Packs palette indices the way `unpack` reads them
+    fn pack(indices: &[usize], bits: usize, spanning: bool) -> Vec<i64> {
+        let per_long = 64 / bits;
+        let longs = if spanning {
+            (indices.len() * bits).div_ceil(64)
+        } else {
+            indices.len().div_ceil(per_long)
+        };
+        let mut data = vec![0u64; longs];
+        for (index, value) in indices.iter().enumerate() {
+            let value = *value as u64;
+            if spanning {
+                let (long, offset) = (index * bits / 64, index * bits % 64);
+                data[long] |= value << offset;
+                if offset + bits > 64 {
+                    data[long + 1] |= value >> (64 - offset);
+                }
+            } else {
+                data[index / per_long] |= value << (index % per_long * bits);
+            }
+        }
+        data.into_iter().map(|long| long as i64).collect()
+    }
+
+    fn state(name: &str) -> BlockState {
+        BlockState::new(block_by_name(name).unwrap())
+    }
+
+    #[test]
+    fn unpacks_entries_across_longs() {
+        let indices: Vec<usize> = (0..SECTION_VOLUME).map(|index| index * 7 % 32).collect();
+
+        let data = pack(&indices, 5, true);
+        // The 13th entry, 20, starts in the last 4 bits of the first long and ends in the next
+        assert_eq!(indices[12], 0b10100);
+        assert_eq!(data[0] as u64 >> 60, 0b0100);
+        assert_eq!(data[1] & 1, 1);
+        assert_eq!(unpack(&data, 5, true).unwrap(), indices);
+        assert!(unpack(&data[1..], 5, true).is_err());
+
+        // 12 entries to a long, leaving 4 bits unused
+        let data = pack(&indices, 5, false);
+        assert_eq!(data.len(), SECTION_VOLUME.div_ceil(12));
+        assert_eq!(unpack(&data, 5, false).unwrap(), indices);
+    }
+
+    #[test]
+    fn decodes_sections_and_their_palettes() {
+        let indices: Vec<usize> = (0..SECTION_VOLUME).map(|index| index % 4).collect();
+        let section = compound(vec![
+            ("Y", Tag::Byte(-4)),
+            (
+                "block_states",
+                compound(vec![
+                    (
+                        "palette",
+                        Tag::List(vec![
+                            palette_entry("minecraft:air", &[]),
+                            palette_entry("minecraft:granite", &[]),
+                            palette_entry("minecraft:birch_log", &[("axis", "x")]),
+                            palette_entry("minecraft:sand", &[]),
+                        ]),
+                    ),
+                    ("data", Tag::LongArray(pack(&indices, 4, false))),
+                ]),
+            ),
+        ]);
+        let chunk = chunk(vec![
+            ("DataVersion", Tag::Int(3465)),
+            ("Status", Tag::String("minecraft:full".to_string())),
+            ("sections", Tag::List(vec![section])),
+        ]);
+
+        let mut summary = ImportSummary::default();
+        let sections = chunk_sections(&chunk, &BlockMapping::default(), &mut summary)
+            .unwrap()
+            .unwrap();
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].y, -4);
+        assert_eq!(
+            sections[0].palette,
+            vec![
+                BlockState::AIR,
+                state("stone"),
+                state("log").with(Property::Axis, AXIS_X),
+                // Sand is not mapped, so it becomes the placeholder
+                state("stone"),
+            ]
+        );
+        assert_eq!(sections[0].indices, indices);
+        assert_eq!(
+            summary.unknown,
+            BTreeMap::from([("minecraft:sand".to_string(), 1)])
+        );
+
+        let mapping = BlockMapping {
+            placeholder: "dirt".to_string(),
+            ..BlockMapping::default()
+        };
+        let sections = chunk_sections(&chunk, &mapping, &mut ImportSummary::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(sections[0].palette[3], state("dirt"));
+    }
+
+    #[test]
+    fn old_sections_span_longs() {
+        // 17 entries take 5 bits each
+        let mut palette = vec![palette_entry("minecraft:air", &[])];
+        palette.extend((0..16).map(|_| palette_entry("minecraft:dirt", &[])));
+        let indices: Vec<usize> = (0..SECTION_VOLUME).map(|index| index * 7 % 17).collect();
+        let section = compound(vec![
+            ("Y", Tag::Byte(2)),
+            ("Palette", Tag::List(palette)),
+            ("BlockStates", Tag::LongArray(pack(&indices, 5, true))),
+        ]);
+        let chunk = chunk(vec![
+            ("DataVersion", Tag::Int(2230)),
+            (
+                "Level",
+                compound(vec![
+                    ("Status", Tag::String("full".to_string())),
+                    ("Sections", Tag::List(vec![section])),
+                ]),
+            ),
+        ]);
+
+        let sections = chunk_sections(
+            &chunk,
+            &BlockMapping::default(),
+            &mut ImportSummary::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(sections[0].indices, indices);
+    }
+
+    #[test]
+    fn unfinished_chunks_and_single_block_sections() {
+        let unfinished = chunk(vec![
+            ("DataVersion", Tag::Int(3465)),
+            ("Status", Tag::String("minecraft:features".to_string())),
+        ]);
+        let mapping = BlockMapping::default();
+        let mut summary = ImportSummary::default();
+        assert!(chunk_sections(&unfinished, &mapping, &mut summary)
+            .unwrap()
+            .is_none());
+
+        let section = compound(vec![
+            ("Y", Tag::Byte(0)),
+            (
+                "block_states",
+                compound(vec![(
+                    "palette",
+                    Tag::List(vec![palette_entry("minecraft:stone", &[])]),
+                )]),
+            ),
+        ]);
+        let chunk = chunk(vec![
+            ("DataVersion", Tag::Int(3465)),
+            ("sections", Tag::List(vec![section])),
+        ]);
+        let sections = chunk_sections(&chunk, &mapping, &mut summary)
+            .unwrap()
+            .unwrap();
+        assert_eq!(sections[0].indices, vec![0; SECTION_VOLUME]);
+    }
+
+    #[test]
+    fn sections_move_by_the_y_offset() {
+        // A single stone at 3, -62, 5 in the lowest section of a modern world
+        let mut indices = vec![0; SECTION_VOLUME];
+        indices[(2 * SECTION_SIZE * SECTION_SIZE + 5 * SECTION_SIZE + 3) as usize] = 1;
+        let sections = [Section {
+            y: -4,
+            palette: vec![BlockState::AIR, state("stone")],
+            indices,
+        }];
+        let stone_at = |chunks: &HashMap<ChunkCoord, Vec<BlockState>>, y: i32| {
+            let block = IVec3::new(3, y, 5);
+            let size = CHUNK_SIZE as i32;
+            let local = IVec3::new(block.x, block.y.rem_euclid(size), block.z);
+            chunks[&block_chunk(block)][((local.x * size + local.y) * size + local.z) as usize]
+                == state("stone")
+        };
+
+        let mut chunks = HashMap::new();
+        place_sections(&mut chunks, (0, 0), &sections, 64);
+        assert_eq!(chunks.len(), WORLD_HEIGHT_IN_CHUNKS);
+        assert!(stone_at(&chunks, 2));
+        let stones: usize = chunks
+            .values()
+            .map(|blocks| {
+                blocks
+                    .iter()
+                    .filter(|block| **block != BlockState::AIR)
+                    .count()
+            })
+            .sum();
+        assert_eq!(stones, 1);
+
+        let mut chunks = HashMap::new();
+        place_sections(&mut chunks, (0, 0), &sections, 100);
+        assert!(stone_at(&chunks, 38));
+
+        // Below the bottom of our world without an offset
+        let mut chunks = HashMap::new();
+        place_sections(&mut chunks, (0, 0), &sections, 0);
+        assert!(chunks
+            .values()
+            .all(|blocks| blocks.iter().all(|block| *block == BlockState::AIR)));
+    }
+}
@@ -0,0 +1,78 @@
+use minecrust::block_mapping::BlockMapping;
+use minecrust::schematic::{Schematic, SchematicFormat};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+/// Converts schematics between formats: `schematic <input> <output>` reads a `.schem` or
+/// `.vox` file and writes it in the format of the output's extension. `--block-mapping <file>`
+/// maps Minecraft's blocks, see `BlockMapping::load`, and `--sponge-version 2` writes Sponge
+/// schematics in version 2. With only an input, prints its size and blocks.
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut paths = Vec::new();
+    let mut mapping = BlockMapping::default();
+    let mut sponge_version = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--block-mapping" => mapping = BlockMapping::load(&PathBuf::from(value()?))?,
+            "--sponge-version" => {
+                let version = value()?;
+                sponge_version = match version.as_str() {
+                    "2" => Some(2),
+                    "3" => Some(3),
+                    _ => return Err(format!("Unknown Sponge schematic version {}", version)),
+                };
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let (input, output) = match paths.as_slice() {
+        [input] => (input, None),
+        [input, output] => (input, Some(output)),
+        _ => return Err(
+            "Usage: schematic <input> [output] [--block-mapping <file>] [--sponge-version <2|3>]"
+                .to_string(),
+        ),
+    };
+    let (schematic, unknown) = Schematic::load(input, &mapping)?;
+    for name in unknown {
+        println!("Replaced unmapped {} with {}", name, mapping.placeholder);
+    }
+
+    let output = match output {
+        Some(output) => output,
+        None => {
+            let size = schematic.size;
+            println!("{} by {} by {} blocks", size.x, size.y, size.z);
+            let mut counts = BTreeMap::new();
+            for state in schematic.blocks.iter() {
+                *counts.entry(state.block_type().name).or_insert(0) += 1;
+            }
+            for (name, count) in counts {
+                println!("{} {}", count, name);
+            }
+            return Ok(());
+        }
+    };
+    let format = match (SchematicFormat::from_path(output)?, sponge_version) {
+        (SchematicFormat::Sponge { .. }, Some(version)) => SchematicFormat::Sponge { version },
+        (format, _) => format,
+    };
+    schematic.save(output, format)?;
+    println!("Wrote {}", output.display());
+    Ok(())
+}
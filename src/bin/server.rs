@@ -0,0 +1,113 @@
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+use minecrust::level::{self, Level};
+use minecrust::player::GameMode;
+use minecrust::protocol::DEFAULT_PORT;
+use minecrust::save::{DEFAULT_WORLD, SAVES_DIR};
+use minecrust::server::Server;
+use minecrust::settings::MIN_WORLD_SIZE;
+use minecrust::voxel_data::WORLD_SIZE_IN_CHUNKS;
+use std::env;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Dedicated server, listening on the address given as the first argument. `--world <world>`
+/// picks the world from the saves directory, which is created if it does not exist yet,
+/// `--world-size <chunks>` how many chunks across it is and `--game-mode <creative|survival>`
+/// what players play in. Typing `stop` saves the world and shuts the server down.
+fn main() {
+    // The logger, without the rest of an app
+    App::new().add_plugin(LogPlugin);
+
+    let mut address = format!("0.0.0.0:{}", DEFAULT_PORT);
+    let mut world = DEFAULT_WORLD.to_string();
+    let mut world_size = WORLD_SIZE_IN_CHUNKS;
+    let mut game_mode = GameMode::Survival;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--world" => match args.next() {
+                Some(name) => world = name,
+                None => {
+                    error!("Missing value for --world");
+                    process::exit(1);
+                }
+            },
+            "--world-size" => match args.next().and_then(|size| size.parse().ok()) {
+                Some(size) => world_size = size,
+                None => {
+                    error!("Missing or invalid value for --world-size");
+                    process::exit(1);
+                }
+            },
+            "--game-mode" => match args.next().as_deref() {
+                Some("creative") => game_mode = GameMode::Creative,
+                Some("survival") => game_mode = GameMode::Survival,
+                _ => {
+                    error!("Missing or invalid value for --game-mode");
+                    process::exit(1);
+                }
+            },
+            _ => address = arg,
+        }
+    }
+
+    let dir = match level::world_dir(Path::new(SAVES_DIR), &world) {
+        Ok(dir) => dir,
+        Err(error) => {
+            error!("{}", error);
+            process::exit(1);
+        }
+    };
+    let level = match open_or_create(&dir, &world) {
+        Ok(level) => level,
+        Err(error) => {
+            error!("{}", error);
+            process::exit(1);
+        }
+    };
+    info!("Serving {} with seed {}", level.name, level.seed);
+
+    let world_size = world_size.clamp(MIN_WORLD_SIZE, WORLD_SIZE_IN_CHUNKS);
+    let mut server = match Server::open(&address, &dir, level, world_size) {
+        Ok(server) => server,
+        Err(error) => {
+            error!("Could not listen on {}: {}", address, error);
+            process::exit(1);
+        }
+    };
+    server.game_mode = game_mode;
+    match server.local_addr() {
+        Ok(address) => info!("Listening on {}", address),
+        Err(_) => info!("Listening on {}", address),
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_command = stop.clone();
+    thread::spawn(move || {
+        // Without a console to read from, the server runs until it is killed
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) if line.trim() == "stop" => {
+                    stop_command.store(true, Ordering::Relaxed);
+                    return;
+                }
+                Ok(line) => warn!("Unknown command {}, only stop is understood", line.trim()),
+                Err(_) => return,
+            }
+        }
+    });
+    server.run(&stop);
+    info!("Stopped");
+}
+
+fn open_or_create(dir: &Path, name: &str) -> Result<Level, String> {
+    if !dir.exists() {
+        Level::new(name, level::random_seed()).save(dir)?;
+    }
+    level::open_world(dir)
+}
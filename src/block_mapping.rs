@@ -0,0 +1,392 @@
+//! Minecraft's blocks mapped to ours, for bringing in what was built in Minecraft.
+
+use crate::block_types::{
+    block_by_name, BlockState, Property, AXIS_X, AXIS_Y, AXIS_Z, HALF_BOTTOM, HALF_TOP,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const MINECRAFT_NAMESPACE: &str = "minecraft:";
+// Minecraft's names of the faces, indexed like `FACE_CHECKS`
+const FACE_NAMES: [&str; 6] = ["west", "east", "up", "down", "south", "north"];
+// Minecraft blocks that are not mapped become this
+pub const DEFAULT_PLACEHOLDER: &str = "stone";
+
+/// Minecraft blocks by name without the namespace, and our blocks they become. A `*` at the
+/// start of a name matches any start, so `*_planks` stands for the planks of every wood.
+pub static DEFAULT_BLOCK_MAPPING: &[(&str, &str)] = &[
+    ("air", "air"),
+    ("cave_air", "air"),
+    ("void_air", "air"),
+    ("stone", "stone"),
+    ("granite", "stone"),
+    ("polished_granite", "stone"),
+    ("diorite", "stone"),
+    ("polished_diorite", "stone"),
+    ("andesite", "stone"),
+    ("polished_andesite", "stone"),
+    ("cobblestone", "stone"),
+    ("mossy_cobblestone", "stone"),
+    ("smooth_stone", "stone"),
+    ("deepslate", "stone"),
+    ("cobbled_deepslate", "stone"),
+    ("tuff", "stone"),
+    ("calcite", "stone"),
+    ("*_ore", "stone"),
+    ("*stone_bricks", "stone"),
+    ("bedrock", "bedrock"),
+    ("grass_block", "grass"),
+    ("mycelium", "grass"),
+    ("dirt", "dirt"),
+    ("coarse_dirt", "dirt"),
+    ("rooted_dirt", "dirt"),
+    ("podzol", "dirt"),
+    ("farmland", "dirt"),
+    ("dirt_path", "dirt"),
+    ("water", "water"),
+    ("bubble_column", "water"),
+    ("lava", "lava"),
+    ("nether_portal", "portal"),
+    ("*_log", "log"),
+    ("*_wood", "log"),
+    ("*_stem", "log"),
+    ("*_hyphae", "log"),
+    ("furnace", "furnace"),
+    ("blast_furnace", "furnace"),
+    ("smoker", "furnace"),
+    ("*_planks", "planks"),
+    ("*_slab", "stone_slab"),
+    ("*_stairs", "planks_stairs"),
+    ("grass", "tall_grass"),
+    ("short_grass", "tall_grass"),
+    ("tall_grass", "tall_grass"),
+    ("fern", "tall_grass"),
+    ("large_fern", "tall_grass"),
+    // Not logs, unlike the other stems
+    ("pumpkin_stem", "tall_grass"),
+    ("melon_stem", "tall_grass"),
+    ("attached_pumpkin_stem", "tall_grass"),
+    ("attached_melon_stem", "tall_grass"),
+    ("poppy", "rose"),
+    ("rose_bush", "rose"),
+    ("red_tulip", "rose"),
+    ("dandelion", "dandelion"),
+    ("sunflower", "dandelion"),
+    ("*_fence", "fence"),
+    ("torch", "torch"),
+    ("wall_torch", "torch"),
+    ("soul_torch", "torch"),
+    ("soul_wall_torch", "torch"),
+];
+
+/// Our blocks and the Minecraft blocks they are saved as in Minecraft's formats. They map back
+/// to the same blocks through the default mapping.
+pub static MINECRAFT_NAMES: &[(&str, &str)] = &[
+    ("air", "air"),
+    ("stone", "stone"),
+    ("bedrock", "bedrock"),
+    ("grass", "grass_block"),
+    ("dirt", "dirt"),
+    ("water", "water"),
+    ("lava", "lava"),
+    ("portal", "nether_portal"),
+    ("log", "oak_log"),
+    ("furnace", "furnace"),
+    ("planks", "oak_planks"),
+    ("stone_slab", "stone_slab"),
+    ("planks_stairs", "oak_stairs"),
+    ("tall_grass", "short_grass"),
+    ("rose", "poppy"),
+    ("dandelion", "dandelion"),
+    ("fence", "oak_fence"),
+    ("torch", "torch"),
+];
+
+/// How Minecraft's blocks become ours. Mapping files are TOML, holding the placeholder and a
+/// `[blocks]` table of Minecraft names and our names, added over the default mapping:
+///
+/// ```toml
+/// placeholder = "dirt"
+///
+/// [blocks]
+/// sand = "dirt"
+/// "*_leaves" = "air"
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockMapping {
+    /// Our block for Minecraft blocks that are not mapped.
+    pub placeholder: String,
+    pub blocks: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct MappingFile {
+    placeholder: Option<String>,
+    #[serde(default)]
+    blocks: BTreeMap<String, String>,
+}
+
+impl Default for BlockMapping {
+    fn default() -> Self {
+        BlockMapping {
+            placeholder: DEFAULT_PLACEHOLDER.to_string(),
+            blocks: DEFAULT_BLOCK_MAPPING
+                .iter()
+                .map(|(minecraft, ours)| (minecraft.to_string(), ours.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl BlockMapping {
+    /// The default mapping with the one in the file at `path` over it.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text =
+            fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        let file: MappingFile =
+            toml::from_str(&text).map_err(|error| format!("{}: {}", path.display(), error))?;
+
+        let mut mapping = BlockMapping::default();
+        if let Some(placeholder) = file.placeholder {
+            mapping.placeholder = placeholder;
+        }
+        for (minecraft, ours) in file.blocks {
+            let minecraft = minecraft
+                .strip_prefix(MINECRAFT_NAMESPACE)
+                .unwrap_or(&minecraft)
+                .to_string();
+            mapping.blocks.insert(minecraft, ours);
+        }
+        mapping.check()?;
+        Ok(mapping)
+    }
+
+    /// Fails if the mapping names a block of ours that does not exist.
+    pub fn check(&self) -> Result<(), String> {
+        for ours in self.blocks.values().chain([&self.placeholder]) {
+            if block_by_name(ours).is_none() {
+                return Err(format!("There is no block called {}", ours));
+            }
+        }
+        Ok(())
+    }
+
+    /// Our block state for a Minecraft block state, `None` if the block is not mapped.
+    /// Properties both blocks have are carried over.
+    pub fn block(&self, name: &str, properties: &[(String, String)]) -> Option<BlockState> {
+        let ours = block_by_name(self.target(name)?)?;
+        let mut state = BlockState::new(ours);
+        for (property, value) in properties {
+            if let Some((property, value)) = minecraft_property(property, value) {
+                state = state.with(property, value);
+            }
+        }
+        Some(state)
+    }
+
+    pub fn placeholder(&self) -> BlockState {
+        BlockState::new(block_by_name(&self.placeholder).unwrap_or(0))
+    }
+
+    // Our block's name for a Minecraft block. Exact names come first, then the pattern matching
+    // the longest part of the name
+    fn target(&self, name: &str) -> Option<&str> {
+        let name = name.strip_prefix(MINECRAFT_NAMESPACE).unwrap_or(name);
+        if let Some(ours) = self.blocks.get(name) {
+            return Some(ours);
+        }
+        self.blocks
+            .iter()
+            .filter_map(|(pattern, ours)| {
+                let suffix = pattern.strip_prefix('*')?;
+                name.ends_with(suffix).then_some((suffix.len(), ours))
+            })
+            .max_by_key(|(length, _)| *length)
+            .map(|(_, ours)| ours.as_str())
+    }
+}
+
+/// A block state as Minecraft writes it, like `minecraft:oak_log[axis=x]`.
+pub fn minecraft_state(state: BlockState) -> String {
+    let block_type = state.block_type();
+    let name = MINECRAFT_NAMES
+        .iter()
+        .find(|(ours, _)| *ours == block_type.name)
+        .map_or(block_type.name, |(_, minecraft)| *minecraft);
+    let properties: Vec<String> = block_type
+        .properties
+        .iter()
+        .filter_map(|property| {
+            let value = state.get(*property)?;
+            let value = match (property, value) {
+                (Property::Axis, AXIS_X) => "x".to_string(),
+                (Property::Axis, AXIS_Z) => "z".to_string(),
+                (Property::Axis, _) => "y".to_string(),
+                (Property::Half, HALF_TOP) => "top".to_string(),
+                (Property::Half, _) => "bottom".to_string(),
+                (Property::Waterlogged, value) => (value != 0).to_string(),
+                (Property::Facing, face) => FACE_NAMES.get(face as usize)?.to_string(),
+                (Property::Level, level) => level.to_string(),
+            };
+            let property = match property {
+                Property::Half if name.ends_with("_slab") => "type",
+                _ => property.name(),
+            };
+            Some(format!("{}={}", property, value))
+        })
+        .collect();
+    if properties.is_empty() {
+        format!("{}{}", MINECRAFT_NAMESPACE, name)
+    } else {
+        format!("{}{}[{}]", MINECRAFT_NAMESPACE, name, properties.join(","))
+    }
+}
+
+/// Name and properties of a block state written like `minecraft:oak_log[axis=x]`.
+pub fn parse_minecraft_state(text: &str) -> (&str, Vec<(String, String)>) {
+    let (name, properties) = match text.split_once('[') {
+        Some((name, properties)) => (name, properties.trim_end_matches(']')),
+        None => (text, ""),
+    };
+    let properties = properties
+        .split(',')
+        .filter_map(|property| {
+            let (name, value) = property.split_once('=')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+    (name, properties)
+}
+
+// Our property and value for a Minecraft block state property, if we have it
+fn minecraft_property(name: &str, value: &str) -> Option<(Property, u8)> {
+    match (name, value) {
+        ("axis", "x") => Some((Property::Axis, AXIS_X)),
+        ("axis", "y") => Some((Property::Axis, AXIS_Y)),
+        ("axis", "z") => Some((Property::Axis, AXIS_Z)),
+        // Stairs have a half, slabs a type
+        ("half" | "type", "top") => Some((Property::Half, HALF_TOP)),
+        ("half" | "type", "bottom") => Some((Property::Half, HALF_BOTTOM)),
+        ("waterlogged", "true") => Some((Property::Waterlogged, 1)),
+        ("waterlogged", "false") => Some((Property::Waterlogged, 0)),
+        ("level", level) => {
+            let level = level.parse::<u8>().ok()?;
+            (level < Property::Level.value_count()).then_some((Property::Level, level))
+        }
+        ("facing", facing) => {
+            let face = FACE_NAMES.iter().position(|name| *name == facing)?;
+            Some((Property::Facing, face as u8))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn state(name: &str) -> BlockState {
+        BlockState::new(block_by_name(name).unwrap())
+    }
+
+    fn properties(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn maps_names_and_properties() {
+        let mapping = BlockMapping::default();
+        assert_eq!(
+            mapping.block("minecraft:spruce_log", &properties(&[("axis", "x")])),
+            Some(state("log").with(Property::Axis, AXIS_X))
+        );
+        assert_eq!(
+            mapping.block(
+                "minecraft:oak_stairs",
+                &properties(&[("facing", "north"), ("half", "top"), ("shape", "straight")])
+            ),
+            Some(
+                state("planks_stairs")
+                    .with(Property::Facing, 5)
+                    .with(Property::Half, HALF_TOP)
+            )
+        );
+        // Properties our block does not have are dropped
+        assert_eq!(
+            mapping.block("minecraft:stone", &properties(&[("axis", "z")])),
+            Some(state("stone"))
+        );
+    }
+
+    #[test]
+    fn exact_names_come_before_the_longest_pattern() {
+        let mut mapping = BlockMapping::default();
+        mapping
+            .blocks
+            .insert("*birch_log".to_string(), "planks".to_string());
+        assert_eq!(mapping.block("warped_stem", &[]), Some(state("log")));
+        assert_eq!(
+            mapping.block("pumpkin_stem", &[]),
+            Some(state("tall_grass"))
+        );
+        assert_eq!(mapping.block("oak_log", &[]), Some(state("log")));
+        assert_eq!(mapping.block("birch_log", &[]), Some(state("planks")));
+    }
+
+    #[test]
+    fn unmapped_blocks_fall_back_to_the_placeholder() {
+        let mapping = BlockMapping::default();
+        assert_eq!(mapping.block("minecraft:sand", &[]), None);
+        assert_eq!(mapping.placeholder(), state(DEFAULT_PLACEHOLDER));
+    }
+
+    #[test]
+    fn minecraft_states_map_back() {
+        let mapping = BlockMapping::default();
+        let states = MINECRAFT_NAMES.iter().map(|(ours, _)| state(ours)).chain([
+            state("log").with(Property::Axis, AXIS_Z),
+            state("stone_slab")
+                .with(Property::Half, HALF_TOP)
+                .with(Property::Waterlogged, 1),
+            state("planks_stairs").with(Property::Facing, 4),
+            state("water").with(Property::Level, 7),
+        ]);
+        for state in states {
+            let text = minecraft_state(state);
+            let (name, properties) = parse_minecraft_state(&text);
+            assert_eq!(mapping.block(name, &properties), Some(state), "{}", text);
+        }
+        assert_eq!(
+            minecraft_state(state("log").with(Property::Axis, AXIS_X)),
+            "minecraft:oak_log[axis=x]"
+        );
+    }
+
+    #[test]
+    fn mapping_files_go_over_the_default() {
+        let path = env::temp_dir().join(format!("minecrust-mapping-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "placeholder = \"dirt\"\n\n[blocks]\n\"minecraft:sand\" = \"dirt\"\nstone = \"planks\"\n",
+        )
+        .unwrap();
+        let mapping = BlockMapping::load(&path).unwrap();
+        assert_eq!(mapping.placeholder(), state("dirt"));
+        assert_eq!(mapping.block("minecraft:sand", &[]), Some(state("dirt")));
+        assert_eq!(mapping.block("minecraft:stone", &[]), Some(state("planks")));
+        assert_eq!(mapping.block("minecraft:dirt", &[]), Some(state("dirt")));
+
+        fs::write(&path, "[blocks]\nsand = \"sandstone\"\n").unwrap();
+        assert_eq!(
+            BlockMapping::load(&path),
+            Err("There is no block called sandstone".to_string())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
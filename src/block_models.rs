@@ -0,0 +1,304 @@
+use crate::block_types::{BlockState, Property, BLOCKTYPES, HALF_TOP};
+use crate::voxel_data::SUBVOXELS;
+use bevy::utils::HashMap;
+use serde::Deserialize;
+
+/// Geometry a block is meshed with. Custom models are looked up by name in `CUSTOM_MODELS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockModel {
+    Cube,
+    /// Lower or upper half of a cube, depending on `Property::Half`.
+    Slab,
+    /// Half slab with a quarter block on top at the back, turned by `Property::Facing` and
+    /// flipped by `Property::Half`.
+    Stairs,
+    /// Two diagonal quads crossing in the middle of the block, drawn from both sides.
+    Cross,
+    Custom(&'static str),
+}
+
+// Custom models, as a list of boxes in sixteenths of a block
+const CUSTOM_MODELS: [(&str, &str); 2] = [
+    ("fence", include_str!("../assets/models/block/fence.json")),
+    ("torch", include_str!("../assets/models/block/torch.json")),
+];
+
+/// Axis aligned box inside a block, in sixteenths of a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct Cuboid {
+    pub from: [u32; 3],
+    pub to: [u32; 3],
+}
+
+#[derive(Deserialize)]
+struct ModelFile {
+    boxes: Vec<Cuboid>,
+}
+
+impl Cuboid {
+    pub const FULL: Cuboid = Cuboid {
+        from: [0, 0, 0],
+        to: [SUBVOXELS, SUBVOXELS, SUBVOXELS],
+    };
+
+    /// Whether face `face` of the box lies on the boundary of the block.
+    pub fn touches_boundary(&self, face: usize) -> bool {
+        match face {
+            0 => self.from[0] == 0,
+            1 => self.to[0] == SUBVOXELS,
+            2 => self.to[1] == SUBVOXELS,
+            3 => self.from[1] == 0,
+            4 => self.to[2] == SUBVOXELS,
+            _ => self.from[2] == 0,
+        }
+    }
+
+    /// Box turned by `turns` quarter turns around the vertical axis through the block centre,
+    /// each turn moving face 0 to face 5 like `Property::Facing`.
+    fn rotated(self, turns: usize) -> Cuboid {
+        let mut cuboid = self;
+        for _ in 0..turns {
+            let (a, b) = (
+                [SUBVOXELS - cuboid.from[2], cuboid.from[1], cuboid.from[0]],
+                [SUBVOXELS - cuboid.to[2], cuboid.to[1], cuboid.to[0]],
+            );
+            cuboid = Cuboid {
+                from: [a[0].min(b[0]), a[1], a[2].min(b[2])],
+                to: [a[0].max(b[0]), b[1], a[2].max(b[2])],
+            };
+        }
+        cuboid
+    }
+
+    fn flipped(self) -> Cuboid {
+        Cuboid {
+            from: [self.from[0], SUBVOXELS - self.to[1], self.from[2]],
+            to: [self.to[0], SUBVOXELS - self.from[1], self.to[2]],
+        }
+    }
+}
+
+/// Parsed custom models, needed to turn block states into boxes.
+pub struct BlockModels {
+    custom: HashMap<&'static str, Vec<Cuboid>>,
+}
+
+impl Default for BlockModels {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockModels {
+    pub fn new() -> Self {
+        let mut custom = HashMap::default();
+
+        for (name, source) in CUSTOM_MODELS {
+            let model: ModelFile = serde_json::from_str(source)
+                .unwrap_or_else(|error| panic!("invalid block model {}: {}", name, error));
+            for cuboid in model.boxes.iter() {
+                assert!(
+                    (0..3)
+                        .all(|axis| cuboid.from[axis] < cuboid.to[axis]
+                            && cuboid.to[axis] <= SUBVOXELS),
+                    "block model {} has a box outside the block",
+                    name
+                );
+            }
+            custom.insert(name, model.boxes);
+        }
+        for block_type in BLOCKTYPES.iter() {
+            if let BlockModel::Custom(name) = block_type.model {
+                assert!(
+                    custom.contains_key(name),
+                    "block {} uses unknown model {}",
+                    block_type.name,
+                    name
+                );
+            }
+        }
+
+        BlockModels { custom }
+    }
+
+    /// Boxes making up the block, empty for air and cross models.
+    pub fn boxes(&self, state: BlockState) -> Vec<Cuboid> {
+        let block_type = state.block_type();
+        if block_type.textures.is_none() {
+            return Vec::new();
+        }
+        let half = SUBVOXELS / 2;
+        let is_top = state.get(Property::Half) == Some(HALF_TOP);
+        let flip = |cuboid: Cuboid| if is_top { cuboid.flipped() } else { cuboid };
+
+        match block_type.model {
+            BlockModel::Cube => vec![Cuboid::FULL],
+            BlockModel::Slab => vec![flip(Cuboid {
+                from: [0, 0, 0],
+                to: [SUBVOXELS, half, SUBVOXELS],
+            })],
+            BlockModel::Stairs => {
+                let turns = stairs_turns(state);
+                vec![
+                    flip(Cuboid {
+                        from: [0, 0, 0],
+                        to: [SUBVOXELS, half, SUBVOXELS],
+                    }),
+                    flip(Cuboid {
+                        from: [half, half, 0],
+                        to: [SUBVOXELS, SUBVOXELS, SUBVOXELS],
+                    })
+                    .rotated(turns),
+                ]
+            }
+            BlockModel::Cross => Vec::new(),
+            BlockModel::Custom(name) => self.custom[name].clone(),
+        }
+    }
+
+    /// Boxes players and items bump into, none for blocks that are not collidable.
+    pub fn collision_boxes(&self, state: BlockState) -> Vec<Cuboid> {
+        if state.block_type().collidable {
+            self.boxes(state)
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+// Quarter turns from a stair facing face 0, vertical facings counting as face 0
+fn stairs_turns(state: BlockState) -> usize {
+    [0, 5, 1, 4]
+        .iter()
+        .position(|face| Some(*face) == state.get(Property::Facing).map(usize::from))
+        .unwrap_or(0)
+}
+
+/// Whether the block covers the whole of its face `face`, hiding the neighbouring face.
+pub fn occludes(state: BlockState, face: usize) -> bool {
+    let block_type = state.block_type();
+    if !block_type.is_solid {
+        return false;
+    }
+    let is_top = state.get(Property::Half) == Some(HALF_TOP);
+    let full_half = if is_top { 2 } else { 3 };
+
+    match block_type.model {
+        BlockModel::Cube => true,
+        BlockModel::Slab => face == full_half,
+        BlockModel::Stairs => face == full_half || face == [1, 4, 0, 5][stairs_turns(state)],
+        BlockModel::Cross | BlockModel::Custom(_) => false,
+    }
+}
+
+/// Corners of the two diagonal quads of a cross model in sixteenths, each listed in both
+/// windings, and the horizontal texture coordinate of each corner.
+pub const CROSS_QUADS: [[[u32; 3]; 4]; 4] = [
+    [[0, 0, 0], [0, 16, 0], [16, 16, 16], [16, 0, 16]],
+    [[16, 0, 16], [16, 16, 16], [0, 16, 0], [0, 0, 0]],
+    [[0, 0, 16], [0, 16, 16], [16, 16, 0], [16, 0, 0]],
+    [[16, 0, 0], [16, 16, 0], [0, 16, 16], [0, 0, 16]],
+];
+pub const CROSS_U: [u32; 4] = [0, 0, 16, 16];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_types::{block_by_name, HALF_BOTTOM};
+
+    fn state(name: &str) -> BlockState {
+        BlockState::new(block_by_name(name).unwrap())
+    }
+
+    fn cuboid(from: [u32; 3], to: [u32; 3]) -> Cuboid {
+        Cuboid { from, to }
+    }
+
+    #[test]
+    fn slabs_are_half_blocks() {
+        let block_models = BlockModels::new();
+        let bottom = state("stone_slab").with(Property::Half, HALF_BOTTOM);
+        let top = state("stone_slab").with(Property::Half, HALF_TOP);
+
+        assert_eq!(
+            block_models.boxes(bottom),
+            vec![cuboid([0, 0, 0], [16, 8, 16])]
+        );
+        assert_eq!(
+            block_models.boxes(top),
+            vec![cuboid([0, 8, 0], [16, 16, 16])]
+        );
+        assert_eq!(block_models.collision_boxes(top), block_models.boxes(top));
+        assert_eq!(
+            (0..6)
+                .filter(|face| occludes(bottom, *face))
+                .collect::<Vec<_>>(),
+            vec![3]
+        );
+        assert_eq!(
+            (0..6)
+                .filter(|face| occludes(top, *face))
+                .collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn stairs_turn_their_step_away_from_the_facing() {
+        let block_models = BlockModels::new();
+        let stairs = state("planks_stairs");
+        // Facing north, the step sits on the south half
+        let north = stairs.with(Property::Facing, 5);
+        assert_eq!(
+            block_models.boxes(north),
+            vec![
+                cuboid([0, 0, 0], [16, 8, 16]),
+                cuboid([0, 8, 8], [16, 16, 16]),
+            ]
+        );
+        assert_eq!(
+            (0..6)
+                .filter(|face| occludes(north, *face))
+                .collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+
+        // Upside down and facing east, the step hangs under the west half
+        let east_top = stairs
+            .with(Property::Facing, 1)
+            .with(Property::Half, HALF_TOP);
+        assert_eq!(
+            block_models.boxes(east_top),
+            vec![
+                cuboid([0, 8, 0], [16, 16, 16]),
+                cuboid([0, 0, 0], [8, 8, 16]),
+            ]
+        );
+
+        for (facing, back) in [(0, 1), (1, 0), (4, 5), (5, 4)] {
+            let step = block_models.boxes(stairs.with(Property::Facing, facing as u8))[1];
+            assert!(step.touches_boundary(back), "facing {}", facing);
+            assert!(!step.touches_boundary(facing), "facing {}", facing);
+        }
+    }
+
+    #[test]
+    fn liquids_have_boxes_but_no_collision() {
+        let block_models = BlockModels::new();
+        let water = state("water");
+        assert_eq!(block_models.boxes(water), vec![Cuboid::FULL]);
+        assert!(block_models.collision_boxes(water).is_empty());
+        assert!(block_models.boxes(BlockState::AIR).is_empty());
+        assert!(block_models.boxes(state("rose")).is_empty());
+    }
+
+    #[test]
+    fn custom_models_stay_inside_the_block() {
+        let block_models = BlockModels::new();
+        let torch = block_models.boxes(state("torch"));
+        assert!(!torch.is_empty());
+        assert!(torch.iter().all(|cuboid| cuboid.from[1] == 0
+            && !cuboid.touches_boundary(0)
+            && !cuboid.touches_boundary(1)));
+    }
+}
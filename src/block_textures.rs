@@ -0,0 +1,676 @@
+use crate::block_types::{BLOCKTYPES, TEXTURE_ANIMATIONS};
+use crate::chunk::MaterialHandle;
+use crate::voxel_material::VoxelMaterial;
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{
+    AddressMode, Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, SamplerDescriptor,
+    TextureAspect, TextureDimension, TextureFormat,
+};
+use bevy::render::renderer::RenderQueue;
+use bevy::render::texture::ImageSampler;
+use bevy::render::{RenderApp, RenderStage};
+use bevy::sprite::Rect;
+use bevy::utils::HashMap;
+use std::fmt;
+use std::num::NonZeroU32;
+
+pub const BLOCK_TEXTURE_DIRECTORY: &str = "textures/block";
+// Edge pixels repeated around every atlas tile so filtering never samples a neighbour
+pub const ATLAS_GUTTER: u32 = 2;
+// Layer used for textures that are missing or could not be packed
+pub const MISSING_TEXTURE: &str = "missing";
+
+#[derive(Debug)]
+pub enum TextureError {
+    Missing {
+        block: &'static str,
+        texture: &'static str,
+    },
+    SizeMismatch {
+        texture: String,
+        found: (u32, u32),
+        expected: (u32, u32),
+    },
+    FormatMismatch {
+        texture: String,
+        found: TextureFormat,
+    },
+    Empty {
+        texture: String,
+    },
+    BadAnimation {
+        texture: String,
+        found: (u32, u32),
+    },
+    BadFrameTimes {
+        texture: String,
+        frame_times: &'static [f32],
+    },
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextureError::Missing { block, texture } => write!(
+                f,
+                "block {} uses texture {}, but there is no assets/{}/{}.png",
+                block, texture, BLOCK_TEXTURE_DIRECTORY, texture
+            ),
+            TextureError::SizeMismatch {
+                texture,
+                found,
+                expected,
+            } => write!(
+                f,
+                "block texture {} is {}x{}, but block textures are {}x{}",
+                texture, found.0, found.1, expected.0, expected.1
+            ),
+            TextureError::FormatMismatch { texture, found } => write!(
+                f,
+                "block texture {} is {:?}, but block textures must be 8 bit RGB or RGBA PNGs, \
+                 which load as {:?}",
+                texture,
+                found,
+                TextureFormat::Rgba8UnormSrgb
+            ),
+            TextureError::Empty { texture } => {
+                write!(f, "block texture {} has no pixels", texture)
+            }
+            TextureError::BadAnimation { texture, found } => write!(
+                f,
+                "animated block texture {} is {}x{}, its height must be a multiple of its width",
+                texture, found.0, found.1
+            ),
+            TextureError::BadFrameTimes {
+                texture,
+                frame_times,
+            } => write!(
+                f,
+                "animated block texture {} has frame times {:?}, they must all be above 0",
+                texture, frame_times
+            ),
+        }
+    }
+}
+
+/// Every PNG in `BLOCK_TEXTURE_DIRECTORY`, packed into a 2D texture array for the voxel
+/// material and a padded atlas for the UI once loaded.
+///
+/// Layers are the textures sorted by file name followed by `MISSING_TEXTURE`. They are known as
+/// soon as the folder is listed at startup, so meshes can be built before the images load.
+pub struct BlockTextures {
+    pub names: Vec<String>,
+    pub face_layers: Vec<[u32; 6]>,
+    pub handles: Vec<Handle<Image>>,
+    pub array: Option<Handle<Image>>,
+    pub atlas: Option<Handle<Image>>,
+    /// Normalized UV rectangle of each texture in the atlas.
+    pub atlas_rects: HashMap<String, Rect>,
+    pub animations: Vec<AnimatedLayer>,
+}
+
+/// Frames of an animated texture, copied into its array layer as time passes.
+pub struct AnimatedLayer {
+    pub layer: u32,
+    pub frames: Vec<Vec<u8>>,
+    pub frame_times: &'static [f32],
+    pub frame: usize,
+    pub elapsed: f32,
+}
+
+impl AnimatedLayer {
+    fn frame_time(&self) -> f32 {
+        self.frame_times[self.frame.min(self.frame_times.len() - 1)]
+    }
+
+    /// Advances the animation, returning whether the displayed frame changed.
+    fn advance(&mut self, delta: f32) -> bool {
+        let frame = self.frame;
+        self.elapsed += delta;
+
+        while self.elapsed >= self.frame_time() {
+            self.elapsed -= self.frame_time();
+            self.frame = (self.frame + 1) % self.frames.len();
+        }
+        self.frame != frame
+    }
+}
+
+impl Default for BlockTextures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockTextures {
+    pub fn new() -> Self {
+        BlockTextures {
+            names: Vec::new(),
+            face_layers: Vec::new(),
+            handles: Vec::new(),
+            array: None,
+            atlas: None,
+            atlas_rects: HashMap::default(),
+            animations: Vec::new(),
+        }
+    }
+
+    pub fn layer(&self, block: u8, face: usize) -> u32 {
+        self.face_layers[block as usize][face]
+    }
+
+    pub fn layer_of(&self, name: &str) -> Option<u32> {
+        self.names
+            .iter()
+            .position(|other| other == name)
+            .map(|layer| layer as u32)
+    }
+
+    pub fn missing_layer(&self) -> u32 {
+        self.names.len() as u32 - 1
+    }
+}
+
+pub fn load_block_textures(
+    asset_server: Res<AssetServer>,
+    mut block_textures: ResMut<BlockTextures>,
+) {
+    let mut textures: Vec<(String, Handle<Image>)> = asset_server
+        .load_folder(BLOCK_TEXTURE_DIRECTORY)
+        .unwrap_or_else(|error| {
+            panic!(
+                "Could not list block textures in assets/{}: {:?}",
+                BLOCK_TEXTURE_DIRECTORY, error
+            )
+        })
+        .into_iter()
+        .filter_map(|handle| {
+            let path = asset_server.get_handle_path(&handle)?;
+            let name = path.path().file_stem()?.to_str()?.to_string();
+            Some((name, handle.typed::<Image>()))
+        })
+        .collect();
+    textures.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let (mut names, handles): (Vec<String>, Vec<Handle<Image>>) = textures.into_iter().unzip();
+    names.push(MISSING_TEXTURE.to_string());
+    block_textures.names = names;
+    block_textures.handles = handles;
+
+    let mut face_layers = Vec::new();
+    for block_type in BLOCKTYPES.iter() {
+        let mut layers = [0; 6];
+        if let Some(textures) = block_type.textures {
+            for (face, texture) in textures.iter().enumerate() {
+                layers[face] = block_textures.layer_of(texture).unwrap_or_else(|| {
+                    error!(
+                        "{}",
+                        TextureError::Missing {
+                            block: block_type.name,
+                            texture,
+                        }
+                    );
+                    block_textures.missing_layer()
+                });
+            }
+        }
+        face_layers.push(layers);
+    }
+    block_textures.face_layers = face_layers;
+}
+
+pub fn build_block_textures(
+    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+    mut block_textures: ResMut<BlockTextures>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+    material_handle: Res<MaterialHandle>,
+) {
+    if block_textures.array.is_some() {
+        return;
+    }
+    match asset_server.get_group_load_state(block_textures.handles.iter().map(|handle| handle.id)) {
+        LoadState::Loaded => (),
+        LoadState::Failed => panic!(
+            "Failed to load the block textures from assets/{}",
+            BLOCK_TEXTURE_DIRECTORY
+        ),
+        _ => return,
+    }
+
+    let _span = info_span!("Build block textures").entered();
+    let images_by_layer: Vec<(&str, &Image)> = block_textures
+        .names
+        .iter()
+        .zip(block_textures.handles.iter())
+        .map(|(name, handle)| (name.as_str(), images.get(handle).unwrap()))
+        .collect();
+    let (size, layers, animations) = validate_layers(&images_by_layer);
+    let (atlas, rects) = pack_atlas(&layers, size);
+
+    let mut array = Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: layers.len() as u32,
+        },
+        TextureDimension::D2,
+        layers.concat(),
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    array.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        address_mode_u: AddressMode::Repeat,
+        address_mode_v: AddressMode::Repeat,
+        ..ImageSampler::nearest_descriptor()
+    });
+
+    block_textures.atlas_rects = block_textures.names.iter().cloned().zip(rects).collect();
+    block_textures.animations = animations;
+    block_textures.atlas = Some(images.add(atlas));
+    let array_handle = images.add(array);
+    materials.get_mut(&material_handle.0).unwrap().texture = array_handle.clone();
+    block_textures.array = Some(array_handle);
+}
+
+/// Pixels of every layer in order, the last one being the missing texture, and the animations.
+/// Layers hold the first frame of animated textures. Textures that do not match the size of the
+/// first one with pixels or are not RGBA are reported and replaced by the missing texture.
+fn validate_layers(images: &[(&str, &Image)]) -> (UVec2, Vec<Vec<u8>>, Vec<AnimatedLayer>) {
+    // Frames are square, so an animated strip still gives the right size
+    let size = images
+        .iter()
+        .map(|(_, image)| image.texture_descriptor.size.width)
+        .find(|width| *width > 0)
+        .map_or(UVec2::new(16, 16), UVec2::splat);
+    let frame_length = (size.x * size.y * 4) as usize;
+    let missing = missing_texture(size);
+    let mut layers = Vec::new();
+    let mut animations = Vec::new();
+
+    for (layer, (name, image)) in images.iter().enumerate() {
+        let animation = TEXTURE_ANIMATIONS
+            .iter()
+            .find(|animation| animation.texture == *name);
+
+        if let Err(error) = check_image(name, image, size, animation.is_some()) {
+            error!("{}", error);
+            layers.push(missing.clone());
+            continue;
+        }
+
+        let frames: Vec<Vec<u8>> = image
+            .data
+            .chunks(frame_length)
+            .map(|frame| frame.to_vec())
+            .collect();
+        layers.push(frames[0].clone());
+
+        if let Some(animation) = animation {
+            // A frame time of 0 would never let the animation catch up
+            if !animation.frame_times.iter().all(|time| *time > 0.0)
+                || animation.frame_times.is_empty()
+            {
+                error!(
+                    "{}",
+                    TextureError::BadFrameTimes {
+                        texture: name.to_string(),
+                        frame_times: animation.frame_times,
+                    }
+                );
+            } else if frames.len() > 1 {
+                animations.push(AnimatedLayer {
+                    layer: layer as u32,
+                    frames,
+                    frame_times: animation.frame_times,
+                    frame: 0,
+                    elapsed: 0.0,
+                });
+            }
+        }
+    }
+    layers.push(missing);
+    (size, layers, animations)
+}
+
+/// Checks the image can be a layer of `size`, or a vertical strip of frames of that size if it
+/// is `animated`.
+fn check_image(name: &str, image: &Image, size: UVec2, animated: bool) -> Result<(), TextureError> {
+    let descriptor = &image.texture_descriptor;
+    let found = (descriptor.size.width, descriptor.size.height);
+
+    if found.0 == 0 || found.1 == 0 {
+        Err(TextureError::Empty {
+            texture: name.to_string(),
+        })
+    } else if descriptor.format != TextureFormat::Rgba8UnormSrgb {
+        Err(TextureError::FormatMismatch {
+            texture: name.to_string(),
+            found: descriptor.format,
+        })
+    } else if found.0 != size.x || (!animated && found.1 != size.y) {
+        Err(TextureError::SizeMismatch {
+            texture: name.to_string(),
+            found,
+            expected: (size.x, size.y),
+        })
+    } else if !found.1.is_multiple_of(size.y) {
+        Err(TextureError::BadAnimation {
+            texture: name.to_string(),
+            found,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Magenta and black checkerboard.
+fn missing_texture(size: UVec2) -> Vec<u8> {
+    let mut data = Vec::with_capacity((size.x * size.y * 4) as usize);
+
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let is_magenta = (x * 2 / size.x + y * 2 / size.y).is_multiple_of(2);
+            data.extend_from_slice(if is_magenta {
+                &[255, 0, 255, 255]
+            } else {
+                &[0, 0, 0, 255]
+            });
+        }
+    }
+    data
+}
+
+/// Packs equally sized RGBA layers into a square grid, surrounding each tile with
+/// `ATLAS_GUTTER` copies of its edge pixels. Returns the atlas and the normalized rectangle of
+/// each tile without its gutter. Layers must not be empty, `validate_layers` sees to that.
+fn pack_atlas(layers: &[Vec<u8>], size: UVec2) -> (Image, Vec<Rect>) {
+    assert!(size.x > 0 && size.y > 0, "block textures must have pixels");
+    let columns = (layers.len() as f32).sqrt().ceil() as u32;
+    let rows = (layers.len() as u32).div_ceil(columns);
+    let cell = size + UVec2::splat(ATLAS_GUTTER * 2);
+    let atlas_size = UVec2::new(columns * cell.x, rows * cell.y);
+    let mut data = vec![0; (atlas_size.x * atlas_size.y * 4) as usize];
+    let mut rects = Vec::new();
+
+    for (index, layer) in layers.iter().enumerate() {
+        let origin = UVec2::new(index as u32 % columns, index as u32 / columns) * cell;
+
+        for y in 0..cell.y {
+            for x in 0..cell.x {
+                // Clamp into the tile so the gutter repeats its edges
+                let source_x = x.saturating_sub(ATLAS_GUTTER).min(size.x - 1);
+                let source_y = y.saturating_sub(ATLAS_GUTTER).min(size.y - 1);
+                let source = ((source_y * size.x + source_x) * 4) as usize;
+                let target = (((origin.y + y) * atlas_size.x + origin.x + x) * 4) as usize;
+                data[target..target + 4].copy_from_slice(&layer[source..source + 4]);
+            }
+        }
+
+        let min = (origin + UVec2::splat(ATLAS_GUTTER)).as_vec2();
+        rects.push(Rect {
+            min: min / atlas_size.as_vec2(),
+            max: (min + size.as_vec2()) / atlas_size.as_vec2(),
+        });
+    }
+
+    let atlas = Image::new(
+        Extent3d {
+            width: atlas_size.x,
+            height: atlas_size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    (atlas, rects)
+}
+
+/// Frames animated textures changed to this frame, by layer of the texture array.
+#[derive(Clone, Default)]
+pub struct ChangedFrames {
+    pub array: Option<Handle<Image>>,
+    pub size: UVec2,
+    pub frames: Vec<(u32, Vec<u8>)>,
+}
+
+/// Writes the frames animated textures change to into their layers of the texture array on the
+/// GPU. Changing the image asset instead would upload the whole array again.
+pub struct AnimatedTexturesPlugin;
+
+impl Plugin for AnimatedTexturesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChangedFrames>();
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<ChangedFrames>()
+                .add_system_to_stage(RenderStage::Extract, extract_changed_frames)
+                .add_system_to_stage(RenderStage::Queue, write_changed_frames);
+        }
+    }
+}
+
+/// Advances every animated texture, collecting the frames that changed.
+pub fn animate_block_textures(
+    time: Res<Time>,
+    images: Res<Assets<Image>>,
+    mut block_textures: ResMut<BlockTextures>,
+    mut changed_frames: ResMut<ChangedFrames>,
+) {
+    changed_frames.frames.clear();
+    let array_handle = match &block_textures.array {
+        Some(array_handle) => array_handle.clone(),
+        None => return,
+    };
+    let size = match images.get(&array_handle) {
+        Some(image) => image.size().as_uvec2(),
+        None => return,
+    };
+    let delta = time.delta_seconds();
+
+    for animation in block_textures.animations.iter_mut() {
+        if animation.advance(delta) {
+            let frame = animation.frames[animation.frame].clone();
+            changed_frames.frames.push((animation.layer, frame));
+        }
+    }
+    changed_frames.array = Some(array_handle);
+    changed_frames.size = size;
+}
+
+fn extract_changed_frames(mut commands: Commands, changed_frames: Res<ChangedFrames>) {
+    commands.insert_resource(changed_frames.clone());
+}
+
+fn write_changed_frames(
+    changed_frames: Res<ChangedFrames>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_queue: Res<RenderQueue>,
+) {
+    let gpu_image = match changed_frames
+        .array
+        .as_ref()
+        .and_then(|array| gpu_images.get(array))
+    {
+        Some(gpu_image) => gpu_image,
+        None => return,
+    };
+    let size = changed_frames.size;
+
+    for (layer, frame) in changed_frames.frames.iter() {
+        render_queue.write_texture(
+            ImageCopyTexture {
+                texture: &gpu_image.texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: *layer,
+                },
+                aspect: TextureAspect::All,
+            },
+            frame,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(size.x * 4),
+                rows_per_image: NonZeroU32::new(size.y),
+            },
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, format: TextureFormat, pixel: u8) -> Image {
+        Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            vec![pixel; (width * height * 4) as usize],
+            format,
+        )
+    }
+
+    fn rgba(width: u32, height: u32) -> Image {
+        image(width, height, TextureFormat::Rgba8UnormSrgb, 255)
+    }
+
+    #[test]
+    fn textures_must_match_the_size() {
+        let size = UVec2::splat(16);
+        assert!(check_image("stone", &rgba(16, 16), size, false).is_ok());
+
+        let error = check_image("stone", &rgba(32, 16), size, false).unwrap_err();
+        assert!(matches!(
+            error,
+            TextureError::SizeMismatch {
+                found: (32, 16),
+                expected: (16, 16),
+                ..
+            }
+        ));
+        assert!(error.to_string().contains("stone"));
+        assert!(matches!(
+            check_image("stone", &rgba(16, 32), size, false),
+            Err(TextureError::SizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn textures_must_be_rgba() {
+        let linear = image(16, 16, TextureFormat::Rgba8Unorm, 255);
+        let error = check_image("stone", &linear, UVec2::splat(16), false).unwrap_err();
+        assert!(matches!(
+            error,
+            TextureError::FormatMismatch {
+                found: TextureFormat::Rgba8Unorm,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn empty_textures_are_rejected() {
+        for (width, height) in [(0, 0), (0, 16), (16, 0)] {
+            assert!(matches!(
+                check_image("stone", &rgba(width, height), UVec2::splat(16), true),
+                Err(TextureError::Empty { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn animations_are_whole_frames() {
+        let size = UVec2::splat(16);
+        assert!(check_image("water", &rgba(16, 48), size, true).is_ok());
+        assert!(matches!(
+            check_image("water", &rgba(16, 40), size, true),
+            Err(TextureError::BadAnimation {
+                found: (16, 40),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn bad_textures_become_the_missing_texture() {
+        let empty = rgba(0, 0);
+        let stone = rgba(16, 16);
+        let dirt = rgba(8, 8);
+        let water = image(16, 32, TextureFormat::Rgba8UnormSrgb, 7);
+        let (size, layers, animations) = validate_layers(&[
+            ("empty", &empty),
+            ("stone", &stone),
+            ("dirt", &dirt),
+            ("water", &water),
+        ]);
+
+        // The size comes from the first texture with pixels
+        assert_eq!(size, UVec2::splat(16));
+        let missing = missing_texture(size);
+        assert_eq!(
+            layers,
+            vec![
+                missing.clone(),
+                stone.data.clone(),
+                missing.clone(),
+                vec![7; 16 * 16 * 4],
+                missing,
+            ]
+        );
+        assert_eq!(animations.len(), 1);
+        assert_eq!(animations[0].layer, 3);
+        assert_eq!(animations[0].frames.len(), 2);
+    }
+
+    #[test]
+    fn gutters_repeat_the_tile_edges() {
+        // A 2x2 tile with a different colour in each pixel, and a plain one
+        let size = UVec2::splat(2);
+        let tile: Vec<u8> = (0..4)
+            .flat_map(|pixel| [pixel, pixel, pixel, 255])
+            .collect();
+        let plain = vec![9; 16];
+        let (atlas, rects) = pack_atlas(&[tile, plain], size);
+
+        let cell = 2 + ATLAS_GUTTER * 2;
+        let width = atlas.texture_descriptor.size.width;
+        assert_eq!(width, cell * 2);
+        assert_eq!(atlas.texture_descriptor.size.height, cell);
+        let pixel = |x: u32, y: u32| atlas.data[((y * width + x) * 4) as usize];
+
+        for y in 0..cell {
+            for x in 0..cell {
+                let source_x = x.saturating_sub(ATLAS_GUTTER).min(1);
+                let source_y = y.saturating_sub(ATLAS_GUTTER).min(1);
+                assert_eq!(
+                    pixel(x, y),
+                    (source_y * 2 + source_x) as u8,
+                    "({}, {})",
+                    x,
+                    y
+                );
+                assert_eq!(pixel(cell + x, y), 9);
+            }
+        }
+
+        // The rectangles leave out the gutters
+        let atlas_size = Vec2::new(width as f32, cell as f32);
+        let gutter = Vec2::splat(ATLAS_GUTTER as f32);
+        assert_eq!(rects[0].min, gutter / atlas_size);
+        assert_eq!(rects[0].max, (gutter + 2.0) / atlas_size);
+        assert_eq!(rects[1].min, (gutter + Vec2::X * cell as f32) / atlas_size);
+    }
+}
@@ -1,38 +1,525 @@
+use crate::block_models::BlockModel;
+
+/// Animated block texture, stored as its frames stacked vertically in one PNG.
+pub struct TextureAnimation {
+    pub texture: &'static str,
+    // Seconds each frame is shown, the last entry applying to all remaining frames
+    pub frame_times: &'static [f32],
+}
+
 pub struct BlockType {
     pub name: &'static str,
     pub is_solid: bool,
-    pub texture_id: Option<[u32; 6]>, //front, back, top, bottom, right, left
+    /// Whether players and items bump into the block's model. Liquids and portals are solid to
+    /// look at but can be walked through.
+    pub collidable: bool,
+    /// Seconds to break the block in survival, divided by `BREAK_TIME_PER_HARDNESS`. Infinite
+    /// for blocks that cannot be broken.
+    pub hardness: f32,
+    pub textures: Option<[&'static str; 6]>, //front, back, top, bottom, right, left
+    pub properties: &'static [Property],
+    pub model: BlockModel,
+}
+
+impl BlockType {
+    /// Whether the block is a solid full cube, hiding the faces of its neighbours and casting
+    /// ambient occlusion.
+    pub fn is_opaque_cube(&self) -> bool {
+        self.is_solid && self.model == BlockModel::Cube
+    }
+
+    /// Seconds to break the block in survival, `None` if it cannot be broken.
+    pub fn break_time(&self) -> Option<f32> {
+        if self.hardness.is_finite() {
+            Some(self.hardness * BREAK_TIME_PER_HARDNESS)
+        } else {
+            None
+        }
+    }
+}
+
+pub const BREAK_TIME_PER_HARDNESS: f32 = 1.5;
+
+/// Id of the block called `name`.
+pub fn block_by_name(name: &str) -> Option<u8> {
+    BLOCKTYPES
+        .iter()
+        .position(|block_type| block_type.name == name)
+        .map(|id| id as u8)
+}
+
+/// Block state property. Values are small integers, see the constants below.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Property {
+    /// World face the block's front face points to, indexed like `FACE_CHECKS`.
+    Facing,
+    Axis,
+    Half,
+    Waterlogged,
+    Level,
 }
 
-pub const BLOCKTYPES: [BlockType; 6] = [
+pub const AXIS_Y: u8 = 0;
+pub const AXIS_X: u8 = 1;
+pub const AXIS_Z: u8 = 2;
+pub const HALF_BOTTOM: u8 = 0;
+pub const HALF_TOP: u8 = 1;
+
+impl Property {
+    pub fn name(self) -> &'static str {
+        match self {
+            Property::Facing => "facing",
+            Property::Axis => "axis",
+            Property::Half => "half",
+            Property::Waterlogged => "waterlogged",
+            Property::Level => "level",
+        }
+    }
+
+    pub fn value_count(self) -> u8 {
+        match self {
+            Property::Facing => 6,
+            Property::Axis => 3,
+            Property::Half | Property::Waterlogged => 2,
+            Property::Level => 16,
+        }
+    }
+
+    fn bits(self) -> u16 {
+        match self {
+            Property::Facing => 3,
+            Property::Axis => 2,
+            Property::Half | Property::Waterlogged => 1,
+            Property::Level => 4,
+        }
+    }
+}
+
+// Horizontal faces in the order a quarter turn around Y moves them
+const HORIZONTAL_FACES: [usize; 4] = [0, 5, 1, 4];
+
+/// What a voxel stores: the block id in the high byte and the block's property values packed
+/// into the low byte, in the order of `BlockType::properties`. All properties default to 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BlockState(pub u16);
+
+impl BlockState {
+    pub const AIR: BlockState = BlockState(0);
+
+    pub const fn new(block: u8) -> Self {
+        BlockState((block as u16) << 8)
+    }
+
+    pub fn block(self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    pub fn block_type(self) -> &'static BlockType {
+        &BLOCKTYPES[self.block() as usize]
+    }
+
+    fn property_offset(self, property: Property) -> Option<u16> {
+        let mut offset = 0;
+
+        for other in self.block_type().properties {
+            if *other == property {
+                return Some(offset);
+            }
+            offset += other.bits();
+        }
+        None
+    }
+
+    /// Whether the block is water or lava, or holds water.
+    pub fn is_liquid(self) -> bool {
+        self.block_type().properties.contains(&Property::Level)
+            || self.get(Property::Waterlogged) == Some(1)
+    }
+
+    /// Value of `property`, or `None` if the block does not have it.
+    pub fn get(self, property: Property) -> Option<u8> {
+        self.property_offset(property)
+            .map(|offset| ((self.0 >> offset) & ((1 << property.bits()) - 1)) as u8)
+    }
+
+    /// Same state with `property` set, unchanged if the block does not have it.
+    pub fn with(self, property: Property, value: u8) -> Self {
+        match self.property_offset(property) {
+            Some(offset) => {
+                let mask = ((1 << property.bits()) - 1) << offset;
+                BlockState((self.0 & !mask) | ((value as u16) << offset & mask))
+            }
+            None => self,
+        }
+    }
+
+    /// Which face of the block's textures is drawn on world face `face`, and by how many
+    /// quarter turns its texture is rotated.
+    pub fn texture_face(self, face: usize) -> (usize, u32) {
+        if let Some(facing) = self.get(Property::Facing) {
+            return facing_texture_face(facing as usize, face);
+        }
+        match self.get(Property::Axis) {
+            Some(AXIS_X) => [(2, 0), (3, 0), (0, 1), (1, 1), (4, 1), (5, 1)][face],
+            Some(AXIS_Z) => [(0, 1), (1, 1), (4, 0), (5, 0), (2, 0), (3, 0)][face],
+            _ => (face, 0),
+        }
+    }
+}
+
+fn facing_texture_face(facing: usize, face: usize) -> (usize, u32) {
+    match facing {
+        // Front turned up or down, rotating around Z
+        2 => [(3, 0), (2, 0), (0, 0), (1, 0), (4, 1), (5, 1)][face],
+        3 => [(2, 0), (3, 0), (1, 0), (0, 0), (4, 3), (5, 3)][face],
+        _ => {
+            let turns = HORIZONTAL_FACES
+                .iter()
+                .position(|other| *other == facing)
+                .unwrap();
+
+            match HORIZONTAL_FACES.iter().position(|other| *other == face) {
+                Some(position) => (HORIZONTAL_FACES[(position + 4 - turns) % 4], 0),
+                None => (face, turns as u32),
+            }
+        }
+    }
+}
+
+pub static BLOCKTYPES: [BlockType; 18] = [
     BlockType {
         name: "air",
         is_solid: false,
-        texture_id: None,
+        collidable: false,
+        hardness: 0.0,
+        textures: None,
+        properties: &[],
+        model: BlockModel::Cube,
     },
     BlockType {
         name: "stone",
         is_solid: true,
-        texture_id: Some([1, 1, 1, 1, 1, 1]),
+        collidable: true,
+        hardness: 1.5,
+        textures: Some(["stone", "stone", "stone", "stone", "stone", "stone"]),
+        properties: &[],
+        model: BlockModel::Cube,
     },
     BlockType {
         name: "bedrock",
         is_solid: true,
-        texture_id: Some([9, 9, 9, 9, 9, 9]),
+        collidable: true,
+        hardness: f32::INFINITY,
+        textures: Some([
+            "bedrock", "bedrock", "bedrock", "bedrock", "bedrock", "bedrock",
+        ]),
+        properties: &[],
+        model: BlockModel::Cube,
     },
     BlockType {
         name: "grass",
         is_solid: true,
-        texture_id: Some([3, 3, 0, 1, 3, 3]),
+        collidable: true,
+        hardness: 0.6,
+        textures: Some([
+            "grass_side",
+            "grass_side",
+            "grass_top",
+            "stone",
+            "grass_side",
+            "grass_side",
+        ]),
+        properties: &[],
+        model: BlockModel::Cube,
     },
     BlockType {
         name: "dirt",
         is_solid: true,
-        texture_id: Some([2, 2, 2, 2, 2, 2]),
+        collidable: true,
+        hardness: 0.5,
+        textures: Some(["dirt", "dirt", "dirt", "dirt", "dirt", "dirt"]),
+        properties: &[],
+        model: BlockModel::Cube,
     },
     BlockType {
         name: "water",
         is_solid: true,
-        texture_id: Some([207, 207, 207, 207, 207, 207]),
+        collidable: false,
+        hardness: f32::INFINITY,
+        textures: Some(["water", "water", "water", "water", "water", "water"]),
+        properties: &[Property::Level],
+        model: BlockModel::Cube,
+    },
+    BlockType {
+        name: "lava",
+        is_solid: true,
+        collidable: false,
+        hardness: f32::INFINITY,
+        textures: Some(["lava", "lava", "lava", "lava", "lava", "lava"]),
+        properties: &[Property::Level],
+        model: BlockModel::Cube,
+    },
+    BlockType {
+        name: "portal",
+        is_solid: true,
+        collidable: false,
+        hardness: f32::INFINITY,
+        textures: Some(["portal", "portal", "portal", "portal", "portal", "portal"]),
+        properties: &[],
+        model: BlockModel::Cube,
+    },
+    BlockType {
+        name: "log",
+        is_solid: true,
+        collidable: true,
+        hardness: 2.0,
+        textures: Some([
+            "log_side", "log_side", "log_top", "log_top", "log_side", "log_side",
+        ]),
+        properties: &[Property::Axis],
+        model: BlockModel::Cube,
+    },
+    BlockType {
+        name: "furnace",
+        is_solid: true,
+        collidable: true,
+        hardness: 3.5,
+        textures: Some([
+            "furnace_front",
+            "furnace_side",
+            "furnace_top",
+            "furnace_top",
+            "furnace_side",
+            "furnace_side",
+        ]),
+        properties: &[Property::Facing],
+        model: BlockModel::Cube,
+    },
+    BlockType {
+        name: "planks",
+        is_solid: true,
+        collidable: true,
+        hardness: 2.0,
+        textures: Some(["planks", "planks", "planks", "planks", "planks", "planks"]),
+        properties: &[],
+        model: BlockModel::Cube,
+    },
+    BlockType {
+        name: "stone_slab",
+        is_solid: true,
+        collidable: true,
+        hardness: 2.0,
+        textures: Some([
+            "stone_slab_side",
+            "stone_slab_side",
+            "stone_slab_top",
+            "stone_slab_top",
+            "stone_slab_side",
+            "stone_slab_side",
+        ]),
+        properties: &[Property::Half, Property::Waterlogged],
+        model: BlockModel::Slab,
+    },
+    BlockType {
+        name: "planks_stairs",
+        is_solid: true,
+        collidable: true,
+        hardness: 2.0,
+        textures: Some(["planks", "planks", "planks", "planks", "planks", "planks"]),
+        properties: &[Property::Facing, Property::Half, Property::Waterlogged],
+        model: BlockModel::Stairs,
+    },
+    BlockType {
+        name: "tall_grass",
+        is_solid: false,
+        collidable: false,
+        hardness: 0.0,
+        textures: Some([
+            "tall_grass",
+            "tall_grass",
+            "tall_grass",
+            "tall_grass",
+            "tall_grass",
+            "tall_grass",
+        ]),
+        properties: &[],
+        model: BlockModel::Cross,
+    },
+    BlockType {
+        name: "rose",
+        is_solid: false,
+        collidable: false,
+        hardness: 0.0,
+        textures: Some(["rose", "rose", "rose", "rose", "rose", "rose"]),
+        properties: &[],
+        model: BlockModel::Cross,
+    },
+    BlockType {
+        name: "dandelion",
+        is_solid: false,
+        collidable: false,
+        hardness: 0.0,
+        textures: Some([
+            "dandelion",
+            "dandelion",
+            "dandelion",
+            "dandelion",
+            "dandelion",
+            "dandelion",
+        ]),
+        properties: &[],
+        model: BlockModel::Cross,
+    },
+    BlockType {
+        name: "fence",
+        is_solid: true,
+        collidable: true,
+        hardness: 2.0,
+        textures: Some(["planks", "planks", "planks", "planks", "planks", "planks"]),
+        properties: &[],
+        model: BlockModel::Custom("fence"),
+    },
+    BlockType {
+        name: "torch",
+        is_solid: false,
+        collidable: false,
+        hardness: 0.0,
+        textures: Some(["torch", "torch", "torch", "torch", "torch", "torch"]),
+        properties: &[],
+        model: BlockModel::Custom("torch"),
     },
 ];
+
+pub const TEXTURE_ANIMATIONS: [TextureAnimation; 3] = [
+    TextureAnimation {
+        texture: "water",
+        frame_times: &[0.1],
+    },
+    TextureAnimation {
+        texture: "lava",
+        frame_times: &[0.3],
+    },
+    TextureAnimation {
+        texture: "portal",
+        frame_times: &[0.2, 0.1, 0.1, 0.2, 0.2, 0.1, 0.1, 0.2],
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(name: &str) -> BlockState {
+        BlockState::new(block_by_name(name).unwrap())
+    }
+
+    // Every combination of the block's property values
+    fn all_values(block: BlockState) -> Vec<Vec<(Property, u8)>> {
+        let mut combinations = vec![Vec::new()];
+        for property in block.block_type().properties {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|values| {
+                    (0..property.value_count()).map(move |value| {
+                        let mut values = values.clone();
+                        values.push((*property, value));
+                        values
+                    })
+                })
+                .collect();
+        }
+        combinations
+    }
+
+    fn pack(block: BlockState, values: &[(Property, u8)]) -> BlockState {
+        values.iter().fold(block, |packed, (property, value)| {
+            packed.with(*property, *value)
+        })
+    }
+
+    #[test]
+    fn properties_round_trip() {
+        for name in ["planks_stairs", "stone_slab", "log", "furnace"] {
+            let block = state(name);
+            let combinations = all_values(block);
+            for values in combinations.iter() {
+                let packed = pack(block, values);
+                assert_eq!(packed.block(), block.block());
+                for (property, value) in values {
+                    assert_eq!(packed.get(*property), Some(*value), "{} {:?}", name, values);
+                }
+            }
+            // Every combination packs differently
+            let mut packed: Vec<_> = combinations
+                .iter()
+                .map(|values| pack(block, values))
+                .collect();
+            packed.sort_by_key(|state| state.0);
+            packed.dedup();
+            assert_eq!(packed.len(), combinations.len(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn missing_properties_are_left_alone() {
+        let log = state("log").with(Property::Axis, AXIS_Z);
+        assert_eq!(log.get(Property::Facing), None);
+        assert_eq!(log.with(Property::Facing, 3), log);
+        assert_eq!(
+            state("stone").with(Property::Half, HALF_TOP),
+            state("stone")
+        );
+    }
+
+    #[test]
+    fn setting_a_property_keeps_the_others() {
+        let stairs = state("planks_stairs")
+            .with(Property::Facing, 5)
+            .with(Property::Half, HALF_TOP)
+            .with(Property::Waterlogged, 1)
+            .with(Property::Half, HALF_BOTTOM);
+        assert_eq!(stairs.get(Property::Facing), Some(5));
+        assert_eq!(stairs.get(Property::Half), Some(HALF_BOTTOM));
+        assert_eq!(stairs.get(Property::Waterlogged), Some(1));
+    }
+
+    fn assert_permutation(faces: [(usize, u32); 6]) {
+        let mut texture_faces: Vec<_> = faces.iter().map(|(face, _)| *face).collect();
+        texture_faces.sort();
+        assert_eq!(texture_faces, [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn the_front_faces_the_facing() {
+        let furnace = state("furnace");
+        for facing in 0..6 {
+            let faces = [0, 1, 2, 3, 4, 5]
+                .map(|face| furnace.with(Property::Facing, facing).texture_face(face));
+            assert_permutation(faces);
+            assert_eq!(faces[facing as usize].0, 0, "facing {}", facing);
+            assert_eq!(faces[facing as usize ^ 1].0, 1, "facing {}", facing);
+        }
+    }
+
+    #[test]
+    fn tops_turn_with_the_facing() {
+        let furnace = state("furnace");
+        for (turns, facing) in HORIZONTAL_FACES.iter().enumerate() {
+            let furnace = furnace.with(Property::Facing, *facing as u8);
+            assert_eq!(furnace.texture_face(2), (2, turns as u32));
+            assert_eq!(furnace.texture_face(3), (3, turns as u32));
+        }
+    }
+
+    #[test]
+    fn log_ends_face_along_the_axis() {
+        let log = state("log");
+        for (axis, ends) in [(AXIS_X, [0, 1]), (AXIS_Y, [2, 3]), (AXIS_Z, [4, 5])] {
+            let faces =
+                [0, 1, 2, 3, 4, 5].map(|face| log.with(Property::Axis, axis).texture_face(face));
+            assert_permutation(faces);
+            assert_eq!(faces[ends[0]].0, 2, "axis {}", axis);
+            assert_eq!(faces[ends[1]].0, 3, "axis {}", axis);
+        }
+    }
+}
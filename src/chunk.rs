@@ -1,28 +1,50 @@
+use crate::block_models::BlockModels;
+use crate::block_textures::BlockTextures;
 use crate::voxel_data::{CHUNK_SIZE, WORLD_SIZE_IN_CHUNKS};
-use crate::world::{ActiveChunks, ChunkCoord, ChunkMap, ChunkToGenerateQueue, ChunkToSpawnQueue};
+use crate::world::{
+    ActiveChunks, ChunkCoord, ChunkMap, ChunkToGenerateQueue, ChunkToRemeshQueue,
+    ChunkToSpawnQueue,
+};
+use crate::culling::{self, FaceConnectivity};
 use crate::mesh;
+use crate::network_client::NetworkClient;
+use crate::region::WorldRegions;
+use crate::shadows::VoxelShadowCaster;
+use bevy::pbr::NotShadowCaster;
 use bevy::prelude::*;
 use crate::voxel_map::VoxelMap;
+use crate::voxel_material::VoxelMaterial;
 
 #[derive(Default)]
-pub struct MaterialHandle(pub Handle<StandardMaterial>);
+pub struct MaterialHandle(pub Handle<VoxelMaterial>);
 
 #[derive(Clone, Debug)]
 pub struct Chunk {
     pub position: ChunkCoord,
     pub is_full: bool,
     pub mesh_handle: Option<Handle<Mesh>>,
+    pub connectivity: FaceConnectivity,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn generate_chunk(
     mut chunk_to_generate_queue: ResMut<ChunkToGenerateQueue>,
     mut chunk_to_spawn_queue: ResMut<ChunkToSpawnQueue>,
+    mut chunk_to_remesh_queue: ResMut<ChunkToRemeshQueue>,
     mut voxel_map: ResMut<VoxelMap>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut chunk_map: ResMut<ChunkMap>,
     mut active_chunks: ResMut<ActiveChunks>,
+    mut world_regions: ResMut<WorldRegions>,
+    block_textures: Res<BlockTextures>,
+    block_models: Res<BlockModels>,
+    network_client: Option<Res<NetworkClient>>,
 ) {
     while let Some(chunk_pos) = chunk_to_generate_queue.0.pop() {
+        // The server's chunks are queued again once they arrive
+        if network_client.as_ref().is_some_and(|client| !client.has_chunk(chunk_pos)) {
+            continue;
+        }
         let is_full;
         let chunk= &mut chunk_map.0[[
             (chunk_pos.x + WORLD_SIZE_IN_CHUNKS as i32 / 2) as usize,
@@ -32,13 +54,35 @@ pub fn generate_chunk(
 
         if chunk.is_none()
         {
-                is_full = voxel_map.populate_voxel_map(chunk_pos);
-                let mesh_handle = Some(meshes.add(mesh::create_mesh(&chunk_pos, &mut voxel_map)));
+                is_full = match network_client {
+                    Some(_) => false,
+                    // Filled in already by an edit made before the chunk was generated
+                    None if voxel_map.filled.contains(&chunk_pos) => {
+                        voxel_map.populate_voxel_map(chunk_pos);
+                        false
+                    }
+                    None => {
+                        let generated_full = voxel_map.populate_voxel_map(chunk_pos);
+                        match world_regions.load_chunk(chunk_pos) {
+                            Some(blocks) => {
+                                voxel_map.set_chunk_blocks(chunk_pos, &blocks);
+                                // Neighbours already built were meshed against the generated blocks
+                                let min = IVec3::new(chunk_pos.x, chunk_pos.y, chunk_pos.z) * CHUNK_SIZE as i32;
+                                chunk_to_remesh_queue.push_region(min, min + IVec3::splat(CHUNK_SIZE as i32 - 1));
+                                false
+                            }
+                            None => generated_full,
+                        }
+                    }
+                };
+                let mesh_handle = Some(meshes.add(mesh::create_mesh(&chunk_pos, &mut voxel_map, &block_textures, &block_models)));
+                let connectivity = culling::chunk_connectivity(&chunk_pos, &voxel_map);
 
                 *chunk = Some(Chunk {
                     position: chunk_pos,
                     is_full,
                     mesh_handle,
+                    connectivity,
                 });
         } else {
             is_full = chunk.as_ref().unwrap().is_full;
@@ -81,6 +125,9 @@ pub fn spawn_chunk(
                         ),
                         ..Default::default()
                     })
+                    // Drawn into the sun's shadow map by `shadows` instead of Bevy's shadow pass
+                    .insert(NotShadowCaster)
+                    .insert(VoxelShadowCaster::covering(Vec3::splat(CHUNK_SIZE as f32)))
                     .insert(Name::new(format!(
                         "Chunk ({}, {}, {})",
                         chunk_pos.x, chunk_pos.y, chunk_pos.z
@@ -91,18 +138,52 @@ pub fn spawn_chunk(
     }
 }
 
+/// Rebuilds the mesh and connectivity of edited chunks. Chunks that were full and had no
+/// entity are spawned once they have faces to show.
+#[allow(clippy::too_many_arguments)]
+pub fn remesh_chunks(
+    mut chunk_to_remesh_queue: ResMut<ChunkToRemeshQueue>,
+    mut chunk_to_spawn_queue: ResMut<ChunkToSpawnQueue>,
+    mut voxel_map: ResMut<VoxelMap>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut chunk_map: ResMut<ChunkMap>,
+    active_chunks: Res<ActiveChunks>,
+    block_textures: Res<BlockTextures>,
+    block_models: Res<BlockModels>,
+) {
+    while let Some(chunk_pos) = chunk_to_remesh_queue.0.pop() {
+        let _span = info_span!("Chunk remesh").entered();
+        // Chunks not generated yet pick up the edit when they are. Edits fill such chunks in
+        // first, see `world_edit::fill_chunks`, so generating them keeps the edit
+        if chunk_map.get(&chunk_pos).0.is_none() {
+            continue;
+        }
+        let mesh = mesh::create_mesh(&chunk_pos, &mut voxel_map, &block_textures, &block_models);
+        let connectivity = culling::chunk_connectivity(&chunk_pos, &voxel_map);
+
+        let (chunk, entity) = chunk_map.get_mut(&chunk_pos);
+        let chunk = chunk.as_mut().unwrap();
+        chunk.connectivity = connectivity;
+        match chunk.mesh_handle.as_ref().and_then(|handle| meshes.get_mut(handle)) {
+            Some(chunk_mesh) => *chunk_mesh = mesh,
+            None => chunk.mesh_handle = Some(meshes.add(mesh)),
+        }
+
+        if chunk.is_full {
+            chunk.is_full = false;
+            if entity.is_none() && active_chunks.0.contains(&chunk_pos) {
+                chunk_to_spawn_queue.0.push((chunk_pos, false));
+            }
+        }
+    }
+}
+
 pub fn generate_material(
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
     mut material_handle: ResMut<MaterialHandle>,
 ) {
-    let texture_handle: Handle<Image> = asset_server.load("texture_atlas.png");
-
-    material_handle.0 = materials.add(StandardMaterial {
-        base_color_texture: Some(texture_handle.clone()),
-        perceptual_roughness: 1.0,
-        metallic: 0.0,
-        reflectance: 0.1,
-        ..default()
+    // The texture array is filled in by `block_textures::build_texture_array` once loaded
+    material_handle.0 = materials.add(VoxelMaterial {
+        texture: Handle::default(),
     });
 }
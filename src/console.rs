@@ -0,0 +1,851 @@
+use crate::block_types::{block_by_name, BLOCKTYPES};
+use crate::inventory::{Inventory, INVENTORY_SIZE, MAX_STACK_SIZE};
+use crate::network_client::NetworkClient;
+use crate::player::{GameMode, PlayerPhysics, Teleport, EYE_HEIGHT};
+use crate::protocol::ClientMessage;
+use crate::voxel_map::VoxelMap;
+use crate::world_edit;
+use crate::world_time::TimeCommand;
+use bevy::ecs::event::Events;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+// Lines kept in the console log, older ones are dropped
+pub const MAX_LOG_LINES: usize = 100;
+// Minecraft's time units, a day starts at sunrise and lasts 24000 ticks
+pub const TICKS_PER_DAY: f32 = 24000.0;
+pub const TIME_NAMES: [(&str, f32); 4] = [
+    ("day", 1000.0),
+    ("noon", 6000.0),
+    ("night", 13000.0),
+    ("midnight", 18000.0),
+];
+
+/// What a command argument is parsed as.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgKind {
+    Integer {
+        min: i64,
+        max: i64,
+    },
+    Number,
+    /// World coordinate, relative to the player's when prefixed with `~`.
+    Coordinate,
+    /// Block name, see `block_by_name`.
+    Block,
+    GameMode,
+    /// Time in ticks or one of `TIME_NAMES`, parsed as a fraction of a day.
+    Time,
+    /// One of a fixed set of words.
+    Word(&'static [&'static str]),
+    /// Name of a file in a directory the command knows, without any path.
+    FileName,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArgValue {
+    Integer(i64),
+    Number(f32),
+    Coordinate { value: f32, relative: bool },
+    Block(u8),
+    GameMode(GameMode),
+    Word(&'static str),
+    FileName(String),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+/// Runs a command on the world with its parsed arguments, returning the message to show.
+pub type CommandFn = fn(&mut World, &[ArgValue]) -> Result<String, String>;
+
+/// A command, named by one or more words: `time set` is its own command with its own arguments.
+/// Optional arguments come last.
+#[derive(Clone, Copy)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub args: &'static [ArgSpec],
+    pub help: &'static str,
+    pub run: CommandFn,
+}
+
+/// All commands the console knows, by name.
+pub struct CommandRegistry {
+    commands: Vec<CommandSpec>,
+}
+
+/// What the console shows, and the lines entered in it waiting to be run.
+pub struct Console {
+    pub open: bool,
+    pub input: String,
+    pub log: VecDeque<String>,
+    submitted: Vec<String>,
+}
+
+impl ArgKind {
+    pub fn parse(&self, word: &str) -> Option<ArgValue> {
+        match *self {
+            ArgKind::Integer { min, max } => word
+                .parse()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .map(ArgValue::Integer),
+            ArgKind::Number => word
+                .parse()
+                .ok()
+                .filter(|value: &f32| value.is_finite())
+                .map(ArgValue::Number),
+            ArgKind::Coordinate => {
+                let (offset, relative) = match word.strip_prefix('~') {
+                    Some("") => {
+                        return Some(ArgValue::Coordinate {
+                            value: 0.0,
+                            relative: true,
+                        })
+                    }
+                    Some(offset) => (offset, true),
+                    None => (word, false),
+                };
+                let value: f32 = offset
+                    .parse()
+                    .ok()
+                    .filter(|value: &f32| value.is_finite())?;
+                Some(ArgValue::Coordinate { value, relative })
+            }
+            ArgKind::Block => block_by_name(word).map(ArgValue::Block),
+            ArgKind::GameMode => match word {
+                "creative" => Some(ArgValue::GameMode(GameMode::Creative)),
+                "survival" => Some(ArgValue::GameMode(GameMode::Survival)),
+                _ => None,
+            },
+            ArgKind::Time => TIME_NAMES
+                .iter()
+                .find(|(name, _)| *name == word)
+                .map(|(_, ticks)| *ticks)
+                .or_else(|| word.parse().ok().filter(|ticks: &f32| ticks.is_finite()))
+                .map(|ticks| ArgValue::Number(ticks / TICKS_PER_DAY)),
+            ArgKind::Word(words) => words
+                .iter()
+                .find(|candidate| **candidate == word)
+                .map(|word| ArgValue::Word(word)),
+            ArgKind::FileName => {
+                let valid = |c: char| c.is_ascii_alphanumeric() || "_-.".contains(c);
+                (!word.starts_with('.') && word.chars().all(valid))
+                    .then(|| ArgValue::FileName(word.to_string()))
+            }
+        }
+    }
+
+    /// Words offered by tab completion.
+    pub fn suggestions(&self) -> Vec<&'static str> {
+        match self {
+            ArgKind::Coordinate => vec!["~"],
+            ArgKind::Block => BLOCKTYPES
+                .iter()
+                .map(|block_type| block_type.name)
+                .collect(),
+            ArgKind::GameMode => vec!["creative", "survival"],
+            ArgKind::Time => TIME_NAMES.iter().map(|(name, _)| *name).collect(),
+            ArgKind::Word(words) => words.to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl ArgSpec {
+    pub const fn required(name: &'static str, kind: ArgKind) -> Self {
+        ArgSpec {
+            name,
+            kind,
+            optional: false,
+        }
+    }
+
+    pub const fn optional(name: &'static str, kind: ArgKind) -> Self {
+        ArgSpec {
+            name,
+            kind,
+            optional: true,
+        }
+    }
+}
+
+impl ArgValue {
+    /// Coordinate resolved against the player's coordinate `current` along the same axis.
+    pub fn coordinate(&self, current: f32) -> f32 {
+        match *self {
+            ArgValue::Coordinate {
+                value,
+                relative: true,
+            } => current + value,
+            ArgValue::Coordinate { value, .. } => value,
+            _ => current,
+        }
+    }
+}
+
+/// Block position given by three coordinate arguments, relative ones resolved against `origin`.
+pub fn block_position(args: &[ArgValue], origin: Vec3) -> IVec3 {
+    let mut position = Vec3::ZERO;
+    for axis in 0..3 {
+        position[axis] = args[axis].coordinate(origin[axis]);
+    }
+    position.floor().as_ivec3()
+}
+
+/// Position of the player's feet, if there is a player.
+pub fn player_feet(world: &mut World) -> Option<Vec3> {
+    let mut query = world.query_filtered::<&Transform, With<super::Player>>();
+    let transform = query.get_single(world).ok()?;
+    Some(transform.translation - Vec3::Y * EYE_HEIGHT)
+}
+
+impl CommandSpec {
+    /// E.g. `/give <block> [count]`.
+    pub fn usage(&self) -> String {
+        let mut usage = format!("/{}", self.name);
+        for arg in self.args {
+            if arg.optional {
+                usage += &format!(" [{}]", arg.name);
+            } else {
+                usage += &format!(" <{}>", arg.name);
+            }
+        }
+        usage
+    }
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandRegistry {
+    /// Registry with the built-in commands.
+    pub fn new() -> Self {
+        let mut registry = CommandRegistry {
+            commands: Vec::new(),
+        };
+        for command in BUILTIN_COMMANDS.into_iter().chain(world_edit::COMMANDS) {
+            registry.register(command);
+        }
+        registry
+    }
+
+    /// Adds a command, replacing any other with the same name.
+    pub fn register(&mut self, command: CommandSpec) {
+        self.commands.retain(|other| other.name != command.name);
+        self.commands.push(command);
+    }
+
+    pub fn commands(&self) -> &[CommandSpec] {
+        &self.commands
+    }
+
+    /// Finds the command a line starts with, the longest name matching, and parses the rest of
+    /// the line as its arguments.
+    pub fn parse(&self, line: &str) -> Result<(&CommandSpec, Vec<ArgValue>), String> {
+        let words: Vec<&str> = line.trim_start_matches('/').split_whitespace().collect();
+        let first = *words.first().ok_or_else(|| "Empty command".to_string())?;
+
+        let command = self
+            .commands
+            .iter()
+            .filter(|command| starts_with_name(&words, command.name))
+            .max_by_key(|command| command.name.split(' ').count());
+        let command = match command {
+            Some(command) => command,
+            None => {
+                let usages: Vec<String> = self
+                    .commands
+                    .iter()
+                    .filter(|command| command.name.split(' ').next() == Some(first))
+                    .map(CommandSpec::usage)
+                    .collect();
+                return Err(if usages.is_empty() {
+                    format!("Unknown command /{}, try one of {}", first, self.names())
+                } else {
+                    format!("Usage: {}", usages.join(", "))
+                });
+            }
+        };
+
+        let words = &words[command.name.split(' ').count()..];
+        if words.len() > command.args.len() {
+            return Err(format!("Usage: {}", command.usage()));
+        }
+        let mut args = Vec::new();
+        for (index, spec) in command.args.iter().enumerate() {
+            let word = match words.get(index) {
+                Some(word) => word,
+                None if spec.optional => break,
+                None => return Err(format!("Usage: {}", command.usage())),
+            };
+            let value = spec.kind.parse(word).ok_or_else(|| {
+                format!(
+                    "Invalid {} '{}', usage: {}",
+                    spec.name,
+                    word,
+                    command.usage()
+                )
+            })?;
+            args.push(value);
+        }
+        Ok((command, args))
+    }
+
+    /// Lines completing the last word of `line`, whether a command name or an argument.
+    pub fn complete(&self, line: &str) -> Vec<String> {
+        let slash = if line.starts_with('/') { "/" } else { "" };
+        let line = line.trim_start_matches('/');
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        if line.is_empty() || line.ends_with(char::is_whitespace) {
+            words.push("");
+        }
+        let (last, previous) = words.split_last().expect("at least one word");
+        let mut candidates = Vec::new();
+
+        for command in self.commands.iter() {
+            let name: Vec<&str> = command.name.split(' ').collect();
+            if previous.len() < name.len() {
+                if previous
+                    .iter()
+                    .zip(name.iter())
+                    .all(|(word, name)| word == name)
+                {
+                    candidates.push(name[previous.len()]);
+                }
+            } else if starts_with_name(previous, command.name) {
+                if let Some(arg) = command.args.get(previous.len() - name.len()) {
+                    candidates.extend(arg.kind.suggestions());
+                }
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let prefix: String = previous.iter().map(|word| format!("{} ", word)).collect();
+        candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(last))
+            .map(|candidate| format!("{}{}{}", slash, prefix, candidate))
+            .collect()
+    }
+
+    fn names(&self) -> String {
+        let mut names: Vec<String> = self
+            .commands
+            .iter()
+            .map(|command| format!("/{}", command.name.split(' ').next().unwrap_or_default()))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names.join(", ")
+    }
+}
+
+fn starts_with_name(words: &[&str], name: &str) -> bool {
+    let name: Vec<&str> = name.split(' ').collect();
+    words.len() >= name.len()
+        && words
+            .iter()
+            .zip(name.iter())
+            .all(|(word, name)| word == name)
+}
+
+/// Runs a command line on the world, with the commands of the `CommandRegistry` in it. This is
+/// all that is needed to drive commands without the console, e.g. from tests. The message is
+/// meant to be shown either way, as an error if the command failed.
+pub fn execute_command(world: &mut World, line: &str) -> Result<String, String> {
+    let registry = world
+        .get_resource::<CommandRegistry>()
+        .ok_or_else(|| "There are no commands".to_string())?;
+    let (command, args) = registry.parse(line)?;
+    let run = command.run;
+    run(world, &args)
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Console {
+            open: false,
+            input: String::new(),
+            log: VecDeque::new(),
+            submitted: Vec::new(),
+        }
+    }
+
+    pub fn print(&mut self, text: &str) {
+        self.log.extend(text.lines().map(str::to_string));
+        while self.log.len() > MAX_LOG_LINES {
+            self.log.pop_front();
+        }
+    }
+
+    /// Queues a line to be run as a command if it starts with `/`, or said in the chat.
+    pub fn submit(&mut self, line: impl Into<String>) {
+        self.submitted.push(line.into());
+    }
+}
+
+/// Runs the lines submitted to the console. Exclusive, since commands can touch anything.
+pub fn run_submitted_commands(world: &mut World) {
+    let lines = std::mem::take(&mut world.resource_mut::<Console>().submitted);
+
+    for line in lines {
+        let line = line.trim().to_string();
+        if !line.starts_with('/') {
+            // Online, the server sends the line back to everyone
+            match world.get_resource_mut::<NetworkClient>() {
+                Some(mut client) => client.send(&ClientMessage::Chat { text: line }),
+                None => world
+                    .resource_mut::<Console>()
+                    .print(&format!("<Player> {}", line)),
+            }
+            continue;
+        }
+        let output = match execute_command(world, &line) {
+            Ok(message) => message,
+            Err(error) => error,
+        };
+        let mut console = world.resource_mut::<Console>();
+        console.print(&format!("> {}", line));
+        console.print(&output);
+    }
+}
+
+const BUILTIN_COMMANDS: [CommandSpec; 10] = [
+    CommandSpec {
+        name: "help",
+        args: &[],
+        help: "Lists the commands",
+        run: help,
+    },
+    CommandSpec {
+        name: "tp",
+        args: &[
+            ArgSpec::required("x", ArgKind::Coordinate),
+            ArgSpec::required("y", ArgKind::Coordinate),
+            ArgSpec::required("z", ArgKind::Coordinate),
+        ],
+        help: "Moves the player's feet to a position",
+        run: teleport,
+    },
+    CommandSpec {
+        name: "seed",
+        args: &[],
+        help: "Shows the seed the terrain is generated from",
+        run: show_seed,
+    },
+    CommandSpec {
+        name: "time set",
+        args: &[ArgSpec::required("time", ArgKind::Time)],
+        help: "Sets the time of day",
+        run: set_time,
+    },
+    CommandSpec {
+        name: "time add",
+        args: &[ArgSpec::required("time", ArgKind::Time)],
+        help: "Moves the time forward",
+        run: add_time,
+    },
+    CommandSpec {
+        name: "time freeze",
+        args: &[],
+        help: "Stops the time of day",
+        run: freeze_time,
+    },
+    CommandSpec {
+        name: "time unfreeze",
+        args: &[],
+        help: "Lets the time of day run again",
+        run: unfreeze_time,
+    },
+    CommandSpec {
+        name: "time daylength",
+        args: &[ArgSpec::required("seconds", ArgKind::Number)],
+        help: "Sets how many seconds a day and night last",
+        run: set_day_length,
+    },
+    CommandSpec {
+        name: "gamemode",
+        args: &[ArgSpec::required("mode", ArgKind::GameMode)],
+        help: "Switches between creative and survival",
+        run: set_game_mode,
+    },
+    CommandSpec {
+        name: "give",
+        args: &[
+            ArgSpec::required("block", ArgKind::Block),
+            ArgSpec::optional(
+                "count",
+                ArgKind::Integer {
+                    min: 1,
+                    max: (INVENTORY_SIZE * MAX_STACK_SIZE as usize) as i64,
+                },
+            ),
+        ],
+        help: "Puts blocks in the player's inventory",
+        run: give,
+    },
+];
+
+fn help(world: &mut World, _: &[ArgValue]) -> Result<String, String> {
+    let registry = world
+        .get_resource::<CommandRegistry>()
+        .ok_or_else(|| "There are no commands".to_string())?;
+    let mut lines: Vec<String> = registry
+        .commands()
+        .iter()
+        .map(|command| format!("{} - {}", command.usage(), command.help))
+        .collect();
+    lines.sort_unstable();
+    Ok(lines.join("\n"))
+}
+
+fn teleport(world: &mut World, args: &[ArgValue]) -> Result<String, String> {
+    if world.contains_resource::<NetworkClient>() {
+        return Err("The server does not let players teleport".to_string());
+    }
+    let mut query =
+        world.query_filtered::<(&mut Transform, Option<&mut PlayerPhysics>), With<super::Player>>();
+    let (mut transform, physics) = query
+        .get_single_mut(world)
+        .map_err(|_| "There is no player".to_string())?;
+
+    let feet = transform.translation - Vec3::Y * EYE_HEIGHT;
+    let mut target = Vec3::ZERO;
+    for axis in 0..3 {
+        target[axis] = args[axis].coordinate(feet[axis]);
+    }
+    transform.translation = target + Vec3::Y * EYE_HEIGHT;
+    if let Some(mut physics) = physics {
+        *physics = PlayerPhysics::default();
+    }
+    if let Some(mut teleports) = world.get_resource_mut::<Events<Teleport>>() {
+        teleports.send(Teleport {
+            position: target + Vec3::Y * EYE_HEIGHT,
+        });
+    }
+    Ok(format!(
+        "Teleported to {:.1} {:.1} {:.1}",
+        target.x, target.y, target.z
+    ))
+}
+
+fn show_seed(world: &mut World, _: &[ArgValue]) -> Result<String, String> {
+    let voxel_map = world
+        .get_resource::<VoxelMap>()
+        .ok_or_else(|| "There is no world".to_string())?;
+    Ok(format!("Seed: {}", voxel_map.seed))
+}
+
+fn send_time_command(world: &mut World, command: TimeCommand) -> Result<(), String> {
+    world
+        .get_resource_mut::<Events<TimeCommand>>()
+        .ok_or_else(|| "There is no world time".to_string())?
+        .send(command);
+    Ok(())
+}
+
+fn time_arg(args: &[ArgValue]) -> f32 {
+    match args.first() {
+        Some(ArgValue::Number(days)) => *days,
+        _ => 0.0,
+    }
+}
+
+fn set_time(world: &mut World, args: &[ArgValue]) -> Result<String, String> {
+    let days = time_arg(args).rem_euclid(1.0);
+    send_time_command(world, TimeCommand::Set(days))?;
+    Ok(format!(
+        "Set the time to {}",
+        (days * TICKS_PER_DAY).round()
+    ))
+}
+
+fn add_time(world: &mut World, args: &[ArgValue]) -> Result<String, String> {
+    let days = time_arg(args);
+    send_time_command(world, TimeCommand::Add(days))?;
+    Ok(format!(
+        "Added {} to the time",
+        (days * TICKS_PER_DAY).round()
+    ))
+}
+
+fn freeze_time(world: &mut World, _: &[ArgValue]) -> Result<String, String> {
+    send_time_command(world, TimeCommand::Freeze(true))?;
+    Ok("Time frozen".to_string())
+}
+
+fn unfreeze_time(world: &mut World, _: &[ArgValue]) -> Result<String, String> {
+    send_time_command(world, TimeCommand::Freeze(false))?;
+    Ok("Time unfrozen".to_string())
+}
+
+fn set_day_length(world: &mut World, args: &[ArgValue]) -> Result<String, String> {
+    let seconds = match args.first() {
+        Some(ArgValue::Number(seconds)) if *seconds > 0.0 => *seconds,
+        _ => return Err("The day length has to be positive".to_string()),
+    };
+    send_time_command(world, TimeCommand::SetDayLength(seconds))?;
+    Ok(format!("A day now lasts {} seconds", seconds))
+}
+
+fn set_game_mode(world: &mut World, args: &[ArgValue]) -> Result<String, String> {
+    if world.contains_resource::<NetworkClient>() {
+        return Err("The server decides the game mode".to_string());
+    }
+    let game_mode = match args.first() {
+        Some(ArgValue::GameMode(game_mode)) => *game_mode,
+        _ => return Err("Missing game mode".to_string()),
+    };
+    let mut query =
+        world.query_filtered::<(&mut GameMode, Option<&mut PlayerPhysics>), With<super::Player>>();
+    let (mut current, physics) = query
+        .get_single_mut(world)
+        .map_err(|_| "There is no player".to_string())?;
+
+    *current = game_mode;
+    if let Some(mut physics) = physics {
+        *physics = PlayerPhysics::default();
+    }
+    Ok(format!("Game mode set to {:?}", game_mode))
+}
+
+fn give(world: &mut World, args: &[ArgValue]) -> Result<String, String> {
+    let (block, count) = match args {
+        [ArgValue::Block(block)] => (*block, 1),
+        [ArgValue::Block(block), ArgValue::Integer(count)] => (*block, *count as usize),
+        _ => return Err("Missing block".to_string()),
+    };
+    if BLOCKTYPES[block as usize].textures.is_none() {
+        return Err(format!(
+            "{} cannot be held",
+            BLOCKTYPES[block as usize].name
+        ));
+    }
+    let mut query = world.query_filtered::<&mut Inventory, With<super::Player>>();
+    let mut inventory = query
+        .get_single_mut(world)
+        .map_err(|_| "There is no player".to_string())?;
+
+    let mut left = count;
+    while left > 0 {
+        let batch = left.min(MAX_STACK_SIZE as usize);
+        let not_added = inventory.add(block, batch as u8) as usize;
+        left -= batch - not_added;
+        if not_added > 0 {
+            break;
+        }
+    }
+    let name = BLOCKTYPES[block as usize].name;
+    if left == 0 {
+        Ok(format!("Gave {} {}", count, name))
+    } else {
+        Ok(format!(
+            "Gave {} {}, {} did not fit",
+            count - left,
+            name,
+            left
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::ItemStack;
+
+    // World with the commands and a survival player standing with its feet at the origin
+    fn world() -> World {
+        let mut world = World::new();
+        world.insert_resource(CommandRegistry::new());
+        world.insert_resource(Events::<TimeCommand>::default());
+        world.insert_resource(Events::<Teleport>::default());
+        world
+            .spawn()
+            .insert(super::super::Player)
+            .insert(Transform::from_translation(Vec3::Y * EYE_HEIGHT))
+            .insert(GameMode::Survival)
+            .insert(PlayerPhysics::default())
+            .insert(Inventory::new());
+        world
+    }
+
+    fn parse_error(registry: &CommandRegistry, line: &str) -> String {
+        match registry.parse(line) {
+            Ok((command, _)) => panic!("{} parsed as /{}", line, command.name),
+            Err(error) => error,
+        }
+    }
+
+    fn time_commands(world: &mut World) -> Vec<TimeCommand> {
+        let mut events = world.resource_mut::<Events<TimeCommand>>();
+        events.drain().collect()
+    }
+
+    #[test]
+    fn parses_arguments_of_the_longest_matching_name() {
+        let registry = CommandRegistry::new();
+        let (command, args) = registry.parse("/time set noon").unwrap();
+        assert_eq!(command.name, "time set");
+        assert_eq!(args, vec![ArgValue::Number(0.25)]);
+
+        let (command, args) = registry.parse("tp ~ ~1.5 -3").unwrap();
+        assert_eq!(command.name, "tp");
+        assert_eq!(
+            args,
+            vec![
+                ArgValue::Coordinate {
+                    value: 0.0,
+                    relative: true
+                },
+                ArgValue::Coordinate {
+                    value: 1.5,
+                    relative: true
+                },
+                ArgValue::Coordinate {
+                    value: -3.0,
+                    relative: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_lines_with_usage() {
+        let registry = CommandRegistry::new();
+        assert_eq!(parse_error(&registry, "  "), "Empty command");
+        assert!(parse_error(&registry, "/fly").starts_with("Unknown command /fly"));
+        assert_eq!(
+            parse_error(&registry, "/give"),
+            "Usage: /give <block> [count]"
+        );
+        assert_eq!(
+            parse_error(&registry, "/give stone 0"),
+            "Invalid count '0', usage: /give <block> [count]"
+        );
+        assert_eq!(
+            parse_error(&registry, "/gamemode survival now"),
+            "Usage: /gamemode <mode>"
+        );
+        assert!(parse_error(&registry, "/time later").contains("/time set <time>"));
+    }
+
+    #[test]
+    fn completes_names_and_arguments() {
+        let registry = CommandRegistry::new();
+        assert_eq!(registry.complete("/gam"), vec!["/gamemode"]);
+        assert_eq!(
+            registry.complete("/time f"),
+            vec!["/time freeze".to_string()]
+        );
+        assert_eq!(
+            registry.complete("/gamemode "),
+            vec!["/gamemode creative", "/gamemode survival"]
+        );
+        assert_eq!(registry.complete("give da"), vec!["give dandelion"]);
+        assert!(registry.complete("/give stone ").is_empty());
+    }
+
+    #[test]
+    fn teleports_relative_to_the_feet() {
+        let mut world = world();
+        assert_eq!(
+            execute_command(&mut world, "/tp 10 ~5 ~-2"),
+            Ok("Teleported to 10.0 5.0 -2.0".to_string())
+        );
+        assert_eq!(player_feet(&mut world), Some(Vec3::new(10.0, 5.0, -2.0)));
+        let teleports: Vec<Teleport> = world.resource_mut::<Events<Teleport>>().drain().collect();
+        assert_eq!(teleports.len(), 1);
+    }
+
+    #[test]
+    fn sends_time_commands() {
+        let mut world = world();
+        assert_eq!(
+            execute_command(&mut world, "/time set night"),
+            Ok("Set the time to 13000".to_string())
+        );
+        execute_command(&mut world, "/time freeze").unwrap();
+        assert_eq!(
+            time_commands(&mut world),
+            vec![
+                TimeCommand::Set(13000.0 / TICKS_PER_DAY),
+                TimeCommand::Freeze(true)
+            ]
+        );
+        assert_eq!(
+            execute_command(&mut world, "/time daylength -5"),
+            Err("The day length has to be positive".to_string())
+        );
+        assert!(time_commands(&mut world).is_empty());
+    }
+
+    #[test]
+    fn switches_game_mode() {
+        let mut world = world();
+        execute_command(&mut world, "/gamemode creative").unwrap();
+        let mut query = world.query::<&GameMode>();
+        assert_eq!(query.single(&world), &GameMode::Creative);
+    }
+
+    #[test]
+    fn gives_blocks_until_the_inventory_is_full() {
+        let mut world = world();
+        assert_eq!(
+            execute_command(&mut world, "/give stone 100"),
+            Ok("Gave 100 stone".to_string())
+        );
+        let mut query = world.query::<&Inventory>();
+        let inventory = query.single(&world);
+        let stone = block_by_name("stone").unwrap();
+        assert_eq!(
+            inventory.slots[1],
+            Some(ItemStack {
+                block: stone,
+                count: 36
+            })
+        );
+
+        let max = INVENTORY_SIZE * MAX_STACK_SIZE as usize;
+        assert_eq!(
+            execute_command(&mut world, &format!("/give dirt {}", max)),
+            Ok(format!("Gave {} dirt, 128 did not fit", max - 128))
+        );
+        assert_eq!(
+            execute_command(&mut world, "/give air"),
+            Err("air cannot be held".to_string())
+        );
+    }
+
+    #[test]
+    fn fails_without_a_player_or_commands() {
+        let mut world = World::new();
+        assert_eq!(
+            execute_command(&mut world, "/seed"),
+            Err("There are no commands".to_string())
+        );
+        world.insert_resource(CommandRegistry::new());
+        assert_eq!(
+            execute_command(&mut world, "/gamemode creative"),
+            Err("There is no player".to_string())
+        );
+        assert_eq!(
+            execute_command(&mut world, "/seed"),
+            Err("There is no world".to_string())
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use crate::console::{CommandRegistry, Console};
+use crate::input_map::{Action, Actions};
+use crate::inventory_ui::InventoryScreen;
+use crate::world_select::WorldSelectScreen;
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Align2};
+use bevy_egui::EguiContext;
+
+pub const CONSOLE_WIDTH: f32 = 500.0;
+// Log lines shown above the input line
+pub const VISIBLE_LINES: usize = 12;
+
+/// Opens the console with the chat or command action and draws it while open. Enter submits
+/// the line, Tab completes the word being typed and the release cursor action closes
+/// the console.
+#[allow(clippy::too_many_arguments)]
+pub fn update_console(
+    keys: Res<Input<KeyCode>>,
+    mut actions: ResMut<Actions>,
+    mut egui_context: ResMut<EguiContext>,
+    mut console: ResMut<Console>,
+    registry: Res<CommandRegistry>,
+    inventory_screen: Res<InventoryScreen>,
+    world_select_screen: Res<WorldSelectScreen>,
+    mut windows: ResMut<Windows>,
+) {
+    let mut move_cursor_to_end = false;
+
+    if !console.open {
+        let prefix = if actions.just_pressed(Action::Chat) {
+            ""
+        } else if actions.just_pressed(Action::Command) {
+            "/"
+        } else {
+            return;
+        };
+        if inventory_screen.open || world_select_screen.open {
+            return;
+        }
+        console.open = true;
+        console.input = prefix.to_string();
+        move_cursor_to_end = true;
+        set_cursor_free(&mut windows, true);
+    } else if actions.just_pressed(Action::ReleaseCursor) {
+        // Used up here, so it does not also free the cursor
+        actions.clear_just_pressed(Action::ReleaseCursor);
+        console.open = false;
+        set_cursor_free(&mut windows, false);
+        return;
+    } else if keys.just_pressed(KeyCode::Return) {
+        let line = std::mem::take(&mut console.input);
+        if !line.trim().is_empty() {
+            console.submit(line);
+        }
+        console.open = false;
+        set_cursor_free(&mut windows, false);
+        return;
+    } else if keys.just_pressed(KeyCode::Tab) {
+        console.input.retain(|c| c != '\t');
+        let completions = registry.complete(&console.input);
+        match completions.as_slice() {
+            [] => (),
+            [line] => console.input = format!("{} ", line),
+            lines => {
+                console.input = common_prefix(lines).to_string();
+                console.print(&lines.join("  "));
+            }
+        }
+        move_cursor_to_end = true;
+    }
+
+    let console = &mut *console;
+    egui::Window::new("Console")
+        .anchor(Align2::LEFT_BOTTOM, [10.0, -80.0])
+        .collapsible(false)
+        .resizable(false)
+        .default_width(CONSOLE_WIDTH)
+        .show(egui_context.ctx_mut(), |ui| {
+            let skipped = console.log.len().saturating_sub(VISIBLE_LINES);
+            for line in console.log.iter().skip(skipped) {
+                ui.label(line);
+            }
+
+            let mut output = egui::TextEdit::singleline(&mut console.input)
+                .desired_width(f32::INFINITY)
+                .show(ui);
+            output.response.request_focus();
+            if move_cursor_to_end {
+                let end = egui::text::CCursor::new(console.input.chars().count());
+                output
+                    .state
+                    .set_ccursor_range(Some(egui::text::CCursorRange::one(end)));
+                output.state.store(ui.ctx(), output.response.id);
+            }
+        });
+}
+
+fn set_cursor_free(windows: &mut Windows, free: bool) {
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(!free);
+        window.set_cursor_visibility(free);
+    }
+}
+
+fn common_prefix(lines: &[String]) -> &str {
+    let first = &lines[0];
+    let length = lines[1..].iter().fold(first.len(), |length, line| {
+        first[..length]
+            .char_indices()
+            .zip(line.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(length.min(line.len()), |((index, _), _)| index)
+    });
+    &first[..length]
+}
@@ -0,0 +1,334 @@
+use crate::lod::LodDistances;
+use crate::voxel_data::{CHUNK_SIZE, FACE_CHECKS, WORLD_HEIGHT_IN_CHUNKS};
+use crate::voxel_map::VoxelMap;
+use crate::world::{
+    get_chunk_from_player_pos, is_chunk_in_world, ActiveChunks, ChunkCoord, ChunkMap,
+};
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use itertools::iproduct;
+use std::collections::VecDeque;
+
+/// Which faces of a chunk can see each other through non solid voxels. Bit `a * 6 + b` is set
+/// when face `a` is connected to face `b`, faces being indexed like `FACE_CHECKS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaceConnectivity(pub u64);
+
+impl FaceConnectivity {
+    pub const NONE: FaceConnectivity = FaceConnectivity(0);
+    pub const ALL: FaceConnectivity = FaceConnectivity((1 << 36) - 1);
+
+    pub fn connects(&self, from: usize, to: usize) -> bool {
+        self.0 & (1 << (from * 6 + to)) != 0
+    }
+
+    fn connect_all(&mut self, faces: u8) {
+        for (from, to) in iproduct!((0..6), (0..6)) {
+            if faces & (1 << from) != 0 && faces & (1 << to) != 0 {
+                self.0 |= 1 << (from * 6 + to);
+            }
+        }
+    }
+}
+
+/// Flood fills the non solid voxels of a chunk and connects the faces touched by each air
+/// pocket. `is_solid` takes coordinates local to the chunk.
+pub fn compute_connectivity(is_solid: impl Fn(usize, usize, usize) -> bool) -> FaceConnectivity {
+    let _span = info_span!("Chunk connectivity").entered();
+    let index = |x: usize, y: usize, z: usize| (x * CHUNK_SIZE + y) * CHUNK_SIZE + z;
+    let mut visited = vec![false; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
+    let mut connectivity = FaceConnectivity::NONE;
+    let mut stack = Vec::new();
+
+    for (x, y, z) in iproduct!((0..CHUNK_SIZE), (0..CHUNK_SIZE), (0..CHUNK_SIZE)) {
+        if visited[index(x, y, z)] || is_solid(x, y, z) {
+            continue;
+        }
+        visited[index(x, y, z)] = true;
+        stack.push((x, y, z));
+        let mut faces = 0u8;
+
+        while let Some((x, y, z)) = stack.pop() {
+            for (face, face_check) in FACE_CHECKS.iter().enumerate() {
+                let (nx, ny, nz) = (
+                    x as i32 + face_check.x as i32,
+                    y as i32 + face_check.y as i32,
+                    z as i32 + face_check.z as i32,
+                );
+                let range = 0..CHUNK_SIZE as i32;
+                if !range.contains(&nx) || !range.contains(&ny) || !range.contains(&nz) {
+                    faces |= 1 << face;
+                    continue;
+                }
+
+                let (nx, ny, nz) = (nx as usize, ny as usize, nz as usize);
+                if !visited[index(nx, ny, nz)] && !is_solid(nx, ny, nz) {
+                    visited[index(nx, ny, nz)] = true;
+                    stack.push((nx, ny, nz));
+                }
+            }
+        }
+        connectivity.connect_all(faces);
+    }
+    connectivity
+}
+
+/// Connectivity of a generated chunk read from the voxel map.
+pub fn chunk_connectivity(chunk_pos: &ChunkCoord, voxel_map: &VoxelMap) -> FaceConnectivity {
+    let min = IVec3::new(chunk_pos.x, chunk_pos.y, chunk_pos.z) * CHUNK_SIZE as i32;
+
+    compute_connectivity(|x, y, z| {
+        voxel_map
+            .get(min + IVec3::new(x as i32, y as i32, z as i32))
+            .block_type()
+            .is_opaque_cube()
+    })
+}
+
+/// Breadth first search from the camera chunk through connected chunk faces.
+///
+/// `connectivity` returns `None` for chunks without data yet, which are treated as open so
+/// nothing behind them gets hidden. The search never turns back on a direction it already
+/// travelled in, stays within `radius` chunks of the start horizontally and within the world
+/// height plus one layer of sky, and skips chunks entirely behind the camera when a view
+/// direction is given.
+pub fn visible_chunks(
+    start: ChunkCoord,
+    radius: i32,
+    view_direction: Option<Vec3>,
+    connectivity: impl Fn(&ChunkCoord) -> Option<FaceConnectivity>,
+) -> HashSet<ChunkCoord> {
+    let _span = info_span!("Chunk visibility").entered();
+    let mut visible = HashSet::default();
+    let mut queue = VecDeque::new();
+    let start = ChunkCoord {
+        y: start.y.clamp(0, WORLD_HEIGHT_IN_CHUNKS as i32),
+        ..start
+    };
+
+    visible.insert(start);
+    queue.push_back((start, None, 0u8));
+
+    while let Some((chunk, entered_through, travelled)) = queue.pop_front() {
+        let chunk_connectivity = connectivity(&chunk).unwrap_or(FaceConnectivity::ALL);
+
+        for (direction, face_check) in FACE_CHECKS.iter().enumerate() {
+            if travelled & (1 << (direction ^ 1)) != 0 {
+                continue;
+            }
+            if let Some(entered_through) = entered_through {
+                if !chunk_connectivity.connects(entered_through, direction) {
+                    continue;
+                }
+            }
+
+            let next = ChunkCoord {
+                x: chunk.x + face_check.x as i32,
+                y: chunk.y + face_check.y as i32,
+                z: chunk.z + face_check.z as i32,
+            };
+            if (next.x - start.x).abs() > radius
+                || (next.z - start.z).abs() > radius
+                || next.y < 0
+                || next.y > WORLD_HEIGHT_IN_CHUNKS as i32
+                || visible.contains(&next)
+            {
+                continue;
+            }
+            if let Some(view_direction) = view_direction {
+                if is_behind(&start, &next, view_direction) {
+                    continue;
+                }
+            }
+
+            visible.insert(next);
+            queue.push_back((next, Some(direction ^ 1), travelled | (1 << direction)));
+        }
+    }
+    visible
+}
+
+/// Whether the bounding sphere of `chunk` lies entirely behind the plane through the centre of
+/// the `start` chunk facing `view_direction`.
+fn is_behind(start: &ChunkCoord, chunk: &ChunkCoord, view_direction: Vec3) -> bool {
+    let offset = Vec3::new(
+        (chunk.x - start.x) as f32,
+        (chunk.y - start.y) as f32,
+        (chunk.z - start.z) as f32,
+    ) * CHUNK_SIZE as f32;
+    // Half diagonal of the chunk plus the largest offset of the camera from its chunk centre
+    let radius = 3f32.sqrt() * CHUNK_SIZE as f32;
+
+    offset.dot(view_direction.normalize_or_zero()) < -radius
+}
+
+pub fn update_chunk_visibility(
+    camera_query: Query<&GlobalTransform, With<super::Player>>,
+    mut visibility_query: Query<&mut Visibility>,
+    chunk_map: Res<ChunkMap>,
+    active_chunks: Res<ActiveChunks>,
+    lod_distances: Res<LodDistances>,
+) {
+    let camera_transform = camera_query.single();
+    let camera_chunk = get_chunk_from_player_pos(camera_transform.translation());
+
+    let visible = visible_chunks(
+        camera_chunk,
+        lod_distances.render_distance() as i32 + 1,
+        Some(
+            camera_transform
+                .compute_matrix()
+                .transform_vector3(-Vec3::Z),
+        ),
+        |chunk_pos| {
+            if !is_chunk_in_world(chunk_pos) {
+                return None;
+            }
+            chunk_map
+                .get(chunk_pos)
+                .0
+                .as_ref()
+                .map(|chunk| chunk.connectivity)
+        },
+    );
+
+    for chunk_pos in active_chunks.0.iter() {
+        if let Some(entity) = chunk_map.get(chunk_pos).1 {
+            if let Ok(mut visibility) = visibility_query.get_mut(entity) {
+                let is_visible = visible.contains(chunk_pos);
+                if visibility.is_visible != is_visible {
+                    visibility.is_visible = is_visible;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array3;
+    use std::collections::HashMap;
+
+    // Faces indexed like `FACE_CHECKS`
+    const WEST: usize = 0;
+    const EAST: usize = 1;
+    const UP: usize = 2;
+
+    fn solid_chunk() -> Array3<bool> {
+        Array3::from_elem((CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE), true)
+    }
+
+    fn connectivity_of(solid: &Array3<bool>) -> FaceConnectivity {
+        compute_connectivity(|x, y, z| solid[[x, y, z]])
+    }
+
+    // Whether exactly the faces in `faces` are connected to each other
+    fn connects_only(connectivity: FaceConnectivity, faces: &[usize]) -> bool {
+        iproduct!((0..6), (0..6)).all(|(from, to)| {
+            connectivity.connects(from, to) == (faces.contains(&from) && faces.contains(&to))
+        })
+    }
+
+    #[test]
+    fn open_chunk_connects_every_face() {
+        let open = Array3::from_elem((CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE), false);
+        assert_eq!(connectivity_of(&open), FaceConnectivity::ALL);
+        assert_eq!(connectivity_of(&solid_chunk()), FaceConnectivity::NONE);
+    }
+
+    #[test]
+    fn sealed_cave_connects_nothing() {
+        let mut solid = solid_chunk();
+        for (x, y, z) in iproduct!((4..28), (4..28), (4..28)) {
+            solid[[x, y, z]] = false;
+        }
+        assert_eq!(connectivity_of(&solid), FaceConnectivity::NONE);
+    }
+
+    #[test]
+    fn tunnel_connects_its_ends() {
+        let mut solid = solid_chunk();
+        for x in 0..CHUNK_SIZE {
+            solid[[x, 16, 16]] = false;
+        }
+        assert!(connects_only(connectivity_of(&solid), &[WEST, EAST]));
+
+        // Turning up halfway leads out of the top instead
+        let mut solid = solid_chunk();
+        for x in 0..16 {
+            solid[[x, 16, 16]] = false;
+        }
+        for y in 16..CHUNK_SIZE {
+            solid[[15, y, 16]] = false;
+        }
+        assert!(connects_only(connectivity_of(&solid), &[WEST, UP]));
+    }
+
+    #[test]
+    fn separate_pockets_stay_apart() {
+        let mut solid = solid_chunk();
+        // A pocket on the west face and one on the top face, not touching
+        for (y, z) in iproduct!((2..6), (2..6)) {
+            solid[[0, y, z]] = false;
+        }
+        for (x, z) in iproduct!((20..24), (20..24)) {
+            solid[[x, CHUNK_SIZE - 1, z]] = false;
+        }
+        let connectivity = connectivity_of(&solid);
+        assert!(connectivity.connects(WEST, WEST));
+        assert!(connectivity.connects(UP, UP));
+        assert!(!connectivity.connects(WEST, UP));
+    }
+
+    fn chunk(x: i32, y: i32, z: i32) -> ChunkCoord {
+        ChunkCoord { x, y, z }
+    }
+
+    #[test]
+    fn sealed_chunks_hide_what_is_behind_them() {
+        let visible = visible_chunks(chunk(0, 1, 0), 3, None, |_| Some(FaceConnectivity::NONE));
+        // The camera chunk and its neighbours only
+        assert_eq!(visible.len(), 7);
+        assert!(visible.contains(&chunk(1, 1, 0)));
+        assert!(!visible.contains(&chunk(2, 1, 0)));
+    }
+
+    #[test]
+    fn tunnels_lead_through_chunks() {
+        let mut solid = solid_chunk();
+        for x in 0..CHUNK_SIZE {
+            solid[[x, 16, 16]] = false;
+        }
+        let tunnel = connectivity_of(&solid);
+        let chunks: HashMap<_, _> = (-3..=3).map(|x| (chunk(x, 1, 0), tunnel)).collect();
+
+        let visible = visible_chunks(chunk(0, 1, 0), 3, None, |chunk_pos| {
+            Some(
+                chunks
+                    .get(chunk_pos)
+                    .copied()
+                    .unwrap_or(FaceConnectivity::NONE),
+            )
+        });
+        // The whole tunnel within the radius, and the other neighbours of the camera chunk
+        assert_eq!(visible.len(), 11);
+        assert!((-3..=3).all(|x| visible.contains(&chunk(x, 1, 0))));
+        assert!(!visible.contains(&chunk(1, 1, 1)));
+    }
+
+    #[test]
+    fn chunks_without_data_are_open() {
+        let visible = visible_chunks(chunk(0, 1, 0), 1, None, |_| None);
+        // Every chunk within the radius, up to one layer above the world
+        assert_eq!(visible.len(), 3 * 3 * (WORLD_HEIGHT_IN_CHUNKS + 1));
+    }
+
+    #[test]
+    fn chunks_behind_the_camera_are_skipped() {
+        let visible = visible_chunks(chunk(0, 1, 0), 3, Some(Vec3::X), |_| None);
+        assert!(visible.contains(&chunk(3, 1, 0)));
+        assert!(visible.contains(&chunk(-1, 1, 0)));
+        assert!(visible.iter().all(|chunk_pos| chunk_pos.x >= -1));
+    }
+}
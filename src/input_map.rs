@@ -0,0 +1,485 @@
+use bevy::input::gamepad::{GamepadAxis, GamepadAxisType, GamepadButton, GamepadButtonType};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use std::collections::BTreeMap;
+
+// How far a stick has to be pushed for its direction to count as pressed
+pub const STICK_THRESHOLD: f32 = 0.5;
+// Stick deflection ignored when looking around, so a stick at rest does not drift
+pub const STICK_DEAD_ZONE: f32 = 0.15;
+
+/// What players do, independent of the keys, buttons or sticks bound to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    /// Jumps when walking, flies up in creative.
+    Jump,
+    /// Flies down in creative.
+    Sneak,
+    Break,
+    Place,
+    OpenInventory,
+    /// Switches between flying in creative and walking in survival.
+    ToggleGameMode,
+    Chat,
+    /// Opens the chat with a `/` typed.
+    Command,
+    ReleaseCursor,
+    Settings,
+    /// Opens the world selection screen.
+    Worlds,
+    HotbarNext,
+    HotbarPrevious,
+    /// Selects a hotbar slot, from 0.
+    Hotbar(u8),
+}
+
+/// A key, mouse button, gamepad button or gamepad stick direction, on any gamepad.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    GamepadButton(GamepadButtonType),
+    /// An axis pushed past `STICK_THRESHOLD` in the direction of the sign.
+    GamepadAxis(GamepadAxisType, f32),
+}
+
+/// Bindings of every action. The settings file stores them by name, see `Action::name` and
+/// `Binding::name`.
+pub struct InputMap {
+    bindings: HashMap<Action, Vec<Binding>>,
+}
+
+/// Which actions are held this frame, and which of them were not held in the last one.
+/// Gameplay systems read these rather than the raw input.
+#[derive(Default)]
+pub struct Actions {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    look: Vec2,
+}
+
+macro_rules! names {
+    ($const:ident: $type:ident, $prefix:literal, $($variant:ident),* $(,)?) => {
+        const $const: &[(&str, $type)] = &[$((concat!($prefix, stringify!($variant)), $type::$variant)),*];
+    };
+}
+
+names!(
+    KEY_NAMES: KeyCode, "",
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Space, Return, Escape, Tab, Back, Delete, Insert, Home, End, PageUp, PageDown,
+    Up, Down, Left, Right, LShift, RShift, LControl, RControl, LAlt, RAlt,
+    Slash, Backslash, Comma, Period, Semicolon, Apostrophe, Minus, Equals, LBracket, RBracket,
+    Grave,
+);
+names!(MOUSE_NAMES: MouseButton, "Mouse", Left, Right, Middle);
+names!(
+    GAMEPAD_BUTTON_NAMES: GamepadButtonType, "Gamepad",
+    South, East, North, West, C, Z, LeftTrigger, LeftTrigger2, RightTrigger, RightTrigger2,
+    Select, Start, Mode, LeftThumb, RightThumb, DPadUp, DPadDown, DPadLeft, DPadRight,
+);
+const HOTBAR_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+const GAMEPAD_AXIS_NAMES: [(&str, GamepadAxisType, f32); 8] = [
+    ("GamepadLeftStickUp", GamepadAxisType::LeftStickY, 1.0),
+    ("GamepadLeftStickDown", GamepadAxisType::LeftStickY, -1.0),
+    ("GamepadLeftStickLeft", GamepadAxisType::LeftStickX, -1.0),
+    ("GamepadLeftStickRight", GamepadAxisType::LeftStickX, 1.0),
+    ("GamepadRightStickUp", GamepadAxisType::RightStickY, 1.0),
+    ("GamepadRightStickDown", GamepadAxisType::RightStickY, -1.0),
+    ("GamepadRightStickLeft", GamepadAxisType::RightStickX, -1.0),
+    ("GamepadRightStickRight", GamepadAxisType::RightStickX, 1.0),
+];
+
+impl Action {
+    pub const ALL: [Action; 26] = [
+        Action::MoveForward,
+        Action::MoveBack,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::Sneak,
+        Action::Break,
+        Action::Place,
+        Action::OpenInventory,
+        Action::ToggleGameMode,
+        Action::Chat,
+        Action::Command,
+        Action::ReleaseCursor,
+        Action::Settings,
+        Action::Worlds,
+        Action::HotbarNext,
+        Action::HotbarPrevious,
+        Action::Hotbar(0),
+        Action::Hotbar(1),
+        Action::Hotbar(2),
+        Action::Hotbar(3),
+        Action::Hotbar(4),
+        Action::Hotbar(5),
+        Action::Hotbar(6),
+        Action::Hotbar(7),
+        Action::Hotbar(8),
+    ];
+
+    /// Name in the `[controls]` table of the settings file.
+    pub fn name(&self) -> String {
+        match self {
+            Action::MoveForward => "move_forward".to_string(),
+            Action::MoveBack => "move_back".to_string(),
+            Action::MoveLeft => "move_left".to_string(),
+            Action::MoveRight => "move_right".to_string(),
+            Action::Jump => "jump".to_string(),
+            Action::Sneak => "sneak".to_string(),
+            Action::Break => "break".to_string(),
+            Action::Place => "place".to_string(),
+            Action::OpenInventory => "open_inventory".to_string(),
+            Action::ToggleGameMode => "toggle_game_mode".to_string(),
+            Action::Chat => "chat".to_string(),
+            Action::Command => "command".to_string(),
+            Action::ReleaseCursor => "release_cursor".to_string(),
+            Action::Settings => "settings".to_string(),
+            Action::Worlds => "worlds".to_string(),
+            Action::HotbarNext => "hotbar_next".to_string(),
+            Action::HotbarPrevious => "hotbar_previous".to_string(),
+            Action::Hotbar(slot) => format!("hotbar_{}", slot + 1),
+        }
+    }
+
+    fn default_bindings(&self) -> Vec<Binding> {
+        use Binding::{GamepadAxis as Axis, GamepadButton as Button, Key, Mouse};
+        match *self {
+            Action::MoveForward => vec![Key(KeyCode::W), Axis(GamepadAxisType::LeftStickY, 1.0)],
+            Action::MoveBack => vec![Key(KeyCode::S), Axis(GamepadAxisType::LeftStickY, -1.0)],
+            Action::MoveLeft => vec![Key(KeyCode::A), Axis(GamepadAxisType::LeftStickX, -1.0)],
+            Action::MoveRight => vec![Key(KeyCode::D), Axis(GamepadAxisType::LeftStickX, 1.0)],
+            Action::Jump => vec![Key(KeyCode::Space), Button(GamepadButtonType::South)],
+            Action::Sneak => vec![Key(KeyCode::LShift), Button(GamepadButtonType::East)],
+            Action::Break => vec![
+                Mouse(MouseButton::Left),
+                Button(GamepadButtonType::RightTrigger2),
+            ],
+            Action::Place => vec![
+                Mouse(MouseButton::Right),
+                Button(GamepadButtonType::LeftTrigger2),
+            ],
+            Action::OpenInventory => vec![Key(KeyCode::E), Button(GamepadButtonType::North)],
+            Action::ToggleGameMode => vec![Key(KeyCode::F4)],
+            Action::Chat => vec![Key(KeyCode::T)],
+            Action::Command => vec![Key(KeyCode::Slash)],
+            Action::ReleaseCursor => vec![Key(KeyCode::Escape)],
+            Action::Settings => vec![Key(KeyCode::F10), Button(GamepadButtonType::Start)],
+            Action::Worlds => vec![Key(KeyCode::F9)],
+            Action::HotbarNext => vec![Button(GamepadButtonType::RightTrigger)],
+            Action::HotbarPrevious => vec![Button(GamepadButtonType::LeftTrigger)],
+            Action::Hotbar(slot) => vec![Key(HOTBAR_KEYS[slot as usize])],
+        }
+    }
+}
+
+impl Binding {
+    pub fn parse(name: &str) -> Option<Binding> {
+        by_name(KEY_NAMES, name)
+            .map(Binding::Key)
+            .or_else(|| by_name(MOUSE_NAMES, name).map(Binding::Mouse))
+            .or_else(|| by_name(GAMEPAD_BUTTON_NAMES, name).map(Binding::GamepadButton))
+            .or_else(|| {
+                GAMEPAD_AXIS_NAMES
+                    .iter()
+                    .find(|(other, _, _)| *other == name)
+                    .map(|(_, axis, sign)| Binding::GamepadAxis(*axis, *sign))
+            })
+    }
+
+    pub fn name(&self) -> String {
+        let name = match *self {
+            Binding::Key(key) => name_of(KEY_NAMES, key),
+            Binding::Mouse(button) => name_of(MOUSE_NAMES, button),
+            Binding::GamepadButton(button) => name_of(GAMEPAD_BUTTON_NAMES, button),
+            Binding::GamepadAxis(axis, sign) => GAMEPAD_AXIS_NAMES
+                .iter()
+                .find(|(_, other, other_sign)| *other == axis && *other_sign == sign)
+                .map(|(name, _, _)| *name),
+        };
+        name.map_or_else(|| format!("{:?}", self), str::to_string)
+    }
+}
+
+fn by_name<T: Copy>(names: &[(&str, T)], name: &str) -> Option<T> {
+    names
+        .iter()
+        .find(|(other, _)| *other == name)
+        .map(|(_, value)| *value)
+}
+
+fn name_of<T: PartialEq>(names: &[(&'static str, T)], value: T) -> Option<&'static str> {
+    names
+        .iter()
+        .find(|(_, other)| *other == value)
+        .map(|(name, _)| *name)
+}
+
+/// The default bindings as stored in the settings file.
+pub fn default_controls() -> BTreeMap<String, Vec<String>> {
+    Action::ALL
+        .iter()
+        .map(|action| {
+            let bindings = action
+                .default_bindings()
+                .iter()
+                .map(Binding::name)
+                .collect();
+            (action.name(), bindings)
+        })
+        .collect()
+}
+
+impl InputMap {
+    /// Bindings from the `[controls]` table of the settings file, and warnings about it.
+    /// Actions missing from it keep their default bindings. Unknown names and inputs bound to
+    /// an action twice are skipped, inputs bound to several actions are kept but warned about.
+    pub fn from_controls(controls: &BTreeMap<String, Vec<String>>) -> (Self, Vec<String>) {
+        let mut bindings = HashMap::default();
+        let mut warnings = Vec::new();
+
+        for action in Action::ALL {
+            let names = match controls.get(&action.name()) {
+                Some(names) => names,
+                None => {
+                    bindings.insert(action, action.default_bindings());
+                    continue;
+                }
+            };
+            let mut action_bindings = Vec::new();
+            for name in names {
+                match Binding::parse(name) {
+                    Some(binding) if action_bindings.contains(&binding) => warnings.push(format!(
+                        "{} is bound to {} more than once",
+                        name,
+                        action.name()
+                    )),
+                    Some(binding) => action_bindings.push(binding),
+                    None => {
+                        warnings.push(format!("Unknown input {} bound to {}", name, action.name()))
+                    }
+                }
+            }
+            bindings.insert(action, action_bindings);
+        }
+        for name in controls.keys() {
+            if !Action::ALL.iter().any(|action| action.name() == *name) {
+                warnings.push(format!("Unknown action {} in the controls", name));
+            }
+        }
+
+        let input_map = InputMap { bindings };
+        // Each input is warned about once, with every action it is bound to
+        let mut warned = Vec::new();
+        for action in Action::ALL {
+            for binding in input_map.bindings(action) {
+                let actions: Vec<String> = Action::ALL
+                    .iter()
+                    .filter(|other| input_map.bindings(**other).contains(binding))
+                    .map(Action::name)
+                    .collect();
+                if actions.len() > 1 && !warned.contains(binding) {
+                    warned.push(*binding);
+                    warnings.push(format!(
+                        "{} is bound to {}",
+                        binding.name(),
+                        actions.join(", ")
+                    ));
+                }
+            }
+        }
+        (input_map, warnings)
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl Actions {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    /// How far the right sticks are pushed, from -1 to 1 along each axis with up and right
+    /// positive, zero within the dead zone.
+    pub fn look(&self) -> Vec2 {
+        self.look
+    }
+
+    /// Stops the press from being seen by the systems running after this one.
+    pub fn clear_just_pressed(&mut self, action: Action) {
+        self.just_pressed.remove(&action);
+    }
+}
+
+/// Works out the actions from the raw input of the keyboard, the mouse and every gamepad.
+pub fn update_actions(
+    input_map: Res<InputMap>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    mut actions: ResMut<Actions>,
+) {
+    let is_pressed = |binding: &Binding| match *binding {
+        Binding::Key(key) => keys.pressed(key),
+        Binding::Mouse(button) => mouse.pressed(button),
+        Binding::GamepadButton(button) => gamepads
+            .iter()
+            .any(|gamepad| gamepad_buttons.pressed(GamepadButton::new(*gamepad, button))),
+        Binding::GamepadAxis(axis, sign) => gamepads.iter().any(|gamepad| {
+            gamepad_axes
+                .get(GamepadAxis::new(*gamepad, axis))
+                .is_some_and(|value| value * sign > STICK_THRESHOLD)
+        }),
+    };
+    let pressed: HashSet<Action> = Action::ALL
+        .into_iter()
+        .filter(|action| input_map.bindings(*action).iter().any(&is_pressed))
+        .collect();
+
+    actions.just_pressed = pressed.difference(&actions.pressed).copied().collect();
+    actions.pressed = pressed;
+
+    let stick = |gamepad: Gamepad, axis| {
+        let value = gamepad_axes
+            .get(GamepadAxis::new(gamepad, axis))
+            .unwrap_or(0.0);
+        if value.abs() > STICK_DEAD_ZONE {
+            value
+        } else {
+            0.0
+        }
+    };
+    let look = gamepads.iter().fold(Vec2::ZERO, |look, gamepad| {
+        look + Vec2::new(
+            stick(*gamepad, GamepadAxisType::RightStickX),
+            stick(*gamepad, GamepadAxisType::RightStickY),
+        )
+    });
+    actions.look = look.clamp(Vec2::NEG_ONE, Vec2::ONE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controls(entries: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        entries
+            .iter()
+            .map(|(action, names)| {
+                let names = names.iter().map(|name| name.to_string()).collect();
+                (action.to_string(), names)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn default_controls_parse_back_without_warnings() {
+        let (input_map, warnings) = InputMap::from_controls(&default_controls());
+        assert_eq!(warnings, Vec::<String>::new());
+        for action in Action::ALL {
+            assert_eq!(input_map.bindings(action), action.default_bindings());
+        }
+    }
+
+    #[test]
+    fn controls_override_the_defaults() {
+        let (input_map, warnings) = InputMap::from_controls(&controls(&[
+            ("jump", &["Q", "MouseMiddle"]),
+            ("sneak", &[]),
+            ("move_forward", &["Up", "GamepadRightStickUp"]),
+        ]));
+        assert!(warnings.is_empty());
+        assert_eq!(
+            input_map.bindings(Action::Jump),
+            [
+                Binding::Key(KeyCode::Q),
+                Binding::Mouse(MouseButton::Middle)
+            ]
+        );
+        assert!(input_map.bindings(Action::Sneak).is_empty());
+        assert_eq!(
+            input_map.bindings(Action::MoveForward),
+            [
+                Binding::Key(KeyCode::Up),
+                Binding::GamepadAxis(GamepadAxisType::RightStickY, 1.0)
+            ]
+        );
+        // Missing actions keep their defaults
+        assert_eq!(
+            input_map.bindings(Action::Chat),
+            Action::Chat.default_bindings()
+        );
+    }
+
+    #[test]
+    fn unknown_names_are_skipped_with_a_warning() {
+        let (input_map, warnings) = InputMap::from_controls(&controls(&[
+            ("jump", &["Spacebar", "Space"]),
+            ("fly", &["F"]),
+        ]));
+        assert_eq!(
+            input_map.bindings(Action::Jump),
+            [Binding::Key(KeyCode::Space)]
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                "Unknown input Spacebar bound to jump".to_string(),
+                "Unknown action fly in the controls".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_bindings_are_warned_about() {
+        let (input_map, warnings) = InputMap::from_controls(&controls(&[
+            ("jump", &["Space", "Space"]),
+            ("chat", &["T", "F"]),
+            ("command", &["F"]),
+            ("worlds", &["F"]),
+        ]));
+        assert_eq!(
+            input_map.bindings(Action::Jump),
+            [Binding::Key(KeyCode::Space)]
+        );
+        // Inputs shared by actions still work for all of them
+        for action in [Action::Chat, Action::Command, Action::Worlds] {
+            assert!(input_map
+                .bindings(action)
+                .contains(&Binding::Key(KeyCode::F)));
+        }
+        assert_eq!(
+            warnings,
+            vec![
+                "Space is bound to jump more than once".to_string(),
+                "F is bound to chat, command, worlds".to_string(),
+            ]
+        );
+    }
+}
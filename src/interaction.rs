@@ -0,0 +1,257 @@
+use crate::block_models::BlockModels;
+use crate::block_types::{BlockState, Property, AXIS_X, AXIS_Y, AXIS_Z, HALF_TOP};
+use crate::input_map::{Action, Actions};
+use crate::inventory::{Inventory, ItemDrop};
+use crate::inventory_ui::InventoryScreen;
+use crate::player::{GameMode, EYE_HEIGHT, PLAYER_SIZE};
+use crate::voxel_data::FACE_CHECKS;
+use crate::voxel_map::VoxelMap;
+use crate::world::ChunkToRemeshQueue;
+use bevy::prelude::*;
+
+// Blocks the player can reach, measured from the camera
+pub const REACH: f32 = 6.0;
+
+/// Block hit by a ray, the face of it the ray entered through, indexed like `FACE_CHECKS`, and
+/// how far along the ray that was.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RaycastHit {
+    pub block: IVec3,
+    pub face: usize,
+    pub distance: f32,
+}
+
+impl RaycastHit {
+    /// Block in front of the hit face, where a placed block goes.
+    pub fn adjacent(&self) -> IVec3 {
+        self.block + FACE_CHECKS[self.face].as_ivec3()
+    }
+}
+
+/// Walks the blocks along a ray one boundary crossing at a time (Amanatides and Woo) and
+/// returns the first one for which `is_target` holds. Blocks are addressed by world position.
+pub fn raycast(
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    is_target: impl Fn(IVec3) -> bool,
+) -> Option<RaycastHit> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return None;
+    }
+    let mut block = origin.floor().as_ivec3();
+    let step = direction.signum().as_ivec3();
+    // Distance along the ray between two boundaries on each axis, and to the first one
+    let delta = direction.recip().abs();
+    let mut next = Vec3::ZERO;
+    for axis in 0..3 {
+        let boundary = if step[axis] > 0 {
+            block[axis] as f32 + 1.0
+        } else {
+            block[axis] as f32
+        };
+        next[axis] = if direction[axis] == 0.0 {
+            f32::INFINITY
+        } else {
+            (boundary - origin[axis]) / direction[axis]
+        };
+    }
+    // Faces entered when stepping in the positive and negative direction of each axis
+    let entered = [[0, 1], [3, 2], [5, 4]];
+
+    loop {
+        let axis = if next.x < next.y && next.x < next.z {
+            0
+        } else if next.y < next.z {
+            1
+        } else {
+            2
+        };
+        let distance = next[axis];
+        if distance > max_distance {
+            return None;
+        }
+        block[axis] += step[axis];
+        next[axis] += delta[axis];
+
+        if is_target(block) {
+            return Some(RaycastHit {
+                block,
+                face: entered[axis][(step[axis] < 0) as usize],
+                distance,
+            });
+        }
+    }
+}
+
+/// State of `block` placed against face `face` of another block by a player looking along
+/// `look`: fronts face the player, logs follow the axis of the face and slabs and stairs
+/// placed against the underside of a block or the upper half of a side go on top.
+pub fn placement_state(block: u8, face: usize, hit_height: f32, look: Vec3) -> BlockState {
+    let horizontal_facing = if look.x.abs() > look.z.abs() {
+        if look.x > 0.0 {
+            0
+        } else {
+            1
+        }
+    } else if look.z > 0.0 {
+        5
+    } else {
+        4
+    };
+    let axis = match face {
+        0 | 1 => AXIS_X,
+        2 | 3 => AXIS_Y,
+        _ => AXIS_Z,
+    };
+    let is_top = face == 3 || (face != 2 && hit_height > 0.5);
+
+    BlockState::new(block)
+        .with(Property::Facing, horizontal_facing)
+        .with(Property::Axis, axis)
+        .with(Property::Half, if is_top { HALF_TOP } else { 0 })
+}
+
+/// A block the player broke or placed, for the server to hear about.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockEdited {
+    pub block: IVec3,
+    pub state: BlockState,
+}
+
+/// Block being broken in survival and for how long it has been.
+pub struct BlockBreaking {
+    pub block: Option<IVec3>,
+    pub elapsed: f32,
+}
+
+impl Default for BlockBreaking {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockBreaking {
+    pub fn new() -> Self {
+        BlockBreaking {
+            block: None,
+            elapsed: 0.0,
+        }
+    }
+}
+
+/// Breaks the targeted block with the break action and places a block from the selected hotbar
+/// slot with the place action.
+///
+/// In creative, blocks that can be broken at all break on click without dropping anything and
+/// placing does not use up the stack. In survival, breaking has to be held on the same block
+/// for its break time, the block drops as an item, and blocks are not placed where they would
+/// overlap the player.
+#[allow(clippy::too_many_arguments)]
+pub fn edit_blocks(
+    actions: Res<Actions>,
+    windows: Res<Windows>,
+    time: Res<Time>,
+    inventory_screen: Res<InventoryScreen>,
+    mut voxel_map: ResMut<VoxelMap>,
+    block_models: Res<BlockModels>,
+    mut chunk_to_remesh_queue: ResMut<ChunkToRemeshQueue>,
+    mut block_breaking: ResMut<BlockBreaking>,
+    mut item_drops: EventWriter<ItemDrop>,
+    mut block_edits: EventWriter<BlockEdited>,
+    mut query: Query<(&GlobalTransform, &mut Inventory, &GameMode), With<super::Player>>,
+) {
+    let cursor_locked = windows
+        .get_primary()
+        .is_some_and(|window| window.cursor_locked());
+    let (transform, mut inventory, game_mode) = match query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    if !cursor_locked || inventory_screen.open || !actions.pressed(Action::Break) {
+        block_breaking.block = None;
+    }
+    if !cursor_locked || inventory_screen.open {
+        return;
+    }
+
+    let origin = transform.translation();
+    let look = transform.compute_matrix().transform_vector3(-Vec3::Z);
+    let hit = match raycast(origin, look, REACH, |block| {
+        voxel_map.get(block).block_type().textures.is_some()
+    }) {
+        Some(hit) => hit,
+        None => {
+            block_breaking.block = None;
+            return;
+        }
+    };
+
+    if actions.pressed(Action::Break) {
+        let state = voxel_map.get(hit.block);
+        let broken = match game_mode {
+            GameMode::Creative => {
+                actions.just_pressed(Action::Break) && state.block_type().break_time().is_some()
+            }
+            GameMode::Survival => {
+                if block_breaking.block != Some(hit.block) {
+                    block_breaking.block = Some(hit.block);
+                    block_breaking.elapsed = 0.0;
+                }
+                block_breaking.elapsed += time.delta_seconds();
+                state
+                    .block_type()
+                    .break_time()
+                    .is_some_and(|break_time| block_breaking.elapsed >= break_time)
+            }
+        };
+
+        if broken && voxel_map.set(hit.block, BlockState::AIR) {
+            block_breaking.block = None;
+            chunk_to_remesh_queue.push_block(hit.block);
+            block_edits.send(BlockEdited {
+                block: hit.block,
+                state: BlockState::AIR,
+            });
+            if *game_mode == GameMode::Survival {
+                item_drops.send(ItemDrop {
+                    block: state.block(),
+                    count: 1,
+                    position: hit.block.as_vec3() + Vec3::splat(0.5),
+                });
+            }
+        }
+    } else if actions.just_pressed(Action::Place) {
+        let target = hit.adjacent();
+        let block = match inventory.selected_stack() {
+            Some(stack) => stack.block,
+            None => return,
+        };
+        if voxel_map.get(target) != BlockState::AIR || target == origin.floor().as_ivec3() {
+            return;
+        }
+
+        // Height of the hit point within the block, to pick the half of slabs and stairs
+        let hit_height = (origin + look.normalize() * hit.distance).y - hit.block.y as f32;
+        let state = placement_state(block, hit.face, hit_height, look);
+
+        let feet = origin - Vec3::Y * EYE_HEIGHT;
+        if *game_mode == GameMode::Survival
+            && PLAYER_SIZE.intersects_block(feet, target, &block_models.collision_boxes(state))
+        {
+            return;
+        }
+
+        if voxel_map.set(target, state) {
+            if *game_mode == GameMode::Survival {
+                inventory.take_selected();
+            }
+            chunk_to_remesh_queue.push_block(target);
+            block_edits.send(BlockEdited {
+                block: target,
+                state,
+            });
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use crate::block_types::block_by_name;
+use crate::input_map::{Action, Actions};
+use bevy::input::mouse::MouseWheel;
+use bevy::prelude::*;
+
+pub const HOTBAR_SIZE: usize = 9;
+// Rows of storage above the hotbar in the inventory screen
+pub const STORAGE_ROWS: usize = 3;
+pub const INVENTORY_SIZE: usize = HOTBAR_SIZE * (STORAGE_ROWS + 1);
+pub const MAX_STACK_SIZE: u8 = 64;
+// Full stacks the player spawns with, filling the hotbar first
+pub const STARTING_BLOCKS: [&str; 14] = [
+    "stone",
+    "dirt",
+    "grass",
+    "planks",
+    "log",
+    "stone_slab",
+    "planks_stairs",
+    "furnace",
+    "torch",
+    "fence",
+    "tall_grass",
+    "rose",
+    "dandelion",
+    "water",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ItemStack {
+    pub block: u8,
+    pub count: u8,
+}
+
+/// Blocks dropped into the world, such as when broken in survival.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ItemDrop {
+    pub block: u8,
+    pub count: u8,
+    pub position: Vec3,
+}
+
+/// Slots `0..HOTBAR_SIZE` are the hotbar, the rest is storage.
+#[derive(Component, Clone, Debug, PartialEq, Eq)]
+pub struct Inventory {
+    pub slots: [Option<ItemStack>; INVENTORY_SIZE],
+    pub selected: usize,
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Inventory {
+            slots: [None; INVENTORY_SIZE],
+            selected: 0,
+        }
+    }
+
+    /// What new players start with, a full stack of each of `STARTING_BLOCKS`.
+    pub fn starting() -> Self {
+        let mut inventory = Inventory::new();
+        for block in STARTING_BLOCKS
+            .iter()
+            .filter_map(|name| block_by_name(name))
+        {
+            inventory.add(block, MAX_STACK_SIZE);
+        }
+        inventory
+    }
+
+    pub fn hotbar(&self) -> &[Option<ItemStack>] {
+        &self.slots[..HOTBAR_SIZE]
+    }
+
+    pub fn selected_stack(&self) -> Option<ItemStack> {
+        self.slots[self.selected]
+    }
+
+    pub fn select(&mut self, slot: usize) {
+        self.selected = slot.min(HOTBAR_SIZE - 1);
+    }
+
+    /// Moves the selection by `steps` hotbar slots, wrapping around at both ends.
+    pub fn scroll(&mut self, steps: i32) {
+        self.selected = (self.selected as i32 + steps).rem_euclid(HOTBAR_SIZE as i32) as usize;
+    }
+
+    /// Adds `count` blocks, topping up existing stacks before filling empty slots, hotbar first.
+    /// Returns how many did not fit.
+    pub fn add(&mut self, block: u8, mut count: u8) -> u8 {
+        for slot in self.slots.iter_mut().flatten() {
+            if count == 0 {
+                break;
+            }
+            if slot.block == block {
+                let moved = count.min(MAX_STACK_SIZE - slot.count);
+                slot.count += moved;
+                count -= moved;
+            }
+        }
+        for slot in self.slots.iter_mut() {
+            if count == 0 {
+                break;
+            }
+            if slot.is_none() {
+                let moved = count.min(MAX_STACK_SIZE);
+                *slot = Some(ItemStack {
+                    block,
+                    count: moved,
+                });
+                count -= moved;
+            }
+        }
+        count
+    }
+
+    /// Takes one block from the selected slot, emptying it when it was the last.
+    pub fn take_selected(&mut self) -> Option<u8> {
+        let slot = &mut self.slots[self.selected];
+        let stack = slot.as_mut()?;
+        let block = stack.block;
+
+        stack.count -= 1;
+        if stack.count == 0 {
+            *slot = None;
+        }
+        Some(block)
+    }
+
+    pub fn contains(&self, block: u8) -> bool {
+        self.slots
+            .iter()
+            .flatten()
+            .any(|stack| stack.block == block)
+    }
+
+    /// Takes one `block` from the first slot holding it. Returns false if there is none.
+    pub fn take(&mut self, block: u8) -> bool {
+        for slot in self.slots.iter_mut() {
+            if let Some(stack) = slot.as_mut().filter(|stack| stack.block == block) {
+                stack.count -= 1;
+                if stack.count == 0 {
+                    *slot = None;
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Moves the stack in slot `from` onto slot `to`, merging as much as fits when both hold
+    /// the same block and swapping them otherwise.
+    pub fn move_stack(&mut self, from: usize, to: usize) {
+        if from == to {
+            return;
+        }
+        match (self.slots[from], self.slots[to]) {
+            (Some(source), Some(mut target)) if source.block == target.block => {
+                let moved = source.count.min(MAX_STACK_SIZE - target.count);
+                target.count += moved;
+                self.slots[to] = Some(target);
+                self.slots[from] = if moved == source.count {
+                    None
+                } else {
+                    Some(ItemStack {
+                        count: source.count - moved,
+                        ..source
+                    })
+                };
+            }
+            _ => self.slots.swap(from, to),
+        }
+    }
+
+    /// Moves half of the stack in slot `from`, rounded up, onto slot `to` when it is empty or
+    /// holds the same block, as much as fits.
+    pub fn split_stack(&mut self, from: usize, to: usize) {
+        let source = match self.slots[from] {
+            Some(source) if from != to => source,
+            _ => return,
+        };
+        let space = match self.slots[to] {
+            None => MAX_STACK_SIZE,
+            Some(target) if target.block == source.block => MAX_STACK_SIZE - target.count,
+            Some(_) => return,
+        };
+        let moved = source.count.div_ceil(2).min(space);
+        if moved == 0 {
+            return;
+        }
+
+        let target = self.slots[to].map_or(0, |target| target.count);
+        self.slots[to] = Some(ItemStack {
+            count: target + moved,
+            ..source
+        });
+        self.slots[from] = if moved == source.count {
+            None
+        } else {
+            Some(ItemStack {
+                count: source.count - moved,
+                ..source
+            })
+        };
+    }
+}
+
+/// Hotbar selection with the hotbar actions and the mouse wheel, while the inventory screen and
+/// the console are closed.
+pub fn select_hotbar_slot(
+    actions: Res<Actions>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    inventory_screen: Res<crate::inventory_ui::InventoryScreen>,
+    console: Res<crate::console::Console>,
+    mut query: Query<&mut Inventory, With<super::Player>>,
+) {
+    let scroll: f32 = mouse_wheel.iter().map(|event| event.y).sum();
+    if inventory_screen.open || console.open {
+        return;
+    }
+    let mut inventory = match query.get_single_mut() {
+        Ok(inventory) => inventory,
+        Err(_) => return,
+    };
+
+    if let Some(slot) =
+        (0..HOTBAR_SIZE).find(|slot| actions.just_pressed(Action::Hotbar(*slot as u8)))
+    {
+        inventory.select(slot);
+    }
+    // Scrolling down moves to the right, like in most games
+    if scroll != 0.0 {
+        inventory.scroll(-scroll.signum() as i32);
+    }
+    if actions.just_pressed(Action::HotbarNext) {
+        inventory.scroll(1);
+    }
+    if actions.just_pressed(Action::HotbarPrevious) {
+        inventory.scroll(-1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(block: u8, count: u8) -> Option<ItemStack> {
+        Some(ItemStack { block, count })
+    }
+
+    #[test]
+    fn adding_tops_up_stacks_before_empty_slots() {
+        let mut inventory = Inventory::new();
+        inventory.slots[3] = stack(1, 60);
+        assert_eq!(inventory.add(1, 10), 0);
+        assert_eq!(inventory.slots[3], stack(1, MAX_STACK_SIZE));
+        assert_eq!(inventory.slots[0], stack(1, 6));
+    }
+
+    #[test]
+    fn adding_splits_into_full_stacks() {
+        let mut inventory = Inventory::new();
+        assert_eq!(inventory.add(2, 150), 0);
+        assert_eq!(inventory.slots[0], stack(2, 64));
+        assert_eq!(inventory.slots[1], stack(2, 64));
+        assert_eq!(inventory.slots[2], stack(2, 22));
+    }
+
+    #[test]
+    fn adding_to_a_full_inventory_returns_the_overflow() {
+        let mut inventory = Inventory::new();
+        inventory.slots = [stack(1, MAX_STACK_SIZE); INVENTORY_SIZE];
+        inventory.slots[5] = stack(2, 60);
+        assert_eq!(inventory.add(2, 10), 6);
+        assert_eq!(inventory.slots[5], stack(2, MAX_STACK_SIZE));
+        assert_eq!(inventory.add(3, 1), 1);
+    }
+
+    #[test]
+    fn taking_the_last_block_empties_the_slot() {
+        let mut inventory = Inventory::new();
+        inventory.slots[0] = stack(1, 2);
+        assert_eq!(inventory.take_selected(), Some(1));
+        assert_eq!(inventory.slots[0], stack(1, 1));
+        assert_eq!(inventory.take_selected(), Some(1));
+        assert_eq!(inventory.slots[0], None);
+        assert_eq!(inventory.take_selected(), None);
+    }
+
+    #[test]
+    fn taking_a_block_uses_the_first_stack_of_it() {
+        let mut inventory = Inventory::new();
+        inventory.slots[2] = stack(1, 1);
+        inventory.slots[7] = stack(1, 5);
+        assert!(inventory.take(1));
+        assert_eq!(inventory.slots[2], None);
+        assert!(inventory.contains(1));
+        assert_eq!(inventory.slots[7], stack(1, 5));
+        assert!(!inventory.take(2));
+        assert!(!inventory.contains(2));
+    }
+
+    #[test]
+    fn moving_merges_the_same_block() {
+        let mut inventory = Inventory::new();
+        inventory.slots[0] = stack(1, 40);
+        inventory.slots[1] = stack(1, 40);
+        inventory.move_stack(0, 1);
+        assert_eq!(inventory.slots[1], stack(1, MAX_STACK_SIZE));
+        assert_eq!(inventory.slots[0], stack(1, 16));
+    }
+
+    #[test]
+    fn moving_swaps_different_blocks_and_empty_slots() {
+        let mut inventory = Inventory::new();
+        inventory.slots[0] = stack(1, 5);
+        inventory.slots[1] = stack(2, 7);
+        inventory.move_stack(0, 1);
+        assert_eq!(inventory.slots[0], stack(2, 7));
+        assert_eq!(inventory.slots[1], stack(1, 5));
+
+        inventory.move_stack(1, 20);
+        assert_eq!(inventory.slots[1], None);
+        assert_eq!(inventory.slots[20], stack(1, 5));
+    }
+
+    #[test]
+    fn splitting_moves_half_rounded_up() {
+        let mut inventory = Inventory::new();
+        inventory.slots[0] = stack(1, 5);
+        inventory.split_stack(0, 1);
+        assert_eq!(inventory.slots[0], stack(1, 2));
+        assert_eq!(inventory.slots[1], stack(1, 3));
+
+        inventory.slots[2] = stack(1, 1);
+        inventory.split_stack(2, 3);
+        assert_eq!(inventory.slots[2], None);
+        assert_eq!(inventory.slots[3], stack(1, 1));
+    }
+
+    #[test]
+    fn splitting_onto_a_stack_moves_what_fits() {
+        let mut inventory = Inventory::new();
+        inventory.slots[0] = stack(1, 20);
+        inventory.slots[1] = stack(1, 60);
+        inventory.split_stack(0, 1);
+        assert_eq!(inventory.slots[0], stack(1, 16));
+        assert_eq!(inventory.slots[1], stack(1, MAX_STACK_SIZE));
+
+        inventory.slots[2] = stack(2, 1);
+        inventory.split_stack(0, 2);
+        assert_eq!(inventory.slots[0], stack(1, 16));
+        assert_eq!(inventory.slots[2], stack(2, 1));
+    }
+
+    #[test]
+    fn selection_wraps_around_the_hotbar() {
+        let mut inventory = Inventory::new();
+        inventory.scroll(-1);
+        assert_eq!(inventory.selected, HOTBAR_SIZE - 1);
+        inventory.scroll(2);
+        assert_eq!(inventory.selected, 1);
+        inventory.select(INVENTORY_SIZE);
+        assert_eq!(inventory.selected, HOTBAR_SIZE - 1);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::block_textures::BlockTextures;
+use crate::block_types::BLOCKTYPES;
+use crate::console::Console;
+use crate::input_map::{Action, Actions};
+use crate::inventory::{Inventory, ItemStack, HOTBAR_SIZE, INVENTORY_SIZE};
+use crate::world_select::WorldSelectScreen;
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Align2, Color32, FontId, Stroke};
+use bevy_egui::EguiContext;
+
+pub const SLOT_SIZE: f32 = 40.0;
+
+/// Whether the inventory screen is shown, and the slot picked up to be moved, if any.
+pub struct InventoryScreen {
+    pub open: bool,
+    held: Option<usize>,
+    atlas: Option<egui::TextureId>,
+}
+
+impl Default for InventoryScreen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InventoryScreen {
+    pub fn new() -> Self {
+        InventoryScreen {
+            open: false,
+            held: None,
+            atlas: None,
+        }
+    }
+}
+
+/// Opens and closes the inventory screen, freeing the cursor while it is open.
+pub fn toggle_inventory_screen(
+    actions: Res<Actions>,
+    mut inventory_screen: ResMut<InventoryScreen>,
+    console: Res<Console>,
+    world_select_screen: Res<WorldSelectScreen>,
+    mut windows: ResMut<Windows>,
+) {
+    if !actions.just_pressed(Action::OpenInventory) || console.open || world_select_screen.open {
+        return;
+    }
+    inventory_screen.open = !inventory_screen.open;
+    inventory_screen.held = None;
+
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(!inventory_screen.open);
+        window.set_cursor_visibility(inventory_screen.open);
+    }
+}
+
+/// Draws the hotbar, and the whole inventory when the screen is open. Clicking a slot picks its
+/// stack up and clicking another one moves it there.
+pub fn draw_inventory(
+    mut egui_context: ResMut<EguiContext>,
+    mut inventory_screen: ResMut<InventoryScreen>,
+    block_textures: Res<BlockTextures>,
+    mut query: Query<&mut Inventory, With<super::Player>>,
+) {
+    let mut inventory = match query.get_single_mut() {
+        Ok(inventory) => inventory,
+        Err(_) => return,
+    };
+    if inventory_screen.atlas.is_none() {
+        if let Some(atlas) = block_textures.atlas.as_ref() {
+            inventory_screen.atlas = Some(egui_context.add_image(atlas.clone_weak()));
+        }
+    }
+    let atlas = inventory_screen.atlas;
+    let ctx = egui_context.ctx_mut();
+
+    egui::Area::new("hotbar")
+        .anchor(Align2::CENTER_BOTTOM, [0.0, -10.0])
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                for (slot, stack) in inventory.hotbar().iter().enumerate() {
+                    draw_slot(
+                        ui,
+                        atlas,
+                        &block_textures,
+                        *stack,
+                        slot == inventory.selected,
+                    );
+                }
+            });
+        });
+
+    if !inventory_screen.open {
+        return;
+    }
+    let mut clicked = None;
+    let mut right_clicked = None;
+    egui::Window::new("Inventory")
+        .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            // Storage rows on top, the hotbar at the bottom like on screen
+            let rows = (HOTBAR_SIZE..INVENTORY_SIZE)
+                .step_by(HOTBAR_SIZE)
+                .chain(std::iter::once(0));
+            for row_start in rows {
+                ui.horizontal(|ui| {
+                    for slot in row_start..row_start + HOTBAR_SIZE {
+                        let response = draw_slot(
+                            ui,
+                            atlas,
+                            &block_textures,
+                            inventory.slots[slot],
+                            inventory_screen.held == Some(slot),
+                        );
+                        if response.clicked() {
+                            clicked = Some(slot);
+                        } else if response.secondary_clicked() {
+                            right_clicked = Some(slot);
+                        }
+                    }
+                });
+            }
+        });
+
+    if let Some(slot) = clicked {
+        match inventory_screen.held.take() {
+            Some(held) => inventory.move_stack(held, slot),
+            None if inventory.slots[slot].is_some() => inventory_screen.held = Some(slot),
+            None => (),
+        }
+    }
+    // Right clicking drops half of the held stack there and keeps holding the rest
+    if let (Some(held), Some(slot)) = (inventory_screen.held, right_clicked) {
+        inventory.split_stack(held, slot);
+        if inventory.slots[held].is_none() {
+            inventory_screen.held = None;
+        }
+    }
+}
+
+fn draw_slot(
+    ui: &mut egui::Ui,
+    atlas: Option<egui::TextureId>,
+    block_textures: &BlockTextures,
+    stack: Option<ItemStack>,
+    highlighted: bool,
+) -> egui::Response {
+    let (rect, response) =
+        ui.allocate_exact_size(egui::vec2(SLOT_SIZE, SLOT_SIZE), egui::Sense::click());
+    let painter = ui.painter();
+
+    painter.rect_filled(rect, 2.0, Color32::from_black_alpha(150));
+    if highlighted {
+        painter.rect_stroke(rect, 2.0, Stroke::new(2.0, Color32::WHITE));
+    }
+    if let Some(stack) = stack {
+        if let (Some(atlas), Some(uv)) = (atlas, icon_uv(block_textures, stack.block)) {
+            painter.add(egui::Shape::image(
+                atlas,
+                rect.shrink(6.0),
+                uv,
+                Color32::WHITE,
+            ));
+        }
+        if stack.count > 1 {
+            painter.text(
+                rect.right_bottom() - egui::vec2(3.0, 1.0),
+                Align2::RIGHT_BOTTOM,
+                stack.count.to_string(),
+                FontId::proportional(14.0),
+                Color32::WHITE,
+            );
+        }
+    }
+    response
+}
+
+/// Atlas rectangle of the front texture of a block, used as its icon.
+fn icon_uv(block_textures: &BlockTextures, block: u8) -> Option<egui::Rect> {
+    let name = BLOCKTYPES[block as usize].textures?[0];
+    let rect = block_textures.atlas_rects.get(name)?;
+
+    Some(egui::Rect::from_min_max(
+        egui::pos2(rect.min.x, rect.min.y),
+        egui::pos2(rect.max.x, rect.max.y),
+    ))
+}
@@ -0,0 +1,224 @@
+use crate::block_models::BlockModels;
+use crate::block_textures::BlockTextures;
+use crate::chunk::MaterialHandle;
+use crate::inventory::{Inventory, ItemDrop, ItemStack, MAX_STACK_SIZE};
+use crate::mesh;
+use crate::physics::{self, Aabb, GRAVITY, TERMINAL_VELOCITY};
+use crate::player::EYE_HEIGHT;
+use crate::shadows::VoxelShadowCaster;
+use crate::voxel_map::VoxelMap;
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+pub const ITEM_SIZE: Aabb = Aabb {
+    half_width: 0.125,
+    height: 0.25,
+};
+// Seconds before a dropped item can be picked up, and before it disappears
+pub const PICKUP_DELAY: f32 = 0.5;
+pub const DESPAWN_TIME: f32 = 300.0;
+// Distance in blocks from the middle of the player's body within which items are picked up
+pub const PICKUP_RADIUS: f32 = 1.5;
+// Distance in blocks within which identical items merge into one stack
+pub const MERGE_RADIUS: f32 = 1.0;
+// Blocks per second an item pops out with when dropped
+pub const DROP_SPEED: f32 = 3.0;
+// Radians per second
+pub const SPIN_SPEED: f32 = 1.5;
+// Fraction of horizontal speed kept per second while on the ground
+const GROUND_FRICTION: f32 = 0.02;
+
+/// A stack lying in the world. Its position is the bottom centre of its cube.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct DroppedItem {
+    pub stack: ItemStack,
+    pub velocity: Vec3,
+    pub age: f32,
+}
+
+/// One cube mesh per block id, shared by all items of that block.
+pub struct ItemMeshes {
+    meshes: HashMap<u8, Handle<Mesh>>,
+    // Dropped so far, to spread the directions items pop out in
+    drop_count: u32,
+}
+
+impl Default for ItemMeshes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ItemMeshes {
+    pub fn new() -> Self {
+        ItemMeshes {
+            meshes: HashMap::default(),
+            drop_count: 0,
+        }
+    }
+}
+
+pub fn spawn_dropped_items(
+    mut commands: Commands,
+    mut item_drops: EventReader<ItemDrop>,
+    mut item_meshes: ResMut<ItemMeshes>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    material_handle: Res<MaterialHandle>,
+    block_textures: Res<BlockTextures>,
+) {
+    for drop in item_drops.iter() {
+        let mesh = item_meshes
+            .meshes
+            .entry(drop.block)
+            .or_insert_with(|| meshes.add(mesh::create_item_mesh(drop.block, &block_textures)))
+            .clone();
+        // Golden angle steps, so consecutive drops fly apart
+        let angle = item_meshes.drop_count as f32 * 2.399_963;
+        item_meshes.drop_count += 1;
+        let velocity = Vec3::new(angle.cos() * 0.3, 1.0, angle.sin() * 0.3) * DROP_SPEED;
+
+        commands
+            .spawn_bundle(SpatialBundle {
+                transform: Transform::from_translation(
+                    drop.position - Vec3::Y * ITEM_SIZE.height / 2.0,
+                ),
+                ..Default::default()
+            })
+            .insert(DroppedItem {
+                stack: ItemStack {
+                    block: drop.block,
+                    count: drop.count,
+                },
+                velocity,
+                age: 0.0,
+            })
+            .insert(Name::new("Dropped item"))
+            .with_children(|parent| {
+                // The mesh spans a whole block from its corner, so it is scaled down and
+                // centred on the item
+                parent
+                    .spawn_bundle(MaterialMeshBundle {
+                        mesh,
+                        material: material_handle.0.clone(),
+                        transform: Transform::from_xyz(
+                            -ITEM_SIZE.half_width,
+                            0.0,
+                            -ITEM_SIZE.half_width,
+                        )
+                        .with_scale(Vec3::splat(ITEM_SIZE.height)),
+                        ..Default::default()
+                    })
+                    .insert(NotShadowCaster)
+                    .insert(VoxelShadowCaster::covering(Vec3::ONE));
+            });
+    }
+}
+
+/// Lets items fall and slide to a stop, spinning in place, and despawns them once too old.
+pub fn update_dropped_items(
+    mut commands: Commands,
+    time: Res<Time>,
+    voxel_map: Res<VoxelMap>,
+    block_models: Res<BlockModels>,
+    mut query: Query<(Entity, &mut DroppedItem, &mut Transform)>,
+) {
+    let delta = time.delta_seconds();
+
+    for (entity, mut item, mut transform) in query.iter_mut() {
+        item.age += delta;
+        if item.age >= DESPAWN_TIME {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        item.velocity.y = (item.velocity.y - GRAVITY * delta).max(-TERMINAL_VELOCITY);
+        let collision = physics::move_and_collide(
+            transform.translation,
+            item.velocity * delta,
+            ITEM_SIZE,
+            |block| block_models.collision_boxes(voxel_map.get(block)),
+        );
+        transform.translation = collision.position;
+
+        for axis in 0..3 {
+            if collision.blocked[axis] {
+                item.velocity[axis] = 0.0;
+            }
+        }
+        if collision.on_ground {
+            let friction = GROUND_FRICTION.powf(delta);
+            item.velocity.x *= friction;
+            item.velocity.z *= friction;
+        }
+        transform.rotate(Quat::from_rotation_y(SPIN_SPEED * delta));
+    }
+}
+
+/// Merges items of the same block lying close together into the older one, as long as the
+/// stack does not grow past its maximum size.
+pub fn merge_dropped_items(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut DroppedItem, &Transform)>,
+) {
+    let mut items: Vec<(Entity, ItemStack, f32, Vec3)> = query
+        .iter()
+        .map(|(entity, item, transform)| (entity, item.stack, item.age, transform.translation))
+        .collect();
+    // Oldest first, so they absorb the newer ones
+    items.sort_by(|a, b| b.2.total_cmp(&a.2));
+    let mut merged = HashSet::default();
+
+    for i in 0..items.len() {
+        if merged.contains(&items[i].0) {
+            continue;
+        }
+        for j in i + 1..items.len() {
+            let (other, other_stack, _, other_position) = items[j];
+            if merged.contains(&other)
+                || other_stack.block != items[i].1.block
+                || items[i].1.count as u32 + other_stack.count as u32 > MAX_STACK_SIZE as u32
+                || items[i].3.distance(other_position) > MERGE_RADIUS
+            {
+                continue;
+            }
+            items[i].1.count += other_stack.count;
+            merged.insert(other);
+        }
+    }
+
+    for (entity, stack, _, _) in items.iter() {
+        if merged.contains(entity) {
+            commands.entity(*entity).despawn_recursive();
+        } else if let Ok((_, mut item, _)) = query.get_mut(*entity) {
+            if item.stack != *stack {
+                item.stack = *stack;
+            }
+        }
+    }
+}
+
+/// Moves items close to the player into their inventory, leaving what does not fit.
+pub fn pick_up_items(
+    mut commands: Commands,
+    mut player_query: Query<(&GlobalTransform, &mut Inventory), With<super::Player>>,
+    mut item_query: Query<(Entity, &mut DroppedItem, &Transform)>,
+) {
+    let (player_transform, mut inventory) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let body = player_transform.translation() - Vec3::Y * EYE_HEIGHT / 2.0;
+
+    for (entity, mut item, transform) in item_query.iter_mut() {
+        if item.age < PICKUP_DELAY || transform.translation.distance(body) > PICKUP_RADIUS {
+            continue;
+        }
+        let left = inventory.add(item.stack.block, item.stack.count);
+        if left == 0 {
+            commands.entity(entity).despawn_recursive();
+        } else if left != item.stack.count {
+            item.stack.count = left;
+        }
+    }
+}
@@ -0,0 +1,615 @@
+use crate::anvil::{self, ImportOptions};
+use crate::migration::{self, LEVEL_MIGRATIONS};
+use crate::region::REGION_DIR;
+use crate::save::{self, DEFAULT_WORLD, PLAYER_FILE};
+use crate::voxel_map::WORLD_SEED;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use toml::value::{Table, Value};
+
+pub const LEVEL_FILE: &str = "level.toml";
+// Bumped whenever what is saved in a world changes, so newer saves are not misread, see
+// `migration`
+pub const LEVEL_FORMAT_VERSION: u32 = 2;
+// The only terrain generator there is, see `voxel_map::TerrainGenerator`
+pub const DEFAULT_GENERATOR: &str = "default";
+// TOML integers are signed, so seeds stay below 2^63
+pub const MAX_SEED: u64 = i64::MAX as u64;
+
+/// What describes a world, kept in `level.toml` next to the rest of its save.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Level {
+    pub name: String,
+    pub seed: u64,
+    /// Id of the terrain generator.
+    pub generator: String,
+    /// Unix timestamps in seconds.
+    pub created: u64,
+    pub last_played: u64,
+    pub format_version: u32,
+    /// Camera position new players start at. The terrain generator picks one if there is none.
+    #[serde(default)]
+    pub spawn: Option<[f32; 3]>,
+    /// Options only the generator understands. Worlds are not opened with settings their
+    /// generator does not know, as it would not generate them as they were meant to be.
+    #[serde(default)]
+    pub generator_settings: BTreeMap<String, String>,
+    #[serde(default)]
+    pub game_rules: GameRules,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameRules {
+    /// Whether the sun moves on its own.
+    pub daylight_cycle: bool,
+    pub fall_damage: bool,
+}
+
+/// A world in the saves directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorldEntry {
+    pub dir: PathBuf,
+    pub level: Level,
+}
+
+/// Options `WorldCommand::from_args` reads, and how many values follow each.
+pub const WORLD_OPTIONS: [(&str, usize); 10] = [
+    ("--world", 1),
+    ("--new-world", 1),
+    ("--seed", 1),
+    ("--list-worlds", 0),
+    ("--rename-world", 2),
+    ("--duplicate-world", 2),
+    ("--delete-world", 1),
+    ("--import-anvil", 1),
+    ("--block-mapping", 1),
+    ("--y-offset", 1),
+];
+
+/// What the command line asks for, see `WorldCommand::from_args`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WorldCommand {
+    /// Play the named world, or the one played last.
+    Play(Option<String>),
+    /// Create a world and play it.
+    Create {
+        name: String,
+        seed: Option<u64>,
+    },
+    List,
+    Rename {
+        world: String,
+        name: String,
+    },
+    Duplicate {
+        world: String,
+        name: String,
+    },
+    Delete {
+        world: String,
+    },
+    /// Import a Minecraft world and play it.
+    ImportAnvil {
+        source: PathBuf,
+        options: ImportOptions,
+    },
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        GameRules {
+            daylight_cycle: true,
+            fall_damage: true,
+        }
+    }
+}
+
+impl Level {
+    pub fn new(name: &str, seed: u64) -> Self {
+        let now = unix_time();
+        Level {
+            name: name.to_string(),
+            seed,
+            generator: DEFAULT_GENERATOR.to_string(),
+            created: now,
+            last_played: now,
+            format_version: LEVEL_FORMAT_VERSION,
+            spawn: None,
+            generator_settings: BTreeMap::new(),
+            game_rules: GameRules::default(),
+        }
+    }
+
+    /// The level of the world saved in `dir`, upgraded if it was saved in an older format.
+    /// Levels from newer versions are read as they are, see `open_world`.
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let path = dir.join(LEVEL_FILE);
+        let text = fs::read_to_string(&path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+        let mut table: Table = toml::from_str(&text)
+            .map_err(|error| format!("Invalid {}: {}", path.display(), error))?;
+
+        let version = table
+            .get("format_version")
+            .and_then(|version| version.as_integer())
+            .unwrap_or(1) as u32;
+        if version < LEVEL_FORMAT_VERSION {
+            migration::migrate(&mut table, version, LEVEL_FORMAT_VERSION, LEVEL_MIGRATIONS)
+                .map_err(|error| format!("Could not upgrade {}: {}", path.display(), error))?;
+            table.insert(
+                "format_version".to_string(),
+                Value::Integer(LEVEL_FORMAT_VERSION as i64),
+            );
+        }
+        Value::Table(table)
+            .try_into()
+            .map_err(|error| format!("Invalid {}: {}", path.display(), error))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|error| error.to_string())?;
+        save::write_atomic(&dir.join(LEVEL_FILE), text.as_bytes())
+    }
+}
+
+impl WorldEntry {
+    /// Name of the world's directory, which is what worlds are opened by.
+    pub fn id(&self) -> String {
+        self.dir
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+    }
+}
+
+impl WorldCommand {
+    /// Reads `--world <world>`, `--new-world <name>` with an optional `--seed <seed>`,
+    /// `--list-worlds`, `--rename-world <world> <name>`, `--duplicate-world <world> <name>` and
+    /// `--delete-world <world>` and `--import-anvil <minecraft world>` with an optional
+    /// `--block-mapping <file>` and `--y-offset <blocks>`. Worlds are named by their directory in
+    /// the saves directory. Unknown options are left for others to handle.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut command = WorldCommand::Play(None);
+        let mut seed = None;
+        let mut import_options = ImportOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--world" => command = WorldCommand::Play(Some(value()?)),
+                "--new-world" => {
+                    command = WorldCommand::Create {
+                        name: value()?,
+                        seed: None,
+                    }
+                }
+                "--seed" => seed = Some(parse_seed(&value()?)?),
+                "--list-worlds" => command = WorldCommand::List,
+                "--rename-world" => {
+                    command = WorldCommand::Rename {
+                        world: value()?,
+                        name: value()?,
+                    }
+                }
+                "--duplicate-world" => {
+                    command = WorldCommand::Duplicate {
+                        world: value()?,
+                        name: value()?,
+                    }
+                }
+                "--delete-world" => command = WorldCommand::Delete { world: value()? },
+                "--import-anvil" => {
+                    command = WorldCommand::ImportAnvil {
+                        source: PathBuf::from(value()?),
+                        options: ImportOptions::default(),
+                    }
+                }
+                "--block-mapping" => import_options.mapping = Some(PathBuf::from(value()?)),
+                "--y-offset" => {
+                    let offset = value()?;
+                    import_options.y_offset = offset
+                        .parse()
+                        .map_err(|_| format!("Invalid height offset {}", offset))?;
+                }
+                _ => (),
+            }
+        }
+        match &mut command {
+            WorldCommand::Create { seed: new_seed, .. } => *new_seed = seed,
+            WorldCommand::ImportAnvil { options, .. } => *options = import_options,
+            _ => (),
+        }
+        Ok(command)
+    }
+
+    /// Carries out the command, printing what was done. Returns the world to play, if the
+    /// command is one that plays a world.
+    pub fn run(self, saves: &Path) -> Result<Option<(PathBuf, Level)>, String> {
+        match self {
+            WorldCommand::Play(Some(world)) => {
+                let dir = world_dir(saves, &world)?;
+                let level = open_world(&dir)?;
+                Ok(Some((dir, level)))
+            }
+            WorldCommand::Play(None) => {
+                // Straight into the world played last, so just starting the game keeps working
+                let dir = match list_worlds(saves).first() {
+                    Some(entry) => entry.dir.clone(),
+                    None if saves.join(DEFAULT_WORLD).is_dir() => saves.join(DEFAULT_WORLD),
+                    None => create_world(saves, DEFAULT_WORLD, random_seed())?,
+                };
+                let level = open_world(&dir)?;
+                Ok(Some((dir, level)))
+            }
+            WorldCommand::Create { name, seed } => {
+                let dir = create_world(saves, &name, seed.unwrap_or_else(random_seed))?;
+                println!("Created {} in {}", name, dir.display());
+                let level = open_world(&dir)?;
+                Ok(Some((dir, level)))
+            }
+            WorldCommand::List => {
+                let worlds = list_worlds(saves);
+                if worlds.is_empty() {
+                    println!("There are no worlds in {}", saves.display());
+                }
+                for entry in worlds {
+                    println!(
+                        "{} - {}, seed {}, last played {}",
+                        entry.id(),
+                        entry.level.name,
+                        entry.level.seed,
+                        format_time(entry.level.last_played)
+                    );
+                }
+                Ok(None)
+            }
+            WorldCommand::Rename { world, name } => {
+                rename_world(&world_dir(saves, &world)?, &name)?;
+                println!("Renamed {} to {}", world, name);
+                Ok(None)
+            }
+            WorldCommand::Duplicate { world, name } => {
+                let dir = duplicate_world(saves, &world_dir(saves, &world)?, &name)?;
+                println!("Copied {} to {}", world, dir.display());
+                Ok(None)
+            }
+            WorldCommand::Delete { world } => {
+                delete_world(&world_dir(saves, &world)?)?;
+                println!("Deleted {}", world);
+                Ok(None)
+            }
+            WorldCommand::ImportAnvil { source, options } => {
+                let (dir, summary) = anvil::import_world(saves, &source, &options)?;
+                for warning in summary.warnings.iter() {
+                    eprintln!("{}", warning);
+                }
+                println!(
+                    "Imported {} chunks of {} into {}, skipped {}",
+                    summary.chunks,
+                    source.display(),
+                    dir.display(),
+                    summary.skipped
+                );
+                for (name, count) in summary.unknown.iter() {
+                    println!(
+                        "Replaced {} with the placeholder in {} sections",
+                        name, count
+                    );
+                }
+                let level = open_world(&dir)?;
+                Ok(Some((dir, level)))
+            }
+        }
+    }
+}
+
+/// The directory of `world` in the saves directory. Worlds are named by a single directory, so
+/// nothing outside the saves directory can be opened, renamed or deleted through them.
+pub fn world_dir(saves: &Path, world: &str) -> Result<PathBuf, String> {
+    let mut components = Path::new(world).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(saves.join(world)),
+        _ => Err(format!("{} is not a world in {}", world, saves.display())),
+    }
+}
+
+/// Seeds are numbers between 0 and `MAX_SEED`.
+pub fn parse_seed(text: &str) -> Result<u64, String> {
+    match text.trim().parse::<u64>() {
+        Ok(seed) if seed <= MAX_SEED => Ok(seed),
+        _ => Err(format!(
+            "Invalid seed {}, seeds go from 0 to {}",
+            text, MAX_SEED
+        )),
+    }
+}
+
+pub fn random_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64);
+    // Spread the clock's low bits over the whole seed
+    nanos.wrapping_mul(0x9e37_79b9_7f4a_7c15) % MAX_SEED
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// A unix timestamp as a UTC date and time.
+pub fn format_time(timestamp: u64) -> String {
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+    // Howard Hinnant's days_from_civil in reverse
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60
+    )
+}
+
+/// Every world with a readable level, the one played last first.
+pub fn list_worlds(saves: &Path) -> Vec<WorldEntry> {
+    let entries = match fs::read_dir(saves) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut worlds: Vec<WorldEntry> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|dir| dir.join(LEVEL_FILE).is_file())
+        .filter_map(|dir| match Level::load(&dir) {
+            Ok(level) => Some(WorldEntry { dir, level }),
+            Err(error) => {
+                eprintln!("{}", error);
+                None
+            }
+        })
+        .collect();
+    worlds.sort_by_key(|entry| Reverse(entry.level.last_played));
+    worlds
+}
+
+/// Creates a world in a new directory named after it, returning the directory.
+pub fn create_world(saves: &Path, name: &str, seed: u64) -> Result<PathBuf, String> {
+    let dir = unused_dir(saves, name);
+    Level::new(name, seed).save(&dir)?;
+    Ok(dir)
+}
+
+/// Checks the world in `dir` can be played, clears away what interrupted saves left of it and
+/// marks it as played now. Worlds saved before there were level files get one, with the seed
+/// they were generated with.
+pub fn open_world(dir: &Path) -> Result<Level, String> {
+    save::remove_temp_files(dir);
+    save::remove_temp_files(&dir.join(REGION_DIR));
+    let mut level = if dir.join(LEVEL_FILE).is_file() {
+        Level::load(dir)?
+    } else if dir.join(PLAYER_FILE).is_file() {
+        let name = dir
+            .file_name()
+            .map_or_else(|| DEFAULT_WORLD.into(), |name| name.to_string_lossy());
+        Level::new(&name, WORLD_SEED)
+    } else {
+        return Err(format!("There is no world in {}", dir.display()));
+    };
+
+    if level.format_version > LEVEL_FORMAT_VERSION {
+        return Err(format!(
+            "{} was saved by a newer version of the game, in format {} rather than {}",
+            level.name, level.format_version, LEVEL_FORMAT_VERSION
+        ));
+    }
+    if level.generator != DEFAULT_GENERATOR {
+        return Err(format!(
+            "{} uses the unknown generator {}",
+            level.name, level.generator
+        ));
+    }
+    // The default generator has no settings
+    if let Some(setting) = level.generator_settings.keys().next() {
+        return Err(format!(
+            "{} uses the generator setting {}, which {} does not have",
+            level.name, setting, level.generator
+        ));
+    }
+    level.last_played = unix_time();
+    level.save(dir)?;
+    Ok(level)
+}
+
+/// Renames the world. Its directory stays the same.
+pub fn rename_world(dir: &Path, name: &str) -> Result<(), String> {
+    let mut level = Level::load(dir)?;
+    level.name = name.to_string();
+    level.save(dir)
+}
+
+/// Copies the world into a new directory as `name`, returning the directory.
+pub fn duplicate_world(saves: &Path, dir: &Path, name: &str) -> Result<PathBuf, String> {
+    let mut level = Level::load(dir)?;
+    let copy = unused_dir(saves, name);
+    copy_dir(dir, &copy).map_err(|error| format!("Could not copy {}: {}", dir.display(), error))?;
+    level.name = name.to_string();
+    level.created = unix_time();
+    level.save(&copy)?;
+    Ok(copy)
+}
+
+/// Deletes the world and everything saved in it.
+pub fn delete_world(dir: &Path) -> Result<(), String> {
+    // Only ever directories that really hold a world
+    if !dir.join(LEVEL_FILE).is_file() {
+        return Err(format!("There is no world in {}", dir.display()));
+    }
+    fs::remove_dir_all(dir)
+        .map_err(|error| format!("Could not delete {}: {}", dir.display(), error))
+}
+
+// A directory in `saves` named after the world that is not taken yet
+fn unused_dir(saves: &Path, name: &str) -> PathBuf {
+    let mut base: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if base.trim_matches('_').is_empty() {
+        base = DEFAULT_WORLD.to_string();
+    }
+    let mut dir = saves.join(&base);
+    let mut number = 2;
+    while dir.exists() {
+        dir = saves.join(format!("{}-{}", base, number));
+        number += 1;
+    }
+    dir
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_saves(name: &str) -> PathBuf {
+        let saves =
+            env::temp_dir().join(format!("minecrust-level-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&saves);
+        saves
+    }
+
+    fn run(saves: &Path, args: &[&str]) -> Result<Option<(PathBuf, Level)>, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        WorldCommand::from_args(&args)?.run(saves)
+    }
+
+    #[test]
+    fn worlds_are_directories_in_the_saves() {
+        let saves = Path::new("saves");
+        assert_eq!(world_dir(saves, "world"), Ok(saves.join("world")));
+        assert_eq!(world_dir(saves, "world-2/"), Ok(saves.join("world-2/")));
+        for world in [
+            "",
+            ".",
+            "..",
+            "../world",
+            "/world",
+            "world/region",
+            "./world",
+        ] {
+            assert!(world_dir(saves, world).is_err(), "{} was accepted", world);
+        }
+    }
+
+    #[test]
+    fn renaming_keeps_the_directory() {
+        let saves = temp_saves("rename");
+        let dir = create_world(&saves, "Island", 7).unwrap();
+        assert_eq!(dir, saves.join("Island"));
+
+        run(&saves, &["--rename-world", "Island", "Isle of Stone"]).unwrap();
+        let level = Level::load(&dir).unwrap();
+        assert_eq!(level.name, "Isle of Stone");
+        assert_eq!(level.seed, 7);
+        assert!(run(&saves, &["--rename-world", "Nowhere", "Isle"]).is_err());
+        fs::remove_dir_all(&saves).unwrap();
+    }
+
+    #[test]
+    fn duplicates_copy_everything_into_a_new_directory() {
+        let saves = temp_saves("duplicate");
+        let dir = create_world(&saves, "Island", 7).unwrap();
+        fs::create_dir_all(dir.join(REGION_DIR)).unwrap();
+        fs::write(dir.join(REGION_DIR).join("r.0.0.bin"), "chunks").unwrap();
+
+        run(&saves, &["--duplicate-world", "Island", "Island"]).unwrap();
+        let copy = saves.join("Island-2");
+        let level = Level::load(&copy).unwrap();
+        assert_eq!(level.name, "Island");
+        assert_eq!(level.seed, 7);
+        assert_eq!(
+            fs::read_to_string(copy.join(REGION_DIR).join("r.0.0.bin")).unwrap(),
+            "chunks"
+        );
+        assert_eq!(Level::load(&dir).unwrap().name, "Island");
+        assert_eq!(list_worlds(&saves).len(), 2);
+        fs::remove_dir_all(&saves).unwrap();
+    }
+
+    #[test]
+    fn only_worlds_are_deleted() {
+        let saves = temp_saves("delete");
+        let dir = create_world(&saves, "Island", 7).unwrap();
+        let other = saves.join("notes");
+        fs::create_dir_all(&other).unwrap();
+
+        assert!(run(&saves, &["--delete-world", "notes"]).is_err());
+        assert!(other.is_dir());
+        assert!(run(&saves, &["--delete-world", "../notes"]).is_err());
+        run(&saves, &["--delete-world", "Island"]).unwrap();
+        assert!(!dir.exists());
+        assert!(list_worlds(&saves).is_empty());
+        fs::remove_dir_all(&saves).unwrap();
+    }
+
+    #[test]
+    fn unknown_generator_settings_are_not_opened() {
+        let saves = temp_saves("generator-settings");
+        let dir = create_world(&saves, "Island", 7).unwrap();
+        assert!(open_world(&dir).is_ok());
+
+        let mut level = Level::load(&dir).unwrap();
+        level
+            .generator_settings
+            .insert("sea_level".to_string(), "40".to_string());
+        level.save(&dir).unwrap();
+        let error = open_world(&dir).unwrap_err();
+        assert!(error.contains("sea_level"), "{}", error);
+        fs::remove_dir_all(&saves).unwrap();
+    }
+}
@@ -0,0 +1,41 @@
+pub mod anvil;
+pub mod block_mapping;
+pub mod block_models;
+pub mod block_textures;
+pub mod block_types;
+pub mod chunk;
+pub mod console;
+pub mod console_ui;
+pub mod culling;
+pub mod input_map;
+pub mod interaction;
+pub mod inventory;
+pub mod inventory_ui;
+pub mod items;
+pub mod level;
+pub mod lod;
+pub mod mesh;
+pub mod migration;
+pub mod nbt;
+pub mod network_client;
+pub mod physics;
+pub mod player;
+pub mod protocol;
+pub mod region;
+pub mod save;
+pub mod schematic;
+pub mod server;
+pub mod settings;
+pub mod shadows;
+pub mod voxel_data;
+pub mod voxel_map;
+pub mod voxel_material;
+pub mod world;
+pub mod world_edit;
+pub mod world_select;
+pub mod world_time;
+
+use bevy::prelude::*;
+
+#[derive(Component)]
+pub struct Player;
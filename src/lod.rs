@@ -0,0 +1,388 @@
+use crate::block_textures::BlockTextures;
+use crate::chunk::MaterialHandle;
+use crate::mesh;
+use crate::network_client::NetworkClient;
+use crate::region::WorldRegions;
+use crate::shadows::VoxelShadowCaster;
+use crate::voxel_data::{
+    CHUNK_SIZE, LOD_DISTANCES, LOD_MESHES_PER_FRAME, SUBVOXELS, WORLD_HEIGHT_IN_CHUNKS,
+};
+use crate::voxel_map::{TerrainGenerator, VoxelMap};
+use crate::world::{get_chunk_from_player_pos, ChunkCoord, ChunkToRemeshQueue, WORLD_HEIGHT};
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use itertools::iproduct;
+
+pub const MAX_LOD_LEVEL: u8 = (LOD_DISTANCES.len() - 1) as u8;
+
+/// A square column of `2^level` by `2^level` chunks spanning the whole world height, meshed at
+/// one cell per `2^level` voxels. `x` and `z` are in units of the region size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LodRegion {
+    pub level: u8,
+    pub x: i32,
+    pub z: i32,
+}
+
+/// Chebyshev distances in chunks up to which each LOD level is used, the first being the render
+/// distance of the full detail chunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LodDistances(pub [usize; LOD_DISTANCES.len()]);
+
+pub struct LodMap(pub HashMap<LodRegion, Entity>);
+pub struct LodToGenerateQueue(pub Vec<LodRegion>);
+pub struct LodLastChunk(Option<ChunkCoord>);
+/// Terrain the LOD meshes sample where the voxel map has nothing, made once the seed is known.
+#[derive(Default)]
+pub struct LodGenerator(Option<TerrainGenerator>);
+
+impl LodRegion {
+    pub fn size_in_chunks(&self) -> i32 {
+        1 << self.level
+    }
+
+    pub fn cell_size(&self) -> usize {
+        1 << self.level
+    }
+
+    /// Chebyshev distance in chunks from `chunk` to the nearest chunk column of the region.
+    pub fn distance_to(&self, chunk: ChunkCoord) -> i32 {
+        let size = self.size_in_chunks();
+        let (min_x, min_z) = (self.x * size, self.z * size);
+        let dx = (min_x - chunk.x).max(chunk.x - (min_x + size - 1)).max(0);
+        let dz = (min_z - chunk.z).max(chunk.z - (min_z + size - 1)).max(0);
+
+        dx.max(dz)
+    }
+
+    /// Whether the region is close enough to the player to be replaced by its four children.
+    pub fn is_subdivided(&self, player_chunk: ChunkCoord, distances: &LodDistances) -> bool {
+        self.level > 0
+            && self.distance_to(player_chunk) < distances.0[self.level as usize - 1] as i32
+    }
+
+    fn children(&self) -> [LodRegion; 4] {
+        let level = self.level - 1;
+        let (x, z) = (self.x * 2, self.z * 2);
+        [
+            LodRegion { level, x, z },
+            LodRegion { level, x: x + 1, z },
+            LodRegion { level, x, z: z + 1 },
+            LodRegion {
+                level,
+                x: x + 1,
+                z: z + 1,
+            },
+        ]
+    }
+}
+
+impl LodDistances {
+    /// The default distances with full detail up to `render_distance`. Coarser levels are
+    /// pushed out to at least twice the distance of the level before them.
+    pub fn new(render_distance: usize) -> Self {
+        let mut distances = LOD_DISTANCES;
+        distances[0] = render_distance;
+        for level in 1..distances.len() {
+            distances[level] = distances[level].max(distances[level - 1] * 2);
+        }
+        LodDistances(distances)
+    }
+
+    pub fn render_distance(&self) -> usize {
+        self.0[0]
+    }
+}
+
+impl Default for LodMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LodMap {
+    pub fn new() -> Self {
+        LodMap(HashMap::default())
+    }
+}
+
+impl Default for LodLastChunk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LodLastChunk {
+    pub fn new() -> Self {
+        LodLastChunk(None)
+    }
+}
+
+/// Whether the chunk column is rendered at full detail by the regular chunk systems.
+pub fn is_full_detail(
+    chunk_pos: ChunkCoord,
+    player_chunk: ChunkCoord,
+    distances: &LodDistances,
+) -> bool {
+    let parent = LodRegion {
+        level: 1,
+        x: chunk_pos.x.div_euclid(2),
+        z: chunk_pos.z.div_euclid(2),
+    };
+
+    parent.is_subdivided(player_chunk, distances)
+}
+
+/// Regions of level 1 and above that should be displayed, found by subdividing the coarsest
+/// regions around the player. The quadtree guarantees regions never overlap each other or the
+/// full detail chunks.
+pub fn desired_regions(player_chunk: ChunkCoord, distances: &LodDistances) -> Vec<LodRegion> {
+    let max_distance = distances.0[MAX_LOD_LEVEL as usize] as i32;
+    let top_size = 1 << MAX_LOD_LEVEL;
+    let mut stack = Vec::new();
+    let mut regions = Vec::new();
+
+    for x in (player_chunk.x - max_distance).div_euclid(top_size)
+        ..=(player_chunk.x + max_distance).div_euclid(top_size)
+    {
+        for z in (player_chunk.z - max_distance).div_euclid(top_size)
+            ..=(player_chunk.z + max_distance).div_euclid(top_size)
+        {
+            let region = LodRegion {
+                level: MAX_LOD_LEVEL,
+                x,
+                z,
+            };
+            if region.distance_to(player_chunk) < max_distance {
+                stack.push(region);
+            }
+        }
+    }
+
+    while let Some(region) = stack.pop() {
+        if region.is_subdivided(player_chunk, distances) {
+            if region.level > 1 {
+                stack.extend(region.children());
+            }
+        } else {
+            regions.push(region);
+        }
+    }
+    regions
+}
+
+pub fn update_lod(
+    query: Query<(&GlobalTransform, With<super::Player>)>,
+    mut commands: Commands,
+    mut lod_map: ResMut<LodMap>,
+    mut lod_queue: ResMut<LodToGenerateQueue>,
+    mut lod_last_chunk: ResMut<LodLastChunk>,
+    lod_distances: Res<LodDistances>,
+) {
+    let player_chunk_pos = get_chunk_from_player_pos(query.single().0.translation());
+
+    if let Some(last_chunk) = lod_last_chunk.0 {
+        if player_chunk_pos.equals2d(last_chunk) && !lod_distances.is_changed() {
+            return;
+        }
+    }
+    lod_last_chunk.0 = Some(player_chunk_pos);
+
+    let _span = info_span!("LOD update").entered();
+    let mut regions = desired_regions(player_chunk_pos, &lod_distances);
+    let desired: HashSet<LodRegion> = regions.iter().copied().collect();
+
+    lod_map.0.retain(|region, entity| {
+        let keep = desired.contains(region);
+        if !keep {
+            commands.entity(*entity).despawn_recursive();
+        }
+        keep
+    });
+
+    // The queue is popped from the back, so the nearest regions go last
+    regions.retain(|region| !lod_map.0.contains_key(region));
+    regions.sort_by_key(|region| -region.distance_to(player_chunk_pos));
+    lod_queue.0 = regions;
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_lod(
+    mut commands: Commands,
+    mut lod_map: ResMut<LodMap>,
+    mut lod_queue: ResMut<LodToGenerateQueue>,
+    mut lod_generator: ResMut<LodGenerator>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    material_handle: Res<MaterialHandle>,
+    block_textures: Res<BlockTextures>,
+    mut voxel_map: ResMut<VoxelMap>,
+    mut world_regions: ResMut<WorldRegions>,
+    mut chunk_to_remesh_queue: ResMut<ChunkToRemeshQueue>,
+    network_client: Option<Res<NetworkClient>>,
+) {
+    if lod_queue.0.is_empty() {
+        return;
+    }
+    // Online, the terrain is the server's, so wait until it has said which seed it uses
+    if network_client
+        .as_ref()
+        .is_some_and(|client| client.seed.is_none())
+    {
+        return;
+    }
+    let seed = voxel_map.seed;
+    let generator = lod_generator
+        .0
+        .get_or_insert_with(|| TerrainGenerator::new(seed));
+
+    for _ in 0..LOD_MESHES_PER_FRAME {
+        let region = match lod_queue.0.pop() {
+            Some(region) => region,
+            None => break,
+        };
+        // Only offline, the local save is not the server's world
+        if network_client.is_none() {
+            load_saved_chunks(
+                &region,
+                &mut voxel_map,
+                &mut world_regions,
+                &mut chunk_to_remesh_queue,
+            );
+        }
+        let block_size = region.size_in_chunks() * CHUNK_SIZE as i32;
+        // Cells are whole blocks in the mesh, divided by `SUBVOXELS` in the shader
+        let mesh_size = Vec3::new(
+            CHUNK_SIZE as f32,
+            (WORLD_HEIGHT / region.cell_size()) as f32,
+            CHUNK_SIZE as f32,
+        ) / SUBVOXELS as f32;
+
+        let entity = commands
+            .spawn_bundle(MaterialMeshBundle {
+                mesh: meshes.add(mesh::create_lod_mesh(
+                    &region,
+                    generator,
+                    &voxel_map,
+                    &block_textures,
+                )),
+                material: material_handle.0.clone(),
+                transform: Transform::from_xyz(
+                    (region.x * block_size) as f32,
+                    0.0,
+                    (region.z * block_size) as f32,
+                )
+                .with_scale(Vec3::splat((region.cell_size() as u32 * SUBVOXELS) as f32)),
+                ..Default::default()
+            })
+            .insert(NotShadowCaster)
+            .insert(VoxelShadowCaster::covering(mesh_size))
+            .insert(Name::new(format!(
+                "LOD {} ({}, {})",
+                region.level, region.x, region.z
+            )))
+            .id();
+        lod_map.0.insert(region, entity);
+    }
+}
+
+/// Loads the saved chunks of the region the voxel map does not hold yet, so its mesh shows them
+/// rather than the generated terrain.
+fn load_saved_chunks(
+    region: &LodRegion,
+    voxel_map: &mut VoxelMap,
+    world_regions: &mut WorldRegions,
+    chunk_to_remesh_queue: &mut ChunkToRemeshQueue,
+) {
+    let size = region.size_in_chunks();
+    for (x, y, z) in iproduct!(
+        (region.x * size..(region.x + 1) * size),
+        (0..WORLD_HEIGHT_IN_CHUNKS as i32),
+        (region.z * size..(region.z + 1) * size)
+    ) {
+        let chunk_pos = ChunkCoord { x, y, z };
+        if !voxel_map.contains_chunk(&chunk_pos) || voxel_map.filled.contains(&chunk_pos) {
+            continue;
+        }
+        if let Some(blocks) = world_regions.load_chunk(chunk_pos) {
+            voxel_map.set_chunk_blocks(chunk_pos, &blocks);
+            // Full detail chunks next to it were meshed against generated blocks
+            let min = IVec3::new(x, y, z) * CHUNK_SIZE as i32;
+            chunk_to_remesh_queue.push_region(min, min + IVec3::splat(CHUNK_SIZE as i32 - 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYER: ChunkCoord = ChunkCoord { x: 3, y: 0, z: -5 };
+
+    // How many times each chunk column is shown, by an LOD region or at full detail
+    fn coverage(player_chunk: ChunkCoord, distances: &LodDistances) -> HashMap<(i32, i32), usize> {
+        let mut coverage = HashMap::default();
+        for region in desired_regions(player_chunk, distances) {
+            let size = region.size_in_chunks();
+            for (x, z) in iproduct!(
+                (region.x * size..(region.x + 1) * size),
+                (region.z * size..(region.z + 1) * size)
+            ) {
+                *coverage.entry((x, z)).or_default() += 1;
+            }
+        }
+        coverage
+    }
+
+    #[test]
+    fn regions_never_overlap_each_other_or_full_detail_chunks() {
+        let distances = LodDistances::new(8);
+        let coverage = coverage(PLAYER, &distances);
+        let reach = distances.0[MAX_LOD_LEVEL as usize] as i32;
+        for (x, z) in iproduct!(
+            (PLAYER.x - reach..=PLAYER.x + reach),
+            (PLAYER.z - reach..=PLAYER.z + reach)
+        ) {
+            let chunk = ChunkCoord { x, y: 0, z };
+            let count = coverage.get(&(x, z)).copied().unwrap_or(0)
+                + is_full_detail(chunk, PLAYER, &distances) as usize;
+            let distance = (x - PLAYER.x).abs().max((z - PLAYER.z).abs());
+            if distance < reach {
+                assert_eq!(count, 1, "({}, {}) is shown {} times", x, z, count);
+            } else {
+                assert!(count <= 1, "({}, {}) is shown {} times", x, z, count);
+            }
+        }
+    }
+
+    #[test]
+    fn regions_follow_the_player() {
+        let distances = LodDistances::new(8);
+        let moved = ChunkCoord {
+            x: PLAYER.x + 40,
+            ..PLAYER
+        };
+        let before: HashSet<_> = desired_regions(PLAYER, &distances).into_iter().collect();
+        let after: HashSet<_> = desired_regions(moved, &distances).into_iter().collect();
+        assert_ne!(before, after);
+
+        // Where the player was is now far enough for an LOD region, and where it went is not
+        assert!(is_full_detail(PLAYER, PLAYER, &distances));
+        assert!(!is_full_detail(PLAYER, moved, &distances));
+        assert!(after.iter().any(|region| region.distance_to(PLAYER) == 0));
+        assert!(after.iter().all(|region| region.distance_to(moved) > 0));
+    }
+
+    #[test]
+    fn distance_is_zero_inside_the_region() {
+        let region = LodRegion {
+            level: 2,
+            x: -1,
+            z: 0,
+        };
+        assert_eq!(region.distance_to(ChunkCoord { x: -4, y: 0, z: 3 }), 0);
+        assert_eq!(region.distance_to(ChunkCoord { x: -1, y: 0, z: 0 }), 0);
+        assert_eq!(region.distance_to(ChunkCoord { x: 0, y: 0, z: 0 }), 1);
+        assert_eq!(region.distance_to(ChunkCoord { x: -6, y: 0, z: 9 }), 6);
+    }
+}
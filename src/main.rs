@@ -1,77 +1,222 @@
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
+use bevy::input::InputSystem;
 use bevy::{prelude::*, render::texture::ImageSettings};
-// use bevy::window::PresentMode::Immediate;
 use bevy_atmosphere::prelude::*;
-use bevy_flycam::{FlyCam, MovementSettings, NoCameraPlayerPlugin};
+use bevy_egui::EguiPlugin;
 use bevy_inspector_egui::WorldInspectorPlugin;
+use std::path::Path;
+use std::{env, process};
 
-pub const HEIGHT: f32 = 1080.0;
-pub const WIDTH: f32 = 1920.0;
-
-mod block_types;
-mod chunk;
-mod mesh;
-mod voxel_data;
-mod voxel_map;
-mod world;
+use minecrust::*;
 
 fn main() {
-    App::new()
+    let args: Vec<String> = env::args().skip(1).collect();
+    let saves = Path::new(save::SAVES_DIR);
+    let command = level::WorldCommand::from_args(&args);
+    let (world_dir, level) = match command.and_then(|command| command.run(saves)) {
+        Ok(Some(world)) => world,
+        // Only managing worlds, not playing
+        Ok(None) => return,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    let (settings, mut settings_menu) = settings::load_settings();
+    let (input_map, control_warnings) = input_map::InputMap::from_controls(&settings.controls);
+    settings_menu.warnings.extend(control_warnings);
+    let voxel_map = voxel_map::VoxelMap::new(level.seed, settings.world_size);
+    let world_spawn = match level.spawn {
+        Some(spawn) => player::WorldSpawn(Vec3::from(spawn)),
+        None => player::WorldSpawn::new(&voxel_map),
+    };
+    let network_client = network_client::connect_from_args(settings.render_distance);
+
+    let mut app = App::new();
+    app
         // Resources
-        // .insert_resource(Msaa { samples: 4 })
+        .insert_resource(Msaa {
+            samples: settings.msaa,
+        })
         .insert_resource(ClearColor(Color::rgb(0.2, 0.2, 0.2)))
         .insert_resource(ImageSettings::default_nearest()) // Fix blurred textures
         .insert_resource(WindowDescriptor {
-            width: WIDTH,
-            height: HEIGHT,
+            width: settings.window_width,
+            height: settings.window_height,
             title: "MinecRust".to_string(),
             resizable: true,
-            // present_mode: Immediate, // Disables V-Sync
-            //mode: WindowMode::BorderlessFullscreen,
+            present_mode: settings.present_mode(),
+            mode: settings.window_mode(),
             ..Default::default()
         })
         .init_resource::<chunk::MaterialHandle>()
-        .insert_resource(voxel_map::VoxelMap::new())
+        .insert_resource(block_textures::BlockTextures::new())
+        .insert_resource(block_models::BlockModels::new())
+        .insert_resource(voxel_map)
         .insert_resource(world::ChunkMap::new())
         .insert_resource(world::ChunkToGenerateQueue(Vec::new()))
         .insert_resource(world::ChunkToSpawnQueue(Vec::new()))
+        .insert_resource(world::ChunkToRemeshQueue(Vec::new()))
         .insert_resource(world::ActiveChunks(Vec::new()))
         .insert_resource(world::PlayerLastChunk::new())
-        .insert_resource(MovementSettings {
-            sensitivity: 0.00012, // default: 0.00012
-            speed: 30.0,          // default: 12.0
-        })
+        .insert_resource(lod::LodDistances::new(settings.render_distance))
+        .insert_resource(lod::LodMap::new())
+        .insert_resource(lod::LodToGenerateQueue(Vec::new()))
+        .insert_resource(lod::LodLastChunk::new())
+        .init_resource::<lod::LodGenerator>()
+        .insert_resource(world_time::WorldTime::new())
+        .insert_resource(Atmosphere::default())
+        .add_event::<world_time::TimeCommand>()
+        .insert_resource(inventory_ui::InventoryScreen::new())
+        .insert_resource(interaction::BlockBreaking::new())
+        .add_event::<inventory::ItemDrop>()
+        .add_event::<interaction::BlockEdited>()
+        .add_event::<player::MovementInput>()
+        .add_event::<player::Teleport>()
+        .insert_resource(items::ItemMeshes::new())
+        .insert_resource(console::CommandRegistry::new())
+        .insert_resource(console::Console::new())
+        .insert_resource(world_edit::EditHistory::new())
+        .insert_resource(region::WorldRegions::new(&world_dir))
+        .insert_resource(save::SaveThread::spawn(world_dir.clone()))
+        .insert_resource(save::WorldSave::new(world_dir))
+        .insert_resource(world_spawn)
+        .insert_resource(level)
+        .insert_resource(world_select::WorldSelectScreen::new(saves.to_path_buf()))
+        .insert_resource(settings.movement())
+        .insert_resource(input_map)
+        .insert_resource(input_map::Actions::default())
+        .insert_resource(settings_menu)
+        .insert_resource(settings)
         // Plugins
         .add_plugins(DefaultPlugins)
+        .add_plugin(EguiPlugin)
         .add_plugin(WorldInspectorPlugin::new()) // Inspector setup
-        .add_plugin(NoCameraPlayerPlugin) // Flycam setup
+        .add_plugin(MaterialPlugin::<voxel_material::VoxelMaterial>::default())
+        .add_plugin(block_textures::AnimatedTexturesPlugin)
+        .add_plugin(shadows::VoxelShadowsPlugin)
         .add_plugin(AtmospherePlugin) // Atmosphere setup
         .add_plugin(LogDiagnosticsPlugin::default()) // Diagnostics setup
-        .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(FrameTimeDiagnosticsPlugin)
         // Systems
         .add_startup_system(world::spawn_world)
         .add_startup_system(spawn_light)
+        .add_startup_system(world_time::spawn_sun)
         .add_startup_system(spawn_camera)
+        .add_startup_system(player::grab_cursor)
         .add_startup_system(chunk::generate_material)
+        .add_startup_system(block_textures::load_block_textures)
+        .add_startup_system(mesh::setup_vertex_memory_diagnostics)
+        .add_startup_system(settings::log_settings_warnings)
+        .add_system_to_stage(
+            CoreStage::PreUpdate,
+            input_map::update_actions.after(InputSystem),
+        )
+        .add_system(block_textures::build_block_textures)
+        .add_system(block_textures::animate_block_textures)
         .add_system(world::check_render_distance)
         .add_system(chunk::generate_chunk)
         .add_system(chunk::spawn_chunk)
-        .run();
+        .add_system(chunk::remesh_chunks)
+        .add_system(culling::update_chunk_visibility)
+        .add_system(lod::update_lod)
+        .add_system(lod::spawn_lod)
+        .add_system(mesh::measure_vertex_memory)
+        .add_system(world_time::advance_time)
+        .add_system(world_time::update_daylight)
+        .add_system(inventory::select_hotbar_slot)
+        .add_system(inventory_ui::toggle_inventory_screen)
+        .add_system(inventory_ui::draw_inventory)
+        .add_system(interaction::edit_blocks)
+        .add_system(items::spawn_dropped_items)
+        .add_system(items::update_dropped_items)
+        .add_system(items::merge_dropped_items)
+        .add_system(items::pick_up_items)
+        .add_system(player::toggle_cursor_grab)
+        .add_system(player::player_look)
+        .add_system(player::toggle_game_mode)
+        .add_system(player::move_player)
+        .add_system(player::respawn_players)
+        .add_system(player::draw_health)
+        .add_system(settings::apply_settings)
+        .add_system(settings::toggle_settings_menu)
+        .add_system(settings::draw_settings_menu)
+        .add_system(world_select::toggle_world_select_screen)
+        .add_system(world_select::draw_world_select_screen)
+        .add_system(console_ui::update_console)
+        .add_system(console::run_submitted_commands.exclusive_system())
+        .add_system(network_client::receive_server_messages)
+        .add_system(network_client::send_block_edits.after(interaction::edit_blocks))
+        .add_system(save::autosave)
+        .add_system_to_stage(CoreStage::Last, save::save_on_exit)
+        .add_system_to_stage(
+            CoreStage::Last,
+            world_select::relaunch_on_exit.after(save::save_on_exit),
+        )
+        .add_system(
+            network_client::interpolate_remote_players
+                .after(network_client::receive_server_messages),
+        )
+        .add_system(
+            network_client::send_predictions
+                .after(network_client::send_block_edits)
+                .after(player::move_player)
+                .after(player::respawn_players),
+        );
+
+    if let Some(network_client) = network_client {
+        app.insert_resource(network_client);
+    }
+    app.run();
 }
 
-#[derive(Component)]
-pub struct Player;
+fn spawn_camera(
+    mut commands: Commands,
+    world_save: Res<save::WorldSave>,
+    world_spawn: Res<player::WorldSpawn>,
+) {
+    // Players come back where they left, new ones start at the world spawn
+    let (transform, look, game_mode, health, inventory) =
+        match save::PlayerData::load(&world_save.player_path()) {
+            Some(data) => {
+                let look = data.look();
+                let transform =
+                    Transform::from_translation(data.position()).with_rotation(look.rotation());
+                (
+                    transform,
+                    look,
+                    data.game_mode,
+                    data.health(),
+                    data.inventory(),
+                )
+            }
+            None => {
+                let inventory = inventory::Inventory::starting();
+                let transform = Transform::from_translation(world_spawn.0)
+                    .looking_at(world_spawn.0 + Vec3::new(1.0, 0.0, 1.0), Vec3::Y);
+                let look = player::PlayerLook::from_rotation(transform.rotation);
+                (
+                    transform,
+                    look,
+                    player::GameMode::Creative,
+                    player::Health(player::MAX_HEALTH),
+                    inventory,
+                )
+            }
+        };
 
-fn spawn_camera(mut commands: Commands) {
     commands
         .spawn_bundle(Camera3dBundle {
-            transform: Transform::from_xyz(1.0, 130.0, 5.0)
-                .looking_at(Vec3::new(16.0, 16.0, 16.0), Vec3::Y),
+            transform,
             ..Default::default()
         })
-        .insert(FlyCam)
         .insert(Name::new("Camera"))
         .insert(AtmosphereCamera(None))
+        .insert(inventory)
+        .insert(game_mode)
+        .insert(look)
+        .insert(player::PlayerPhysics::default())
+        .insert(health)
         .insert(Player);
 }
 
@@ -90,7 +235,7 @@ fn spawn_light(mut commands: Commands) {
         // })
         .insert_resource(AmbientLight {
             color: Color::WHITE,
-            brightness: 0.5,
+            brightness: world_time::DAY_AMBIENT,
         })
     // .insert(Name::new("Light"));
 }
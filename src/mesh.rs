@@ -1,101 +1,591 @@
-use crate::voxel_data::{CHUNK_SIZE, FACE_CHECKS, INDICES, NORMALS, VERTICES};
-use crate::voxel_map;
+use crate::block_models::{self, BlockModel, BlockModels, Cuboid, CROSS_QUADS, CROSS_U};
+use crate::block_textures::BlockTextures;
+use crate::lod::LodRegion;
+use crate::voxel_data::{
+    CHUNK_SIZE, FACE_CHECKS, FLIPPED_INDICES, INDICES, LOD_SKIRT_DEPTH, SUBVOXELS,
+    UNPACKED_VERTEX_SIZE, VERTICES,
+};
+use crate::voxel_map::{self, block_chunk, TerrainGenerator, VoxelMap};
 use crate::world::{ChunkCoord, WORLD_HEIGHT, WORLD_SIZE};
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics};
 use bevy::log::info_span;
-use bevy::prelude::{Mesh, ResMut, Vec2, Vec3};
-use bevy::render::mesh::{self, PrimitiveTopology};
+use bevy::prelude::{Assets, IVec2, IVec3, Mesh, Res, ResMut, Vec3};
+use bevy::render::mesh::{self, MeshVertexAttribute, PrimitiveTopology};
+use bevy::render::render_resource::VertexFormat;
 use itertools::iproduct;
+use ndarray::{Array2, Array3};
 
 use super::block_types;
-use super::world;
 
-pub fn create_mesh(chunk_pos: &ChunkCoord, voxel_map: &mut ResMut<voxel_map::VoxelMap>) -> Mesh {
+/// Two `u32`s per vertex, unpacked by `voxel.wgsl`:
+/// - bits 0-29: x, y and z in the chunk in `SUBVOXELS` steps (10 bits each), 30-31: AO
+/// - bits 0-11: texture array layer, 12-15: light level, 16-18: face whose normal lights the
+///   vertex, 19-23 and 24-28: texture coordinates in `SUBVOXELS` steps
+pub const ATTRIBUTE_PACKED_VOXEL: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_PackedVoxel", 1_285_934_721, VertexFormat::Uint32x2);
+
+pub const VERTEX_MEMORY: DiagnosticId =
+    DiagnosticId::from_u128(0x3f0b_9d42_7a61_4c1e_b8d5_2e97_c4a0_6f13);
+pub const UNPACKED_VERTEX_MEMORY: DiagnosticId =
+    DiagnosticId::from_u128(0x9a27_c5e8_13d4_4b6f_a0e1_7c38_5d92_b4f6);
+
+pub const MAX_LIGHT_LEVEL: u32 = 15;
+pub const NO_OCCLUSION: [u32; 4] = [3; 4];
+
+/// Accumulates packed faces for a chunk or LOD mesh.
+pub struct MeshBuilder {
+    vertices: Vec<[u32; 2]>,
+    indices: Vec<u32>,
+    // Vertex position steps per block
+    steps_per_block: u32,
+}
+
+impl Default for MeshBuilder {
+    fn default() -> Self {
+        MeshBuilder {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            steps_per_block: SUBVOXELS,
+        }
+    }
+}
+
+impl MeshBuilder {
+    /// Builder storing positions in whole blocks, for meshes of full cubes only that span more
+    /// than the `SUBVOXELS` steps of a chunk. The shader still divides positions by
+    /// `SUBVOXELS`, so the mesh has to be scaled up by as much.
+    pub fn whole_blocks() -> Self {
+        MeshBuilder {
+            steps_per_block: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Adds a quad with corners in `SUBVOXELS` steps, listed like `VERTICES`, lit as if facing
+    /// `face`, with ambient occlusion per corner from 0 (fully occluded) to 3.
+    pub fn add_quad(
+        &mut self,
+        corners: [[u32; 3]; 4],
+        uvs: [[u32; 2]; 4],
+        face: usize,
+        layer: u32,
+        ao: [u32; 4],
+    ) {
+        let index = self.vertices.len() as u32;
+
+        for corner in 0..4 {
+            let [x, y, z] = corners[corner];
+            let [u, v] = uvs[corner];
+
+            self.vertices.push([
+                x | y << 10 | z << 20 | ao[corner] << 30,
+                layer | MAX_LIGHT_LEVEL << 12 | (face as u32) << 16 | u << 19 | v << 24,
+            ]);
+        }
+
+        // Flip the quad along the other diagonal so AO interpolates symmetrically
+        let quad_indices = if ao[0] + ao[2] < ao[1] + ao[3] {
+            FLIPPED_INDICES
+        } else {
+            INDICES
+        };
+        for triangle_index in quad_indices.iter() {
+            self.indices.push(*triangle_index + index);
+        }
+    }
+
+    /// Adds face `face` of `cuboid` in the block at `position`, with its texture turned
+    /// `rotation` quarter turns. Textures are projected onto the box, so a slab side shows
+    /// the lower half of its texture.
+    pub fn add_box_face(
+        &mut self,
+        position: [i32; 3],
+        cuboid: &Cuboid,
+        face: usize,
+        layer: u32,
+        rotation: u32,
+        ao: [u32; 4],
+    ) {
+        let mut corners = [[0; 3]; 4];
+        let mut uvs = [[0; 2]; 4];
+
+        for (corner, vertex) in VERTICES[face].iter().enumerate() {
+            let mut local = cuboid.from;
+            for axis in 0..3 {
+                if vertex[axis] > 0.0 {
+                    local[axis] = cuboid.to[axis];
+                }
+                // Signed, as LOD meshes add faces of cells just outside the region
+                let steps = (position[axis] * SUBVOXELS as i32 + local[axis] as i32)
+                    * self.steps_per_block as i32
+                    / SUBVOXELS as i32;
+                corners[corner][axis] = steps as u32;
+            }
+
+            let [x, y, z] = local;
+            let mut uv = match face {
+                0 => [z, SUBVOXELS - y],
+                1 => [SUBVOXELS - z, SUBVOXELS - y],
+                2 => [z, SUBVOXELS - x],
+                3 => [z, x],
+                4 => [x, SUBVOXELS - y],
+                _ => [SUBVOXELS - x, SUBVOXELS - y],
+            };
+            for _ in 0..rotation {
+                uv = [SUBVOXELS - uv[1], uv[0]];
+            }
+            uvs[corner] = uv;
+        }
+
+        self.add_quad(corners, uvs, face, layer, ao);
+    }
+
+    /// Adds face `face` of the full block at `position`.
+    pub fn add_face(
+        &mut self,
+        position: [i32; 3],
+        face: usize,
+        layer: u32,
+        rotation: u32,
+        ao: [u32; 4],
+    ) {
+        self.add_box_face(position, &Cuboid::FULL, face, layer, rotation, ao);
+    }
+
+    pub fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(ATTRIBUTE_PACKED_VOXEL, self.vertices);
+        mesh.set_indices(Some(mesh::Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+/// Vertex buffer size of a mesh in bytes, next to the size the same vertices would take as
+/// separate `f32` positions, normals and UVs.
+pub fn vertex_memory(mesh: &Mesh) -> (usize, usize) {
+    let vertex_count = mesh.count_vertices();
+
+    (
+        vertex_count * mesh.get_mesh_vertex_buffer_layout().layout().array_stride as usize,
+        vertex_count * UNPACKED_VERTEX_SIZE,
+    )
+}
+
+pub fn setup_vertex_memory_diagnostics(mut diagnostics: ResMut<Diagnostics>) {
+    diagnostics.add(Diagnostic::new(
+        VERTEX_MEMORY,
+        "voxel_vertex_memory_kib",
+        20,
+    ));
+    diagnostics.add(Diagnostic::new(
+        UNPACKED_VERTEX_MEMORY,
+        "voxel_vertex_memory_unpacked_kib",
+        20,
+    ));
+}
+
+/// Reports the vertex memory of all voxel meshes, and what it would be without packing.
+pub fn measure_vertex_memory(meshes: Res<Assets<Mesh>>, mut diagnostics: ResMut<Diagnostics>) {
+    let (mut packed, mut unpacked) = (0, 0);
+
+    for (_, mesh) in meshes.iter() {
+        if mesh.attribute(ATTRIBUTE_PACKED_VOXEL).is_some() {
+            let (mesh_packed, mesh_unpacked) = vertex_memory(mesh);
+            packed += mesh_packed;
+            unpacked += mesh_unpacked;
+        }
+    }
+
+    diagnostics.add_measurement(VERTEX_MEMORY, || packed as f64 / 1024.0);
+    diagnostics.add_measurement(UNPACKED_VERTEX_MEMORY, || unpacked as f64 / 1024.0);
+}
+
+pub fn create_mesh(
+    chunk_pos: &ChunkCoord,
+    voxel_map: &mut ResMut<voxel_map::VoxelMap>,
+    block_textures: &BlockTextures,
+    block_models: &BlockModels,
+) -> Mesh {
     let _span = info_span!("Create mesh").entered();
-    let mut positions = Vec::new();
-    let mut indices = Vec::new();
-    let mut normals = Vec::new();
-    let mut uvs = Vec::new();
-    let mut index: u32 = 0;
-    let shifted_global_x = chunk_pos.x * CHUNK_SIZE as i32 + (WORLD_SIZE / 2) as i32;
-    let shifted_global_y = chunk_pos.y * CHUNK_SIZE as i32;
-    let shifted_global_z = chunk_pos.z * CHUNK_SIZE as i32 + (WORLD_SIZE / 2) as i32;
+    let mut builder = MeshBuilder::default();
+    let min = IVec3::new(chunk_pos.x, chunk_pos.y, chunk_pos.z) * CHUNK_SIZE as i32;
 
     for (x, y, z) in iproduct!((0..CHUNK_SIZE), (0..CHUNK_SIZE), (0..CHUNK_SIZE)) {
-        if check_voxel(
-            shifted_global_x + x as i32,
-            shifted_global_y + y as i32,
-            shifted_global_z + z as i32,
-            voxel_map,
-        ) {
-            let block_type = &block_types::BLOCKTYPES[voxel_map.voxels[[
-                (x as i32 + shifted_global_x) as usize,
-                (y as i32 + shifted_global_y) as usize,
-                (z as i32 + shifted_global_z) as usize,
-            ]] as usize];
+        // World block position
+        let global = [min.x + x as i32, min.y + y as i32, min.z + z as i32];
+        let position = [x as i32, y as i32, z as i32];
+        let state = voxel_map.get(IVec3::from(global));
+        let block_type = state.block_type();
 
-            for i in 0..6 {
-                let face_check = FACE_CHECKS[i];
-
-                if !check_voxel(
-                    shifted_global_x + face_check.x as i32 + x as i32,
-                    shifted_global_y + face_check.y as i32 + y as i32,
-                    shifted_global_z + face_check.z as i32 + z as i32,
-                    voxel_map,
-                ) {
-                    for position in VERTICES[i].iter() {
-                        positions
-                            .push((*position + Vec3::new(x as f32, y as f32, z as f32)).to_array());
-                        normals.push(NORMALS[i].to_array());
-                    }
-                    for triangle_index in INDICES.iter() {
-                        indices.push(*triangle_index + index);
-                    }
-                    for uv in add_texture(block_type.texture_id.unwrap()[i]) {
-                        uvs.push(uv.to_array());
+        if block_type.textures.is_none() {
+            continue;
+        }
+
+        if block_type.model == BlockModel::Cross {
+            let layer = block_textures.layer(state.block(), 0);
+
+            for quad in CROSS_QUADS.iter() {
+                let mut corners = [[0; 3]; 4];
+                let mut uvs = [[0; 2]; 4];
+                for corner in 0..4 {
+                    for axis in 0..3 {
+                        corners[corner][axis] =
+                            position[axis] as u32 * SUBVOXELS + quad[corner][axis];
                     }
-                    index += 4;
+                    uvs[corner] = [CROSS_U[corner], SUBVOXELS - quad[corner][1]];
                 }
+                // Plants are lit like the top of a block, whichever side they are seen from
+                builder.add_quad(corners, uvs, 2, layer, NO_OCCLUSION);
+            }
+            continue;
+        }
+
+        let is_cube = block_type.model == BlockModel::Cube;
+        for cuboid in block_models.boxes(state).iter() {
+            for i in 0..6 {
+                // Faces inside the block are always drawn, faces on its boundary only when the
+                // neighbour does not cover them
+                if cuboid.touches_boundary(i) && neighbour_occludes(global, i, voxel_map) {
+                    continue;
+                }
+
+                let (texture_face, rotation) = state.texture_face(i);
+                let ao = if is_cube {
+                    face_ambient_occlusion(global, i, voxel_map)
+                } else {
+                    NO_OCCLUSION
+                };
+                builder.add_box_face(
+                    position,
+                    cuboid,
+                    i,
+                    block_textures.layer(state.block(), texture_face),
+                    rotation,
+                    ao,
+                );
+            }
+        }
+    }
+
+    builder.build()
+}
+
+/// Whether the block next to `global` across face `face` covers that face entirely.
+fn neighbour_occludes(global: [i32; 3], face: usize, voxel_map: &voxel_map::VoxelMap) -> bool {
+    let face_check = FACE_CHECKS[face];
+    let (x, y, z) = (
+        global[0] + face_check.x as i32,
+        global[1] + face_check.y as i32,
+        global[2] + face_check.z as i32,
+    );
+    block_models::occludes(voxel_map.get(IVec3::new(x, y, z)), face ^ 1)
+}
+
+/// Per corner AO of a face, from the three voxels touching each corner in front of the face.
+fn face_ambient_occlusion(
+    global: [i32; 3],
+    face: usize,
+    voxel_map: &voxel_map::VoxelMap,
+) -> [u32; 4] {
+    let normal = FACE_CHECKS[face];
+    let front = Vec3::new(global[0] as f32, global[1] as f32, global[2] as f32) + normal;
+    let mut ao = [0; 4];
+
+    for (corner, vertex) in VERTICES[face].iter().enumerate() {
+        // Step from the centre of the block in front of the face towards the corner, along the
+        // two axes of the face plane
+        let offset = (*vertex * 2.0 - Vec3::ONE) * (Vec3::ONE - normal.abs());
+        let axes = if normal.x != 0.0 {
+            [Vec3::new(0.0, offset.y, 0.0), Vec3::new(0.0, 0.0, offset.z)]
+        } else if normal.y != 0.0 {
+            [Vec3::new(offset.x, 0.0, 0.0), Vec3::new(0.0, 0.0, offset.z)]
+        } else {
+            [Vec3::new(offset.x, 0.0, 0.0), Vec3::new(0.0, offset.y, 0.0)]
+        };
+        let is_solid = |position: Vec3| {
+            check_voxel(
+                position.x as i32,
+                position.y as i32,
+                position.z as i32,
+                voxel_map,
+            )
+        };
+
+        let side1 = is_solid(front + axes[0]);
+        let side2 = is_solid(front + axes[1]);
+        let corner_solid = is_solid(front + axes[0] + axes[1]);
+
+        ao[corner] = if side1 && side2 {
+            0
+        } else {
+            3 - (side1 as u32 + side2 as u32 + corner_solid as u32)
+        };
+    }
+    ao
+}
+
+/// Unit cube with the face textures of `block`, for items and other loose blocks.
+pub fn create_item_mesh(block: u8, block_textures: &BlockTextures) -> Mesh {
+    let mut builder = MeshBuilder::default();
+
+    for face in 0..6 {
+        builder.add_face(
+            [0, 0, 0],
+            face,
+            block_textures.layer(block, face),
+            0,
+            NO_OCCLUSION,
+        );
+    }
+    builder.build()
+}
+
+/// Builds the mesh of an LOD region in whole cells; the entity transform scales it by the cell
+/// size times `SUBVOXELS`.
+///
+/// Cells in chunks the voxel map holds, generated, loaded from the save or sent by the server,
+/// are sampled from it so edits show at a distance. The others are sampled from the terrain
+/// generator rather than generated first, so regions can lie outside the loaded world. To hide
+/// seams against neighbours of a different level, faces on the
+/// region border are kept near the surface even when the neighbour is solid (skirts), and exposed
+/// neighbour cells just outside the border contribute their inward facing faces.
+pub fn create_lod_mesh(
+    region: &LodRegion,
+    generator: &TerrainGenerator,
+    voxel_map: &VoxelMap,
+    block_textures: &BlockTextures,
+) -> Mesh {
+    let _span = info_span!("Create LOD mesh").entered();
+    let mut builder = MeshBuilder::whole_blocks();
+
+    let cell_size = region.cell_size() as i32;
+    let cells = CHUNK_SIZE as i32;
+    let cells_y = (WORLD_HEIGHT / region.cell_size()) as i32;
+    let origin = IVec2::new(region.x, region.z) * cells * cell_size;
+
+    // Cell columns are sampled in their middle, with a one cell border
+    let column = |i: usize, k: usize| {
+        origin + (IVec2::new(i as i32, k as i32) - 1) * cell_size + cell_size / 2
+    };
+    let heights = Array2::from_shape_fn((cells as usize + 2, cells as usize + 2), |(i, k)| {
+        let global = column(i, k) + (WORLD_SIZE / 2) as i32;
+        generator.height_at(global.x, global.y)
+    });
+    let blocks = Array3::from_shape_fn(
+        (cells as usize + 2, cells_y as usize, cells as usize + 2),
+        |(i, j, k)| {
+            let column = column(i, k);
+            let bottom = IVec3::new(column.x, j as i32 * cell_size, column.y);
+            if voxel_map.filled.contains(&block_chunk(bottom)) {
+                voxel_cell_block(voxel_map, bottom, cell_size)
+            } else {
+                lod_cell_block(heights[[i, k]], j as i32, cell_size)
+            }
+        },
+    );
+    let cell_block = |i: i32, j: i32, k: i32| -> u8 {
+        if j < 0 {
+            return 1;
+        }
+        if j >= cells_y {
+            return 0;
+        }
+        blocks[[(i + 1) as usize, j as usize, (k + 1) as usize]]
+    };
+    let is_solid = |block: u8| block_types::BLOCKTYPES[block as usize].is_solid;
+    let is_inside = |i: i32, k: i32| i >= 0 && i < cells && k >= 0 && k < cells;
+
+    let mut tops = Array2::<i32>::zeros((cells as usize, cells as usize));
+    for (i, k) in iproduct!((0..cells), (0..cells)) {
+        tops[[i as usize, k as usize]] = (0..cells_y)
+            .rev()
+            .find(|j| is_solid(cell_block(i, *j, k)))
+            .unwrap_or(-1);
+    }
+
+    for (i, j, k) in iproduct!((0..cells), (0..cells_y), (0..cells)) {
+        let block = cell_block(i, j, k);
+
+        for (face, face_check) in FACE_CHECKS.iter().enumerate() {
+            let (ni, nj, nk) = (
+                i + face_check.x as i32,
+                j + face_check.y as i32,
+                k + face_check.z as i32,
+            );
+            let neighbour = cell_block(ni, nj, nk);
+            let on_border = !is_inside(ni, nk);
+
+            if is_solid(block) {
+                let is_skirt =
+                    on_border && j + LOD_SKIRT_DEPTH as i32 > tops[[i as usize, k as usize]];
+
+                if !is_solid(neighbour) || is_skirt {
+                    builder.add_face(
+                        [i, j, k],
+                        face,
+                        block_textures.layer(block, face),
+                        0,
+                        NO_OCCLUSION,
+                    );
+                }
+            } else if on_border && is_solid(neighbour) {
+                let opposite = face ^ 1;
+                builder.add_face(
+                    [ni, nj, nk],
+                    opposite,
+                    block_textures.layer(neighbour, opposite),
+                    0,
+                    NO_OCCLUSION,
+                );
             }
         }
     }
 
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-    mesh.set_indices(Some(mesh::Indices::U32(indices)));
-    mesh
+    builder.build()
 }
 
-pub fn check_voxel(x: i32, y: i32, z: i32, voxel_map: &mut ResMut<voxel_map::VoxelMap>) -> bool {
-    if x > WORLD_SIZE as i32 - 1
-        || x < 0
-        || y > WORLD_HEIGHT as i32 - 1
-        || y < 0
-        || z > WORLD_SIZE as i32 - 1
-        || z < 0
-    {
-        return false;
+/// Block of an LOD cell, chosen so that cells containing the surface show its top block.
+fn lod_cell_block(height: usize, j: i32, cell_size: i32) -> u8 {
+    let bottom = (j * cell_size) as usize;
+    let top = bottom + cell_size as usize - 1;
+
+    if bottom <= height && height <= top {
+        TerrainGenerator::block_at(height, height)
+    } else if bottom > height {
+        TerrainGenerator::block_at(bottom, height)
+    } else {
+        TerrainGenerator::block_at(top, height)
     }
+}
+
+/// Block of an LOD cell in a chunk the voxel map holds, the highest solid block in the middle
+/// column of the cell like the generated cells show the surface.
+fn voxel_cell_block(voxel_map: &VoxelMap, bottom: IVec3, cell_size: i32) -> u8 {
+    (0..cell_size)
+        .rev()
+        .map(|y| voxel_map.get(bottom + IVec3::Y * y))
+        .find(|state| state.block_type().is_solid)
+        .map_or(0, |state| state.block())
+}
 
-    block_types::BLOCKTYPES[voxel_map.voxels[[x as usize, y as usize, z as usize]] as usize]
-        .is_solid
+/// Whether the voxel is a solid full cube, as used for ambient occlusion.
+pub fn check_voxel(x: i32, y: i32, z: i32, voxel_map: &voxel_map::VoxelMap) -> bool {
+    voxel_map
+        .get(IVec3::new(x, y, z))
+        .block_type()
+        .is_opaque_cube()
 }
 
-fn add_texture(texture_id: u32) -> [Vec2; 4] {
-    let mut y = (texture_id / world::TEXTURE_ATLAS_SIZE_IN_BLOCKS as u32) as f32;
-    let mut x = (texture_id % world::TEXTURE_ATLAS_SIZE_IN_BLOCKS as u32) as f32;
-
-    x *= world::NORMALIZED_BLOCK_TEXTURE_SIZE;
-    y *= world::NORMALIZED_BLOCK_TEXTURE_SIZE;
-
-    [
-        Vec2::new(x, y + world::NORMALIZED_BLOCK_TEXTURE_SIZE),
-        Vec2::new(x, y),
-        Vec2::new(x + world::NORMALIZED_BLOCK_TEXTURE_SIZE, y),
-        Vec2::new(
-            x + world::NORMALIZED_BLOCK_TEXTURE_SIZE,
-            y + world::NORMALIZED_BLOCK_TEXTURE_SIZE,
-        ),
-    ]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_types::{block_by_name, BlockState, BLOCKTYPES};
+    use crate::protocol::CHUNK_VOLUME;
+    use crate::settings::MIN_WORLD_SIZE;
+    use crate::voxel_map::WORLD_SEED;
+
+    const GROUND_HEIGHT: i32 = 40;
+
+    // Every chunk of the map filled with stone up to `GROUND_HEIGHT`
+    fn flat_map() -> VoxelMap {
+        let mut voxel_map = VoxelMap::new(WORLD_SEED, MIN_WORLD_SIZE);
+        let stone = BlockState::new(block_by_name("stone").unwrap());
+        let half = MIN_WORLD_SIZE as i32 / 2;
+        for (x, y, z) in iproduct!((-half..half), (0..WORLD_HEIGHT / CHUNK_SIZE), (-half..half)) {
+            let blocks: Vec<_> = (0..CHUNK_VOLUME)
+                .map(|index| {
+                    let block_y = (y * CHUNK_SIZE + index / CHUNK_SIZE % CHUNK_SIZE) as i32;
+                    if block_y < GROUND_HEIGHT {
+                        stone
+                    } else {
+                        BlockState::AIR
+                    }
+                })
+                .collect();
+            voxel_map.set_chunk_blocks(ChunkCoord { x, y: y as i32, z }, &blocks);
+        }
+        voxel_map
+    }
+
+    fn untextured() -> BlockTextures {
+        BlockTextures {
+            face_layers: vec![[0; 6]; BLOCKTYPES.len()],
+            ..BlockTextures::new()
+        }
+    }
+
+    // Face and smallest corner in cells of every quad
+    fn quads(mesh: &Mesh) -> Vec<(usize, [u32; 3])> {
+        let vertices = match mesh.attribute(ATTRIBUTE_PACKED_VOXEL) {
+            Some(mesh::VertexAttributeValues::Uint32x2(vertices)) => vertices,
+            _ => panic!("LOD meshes are packed"),
+        };
+        vertices
+            .chunks(4)
+            .map(|quad| {
+                let face = (quad[0][1] >> 16 & 7) as usize;
+                let corner = quad.iter().fold([u32::MAX; 3], |corner, vertex| {
+                    let position = [
+                        vertex[0] & 1023,
+                        vertex[0] >> 10 & 1023,
+                        vertex[0] >> 20 & 1023,
+                    ];
+                    [0, 1, 2].map(|axis| corner[axis].min(position[axis]))
+                });
+                (face, corner)
+            })
+            .collect()
+    }
+
+    const LEVEL_1: LodRegion = LodRegion {
+        level: 1,
+        x: 0,
+        z: 0,
+    };
+
+    #[test]
+    fn region_borders_get_skirts() {
+        let generator = TerrainGenerator::new(WORLD_SEED);
+        let mesh = create_lod_mesh(&LEVEL_1, &generator, &flat_map(), &untextured());
+
+        // The neighbour on the -X side is as high, yet the top cells of the border keep their
+        // sides down to the skirt depth
+        let sides: Vec<_> = quads(&mesh)
+            .into_iter()
+            .filter(|(face, corner)| *face == 0 && corner[0] == 0)
+            .collect();
+        assert_eq!(sides.len(), CHUNK_SIZE * LOD_SKIRT_DEPTH);
+        let top = (GROUND_HEIGHT / 2) as u32;
+        for (_, corner) in sides {
+            assert!(corner[1] < top && corner[1] >= top - LOD_SKIRT_DEPTH as u32);
+        }
+    }
+
+    #[test]
+    fn regions_show_the_voxel_map() {
+        let generator = TerrainGenerator::new(WORLD_SEED);
+        let mut voxel_map = flat_map();
+        let flat = quads(&create_lod_mesh(
+            &LEVEL_1,
+            &generator,
+            &voxel_map,
+            &untextured(),
+        ));
+        assert!(flat
+            .iter()
+            .all(|(face, corner)| *face != 2 || corner[1] == (GROUND_HEIGHT / 2) as u32));
+
+        // A pillar in the middle of a cell column shows on top of the ground
+        let stone = BlockState::new(block_by_name("stone").unwrap());
+        for y in GROUND_HEIGHT..GROUND_HEIGHT + 10 {
+            voxel_map.set(IVec3::new(9, y, 9), stone);
+        }
+        let edited = quads(&create_lod_mesh(
+            &LEVEL_1,
+            &generator,
+            &voxel_map,
+            &untextured(),
+        ));
+        assert!(edited.contains(&(2, [4, (GROUND_HEIGHT / 2 + 5) as u32, 4])));
+    }
 }
@@ -0,0 +1,152 @@
+//! Upgrades of saves written by older versions of the game.
+//!
+//! Level files, region files and the chunks in them each carry a format version. Whenever one
+//! of the formats changes, its version is bumped and a migration from the previous version is
+//! registered below. Loading runs every migration from the saved version up to the current one
+//! in order, so a save from any earlier version can be read.
+
+use crate::region::SavedChunk;
+use toml::value::Table;
+
+/// Upgrades data of type `T` from format `from` to the next one.
+pub struct Migration<T: 'static> {
+    pub from: u32,
+    pub description: &'static str,
+    pub migrate: fn(&mut T) -> Result<(), String>,
+}
+
+/// Level files, as the TOML table they are read into before becoming a `level::Level`.
+pub static LEVEL_MIGRATIONS: &[Migration<Table>] = &[Migration {
+    from: 1,
+    description: "Worlds hold the chunks that were changed in region files",
+    migrate: no_changes,
+}];
+
+/// Region files after the magic bytes and the version, see `region`.
+pub static REGION_MIGRATIONS: &[Migration<Vec<u8>>] = &[];
+
+/// Chunks in region files. Their layout stays the same, migrations rewrite the palette and
+/// blocks to what they mean in the next version.
+pub static CHUNK_MIGRATIONS: &[Migration<SavedChunk>] = &[];
+
+/// Upgrades `data` saved in format `version` to format `current`. Fails if the data is newer
+/// than this version of the game understands or a migration is missing.
+pub fn migrate<T>(
+    data: &mut T,
+    version: u32,
+    current: u32,
+    migrations: &[Migration<T>],
+) -> Result<(), String> {
+    if version > current {
+        return Err(format!(
+            "saved in format {}, newer than the {} this version reads",
+            version, current
+        ));
+    }
+    for from in version..current {
+        let migration = migrations
+            .iter()
+            .find(|migration| migration.from == from)
+            .ok_or_else(|| format!("no upgrade from format {}", from))?;
+        (migration.migrate)(data)
+            .map_err(|error| format!("{}: {}", migration.description, error))?;
+    }
+    Ok(())
+}
+
+// For format changes that only add what older saves do not have
+fn no_changes<T>(_: &mut T) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::LEVEL_FORMAT_VERSION;
+
+    // Each step records the format it upgraded from
+    fn from_1(data: &mut Vec<u32>) -> Result<(), String> {
+        data.push(1);
+        Ok(())
+    }
+
+    fn from_2(data: &mut Vec<u32>) -> Result<(), String> {
+        data.push(2);
+        Ok(())
+    }
+
+    fn from_3(data: &mut Vec<u32>) -> Result<(), String> {
+        data.push(3);
+        Ok(())
+    }
+
+    fn broken(_: &mut Vec<u32>) -> Result<(), String> {
+        Err("unreadable".to_string())
+    }
+
+    fn step(from: u32, migrate: fn(&mut Vec<u32>) -> Result<(), String>) -> Migration<Vec<u32>> {
+        Migration {
+            from,
+            description: "test step",
+            migrate,
+        }
+    }
+
+    #[test]
+    fn steps_run_in_order_from_the_saved_version() {
+        // Registered out of order on purpose
+        let migrations = [step(3, from_3), step(1, from_1), step(2, from_2)];
+
+        let mut data = Vec::new();
+        migrate(&mut data, 1, 4, &migrations).unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+
+        let mut data = Vec::new();
+        migrate(&mut data, 2, 4, &migrations).unwrap();
+        assert_eq!(data, vec![2, 3]);
+
+        let mut data = Vec::new();
+        migrate(&mut data, 4, 4, &migrations).unwrap();
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn gaps_are_errors() {
+        let migrations = [step(1, from_1), step(3, from_3)];
+        let mut data = Vec::new();
+        let error = migrate(&mut data, 1, 4, &migrations).unwrap_err();
+        assert_eq!(error, "no upgrade from format 2");
+        assert_eq!(data, vec![1]);
+    }
+
+    #[test]
+    fn newer_versions_are_errors() {
+        let migrations = [step(1, from_1)];
+        let mut data = Vec::new();
+        let error = migrate(&mut data, 3, 2, &migrations).unwrap_err();
+        assert!(error.contains("newer"), "{}", error);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn failed_steps_say_which_step() {
+        let migrations = [step(1, from_1), step(2, broken), step(3, from_3)];
+        let mut data = Vec::new();
+        let error = migrate(&mut data, 1, 4, &migrations).unwrap_err();
+        assert_eq!(error, "test step: unreadable");
+        assert_eq!(data, vec![1]);
+    }
+
+    #[test]
+    fn every_level_format_can_be_upgraded() {
+        for version in 1..=LEVEL_FORMAT_VERSION {
+            migrate(
+                &mut Table::new(),
+                version,
+                LEVEL_FORMAT_VERSION,
+                LEVEL_MIGRATIONS,
+            )
+            .unwrap();
+        }
+    }
+}
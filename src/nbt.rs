@@ -0,0 +1,430 @@
+//! Minecraft's Named Binary Tag format, which its worlds and schematics are saved in. Values are
+//! big endian, strings are prefixed by their u16 length.
+
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::{decompress_to_vec_with_limit, decompress_to_vec_zlib_with_limit};
+use std::collections::HashMap;
+
+// Compression level of the gzip files written, from 0 to 10
+const GZIP_LEVEL: u8 = 6;
+// Tags nest no deeper than this in files Minecraft writes
+const MAX_DEPTH: usize = 512;
+// Larger files are treated as broken rather than inflated
+pub const MAX_NBT_SIZE: usize = 64 << 20;
+
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_DOUBLE: u8 = 6;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+const TAG_LONG_ARRAY: u8 = 12;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(HashMap<String, Tag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    /// Entry `name` of a compound.
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(entries) => entries.get(name),
+            _ => None,
+        }
+    }
+
+    /// Any integer tag.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Tag::Byte(value) => Some(value as i64),
+            Tag::Short(value) => Some(value as i64),
+            Tag::Int(value) => Some(value as i64),
+            Tag::Long(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Tag::List(tags) => Some(tags),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&HashMap<String, Tag>> {
+        match self {
+            Tag::Compound(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    pub fn as_long_array(&self) -> Option<&[i64]> {
+        match self {
+            Tag::LongArray(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_int_array(&self) -> Option<&[i32]> {
+        match self {
+            Tag::IntArray(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_byte_array(&self) -> Option<&[i8]> {
+        match self {
+            Tag::ByteArray(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    fn tag_type(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => TAG_BYTE,
+            Tag::Short(_) => TAG_SHORT,
+            Tag::Int(_) => TAG_INT,
+            Tag::Long(_) => TAG_LONG,
+            Tag::Float(_) => TAG_FLOAT,
+            Tag::Double(_) => TAG_DOUBLE,
+            Tag::ByteArray(_) => TAG_BYTE_ARRAY,
+            Tag::String(_) => TAG_STRING,
+            Tag::List(_) => TAG_LIST,
+            Tag::Compound(_) => TAG_COMPOUND,
+            Tag::IntArray(_) => TAG_INT_ARRAY,
+            Tag::LongArray(_) => TAG_LONG_ARRAY,
+        }
+    }
+
+    fn write_payload(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Tag::Byte(value) => bytes.push(*value as u8),
+            Tag::Short(value) => bytes.extend(value.to_be_bytes()),
+            Tag::Int(value) => bytes.extend(value.to_be_bytes()),
+            Tag::Long(value) => bytes.extend(value.to_be_bytes()),
+            Tag::Float(value) => bytes.extend(value.to_be_bytes()),
+            Tag::Double(value) => bytes.extend(value.to_be_bytes()),
+            Tag::ByteArray(values) => {
+                bytes.extend((values.len() as i32).to_be_bytes());
+                bytes.extend(values.iter().map(|value| *value as u8));
+            }
+            Tag::String(value) => write_string(bytes, value),
+            Tag::List(tags) => {
+                bytes.push(tags.first().map_or(TAG_END, Tag::tag_type));
+                bytes.extend((tags.len() as i32).to_be_bytes());
+                for tag in tags {
+                    tag.write_payload(bytes);
+                }
+            }
+            Tag::Compound(entries) => {
+                for (name, tag) in entries {
+                    bytes.push(tag.tag_type());
+                    write_string(bytes, name);
+                    tag.write_payload(bytes);
+                }
+                bytes.push(TAG_END);
+            }
+            Tag::IntArray(values) => {
+                bytes.extend((values.len() as i32).to_be_bytes());
+                for value in values {
+                    bytes.extend(value.to_be_bytes());
+                }
+            }
+            Tag::LongArray(values) => {
+                bytes.extend((values.len() as i32).to_be_bytes());
+                for value in values {
+                    bytes.extend(value.to_be_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// The root compound of NBT data, which may be gzip or zlib compressed or not at all.
+pub fn read(bytes: &[u8]) -> Result<Tag, String> {
+    let raw = match bytes {
+        [0x1f, 0x8b, ..] => gunzip(bytes)?,
+        // A zlib header, whose check bits make it a multiple of 31
+        [cmf, flg, ..]
+            if cmf & 0x0f == 8 && (*cmf as u16 * 256 + *flg as u16).is_multiple_of(31) =>
+        {
+            decompress_to_vec_zlib_with_limit(bytes, MAX_NBT_SIZE)
+                .map_err(|_| "invalid zlib data".to_string())?
+        }
+        _ => bytes.to_vec(),
+    };
+    let mut reader = NbtReader { bytes: &raw };
+    let tag_type = reader.u8()?;
+    if tag_type != TAG_COMPOUND {
+        return Err(format!("root tag is of type {}, not a compound", tag_type));
+    }
+    reader.string()?;
+    reader.payload(TAG_COMPOUND, 0)
+}
+
+/// Uncompressed NBT of a root compound called `name`. Lists must only hold tags of one type.
+pub fn write(name: &str, root: &HashMap<String, Tag>) -> Vec<u8> {
+    let mut bytes = vec![TAG_COMPOUND];
+    write_string(&mut bytes, name);
+    for (name, tag) in root {
+        bytes.push(tag.tag_type());
+        write_string(&mut bytes, name);
+        tag.write_payload(&mut bytes);
+    }
+    bytes.push(TAG_END);
+    bytes
+}
+
+// Strings longer than a u16 can count are cut short, no name or value Minecraft reads is
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    let mut length = value.len().min(u16::MAX as usize);
+    while !value.is_char_boundary(length) {
+        length -= 1;
+    }
+    bytes.extend((length as u16).to_be_bytes());
+    bytes.extend(&value.as_bytes()[..length]);
+}
+
+/// Compresses data into the gzip format, which Minecraft's NBT files are saved in.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // No flags, no modification time, unknown OS
+    let mut bytes = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    bytes.extend(compress_to_vec(data, GZIP_LEVEL));
+    bytes.extend(crc32(data).to_le_bytes());
+    bytes.extend((data.len() as u32).to_le_bytes());
+    bytes
+}
+
+// The CRC-32 gzip ends with, one bit at a time since files are only written on command
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Inflates gzip data, skipping its header. The checksum at the end is not checked.
+pub fn gunzip(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+    let invalid = || "invalid gzip data".to_string();
+
+    if bytes.len() < 10 || bytes[..3] != [0x1f, 0x8b, 8] {
+        return Err(invalid());
+    }
+    let flags = bytes[3];
+    let mut start = 10;
+    if flags & FEXTRA != 0 {
+        let length = bytes.get(start..start + 2).ok_or_else(invalid)?;
+        start += 2 + u16::from_le_bytes([length[0], length[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = bytes
+                .get(start..)
+                .and_then(|rest| rest.iter().position(|byte| *byte == 0))
+                .ok_or_else(invalid)?;
+            start += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        start += 2;
+    }
+    let body = bytes.get(start..).ok_or_else(invalid)?;
+    decompress_to_vec_with_limit(body, MAX_NBT_SIZE).map_err(|_| invalid())
+}
+
+struct NbtReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> NbtReader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if count > self.bytes.len() {
+            return Err("NBT data ends early".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_be_bytes(self.array()?))
+    }
+
+    // Minecraft writes modified UTF-8, which only differs from UTF-8 in characters no block or
+    // entry is named with
+    fn string(&mut self) -> Result<String, String> {
+        let length = u16::from_be_bytes(self.array()?) as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).into_owned())
+    }
+
+    // Array and list lengths, checked against what is left so a broken length cannot make
+    // the reader allocate without end
+    fn length(&mut self, element_size: usize) -> Result<usize, String> {
+        let length = self.i32()?;
+        let length = usize::try_from(length.max(0)).unwrap_or(0);
+        if length.saturating_mul(element_size) > self.bytes.len() {
+            return Err(format!("invalid length {}", length));
+        }
+        Ok(length)
+    }
+
+    fn payload(&mut self, tag_type: u8, depth: usize) -> Result<Tag, String> {
+        if depth > MAX_DEPTH {
+            return Err("NBT nested too deep".to_string());
+        }
+        Ok(match tag_type {
+            TAG_BYTE => Tag::Byte(self.u8()? as i8),
+            TAG_SHORT => Tag::Short(i16::from_be_bytes(self.array()?)),
+            TAG_INT => Tag::Int(self.i32()?),
+            TAG_LONG => Tag::Long(self.i64()?),
+            TAG_FLOAT => Tag::Float(f32::from_be_bytes(self.array()?)),
+            TAG_DOUBLE => Tag::Double(f64::from_be_bytes(self.array()?)),
+            TAG_BYTE_ARRAY => {
+                let length = self.length(1)?;
+                Tag::ByteArray(self.take(length)?.iter().map(|byte| *byte as i8).collect())
+            }
+            TAG_STRING => Tag::String(self.string()?),
+            TAG_LIST => {
+                let element_type = self.u8()?;
+                // Only empty lists hold nothing, every other element takes at least a byte
+                let length = self.length(if element_type == TAG_END { 0 } else { 1 })?;
+                if element_type == TAG_END && length > 0 {
+                    return Err(format!("list of {} end tags", length));
+                }
+                let mut tags = Vec::with_capacity(length.min(self.bytes.len()));
+                for _ in 0..length {
+                    tags.push(self.payload(element_type, depth + 1)?);
+                }
+                Tag::List(tags)
+            }
+            TAG_COMPOUND => {
+                let mut entries = HashMap::new();
+                loop {
+                    let entry_type = self.u8()?;
+                    if entry_type == TAG_END {
+                        break;
+                    }
+                    let name = self.string()?;
+                    entries.insert(name, self.payload(entry_type, depth + 1)?);
+                }
+                Tag::Compound(entries)
+            }
+            TAG_INT_ARRAY => {
+                let length = self.length(4)?;
+                let mut values = Vec::with_capacity(length);
+                for _ in 0..length {
+                    values.push(self.i32()?);
+                }
+                Tag::IntArray(values)
+            }
+            TAG_LONG_ARRAY => {
+                let length = self.length(8)?;
+                let mut values = Vec::with_capacity(length);
+                for _ in 0..length {
+                    values.push(self.i64()?);
+                }
+                Tag::LongArray(values)
+            }
+            _ => return Err(format!("unknown tag type {}", tag_type)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A root compound holding only a list called "list" of `length` elements of
+    // `element_type`, followed by `elements`
+    fn list(element_type: u8, length: i32, elements: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![TAG_COMPOUND, 0, 0, TAG_LIST];
+        write_string(&mut bytes, "list");
+        bytes.push(element_type);
+        bytes.extend(length.to_be_bytes());
+        bytes.extend(elements);
+        bytes.push(TAG_END);
+        bytes
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let root = HashMap::from([
+            ("byte".to_string(), Tag::Byte(-3)),
+            ("string".to_string(), Tag::String("stone".to_string())),
+            (
+                "list".to_string(),
+                Tag::List(vec![Tag::Int(1), Tag::Int(2)]),
+            ),
+            ("empty".to_string(), Tag::List(Vec::new())),
+            ("longs".to_string(), Tag::LongArray(vec![i64::MIN, 0, 7])),
+        ]);
+        let bytes = gzip(&write("root", &root));
+        assert_eq!(read(&bytes), Ok(Tag::Compound(root)));
+    }
+
+    #[test]
+    fn empty_lists_of_end_tags_are_read() {
+        let tag = read(&list(TAG_END, 0, &[])).unwrap();
+        assert_eq!(tag.get("list"), Some(&Tag::List(Vec::new())));
+    }
+
+    #[test]
+    fn lists_of_end_tags_with_elements_are_refused() {
+        assert!(read(&list(TAG_END, i32::MAX, &[])).is_err());
+        assert!(read(&list(TAG_END, 1, &[])).is_err());
+    }
+
+    #[test]
+    fn lengths_longer_than_the_data_are_refused() {
+        assert!(read(&list(TAG_BYTE, 1000, &[1, 2, 3])).is_err());
+        assert!(read(&list(TAG_LIST, i32::MAX, &[])).is_err());
+    }
+}
@@ -0,0 +1,741 @@
+use crate::block_models::BlockModels;
+use crate::console::Console;
+use crate::interaction::BlockEdited;
+use crate::lod::{self, LodDistances};
+use crate::player::{
+    self, GameMode, MovementInput, PlayerPhysics, Teleport, WorldSpawn, EYE_HEIGHT, PLAYER_SIZE,
+};
+use crate::protocol::{
+    ClientMessage, Connection, EntityKind, ServerMessage, CHUNK_VOLUME, DEFAULT_PORT,
+    PROTOCOL_VERSION, TIMEOUT,
+};
+use crate::server::TICKS_PER_SECOND;
+use crate::voxel_data::CHUNK_SIZE;
+use crate::voxel_map::VoxelMap;
+use crate::world::{
+    get_chunk_from_player_pos, ChunkCoord, ChunkMap, ChunkToGenerateQueue, ChunkToRemeshQueue,
+};
+use bevy::prelude::*;
+use std::collections::{HashSet, VecDeque};
+use std::env;
+use std::io;
+use std::time::{Duration, Instant};
+
+// Remote players are shown this far in the past, so there is a later snapshot to move towards
+pub const INTERPOLATION_DELAY: f64 = 2.0 / TICKS_PER_SECOND as f64;
+
+/// Connection to a dedicated server. While it exists the server owns the world: chunks come
+/// from it instead of the terrain generator, and edits and movement are sent to it.
+pub struct NetworkClient {
+    connection: Connection,
+    pub address: String,
+    pub player_id: Option<u32>,
+    /// Seed of the server's terrain, once it has sent it.
+    pub seed: Option<u64>,
+    received_chunks: HashSet<ChunkCoord>,
+    last_heard: Instant,
+    predictions: PendingPredictions,
+    latency: SimulatedLatency,
+}
+
+/// Inputs and teleports sent but not yet applied by the server, oldest first, numbered in the
+/// order they were sent.
+pub struct PendingPredictions {
+    unacknowledged: VecDeque<(u32, Prediction)>,
+    next_sequence: u32,
+}
+
+/// Something the player did that moves it, predicted locally before the server confirms it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Prediction {
+    Input(MovementInput),
+    Teleport(Vec3),
+}
+
+/// Holds back messages in both directions to play as if the server were far away. Each way
+/// takes half of `round_trip`.
+pub struct SimulatedLatency {
+    pub round_trip: Duration,
+    outgoing: VecDeque<(Instant, ClientMessage)>,
+    incoming: VecDeque<(Instant, ServerMessage)>,
+}
+
+/// Another player on the server.
+#[derive(Component)]
+pub struct RemotePlayer {
+    pub id: u32,
+    /// Times the positions were received at, with the body position and the yaw.
+    snapshots: VecDeque<(f64, Vec3, f32)>,
+}
+
+impl NetworkClient {
+    /// Connects to `address`, adding `DEFAULT_PORT` when it has no port, and joins as `name`.
+    pub fn connect(address: &str, name: &str, view_distance: usize) -> io::Result<Self> {
+        let address = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("{}:{}", address, DEFAULT_PORT)
+        };
+        let mut connection = Connection::connect(address.as_str())?;
+        connection.send(&ClientMessage::Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            name: name.to_string(),
+            view_distance: view_distance as u32,
+        });
+        connection.flush()?;
+        Ok(NetworkClient {
+            connection,
+            address,
+            player_id: None,
+            seed: None,
+            received_chunks: HashSet::new(),
+            last_heard: Instant::now(),
+            predictions: PendingPredictions::new(),
+            latency: SimulatedLatency::new(Duration::ZERO),
+        })
+    }
+
+    /// Whether the server has sent the blocks of the chunk yet.
+    pub fn has_chunk(&self, chunk_pos: ChunkCoord) -> bool {
+        self.received_chunks.contains(&chunk_pos)
+    }
+
+    /// Queues a message, sent at the end of the frame.
+    pub fn send(&mut self, message: &ClientMessage) {
+        self.latency.delay_outgoing(message.clone());
+    }
+
+    /// Sends the prediction to the server and keeps it to replay until the server has
+    /// applied it.
+    pub fn send_prediction(&mut self, prediction: Prediction) {
+        let sequence = self.predictions.push(prediction);
+        self.send(&match prediction {
+            Prediction::Input(input) => ClientMessage::Input { sequence, input },
+            Prediction::Teleport(position) => ClientMessage::Teleport { sequence, position },
+        });
+    }
+
+    /// See `PendingPredictions::reconcile`.
+    pub fn reconcile(
+        &mut self,
+        sequence: u32,
+        position: Vec3,
+        physics: PlayerPhysics,
+        voxel_map: &VoxelMap,
+        block_models: &BlockModels,
+    ) -> (Vec3, PlayerPhysics) {
+        self.predictions
+            .reconcile(sequence, position, physics, voxel_map, block_models)
+    }
+
+    /// Messages the server sent, once their simulated latency has passed.
+    pub fn receive(&mut self) -> io::Result<Vec<ServerMessage>> {
+        for message in self.connection.receive()? {
+            self.latency.delay_incoming(message);
+        }
+        Ok(self.latency.ready_incoming())
+    }
+
+    /// Writes the messages whose simulated latency has passed.
+    pub fn flush(&mut self) -> io::Result<()> {
+        for message in self.latency.ready_outgoing() {
+            self.connection.send(&message);
+        }
+        self.connection.flush()
+    }
+}
+
+impl Default for PendingPredictions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PendingPredictions {
+    pub fn new() -> Self {
+        PendingPredictions {
+            unacknowledged: VecDeque::new(),
+            next_sequence: 1,
+        }
+    }
+
+    /// Keeps the prediction until the server has applied it, returning its sequence number.
+    pub fn push(&mut self, prediction: Prediction) -> u32 {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        self.unacknowledged.push_back((sequence, prediction));
+        sequence
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unacknowledged.is_empty()
+    }
+
+    /// Moves the player from where the server has it after the input `sequence` through the
+    /// predictions the server has not applied yet, and forgets the ones it has.
+    pub fn reconcile(
+        &mut self,
+        sequence: u32,
+        mut position: Vec3,
+        mut physics: PlayerPhysics,
+        voxel_map: &VoxelMap,
+        block_models: &BlockModels,
+    ) -> (Vec3, PlayerPhysics) {
+        while let Some((oldest, _)) = self.unacknowledged.front() {
+            // Wrapping comparison, so sequence numbers can overflow
+            if sequence.wrapping_sub(*oldest) as i32 >= 0 {
+                self.unacknowledged.pop_front();
+            } else {
+                break;
+            }
+        }
+        for (_, prediction) in self.unacknowledged.iter() {
+            match prediction {
+                Prediction::Input(input) => {
+                    player::step_movement(
+                        &mut position,
+                        &mut physics,
+                        input,
+                        block_models,
+                        |block| voxel_map.get(block),
+                    );
+                }
+                Prediction::Teleport(target) => {
+                    position = *target;
+                    physics = PlayerPhysics::default();
+                }
+            }
+        }
+        (position, physics)
+    }
+}
+
+impl SimulatedLatency {
+    pub fn new(round_trip: Duration) -> Self {
+        SimulatedLatency {
+            round_trip,
+            outgoing: VecDeque::new(),
+            incoming: VecDeque::new(),
+        }
+    }
+
+    pub fn delay_outgoing(&mut self, message: ClientMessage) {
+        self.outgoing
+            .push_back((Instant::now() + self.round_trip / 2, message));
+    }
+
+    pub fn delay_incoming(&mut self, message: ServerMessage) {
+        self.incoming
+            .push_back((Instant::now() + self.round_trip / 2, message));
+    }
+
+    pub fn ready_outgoing(&mut self) -> Vec<ClientMessage> {
+        ready(&mut self.outgoing)
+    }
+
+    pub fn ready_incoming(&mut self) -> Vec<ServerMessage> {
+        ready(&mut self.incoming)
+    }
+}
+
+// Takes the messages that are due from the front of the queue, in order
+fn ready<T>(queue: &mut VecDeque<(Instant, T)>) -> Vec<T> {
+    let now = Instant::now();
+    let mut messages = Vec::new();
+    while queue.front().is_some_and(|(due, _)| *due <= now) {
+        if let Some((_, message)) = queue.pop_front() {
+            messages.push(message);
+        }
+    }
+    messages
+}
+
+impl RemotePlayer {
+    pub fn new(id: u32) -> Self {
+        RemotePlayer {
+            id,
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    /// Keeps the body position and yaw the server sent at `time`.
+    pub fn push_snapshot(&mut self, time: f64, position: Vec3, yaw: f32) {
+        self.snapshots.push_back((time, position, yaw));
+    }
+
+    /// Body position and rotation at `render_time`, between the snapshots around it, and
+    /// forgets the snapshots before them. `None` until the first snapshot arrives.
+    pub fn interpolate(&mut self, render_time: f64) -> Option<(Vec3, Quat)> {
+        let snapshots = &mut self.snapshots;
+        // Only the last snapshot before the render time is still needed
+        while snapshots.len() > 2 && snapshots[1].0 <= render_time {
+            snapshots.pop_front();
+        }
+        match (snapshots.front(), snapshots.get(1)) {
+            (Some(&(from_time, from, from_yaw)), Some(&(to_time, to, to_yaw)))
+                if render_time > from_time =>
+            {
+                let t = ((render_time - from_time) / (to_time - from_time)).min(1.0) as f32;
+                let from_rotation = Quat::from_rotation_y(from_yaw);
+                Some((
+                    from.lerp(to, t),
+                    from_rotation.slerp(Quat::from_rotation_y(to_yaw), t),
+                ))
+            }
+            (Some(&(_, position, yaw)), _) => Some((position, Quat::from_rotation_y(yaw))),
+            (None, _) => None,
+        }
+    }
+}
+
+/// Connection to the server named by `--connect`, joining with the name given by `--name`.
+/// `--simulate-latency` followed by a round trip in milliseconds delays every message, to try
+/// out how playing on a distant server feels. None when playing alone, or when the server
+/// cannot be reached.
+pub fn connect_from_args(view_distance: usize) -> Option<NetworkClient> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let value = |option: &str| {
+        args.iter()
+            .position(|arg| arg == option)
+            .and_then(|index| args.get(index + 1))
+    };
+    let address = value("--connect")?;
+    let name = value("--name").map_or("Player", |name| name.as_str());
+
+    let mut client = match NetworkClient::connect(address, name, view_distance) {
+        Ok(client) => client,
+        Err(error) => {
+            // Called before the app and its logger exist
+            eprintln!("Could not connect to {}: {}, playing alone", address, error);
+            return None;
+        }
+    };
+    if let Some(latency) = value("--simulate-latency") {
+        match latency.parse() {
+            Ok(milliseconds) => {
+                client.latency.round_trip = Duration::from_millis(milliseconds);
+            }
+            Err(_) => eprintln!("Invalid value {} for --simulate-latency", latency),
+        }
+    }
+    Some(client)
+}
+
+/// Applies everything the server sent since the last frame.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn receive_server_messages(
+    mut commands: Commands,
+    client: Option<ResMut<NetworkClient>>,
+    time: Res<Time>,
+    mut voxel_map: ResMut<VoxelMap>,
+    block_models: Res<BlockModels>,
+    mut world_spawn: ResMut<WorldSpawn>,
+    chunk_map: Res<ChunkMap>,
+    mut chunk_to_generate_queue: ResMut<ChunkToGenerateQueue>,
+    mut chunk_to_remesh_queue: ResMut<ChunkToRemeshQueue>,
+    lod_distances: Res<LodDistances>,
+    mut console: ResMut<Console>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut player_query: Query<
+        (&mut Transform, &mut PlayerPhysics, &mut GameMode),
+        (With<super::Player>, Without<RemotePlayer>),
+    >,
+    mut remote_query: Query<(Entity, &mut RemotePlayer), Without<super::Player>>,
+) {
+    let mut client = match client {
+        Some(client) => client,
+        None => return,
+    };
+    let messages = match client.receive() {
+        Ok(messages) if !messages.is_empty() => {
+            client.last_heard = Instant::now();
+            messages
+        }
+        Ok(_) if client.last_heard.elapsed().as_secs_f32() > TIMEOUT => {
+            vec![ServerMessage::Disconnect {
+                reason: "Timed out".to_string(),
+            }]
+        }
+        Ok(messages) => messages,
+        Err(error) => vec![ServerMessage::Disconnect {
+            reason: error.to_string(),
+        }],
+    };
+
+    for message in messages {
+        match message {
+            ServerMessage::Disconnect { reason } => {
+                console.print(&format!("Disconnected from {}: {}", client.address, reason));
+                for (entity, _) in remote_query.iter() {
+                    commands.entity(entity).despawn_recursive();
+                }
+                commands.remove_resource::<NetworkClient>();
+                return;
+            }
+            ServerMessage::KeepAlive { id } => client.send(&ClientMessage::KeepAlive { id }),
+            ServerMessage::Welcome {
+                player_id,
+                position,
+            } => {
+                client.player_id = Some(player_id);
+                for (mut transform, _, _) in player_query.iter_mut() {
+                    transform.translation = position;
+                }
+                console.print(&format!("Connected to {}", client.address));
+            }
+            ServerMessage::WorldInfo { seed, spawn } => {
+                client.seed = Some(seed);
+                voxel_map.seed = seed;
+                world_spawn.0 = spawn;
+            }
+            ServerMessage::PlayerState {
+                sequence,
+                position,
+                physics,
+            } => {
+                let (position, physics) =
+                    client.reconcile(sequence, position, physics, &voxel_map, &block_models);
+                for (mut transform, mut player_physics, _) in player_query.iter_mut() {
+                    transform.translation = position;
+                    *player_physics = physics;
+                }
+            }
+            ServerMessage::GameMode { game_mode } => {
+                for (_, mut physics, mut current) in player_query.iter_mut() {
+                    *current = game_mode;
+                    *physics = PlayerPhysics::default();
+                }
+            }
+            ServerMessage::Chunk { position, blocks } => {
+                if blocks.len() != CHUNK_VOLUME {
+                    continue;
+                }
+                voxel_map.set_chunk_blocks(position, &blocks);
+                client.received_chunks.insert(position);
+
+                // Neighbours already built were meshed against missing blocks on this side
+                let min = IVec3::new(position.x, position.y, position.z) * CHUNK_SIZE as i32;
+                chunk_to_remesh_queue.push_region(min, min + IVec3::splat(CHUNK_SIZE as i32 - 1));
+
+                let player_chunk = match player_query.get_single() {
+                    Ok((transform, _, _)) => get_chunk_from_player_pos(transform.translation),
+                    Err(_) => continue,
+                };
+                if chunk_map.get(&position).1.is_none()
+                    && lod::is_full_detail(position, player_chunk, &lod_distances)
+                {
+                    chunk_to_generate_queue.0.push(position);
+                }
+            }
+            ServerMessage::BlockChange { block, state } => {
+                if voxel_map.get(block) != state && voxel_map.set(block, state) {
+                    chunk_to_remesh_queue.push_block(block);
+                }
+            }
+            ServerMessage::SpawnEntity {
+                entity_id,
+                kind: EntityKind::Player { name },
+                position,
+            } => {
+                let width = PLAYER_SIZE.half_width * 2.0;
+                commands
+                    .spawn_bundle(PbrBundle {
+                        mesh: meshes.add(Mesh::from(shape::Box::new(
+                            width,
+                            PLAYER_SIZE.height,
+                            width,
+                        ))),
+                        material: materials.add(Color::rgb(0.2, 0.4, 0.8).into()),
+                        transform: Transform::from_translation(body_position(position)),
+                        ..Default::default()
+                    })
+                    .insert(Name::new(name))
+                    .insert(RemotePlayer::new(entity_id));
+            }
+            ServerMessage::MoveEntity {
+                entity_id,
+                position,
+                yaw,
+                ..
+            } => {
+                for (_, mut remote_player) in remote_query.iter_mut() {
+                    if remote_player.id == entity_id {
+                        remote_player.push_snapshot(
+                            time.seconds_since_startup(),
+                            body_position(position),
+                            yaw,
+                        );
+                    }
+                }
+            }
+            ServerMessage::DespawnEntity { entity_id } => {
+                for (entity, remote_player) in remote_query.iter() {
+                    if remote_player.id == entity_id {
+                        commands.entity(entity).despawn_recursive();
+                    }
+                }
+            }
+            ServerMessage::Chat { text } => console.print(&text),
+        }
+    }
+}
+
+// Center of the body of a player whose camera is at `position`
+fn body_position(position: Vec3) -> Vec3 {
+    position + Vec3::Y * (PLAYER_SIZE.height / 2.0 - EYE_HEIGHT)
+}
+
+/// Moves remote players smoothly between the positions the server sent, `INTERPOLATION_DELAY`
+/// behind the latest one.
+pub fn interpolate_remote_players(
+    time: Res<Time>,
+    mut query: Query<(&mut RemotePlayer, &mut Transform)>,
+) {
+    let render_time = time.seconds_since_startup() - INTERPOLATION_DELAY;
+
+    for (mut remote_player, mut transform) in query.iter_mut() {
+        if let Some((position, rotation)) = remote_player.interpolate(render_time) {
+            transform.translation = position;
+            transform.rotation = rotation;
+        }
+    }
+}
+
+/// Forwards the blocks the player broke or placed to the server, which sends back the block
+/// as it really is if the edit is not allowed.
+pub fn send_block_edits(
+    client: Option<ResMut<NetworkClient>>,
+    mut block_edits: EventReader<BlockEdited>,
+) {
+    let mut client = match client {
+        Some(client) => client,
+        None => return,
+    };
+    for edit in block_edits.iter() {
+        client.send(&ClientMessage::SetBlock {
+            block: edit.block,
+            state: edit.state,
+        });
+    }
+}
+
+/// Sends this frame's movement inputs and teleports, already applied locally, and flushes
+/// everything queued for the server.
+pub fn send_predictions(
+    client: Option<ResMut<NetworkClient>>,
+    mut inputs: EventReader<MovementInput>,
+    mut teleports: EventReader<Teleport>,
+) {
+    let mut client = match client {
+        Some(client) => client,
+        None => return,
+    };
+    for input in inputs.iter() {
+        client.send_prediction(Prediction::Input(*input));
+    }
+    // Teleports happen after the movement of the frame
+    for teleport in teleports.iter() {
+        client.send_prediction(Prediction::Teleport(teleport.position));
+    }
+    // A broken connection is noticed when receiving
+    if let Err(error) = client.flush() {
+        warn!("Could not send to {}: {}", client.address, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Server;
+    use crate::settings::MIN_WORLD_SIZE;
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+    use std::thread;
+
+    const SEED: u64 = 7;
+    const FRAME: f32 = 1.0 / 60.0;
+    // Frames spent joining and receiving the chunks around the player, walking forward, and
+    // standing still while the last states come back
+    const JOIN_FRAMES: usize = 60;
+    const WALK_FRAMES: usize = 60;
+    const SETTLE_FRAMES: usize = 30;
+
+    fn fly(forward: bool) -> Prediction {
+        Prediction::Input(MovementInput {
+            delta: FRAME,
+            forward,
+            back: !forward,
+            flying: true,
+            fly_speed: 60.0,
+            ..Default::default()
+        })
+    }
+
+    // Where reconciling with the server's state after `sequence` puts the player
+    fn reconcile(predictions: &mut PendingPredictions, sequence: u32, server: Vec3) -> Vec3 {
+        let voxel_map = VoxelMap::new(SEED, MIN_WORLD_SIZE);
+        let block_models = BlockModels::new();
+        let physics = PlayerPhysics::default();
+        predictions
+            .reconcile(sequence, server, physics, &voxel_map, &block_models)
+            .0
+    }
+
+    #[test]
+    fn reconciling_replays_what_the_server_has_not_applied() {
+        let mut predictions = PendingPredictions::new();
+        let sequences: Vec<u32> = [fly(false), fly(true), fly(true)]
+            .into_iter()
+            .map(|prediction| predictions.push(prediction))
+            .collect();
+        assert_eq!(sequences, vec![1, 2, 3]);
+
+        // The server has applied the first input and put the player somewhere else
+        let server = Vec3::new(3.0, 70.0, 3.0);
+        let mut expected = PendingPredictions::new();
+        expected.push(fly(true));
+        expected.push(fly(true));
+        let replayed = reconcile(&mut expected, 0, server);
+        assert_eq!(reconcile(&mut predictions, 1, server), replayed);
+        assert_ne!(replayed, server);
+        assert_eq!(predictions.unacknowledged.len(), 2);
+
+        // Old states do not bring back what was forgotten
+        assert_eq!(reconcile(&mut predictions, 0, server), replayed);
+        assert_eq!(reconcile(&mut predictions, 3, server), server);
+        assert!(predictions.is_empty());
+    }
+
+    #[test]
+    fn teleports_replace_the_position() {
+        let mut predictions = PendingPredictions::new();
+        predictions.push(fly(true));
+        predictions.push(Prediction::Teleport(Vec3::Y * 90.0));
+        predictions.push(fly(false));
+        let mut expected = PendingPredictions::new();
+        expected.push(fly(false));
+
+        let server = Vec3::new(3.0, 70.0, 3.0);
+        assert_eq!(
+            reconcile(&mut predictions, 0, server),
+            reconcile(&mut expected, 0, Vec3::Y * 90.0)
+        );
+    }
+
+    #[test]
+    fn sequence_numbers_wrap_around() {
+        let mut predictions = PendingPredictions::new();
+        predictions.next_sequence = u32::MAX;
+        assert_eq!(predictions.push(fly(true)), u32::MAX);
+        assert_eq!(predictions.push(fly(true)), 0);
+        assert_eq!(predictions.push(fly(true)), 1);
+
+        reconcile(&mut predictions, 0, Vec3::ZERO);
+        assert_eq!(predictions.unacknowledged.len(), 1);
+        assert_eq!(predictions.unacknowledged[0].0, 1);
+    }
+
+    #[test]
+    fn remote_players_move_between_snapshots() {
+        let mut remote_player = RemotePlayer::new(2);
+        assert_eq!(remote_player.interpolate(0.0), None);
+
+        remote_player.push_snapshot(1.0, Vec3::ZERO, 0.0);
+        remote_player.push_snapshot(2.0, Vec3::X * 10.0, FRAC_PI_2);
+        // Before the second snapshot is due they stay where they were first seen
+        let (position, rotation) = remote_player.interpolate(0.5).unwrap();
+        assert_eq!(position, Vec3::ZERO);
+        assert_eq!(rotation, Quat::IDENTITY);
+
+        let (position, rotation) = remote_player.interpolate(1.5).unwrap();
+        assert!(position.distance(Vec3::X * 5.0) < 1e-5);
+        assert!(rotation.angle_between(Quat::from_rotation_y(FRAC_PI_4)) < 1e-3);
+        // Past the latest snapshot they wait there for the next one
+        let (position, _) = remote_player.interpolate(3.0).unwrap();
+        assert_eq!(position, Vec3::X * 10.0);
+
+        remote_player.push_snapshot(3.0, Vec3::X * 20.0, FRAC_PI_2);
+        let (position, _) = remote_player.interpolate(2.25).unwrap();
+        assert!(position.distance(Vec3::X * 12.5) < 1e-5);
+        assert_eq!(remote_player.snapshots.len(), 2);
+    }
+
+    #[test]
+    fn reconciling_under_latency_converges_without_snapping() {
+        let mut server = Server::bind("127.0.0.1:0", SEED, MIN_WORLD_SIZE).unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let mut client = NetworkClient::connect(&address, "Alice", 1).unwrap();
+        client.latency = SimulatedLatency::new(Duration::from_millis(100));
+        let mut voxel_map = VoxelMap::new(SEED, MIN_WORLD_SIZE);
+        let block_models = BlockModels::new();
+
+        let mut spawn = Vec3::ZERO;
+        let mut position = Vec3::ZERO;
+        let mut physics = PlayerPhysics::default();
+        let mut server_position = None;
+        let mut largest_correction: f32 = 0.0;
+        for frame in 0..JOIN_FRAMES + WALK_FRAMES + SETTLE_FRAMES {
+            if (JOIN_FRAMES..JOIN_FRAMES + WALK_FRAMES).contains(&frame) {
+                let input = MovementInput {
+                    delta: FRAME,
+                    forward: true,
+                    ..Default::default()
+                };
+                player::step_movement(
+                    &mut position,
+                    &mut physics,
+                    &input,
+                    &block_models,
+                    |block| voxel_map.get(block),
+                );
+                client.send_prediction(Prediction::Input(input));
+            }
+            client.flush().unwrap();
+            server.tick();
+
+            for message in client.receive().unwrap() {
+                match message {
+                    ServerMessage::Welcome {
+                        position: welcome_position,
+                        ..
+                    } => {
+                        spawn = welcome_position;
+                        position = spawn;
+                    }
+                    ServerMessage::Chunk {
+                        position: chunk_pos,
+                        blocks,
+                    } => voxel_map.set_chunk_blocks(chunk_pos, &blocks),
+                    ServerMessage::PlayerState {
+                        sequence,
+                        position: state_position,
+                        physics: state_physics,
+                    } => {
+                        let (reconciled, reconciled_physics) = client.reconcile(
+                            sequence,
+                            state_position,
+                            state_physics,
+                            &voxel_map,
+                            &block_models,
+                        );
+                        largest_correction = largest_correction.max(reconciled.distance(position));
+                        position = reconciled;
+                        physics = reconciled_physics;
+                        server_position = Some(state_position);
+                    }
+                    _ => (),
+                }
+            }
+            thread::sleep(Duration::from_secs_f32(FRAME));
+        }
+
+        assert!(
+            largest_correction < 1e-4,
+            "Snapped by {}",
+            largest_correction
+        );
+        assert!(position.distance(spawn) > 1.0, "Did not walk");
+        assert!(client.predictions.is_empty());
+        assert_eq!(server_position, Some(position));
+    }
+}
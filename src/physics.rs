@@ -0,0 +1,233 @@
+use crate::block_models::Cuboid;
+use crate::voxel_data::SUBVOXELS;
+use bevy::prelude::*;
+
+// Blocks per second squared
+pub const GRAVITY: f32 = 32.0;
+pub const TERMINAL_VELOCITY: f32 = 78.4;
+// Gap kept between a box and the blocks it rests against, so it does not count as inside them
+const SKIN: f32 = 0.001;
+
+/// Box standing on its position: `half_width` out to each side horizontally and `height` up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub half_width: f32,
+    pub height: f32,
+}
+
+/// Outcome of `move_and_collide`: where the box ended up and along which axes it was stopped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Collision {
+    pub position: Vec3,
+    pub blocked: [bool; 3],
+    pub on_ground: bool,
+}
+
+impl Aabb {
+    // Extent of the box below and above its position along `axis`
+    fn extent(&self, axis: usize) -> (f32, f32) {
+        if axis == 1 {
+            (0.0, self.height)
+        } else {
+            (-self.half_width, self.half_width)
+        }
+    }
+
+    /// Blocks the box at `position` overlaps.
+    pub fn blocks(&self, position: Vec3) -> impl Iterator<Item = IVec3> {
+        let mut min = IVec3::ZERO;
+        let mut max = IVec3::ZERO;
+        for axis in 0..3 {
+            let (low, high) = self.extent(axis);
+            min[axis] = (position[axis] + low + SKIN).floor() as i32;
+            max[axis] = (position[axis] + high - SKIN).floor() as i32;
+        }
+        (min.x..=max.x).flat_map(move |x| {
+            (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z)))
+        })
+    }
+
+    /// Whether the box at `position` overlaps any of the boxes of the block at world block
+    /// position `block`.
+    pub fn intersects_block(&self, position: Vec3, block: IVec3, boxes: &[Cuboid]) -> bool {
+        boxes.iter().any(|cuboid| {
+            let (from, to) = cuboid_bounds(block, cuboid);
+            (0..3).all(|axis| {
+                let (low, high) = self.extent(axis);
+                position[axis] + high > from[axis] && position[axis] + low < to[axis]
+            })
+        })
+    }
+}
+
+// Corners of a box of the block at world block position `block`, in world units
+fn cuboid_bounds(block: IVec3, cuboid: &Cuboid) -> (Vec3, Vec3) {
+    let corner = |sixteenths: [u32; 3]| {
+        block.as_vec3() + Vec3::from(sixteenths.map(|value| value as f32)) / SUBVOXELS as f32
+    };
+    (corner(cuboid.from), corner(cuboid.to))
+}
+
+/// Moves a box by `motion` one axis at a time, vertical first, stopping it against the boxes
+/// `collision_boxes` gives for each block. Boxes the moving box already overlaps are ignored so
+/// it can get out.
+pub fn move_and_collide(
+    mut position: Vec3,
+    motion: Vec3,
+    size: Aabb,
+    collision_boxes: impl Fn(IVec3) -> Vec<Cuboid>,
+) -> Collision {
+    let mut blocked = [false; 3];
+
+    for axis in [1, 0, 2] {
+        if motion[axis] == 0.0 {
+            continue;
+        }
+        let (low, high) = size.extent(axis);
+        let mut target = position[axis] + motion[axis];
+
+        // Blocks swept along the axis, across the cross section of the box
+        let (start, end) = if motion[axis] > 0.0 {
+            (position[axis] + high, target + high)
+        } else {
+            (target + low, position[axis] + low)
+        };
+        let mut min = IVec3::ZERO;
+        let mut max = IVec3::ZERO;
+        for other in 0..3 {
+            let (other_low, other_high) = size.extent(other);
+            if other == axis {
+                min[other] = start.floor() as i32;
+                max[other] = (end - SKIN).floor() as i32;
+            } else {
+                min[other] = (position[other] + other_low + SKIN).floor() as i32;
+                max[other] = (position[other] + other_high - SKIN).floor() as i32;
+            }
+        }
+
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let block = IVec3::new(x, y, z);
+                    for cuboid in collision_boxes(block).iter() {
+                        let (from, to) = cuboid_bounds(block, cuboid);
+                        // Boxes smaller than the block can miss the cross section
+                        let across = (0..3).filter(|other| *other != axis).all(|other| {
+                            let (other_low, other_high) = size.extent(other);
+                            position[other] + other_high - SKIN > from[other]
+                                && position[other] + other_low + SKIN < to[other]
+                        });
+                        if !across {
+                            continue;
+                        }
+                        let limit = if motion[axis] > 0.0
+                            && from[axis] >= position[axis] + high - SKIN
+                        {
+                            from[axis] - high - SKIN
+                        } else if motion[axis] < 0.0 && to[axis] <= position[axis] + low + SKIN {
+                            to[axis] - low + SKIN
+                        } else {
+                            continue;
+                        };
+                        // Boxes in the last block swept can be beyond the target
+                        if (limit - target) * motion[axis].signum() <= 0.0 {
+                            target = limit;
+                            blocked[axis] = true;
+                        }
+                    }
+                }
+            }
+        }
+        position[axis] = target;
+    }
+
+    Collision {
+        position,
+        blocked,
+        on_ground: blocked[1] && motion.y < 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_models::BlockModels;
+    use crate::block_types::{block_by_name, BlockState};
+
+    const SIZE: Aabb = Aabb {
+        half_width: 0.3,
+        height: 1.8,
+    };
+
+    // Falls a box from above the middle of the block at the origin, which is `name`, with stone
+    // below it
+    fn land_on(name: &str) -> Collision {
+        let block_models = BlockModels::new();
+        let state = BlockState::new(block_by_name(name).unwrap());
+        let stone = BlockState::new(block_by_name("stone").unwrap());
+        move_and_collide(
+            Vec3::new(0.5, 3.0, 0.5),
+            Vec3::new(0.0, -10.0, 0.0),
+            SIZE,
+            |block| match block.y {
+                0 if block.x == 0 && block.z == 0 => block_models.collision_boxes(state),
+                -1 => block_models.collision_boxes(stone),
+                _ => Vec::new(),
+            },
+        )
+    }
+
+    #[test]
+    fn lands_on_top_of_full_blocks() {
+        let collision = land_on("stone");
+        assert!(collision.on_ground);
+        assert!((collision.position.y - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn lands_on_top_of_slabs() {
+        let collision = land_on("stone_slab");
+        assert!(collision.on_ground);
+        assert!((collision.position.y - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn sinks_through_liquids_and_portals() {
+        for name in ["water", "lava", "portal", "tall_grass"] {
+            let collision = land_on(name);
+            assert!(collision.on_ground, "{}", name);
+            assert!(collision.position.y.abs() < 0.01, "{}", name);
+        }
+    }
+
+    #[test]
+    fn stops_against_the_rails_of_fences() {
+        let block_models = BlockModels::new();
+        let fence = BlockState::new(block_by_name("fence").unwrap());
+        let collision =
+            move_and_collide(Vec3::new(-1.0, 0.0, 0.05), Vec3::X * 3.0, SIZE, |block| {
+                if block == IVec3::ZERO {
+                    block_models.collision_boxes(fence)
+                } else {
+                    Vec::new()
+                }
+            });
+        // Beside the post and the rails along x, up to the rail along z 7 sixteenths in
+        assert!(collision.blocked[0]);
+        assert!((collision.position.x - (7.0 / 16.0 - SIZE.half_width)).abs() < 0.01);
+    }
+
+    #[test]
+    fn boxes_overlap_the_blocks_they_reach_into() {
+        let blocks: Vec<IVec3> = SIZE.blocks(Vec3::new(0.5, 1.0, 0.9)).collect();
+        assert_eq!(
+            blocks,
+            vec![
+                IVec3::new(0, 1, 0),
+                IVec3::new(0, 1, 1),
+                IVec3::new(0, 2, 0),
+                IVec3::new(0, 2, 1),
+            ]
+        );
+    }
+}
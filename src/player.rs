@@ -0,0 +1,450 @@
+use crate::block_models::BlockModels;
+use crate::block_types::BlockState;
+use crate::console::Console;
+use crate::input_map::{Action, Actions};
+use crate::inventory_ui::InventoryScreen;
+use crate::level::Level;
+use crate::network_client::NetworkClient;
+use crate::physics::{self, Aabb, GRAVITY, TERMINAL_VELOCITY};
+use crate::voxel_data::CHUNK_SIZE;
+use crate::voxel_map::{TerrainGenerator, VoxelMap};
+use crate::world::{get_chunk_from_player_pos, ChunkMap};
+use crate::world_select::WorldSelectScreen;
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use serde::{Deserialize, Serialize};
+
+pub const PLAYER_SIZE: Aabb = Aabb {
+    half_width: 0.3,
+    height: 1.8,
+};
+// Height of the camera above the player's feet
+pub const EYE_HEIGHT: f32 = 1.62;
+pub const MAX_HEALTH: f32 = 20.0;
+// Falls up to this many blocks are harmless, every further block costs a health point
+pub const SAFE_FALL_DISTANCE: f32 = 3.0;
+// Blocks per second
+pub const WALK_SPEED: f32 = 4.3;
+pub const JUMP_SPEED: f32 = 8.4;
+const MAX_PITCH: f32 = 1.54;
+// Degrees per second at full right stick deflection
+const GAMEPAD_LOOK_SPEED: f32 = 180.0;
+// Longest movement step in seconds, longer frames move the player less rather than through walls
+pub const MAX_STEP: f32 = 0.25;
+
+/// Creative players fly through blocks, place from infinite stacks and break blocks instantly.
+/// Survival players walk, take fall damage and break blocks over time into drops.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameMode {
+    Creative,
+    Survival,
+}
+
+/// Camera position new players start at and dead players respawn at, above the dry land the
+/// terrain generator picked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldSpawn(pub Vec3);
+
+pub struct MovementSettings {
+    pub sensitivity: f32,
+    pub fly_speed: f32,
+}
+
+/// Camera angles in radians, yaw around the vertical axis and pitch up from the horizon.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct PlayerLook {
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlayerPhysics {
+    pub velocity: Vec3,
+    pub on_ground: bool,
+    // Blocks fallen since last standing on the ground
+    pub fall_distance: f32,
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Health(pub f32);
+
+/// What the player asked to do over one frame, enough to replay the movement anywhere with
+/// `step_movement`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MovementInput {
+    /// Seconds the input lasted.
+    pub delta: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub forward: bool,
+    pub back: bool,
+    pub left: bool,
+    pub right: bool,
+    pub jump: bool,
+    pub sneak: bool,
+    /// Creative flight, at `fly_speed` blocks per second.
+    pub flying: bool,
+    pub fly_speed: f32,
+}
+
+/// The player was moved somewhere rather than walked there, by respawning or `/tp`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Teleport {
+    pub position: Vec3,
+}
+
+impl WorldSpawn {
+    /// Spawn point of the map's world, on dry land within the map if there is any.
+    pub fn new(voxel_map: &VoxelMap) -> Self {
+        let radius = (voxel_map.size_in_chunks * CHUNK_SIZE / 2) as i32 - 1;
+        let surface = TerrainGenerator::new(voxel_map.seed).find_spawn(radius);
+        WorldSpawn(surface.as_vec3() + Vec3::new(0.5, 1.0 + EYE_HEIGHT, 0.5))
+    }
+}
+
+impl PlayerLook {
+    pub fn from_rotation(rotation: Quat) -> Self {
+        let (yaw, pitch, _) = rotation.to_euler(EulerRot::YXZ);
+        PlayerLook { yaw, pitch }
+    }
+
+    pub fn rotation(&self) -> Quat {
+        Quat::from_rotation_y(self.yaw) * Quat::from_rotation_x(self.pitch)
+    }
+}
+
+pub fn grab_cursor(mut windows: ResMut<Windows>) {
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(true);
+        window.set_cursor_visibility(false);
+    }
+}
+
+pub fn toggle_cursor_grab(
+    actions: Res<Actions>,
+    inventory_screen: Res<InventoryScreen>,
+    console: Res<Console>,
+    mut windows: ResMut<Windows>,
+) {
+    if !actions.just_pressed(Action::ReleaseCursor) || inventory_screen.open || console.open {
+        return;
+    }
+    if let Some(window) = windows.get_primary_mut() {
+        let locked = !window.cursor_locked();
+        window.set_cursor_lock_mode(locked);
+        window.set_cursor_visibility(!locked);
+    }
+}
+
+pub fn player_look(
+    settings: Res<MovementSettings>,
+    windows: Res<Windows>,
+    time: Res<Time>,
+    actions: Res<Actions>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut query: Query<(&mut PlayerLook, &mut Transform), With<super::Player>>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let delta = mouse_motion
+        .iter()
+        .fold(Vec2::ZERO, |delta, event| delta + event.delta);
+    if !window.cursor_locked() {
+        return;
+    }
+    // Scaled by the window size so the sensitivity feels the same at any resolution
+    let scale = window.width().min(window.height()) * settings.sensitivity;
+    // In degrees like the mouse, and down positive like its motion
+    let turn = delta * scale
+        + actions.look() * Vec2::new(1.0, -1.0) * GAMEPAD_LOOK_SPEED * time.delta_seconds();
+    if turn == Vec2::ZERO {
+        return;
+    }
+
+    for (mut look, mut transform) in query.iter_mut() {
+        look.yaw -= turn.x.to_radians();
+        look.pitch = (look.pitch - turn.y.to_radians()).clamp(-MAX_PITCH, MAX_PITCH);
+        transform.rotation = look.rotation();
+    }
+}
+
+pub fn toggle_game_mode(
+    actions: Res<Actions>,
+    network_client: Option<Res<NetworkClient>>,
+    mut query: Query<(&mut GameMode, &mut PlayerPhysics), With<super::Player>>,
+) {
+    if !actions.just_pressed(Action::ToggleGameMode) {
+        return;
+    }
+    if network_client.is_some() {
+        info!("The server decides the game mode");
+        return;
+    }
+    for (mut game_mode, mut physics) in query.iter_mut() {
+        *game_mode = match *game_mode {
+            GameMode::Creative => GameMode::Survival,
+            GameMode::Survival => GameMode::Creative,
+        };
+        *physics = PlayerPhysics::default();
+        info!("Game mode set to {:?}", *game_mode);
+    }
+}
+
+impl MovementInput {
+    /// Input held down over the last `delta` seconds, none of it while `typing`.
+    pub fn from_actions(actions: &Actions, typing: bool, look: &PlayerLook, delta: f32) -> Self {
+        let pressed = |action| !typing && actions.pressed(action);
+        MovementInput {
+            delta: delta.min(MAX_STEP),
+            yaw: look.yaw,
+            pitch: look.pitch,
+            forward: pressed(Action::MoveForward),
+            back: pressed(Action::MoveBack),
+            left: pressed(Action::MoveLeft),
+            right: pressed(Action::MoveRight),
+            jump: pressed(Action::Jump),
+            sneak: pressed(Action::Sneak),
+            flying: false,
+            fly_speed: 0.0,
+        }
+    }
+
+    /// Direction the movement input points to, horizontal and relative to the camera yaw.
+    pub fn direction(&self) -> Vec3 {
+        let rotation = Quat::from_rotation_y(self.yaw);
+        let forward = rotation * -Vec3::Z;
+        let right = rotation * Vec3::X;
+        let mut direction = Vec3::ZERO;
+
+        if self.forward {
+            direction += forward;
+        }
+        if self.back {
+            direction -= forward;
+        }
+        if self.right {
+            direction += right;
+        }
+        if self.left {
+            direction -= right;
+        }
+        direction.normalize_or_zero()
+    }
+}
+
+/// Moves a player with its camera at `position` by one input, flying straight through blocks
+/// or walking with gravity and bumping into the collision boxes of the blocks `block_at` gives.
+/// Returns the fall damage taken on landing; falling through liquids does not count. The client predicts its player with this and the
+/// server runs the same inputs through it, so both end up in the same place.
+pub fn step_movement(
+    position: &mut Vec3,
+    physics: &mut PlayerPhysics,
+    input: &MovementInput,
+    block_models: &BlockModels,
+    block_at: impl Fn(IVec3) -> BlockState,
+) -> f32 {
+    let mut direction = input.direction();
+    let delta = input.delta;
+
+    if input.flying {
+        if input.jump {
+            direction += Vec3::Y;
+        }
+        if input.sneak {
+            direction -= Vec3::Y;
+        }
+        *position += direction.normalize_or_zero() * input.fly_speed * delta;
+        *physics = PlayerPhysics::default();
+        return 0.0;
+    }
+
+    physics.velocity.x = direction.x * WALK_SPEED;
+    physics.velocity.z = direction.z * WALK_SPEED;
+    if physics.on_ground && input.jump {
+        physics.velocity.y = JUMP_SPEED;
+    }
+    physics.velocity.y = (physics.velocity.y - GRAVITY * delta).max(-TERMINAL_VELOCITY);
+
+    let feet = *position - Vec3::Y * EYE_HEIGHT;
+    let collision =
+        physics::move_and_collide(feet, physics.velocity * delta, PLAYER_SIZE, |block| {
+            block_models.collision_boxes(block_at(block))
+        });
+    *position = collision.position + Vec3::Y * EYE_HEIGHT;
+
+    let mut damage = 0.0;
+    let in_liquid = PLAYER_SIZE
+        .blocks(collision.position)
+        .any(|block| block_at(block).is_liquid());
+    if in_liquid {
+        physics.fall_distance = 0.0;
+    } else if collision.position.y < feet.y {
+        physics.fall_distance += feet.y - collision.position.y;
+    }
+    if collision.blocked[1] {
+        physics.velocity.y = 0.0;
+    }
+    if collision.on_ground {
+        damage = (physics.fall_distance - SAFE_FALL_DISTANCE)
+            .floor()
+            .max(0.0);
+        physics.fall_distance = 0.0;
+    }
+    physics.on_ground = collision.on_ground;
+    damage
+}
+
+/// Flies creative players freely. Survival players walk with gravity and collisions, and take
+/// damage when landing from high up unless the world turns that off; they stay put until the
+/// chunk they are in is generated.
+/// Every input that moved the player is sent on as an event.
+#[allow(clippy::too_many_arguments)]
+pub fn move_player(
+    actions: Res<Actions>,
+    time: Res<Time>,
+    settings: Res<MovementSettings>,
+    inventory_screen: Res<InventoryScreen>,
+    console: Res<Console>,
+    world_select_screen: Res<WorldSelectScreen>,
+    voxel_map: Res<VoxelMap>,
+    block_models: Res<BlockModels>,
+    chunk_map: Res<ChunkMap>,
+    level: Res<Level>,
+    mut inputs: EventWriter<MovementInput>,
+    mut query: Query<
+        (
+            &mut Transform,
+            &PlayerLook,
+            &GameMode,
+            &mut PlayerPhysics,
+            &mut Health,
+        ),
+        With<super::Player>,
+    >,
+) {
+    let delta = time.delta_seconds();
+    // Keys typed into a screen do not move the player
+    let typing = inventory_screen.open || console.open || world_select_screen.open;
+
+    for (mut transform, look, game_mode, mut physics, mut health) in query.iter_mut() {
+        let mut input = MovementInput::from_actions(&actions, typing, look, delta);
+
+        if *game_mode == GameMode::Creative {
+            input.flying = true;
+            input.fly_speed = settings.fly_speed;
+        } else {
+            let chunk_pos = get_chunk_from_player_pos(transform.translation);
+            if voxel_map.contains_chunk(&chunk_pos) && chunk_map.get(&chunk_pos).0.is_none() {
+                continue;
+            }
+        }
+
+        let damage = step_movement(
+            &mut transform.translation,
+            &mut physics,
+            &input,
+            &block_models,
+            |block| voxel_map.get(block),
+        );
+        if level.game_rules.fall_damage {
+            health.0 -= damage;
+        }
+        inputs.send(input);
+    }
+}
+
+/// Sends players that ran out of health back to the spawn point.
+pub fn respawn_players(
+    world_spawn: Res<WorldSpawn>,
+    mut teleports: EventWriter<Teleport>,
+    mut query: Query<(&mut Transform, &mut Health, &mut PlayerPhysics), With<super::Player>>,
+) {
+    for (mut transform, mut health, mut physics) in query.iter_mut() {
+        if health.0 <= 0.0 {
+            info!("Player died, respawning");
+            transform.translation = world_spawn.0;
+            health.0 = MAX_HEALTH;
+            *physics = PlayerPhysics::default();
+            teleports.send(Teleport {
+                position: world_spawn.0,
+            });
+        }
+    }
+}
+
+pub fn draw_health(
+    mut egui_context: ResMut<EguiContext>,
+    query: Query<(&Health, &GameMode), With<super::Player>>,
+) {
+    let (health, game_mode) = match query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    if *game_mode != GameMode::Survival {
+        return;
+    }
+
+    egui::Area::new("health")
+        .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -60.0])
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.add(
+                egui::ProgressBar::new(health.0 / MAX_HEALTH)
+                    .desired_width(200.0)
+                    .text(format!("Health {}/{}", health.0, MAX_HEALTH)),
+            );
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_types::block_by_name;
+
+    // Drops a survival player from 20.5 blocks up onto stone, through `fill` down to it, and
+    // returns the damage taken on landing
+    fn fall_through(fill: &str) -> f32 {
+        let block_models = BlockModels::new();
+        let stone = BlockState::new(block_by_name("stone").unwrap());
+        let fill = BlockState::new(block_by_name(fill).unwrap());
+        let mut position = Vec3::new(0.5, 21.5 + EYE_HEIGHT, 0.5);
+        let mut physics = PlayerPhysics::default();
+        let input = MovementInput {
+            delta: 1.0 / 60.0,
+            ..Default::default()
+        };
+
+        for _ in 0..600 {
+            let damage = step_movement(
+                &mut position,
+                &mut physics,
+                &input,
+                &block_models,
+                |block| match block.y {
+                    0 => stone,
+                    1..=20 => fill,
+                    _ => BlockState::AIR,
+                },
+            );
+            if physics.on_ground {
+                assert!((position.y - 1.0 - EYE_HEIGHT).abs() < 0.01);
+                return damage;
+            }
+        }
+        panic!("never landed");
+    }
+
+    #[test]
+    fn falling_hurts() {
+        assert_eq!(fall_through("air"), (20.5 - SAFE_FALL_DISTANCE).floor());
+    }
+
+    #[test]
+    fn sinking_through_liquids_does_not_hurt() {
+        assert_eq!(fall_through("water"), 0.0);
+        assert_eq!(fall_through("lava"), 0.0);
+    }
+}
@@ -0,0 +1,1037 @@
+//! Packets exchanged between the client and the dedicated server.
+//!
+//! A connection is a TCP stream of frames, each a little endian u32 length followed by that
+//! many bytes of packet. A packet starts with its tag, a u8, followed by its fields in order.
+//! Numbers are little endian, `f32` is IEEE 754, vectors are their x, y and z in turn, strings
+//! are a u32 byte length and UTF-8, and block states are their u16 value.
+//!
+//! The client opens with `ClientMessage::Handshake`. If its `protocol_version` is not
+//! `PROTOCOL_VERSION` the server answers with `ServerMessage::Disconnect` and closes the
+//! connection, otherwise with `ServerMessage::Welcome`. These three packets keep their tag and
+//! layout in every version, so mismatched versions can always tell each other apart. The server
+//! sends `ServerMessage::KeepAlive` every `KEEPALIVE_INTERVAL` seconds and the client echoes
+//! it back; either side hangs up after `TIMEOUT` seconds without hearing anything.
+//!
+//! Chunks are palette encoded: the distinct block states of the chunk as a u16 count and the
+//! states, then for every block its index into the palette in as few bits as fit the largest
+//! index, least significant bit first. The whole is compressed with DEFLATE and sent as a u32
+//! length and the compressed bytes.
+
+use crate::block_types::BlockState;
+use crate::player::{GameMode, MovementInput, PlayerPhysics};
+use crate::voxel_data::CHUNK_SIZE;
+use crate::world::ChunkCoord;
+use bevy::prelude::*;
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+// Bumped whenever a packet changes
+pub const PROTOCOL_VERSION: u16 = 4;
+pub const DEFAULT_PORT: u16 = 25580;
+// Larger frames are treated as a broken connection rather than buffered
+pub const MAX_FRAME_SIZE: usize = 1 << 20;
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+// In seconds
+pub const KEEPALIVE_INTERVAL: f32 = 5.0;
+pub const TIMEOUT: f32 = 30.0;
+// DEFLATE level of chunk data, 1 to 10
+pub const CHUNK_COMPRESSION_LEVEL: u8 = 6;
+
+/// What clients ask the server for. The server decides what actually happens.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    /// Tag 0. `view_distance` is how far around the player to send chunks, in chunks.
+    Handshake {
+        protocol_version: u16,
+        name: String,
+        view_distance: u32,
+    },
+    /// Tag 1, the `id` of the `ServerMessage::KeepAlive` being answered.
+    KeepAlive { id: u32 },
+    /// Tag 2, one frame of movement. `sequence` counts up with every input and teleport, so
+    /// the server can say which it has applied.
+    Input { sequence: u32, input: MovementInput },
+    /// Tag 3.
+    SetBlock { block: IVec3, state: BlockState },
+    /// Tag 4.
+    Chat { text: String },
+    /// Tag 5, the player respawned at the camera `position`. The server refuses any other
+    /// teleport.
+    Teleport { sequence: u32, position: Vec3 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerMessage {
+    /// Tag 0, sent before the server closes the connection.
+    Disconnect { reason: String },
+    /// Tag 1, the handshake was accepted. `position` is where the player starts.
+    Welcome { player_id: u32, position: Vec3 },
+    /// Tag 2.
+    KeepAlive { id: u32 },
+    /// Tag 3, the blocks of a chunk in the order of `VoxelMap::chunk_blocks`, palette encoded
+    /// and compressed on the wire.
+    Chunk {
+        position: ChunkCoord,
+        blocks: Vec<BlockState>,
+    },
+    /// Tag 4.
+    BlockChange { block: IVec3, state: BlockState },
+    /// Tag 5, the kind is a tag of its own followed by its fields.
+    SpawnEntity {
+        entity_id: u32,
+        kind: EntityKind,
+        position: Vec3,
+    },
+    /// Tag 6.
+    MoveEntity {
+        entity_id: u32,
+        position: Vec3,
+        yaw: f32,
+        pitch: f32,
+    },
+    /// Tag 7.
+    DespawnEntity { entity_id: u32 },
+    /// Tag 8.
+    Chat { text: String },
+    /// Tag 9, where the server has the player after applying every input up to `sequence`.
+    /// The physics are the velocity, whether on the ground as a u8 and the fall distance.
+    PlayerState {
+        sequence: u32,
+        position: Vec3,
+        physics: PlayerPhysics,
+    },
+    /// Tag 10, sent after `Welcome`: the seed the terrain is generated from, which clients
+    /// need for the distant LOD terrain, and the point players respawn at.
+    WorldInfo { seed: u64, spawn: Vec3 },
+    /// Tag 11, the player's game mode as a u8, 0 for survival and 1 for creative. Only the
+    /// server changes it, and only creative players fly.
+    GameMode { game_mode: GameMode },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntityKind {
+    /// Tag 0, another player, positioned by its camera.
+    Player { name: String },
+}
+
+/// A message that can be sent over a `Connection`.
+pub trait Message: Sized {
+    fn encode(&self, writer: &mut Writer);
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError>;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    UnknownTag(u8),
+    InvalidString,
+    InvalidLength(usize),
+    InvalidChunk,
+    TrailingBytes(usize),
+}
+
+/// Appends values in little endian.
+#[derive(Default)]
+pub struct Writer(pub Vec<u8>);
+
+/// Reads values written by a `Writer`.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+/// A TCP stream carrying messages in frames. Reads and writes never block, both are buffered
+/// until they can be done.
+pub struct Connection {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "packet ends early"),
+            DecodeError::UnknownTag(tag) => write!(f, "unknown tag {}", tag),
+            DecodeError::InvalidString => write!(f, "invalid UTF-8 string"),
+            DecodeError::InvalidLength(length) => write!(f, "invalid length {}", length),
+            DecodeError::InvalidChunk => write!(f, "invalid chunk data"),
+            DecodeError::TrailingBytes(count) => write!(f, "{} bytes after the packet", count),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Writer {
+    pub fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn i32(&mut self, value: i32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn vec3(&mut self, value: Vec3) {
+        for axis in 0..3 {
+            self.f32(value[axis]);
+        }
+    }
+
+    pub fn ivec3(&mut self, value: IVec3) {
+        for axis in 0..3 {
+            self.i32(value[axis]);
+        }
+    }
+
+    pub fn chunk_coord(&mut self, value: ChunkCoord) {
+        self.ivec3(IVec3::new(value.x, value.y, value.z));
+    }
+
+    pub fn block_state(&mut self, value: BlockState) {
+        self.u16(value.0);
+    }
+
+    /// The delta, yaw and pitch, the buttons as bits in the order of the fields, from the
+    /// least significant, then the fly speed.
+    pub fn movement_input(&mut self, value: &MovementInput) {
+        self.f32(value.delta);
+        self.f32(value.yaw);
+        self.f32(value.pitch);
+        let buttons = [
+            value.forward,
+            value.back,
+            value.left,
+            value.right,
+            value.jump,
+            value.sneak,
+            value.flying,
+        ];
+        let bits = buttons
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (index, pressed)| bits | (*pressed as u8) << index);
+        self.u8(bits);
+        self.f32(value.fly_speed);
+    }
+
+    pub fn player_physics(&mut self, value: &PlayerPhysics) {
+        self.vec3(value.velocity);
+        self.bool(value.on_ground);
+        self.f32(value.fall_distance);
+    }
+
+    pub fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    /// Length as a u32, then the bytes.
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value);
+    }
+
+    /// Indices of `bits` bits each, packed least significant bit first and padded to a whole
+    /// byte.
+    pub fn packed(&mut self, indices: impl IntoIterator<Item = usize>, bits: u32) {
+        let mut buffer: u64 = 0;
+        let mut buffered = 0;
+        for index in indices {
+            buffer |= (index as u64) << buffered;
+            buffered += bits;
+            while buffered >= 8 {
+                self.u8(buffer as u8);
+                buffer >>= 8;
+                buffered -= 8;
+            }
+        }
+        if buffered > 0 {
+            self.u8(buffer as u8);
+        }
+    }
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        if count > self.bytes.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, DecodeError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, DecodeError> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, DecodeError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    pub fn vec3(&mut self) -> Result<Vec3, DecodeError> {
+        Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    pub fn ivec3(&mut self) -> Result<IVec3, DecodeError> {
+        Ok(IVec3::new(self.i32()?, self.i32()?, self.i32()?))
+    }
+
+    pub fn chunk_coord(&mut self) -> Result<ChunkCoord, DecodeError> {
+        let position = self.ivec3()?;
+        Ok(ChunkCoord {
+            x: position.x,
+            y: position.y,
+            z: position.z,
+        })
+    }
+
+    pub fn block_state(&mut self) -> Result<BlockState, DecodeError> {
+        Ok(BlockState(self.u16()?))
+    }
+
+    pub fn movement_input(&mut self) -> Result<MovementInput, DecodeError> {
+        let (delta, yaw, pitch) = (self.f32()?, self.f32()?, self.f32()?);
+        let bits = self.u8()?;
+        let pressed = |index: u32| bits & 1 << index != 0;
+        Ok(MovementInput {
+            delta,
+            yaw,
+            pitch,
+            forward: pressed(0),
+            back: pressed(1),
+            left: pressed(2),
+            right: pressed(3),
+            jump: pressed(4),
+            sneak: pressed(5),
+            flying: pressed(6),
+            fly_speed: self.f32()?,
+        })
+    }
+
+    pub fn player_physics(&mut self) -> Result<PlayerPhysics, DecodeError> {
+        Ok(PlayerPhysics {
+            velocity: self.vec3()?,
+            on_ground: self.bool()?,
+            fall_distance: self.f32()?,
+        })
+    }
+
+    pub fn string(&mut self) -> Result<String, DecodeError> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidString)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let length = self.u32()? as usize;
+        if length > self.bytes.len() {
+            return Err(DecodeError::InvalidLength(length));
+        }
+        self.take(length)
+    }
+
+    /// `count` indices written by `Writer::packed`.
+    pub fn packed(&mut self, count: usize, bits: u32) -> Result<Vec<usize>, DecodeError> {
+        let mask = (1u64 << bits) - 1;
+        let mut bytes = self.take((count * bits as usize).div_ceil(8))?.iter();
+        let mut buffer: u64 = 0;
+        let mut buffered = 0;
+        let mut indices = Vec::with_capacity(count);
+        for _ in 0..count {
+            while buffered < bits {
+                buffer |= (*bytes.next().ok_or(DecodeError::UnexpectedEnd)? as u64) << buffered;
+                buffered += 8;
+            }
+            indices.push((buffer & mask) as usize);
+            buffer >>= bits;
+            buffered -= bits;
+        }
+        Ok(indices)
+    }
+}
+
+/// Bits needed to store indices into a palette of `length` entries.
+pub fn palette_bits(length: usize) -> u32 {
+    match length {
+        0 | 1 => 0,
+        _ => usize::BITS - (length - 1).leading_zeros(),
+    }
+}
+
+/// Palette encodes and compresses the blocks of a chunk.
+pub fn encode_chunk(blocks: &[BlockState]) -> Vec<u8> {
+    let mut palette: Vec<BlockState> = Vec::new();
+    let indices: Vec<usize> = blocks
+        .iter()
+        .map(
+            |block| match palette.iter().position(|entry| entry == block) {
+                Some(index) => index,
+                None => {
+                    palette.push(*block);
+                    palette.len() - 1
+                }
+            },
+        )
+        .collect();
+
+    let mut writer = Writer::default();
+    writer.u16(palette.len() as u16);
+    for entry in palette.iter() {
+        writer.block_state(*entry);
+    }
+
+    writer.packed(indices, palette_bits(palette.len()));
+
+    compress_to_vec(&writer.0, CHUNK_COMPRESSION_LEVEL)
+}
+
+/// Blocks of a chunk encoded by `encode_chunk`.
+pub fn decode_chunk(data: &[u8]) -> Result<Vec<BlockState>, DecodeError> {
+    // A palette of every block state with the indices takes less than this
+    let limit = 2 + 2 * CHUNK_VOLUME + 2 * CHUNK_VOLUME;
+    let raw = decompress_to_vec_with_limit(data, limit).map_err(|_| DecodeError::InvalidChunk)?;
+    let mut reader = Reader::new(&raw);
+
+    let length = reader.u16()? as usize;
+    if length == 0 || length > CHUNK_VOLUME {
+        return Err(DecodeError::InvalidChunk);
+    }
+    let palette = (0..length)
+        .map(|_| reader.block_state())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let indices = reader.packed(CHUNK_VOLUME, palette_bits(length))?;
+    if reader.remaining() > 0 {
+        return Err(DecodeError::TrailingBytes(reader.remaining()));
+    }
+    indices
+        .into_iter()
+        .map(|index| palette.get(index).copied().ok_or(DecodeError::InvalidChunk))
+        .collect()
+}
+
+impl Message for ClientMessage {
+    fn encode(&self, writer: &mut Writer) {
+        match self {
+            ClientMessage::Handshake {
+                protocol_version,
+                name,
+                view_distance,
+            } => {
+                writer.u8(0);
+                writer.u16(*protocol_version);
+                writer.string(name);
+                writer.u32(*view_distance);
+            }
+            ClientMessage::KeepAlive { id } => {
+                writer.u8(1);
+                writer.u32(*id);
+            }
+            ClientMessage::Input { sequence, input } => {
+                writer.u8(2);
+                writer.u32(*sequence);
+                writer.movement_input(input);
+            }
+            ClientMessage::SetBlock { block, state } => {
+                writer.u8(3);
+                writer.ivec3(*block);
+                writer.block_state(*state);
+            }
+            ClientMessage::Chat { text } => {
+                writer.u8(4);
+                writer.string(text);
+            }
+            ClientMessage::Teleport { sequence, position } => {
+                writer.u8(5);
+                writer.u32(*sequence);
+                writer.vec3(*position);
+            }
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(match reader.u8()? {
+            0 => ClientMessage::Handshake {
+                protocol_version: reader.u16()?,
+                name: reader.string()?,
+                view_distance: reader.u32()?,
+            },
+            1 => ClientMessage::KeepAlive { id: reader.u32()? },
+            2 => ClientMessage::Input {
+                sequence: reader.u32()?,
+                input: reader.movement_input()?,
+            },
+            3 => ClientMessage::SetBlock {
+                block: reader.ivec3()?,
+                state: reader.block_state()?,
+            },
+            4 => ClientMessage::Chat {
+                text: reader.string()?,
+            },
+            5 => ClientMessage::Teleport {
+                sequence: reader.u32()?,
+                position: reader.vec3()?,
+            },
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        })
+    }
+}
+
+impl Message for ServerMessage {
+    fn encode(&self, writer: &mut Writer) {
+        match self {
+            ServerMessage::Disconnect { reason } => {
+                writer.u8(0);
+                writer.string(reason);
+            }
+            ServerMessage::Welcome {
+                player_id,
+                position,
+            } => {
+                writer.u8(1);
+                writer.u32(*player_id);
+                writer.vec3(*position);
+            }
+            ServerMessage::KeepAlive { id } => {
+                writer.u8(2);
+                writer.u32(*id);
+            }
+            ServerMessage::Chunk { position, blocks } => {
+                writer.u8(3);
+                writer.chunk_coord(*position);
+                writer.bytes(&encode_chunk(blocks));
+            }
+            ServerMessage::BlockChange { block, state } => {
+                writer.u8(4);
+                writer.ivec3(*block);
+                writer.block_state(*state);
+            }
+            ServerMessage::SpawnEntity {
+                entity_id,
+                kind,
+                position,
+            } => {
+                writer.u8(5);
+                writer.u32(*entity_id);
+                match kind {
+                    EntityKind::Player { name } => {
+                        writer.u8(0);
+                        writer.string(name);
+                    }
+                }
+                writer.vec3(*position);
+            }
+            ServerMessage::MoveEntity {
+                entity_id,
+                position,
+                yaw,
+                pitch,
+            } => {
+                writer.u8(6);
+                writer.u32(*entity_id);
+                writer.vec3(*position);
+                writer.f32(*yaw);
+                writer.f32(*pitch);
+            }
+            ServerMessage::DespawnEntity { entity_id } => {
+                writer.u8(7);
+                writer.u32(*entity_id);
+            }
+            ServerMessage::Chat { text } => {
+                writer.u8(8);
+                writer.string(text);
+            }
+            ServerMessage::PlayerState {
+                sequence,
+                position,
+                physics,
+            } => {
+                writer.u8(9);
+                writer.u32(*sequence);
+                writer.vec3(*position);
+                writer.player_physics(physics);
+            }
+            ServerMessage::WorldInfo { seed, spawn } => {
+                writer.u8(10);
+                writer.u64(*seed);
+                writer.vec3(*spawn);
+            }
+            ServerMessage::GameMode { game_mode } => {
+                writer.u8(11);
+                writer.u8(match game_mode {
+                    GameMode::Survival => 0,
+                    GameMode::Creative => 1,
+                });
+            }
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(match reader.u8()? {
+            0 => ServerMessage::Disconnect {
+                reason: reader.string()?,
+            },
+            1 => ServerMessage::Welcome {
+                player_id: reader.u32()?,
+                position: reader.vec3()?,
+            },
+            2 => ServerMessage::KeepAlive { id: reader.u32()? },
+            3 => ServerMessage::Chunk {
+                position: reader.chunk_coord()?,
+                blocks: decode_chunk(reader.bytes()?)?,
+            },
+            4 => ServerMessage::BlockChange {
+                block: reader.ivec3()?,
+                state: reader.block_state()?,
+            },
+            5 => ServerMessage::SpawnEntity {
+                entity_id: reader.u32()?,
+                kind: match reader.u8()? {
+                    0 => EntityKind::Player {
+                        name: reader.string()?,
+                    },
+                    tag => return Err(DecodeError::UnknownTag(tag)),
+                },
+                position: reader.vec3()?,
+            },
+            6 => ServerMessage::MoveEntity {
+                entity_id: reader.u32()?,
+                position: reader.vec3()?,
+                yaw: reader.f32()?,
+                pitch: reader.f32()?,
+            },
+            7 => ServerMessage::DespawnEntity {
+                entity_id: reader.u32()?,
+            },
+            8 => ServerMessage::Chat {
+                text: reader.string()?,
+            },
+            9 => ServerMessage::PlayerState {
+                sequence: reader.u32()?,
+                position: reader.vec3()?,
+                physics: reader.player_physics()?,
+            },
+            10 => ServerMessage::WorldInfo {
+                seed: reader.u64()?,
+                spawn: reader.vec3()?,
+            },
+            11 => ServerMessage::GameMode {
+                game_mode: match reader.u8()? {
+                    0 => GameMode::Survival,
+                    1 => GameMode::Creative,
+                    tag => return Err(DecodeError::UnknownTag(tag)),
+                },
+            },
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        })
+    }
+}
+
+/// Decodes a whole message, failing if any bytes are left over.
+pub fn decode_message<M: Message>(bytes: &[u8]) -> Result<M, DecodeError> {
+    let mut reader = Reader::new(bytes);
+    let message = M::decode(&mut reader)?;
+    match reader.remaining() {
+        0 => Ok(message),
+        count => Err(DecodeError::TrailingBytes(count)),
+    }
+}
+
+pub fn encode_message<M: Message>(message: &M) -> Vec<u8> {
+    let mut writer = Writer::default();
+    message.encode(&mut writer);
+    writer.0
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        Connection::new(TcpStream::connect(address)?)
+    }
+
+    /// Queues a message, sent by the next `flush`.
+    pub fn send<M: Message>(&mut self, message: &M) {
+        let payload = encode_message(message);
+        self.outgoing
+            .extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        self.outgoing.extend_from_slice(&payload);
+    }
+
+    /// Writes as much of the queued messages as the socket takes.
+    pub fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => (),
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+
+    /// Messages fully received so far. Fails once the other side is gone or sent garbage.
+    pub fn receive<M: Message>(&mut self) -> io::Result<Vec<M>> {
+        let mut buffer = [0; 16 * 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => self.incoming.extend_from_slice(&buffer[..read]),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => (),
+                Err(error) => return Err(error),
+            }
+        }
+
+        let mut messages = Vec::new();
+        let mut start = 0;
+        while self.incoming.len() - start >= 4 {
+            let mut length = [0; 4];
+            length.copy_from_slice(&self.incoming[start..start + 4]);
+            let length = u32::from_le_bytes(length) as usize;
+            if length > MAX_FRAME_SIZE {
+                return Err(invalid_data(DecodeError::InvalidLength(length)));
+            }
+            if self.incoming.len() - start < 4 + length {
+                break;
+            }
+            let payload = &self.incoming[start + 4..start + 4 + length];
+            messages.push(decode_message(payload).map_err(invalid_data)?);
+            start += 4 + length;
+        }
+        self.incoming.drain(..start);
+        Ok(messages)
+    }
+}
+
+fn invalid_data(error: DecodeError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::net::TcpListener;
+
+    // Xorshift, so the random chunks are the same on every run
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, limit: usize) -> usize {
+            (self.next() % limit as u64) as usize
+        }
+    }
+
+    // Blocks drawn from `palette_size` different states
+    fn random_chunk(random: &mut Random, palette_size: usize) -> Vec<BlockState> {
+        let palette: Vec<BlockState> = (0..palette_size)
+            .map(|index| BlockState((index * 7 + 1) as u16))
+            .collect();
+        // Every state at least once, so the palette has the size asked for
+        let mut blocks: Vec<BlockState> = (0..CHUNK_VOLUME)
+            .map(|index| palette[index % palette_size])
+            .collect();
+        for index in 0..CHUNK_VOLUME {
+            blocks.swap(index, random.below(CHUNK_VOLUME));
+        }
+        blocks
+    }
+
+    fn client_messages() -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::Handshake {
+                protocol_version: PROTOCOL_VERSION,
+                name: "Ałice".to_string(),
+                view_distance: 12,
+            },
+            ClientMessage::KeepAlive { id: u32::MAX },
+            ClientMessage::Input {
+                sequence: 41,
+                input: MovementInput {
+                    delta: 0.016,
+                    yaw: -1.5,
+                    pitch: 0.25,
+                    forward: true,
+                    back: false,
+                    left: true,
+                    right: false,
+                    jump: true,
+                    sneak: false,
+                    flying: true,
+                    fly_speed: 30.0,
+                },
+            },
+            ClientMessage::SetBlock {
+                block: IVec3::new(-5, 64, 1 << 20),
+                state: BlockState(1234),
+            },
+            ClientMessage::Chat {
+                text: String::new(),
+            },
+            ClientMessage::Teleport {
+                sequence: 42,
+                position: Vec3::new(0.5, 130.0, -7.25),
+            },
+        ]
+    }
+
+    fn server_messages() -> Vec<ServerMessage> {
+        vec![
+            ServerMessage::Disconnect {
+                reason: "Timed out".to_string(),
+            },
+            ServerMessage::Welcome {
+                player_id: 3,
+                position: Vec3::new(1.0, 2.0, 3.0),
+            },
+            ServerMessage::KeepAlive { id: 0 },
+            ServerMessage::Chunk {
+                position: ChunkCoord { x: -3, y: 7, z: 2 },
+                blocks: random_chunk(&mut Random(1), 5),
+            },
+            ServerMessage::BlockChange {
+                block: IVec3::new(0, 0, -1),
+                state: BlockState::AIR,
+            },
+            ServerMessage::SpawnEntity {
+                entity_id: 9,
+                kind: EntityKind::Player {
+                    name: "Bob".to_string(),
+                },
+                position: Vec3::new(-1.0, 80.0, 4.5),
+            },
+            ServerMessage::MoveEntity {
+                entity_id: 9,
+                position: Vec3::new(-1.5, 80.0, 4.5),
+                yaw: 3.0,
+                pitch: -0.5,
+            },
+            ServerMessage::DespawnEntity { entity_id: 9 },
+            ServerMessage::Chat {
+                text: "<Bob> hi".to_string(),
+            },
+            ServerMessage::PlayerState {
+                sequence: 41,
+                position: Vec3::new(0.5, 129.0, -7.25),
+                physics: PlayerPhysics {
+                    velocity: Vec3::new(4.3, -9.5, 0.0),
+                    on_ground: true,
+                    fall_distance: 2.5,
+                },
+            },
+            ServerMessage::WorldInfo {
+                seed: u64::MAX,
+                spawn: Vec3::new(0.5, 70.62, 0.5),
+            },
+            ServerMessage::GameMode {
+                game_mode: GameMode::Creative,
+            },
+        ]
+    }
+
+    // Checks every message comes back the same, and that there is one for every tag
+    fn assert_round_trips<M: Message + PartialEq + fmt::Debug>(messages: &[M], tags: u8) {
+        let mut seen = BTreeSet::new();
+        for message in messages {
+            let bytes = encode_message(message);
+            seen.insert(bytes[0]);
+            assert_eq!(decode_message::<M>(&bytes).as_ref(), Ok(message));
+        }
+        assert_eq!(seen, (0..tags).collect());
+    }
+
+    // A connected pair of connections on localhost, and the raw stream of the first
+    fn connection_pair() -> (TcpStream, Connection) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        (stream, Connection::new(accepted).unwrap())
+    }
+
+    // Reads until something arrives, since the connection does not block
+    fn receive_server_messages(connection: &mut Connection) -> io::Result<Vec<ServerMessage>> {
+        for _ in 0..1000 {
+            let messages = connection.receive()?;
+            if !messages.is_empty() {
+                return Ok(messages);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        Ok(Vec::new())
+    }
+
+    #[test]
+    fn every_client_message_round_trips() {
+        assert_round_trips(&client_messages(), 6);
+    }
+
+    #[test]
+    fn every_server_message_round_trips() {
+        assert_round_trips(&server_messages(), 12);
+    }
+
+    #[test]
+    fn random_chunks_round_trip() {
+        let mut random = Random(0x5eed);
+        for palette_size in [1, 2, 3, 16, 17, 255, 256, 1000, CHUNK_VOLUME] {
+            let blocks = random_chunk(&mut random, palette_size);
+            let data = encode_chunk(&blocks);
+            assert_eq!(decode_chunk(&data), Ok(blocks.clone()));
+
+            let message = ServerMessage::Chunk {
+                position: ChunkCoord { x: 1, y: 2, z: 3 },
+                blocks,
+            };
+            let bytes = encode_message(&message);
+            assert_eq!(decode_message(&bytes), Ok(message));
+        }
+    }
+
+    #[test]
+    fn truncated_messages_fail() {
+        for message in server_messages() {
+            let bytes = encode_message(&message);
+            for length in 0..bytes.len() {
+                assert!(decode_message::<ServerMessage>(&bytes[..length]).is_err());
+            }
+        }
+        for message in client_messages() {
+            let bytes = encode_message(&message);
+            for length in 0..bytes.len() {
+                assert!(decode_message::<ClientMessage>(&bytes[..length]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn garbage_fails_without_panicking() {
+        let mut random = Random(0xbad);
+        for _ in 0..10_000 {
+            let length = random.below(64);
+            let bytes: Vec<u8> = (0..length).map(|_| random.next() as u8).collect();
+            let _ = decode_message::<ServerMessage>(&bytes);
+            let _ = decode_message::<ClientMessage>(&bytes);
+            let _ = decode_chunk(&bytes);
+        }
+        assert_eq!(
+            decode_message::<ServerMessage>(&[200]),
+            Err(DecodeError::UnknownTag(200))
+        );
+        assert!(decode_chunk(&[0xff; 32]).is_err());
+    }
+
+    #[test]
+    fn frames_are_reassembled() {
+        let (mut stream, mut connection) = connection_pair();
+        let message = ServerMessage::Chat {
+            text: "split".to_string(),
+        };
+        let payload = encode_message(&message);
+        let mut frame = (payload.len() as u32).to_le_bytes().to_vec();
+        frame.extend(&payload);
+        // Half a frame is kept until the rest arrives
+        stream.write_all(&frame[..3]).unwrap();
+        stream.flush().unwrap();
+        assert!(connection.receive::<ServerMessage>().unwrap().is_empty());
+        stream.write_all(&frame[3..]).unwrap();
+        assert_eq!(
+            receive_server_messages(&mut connection).unwrap(),
+            vec![message]
+        );
+    }
+
+    #[test]
+    fn garbage_frames_fail() {
+        let (mut stream, mut connection) = connection_pair();
+        stream.write_all(&3u32.to_le_bytes()).unwrap();
+        stream.write_all(&[200, 1, 2]).unwrap();
+        let error = receive_server_messages(&mut connection).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn oversized_frames_fail() {
+        let (mut stream, mut connection) = connection_pair();
+        stream
+            .write_all(&(MAX_FRAME_SIZE as u32 + 1).to_le_bytes())
+            .unwrap();
+        let error = receive_server_messages(&mut connection).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
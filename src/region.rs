@@ -0,0 +1,354 @@
+//! Region files, holding the chunks of a world that were changed from what the terrain
+//! generator makes.
+//!
+//! A region is `REGION_SIZE` by `REGION_SIZE` chunk columns over the whole world height, saved
+//! as `region/r.<x>.<z>.bin` in the world's directory. The file starts with the bytes `MCRR`
+//! and the region format version as a u32, then the chunk count as a u32 and for every chunk its
+//! position, its own format version as a u32 and its data, written like network packets, see
+//! `protocol`.
+//!
+//! Chunk data is compressed with DEFLATE. It holds a palette of block states by name, each as
+//! the block's name and a u8 count of name and u8 value pairs for its properties, as a u32 count
+//! and the entries, then the u32 block count and the index into the palette of every block,
+//! packed like in `protocol::encode_chunk`. Block ids and property bits can change between
+//! versions, names only through a migration, see `migration`.
+
+use crate::block_types::{block_by_name, BlockState, BLOCKTYPES};
+use crate::migration::{self, CHUNK_MIGRATIONS, REGION_MIGRATIONS};
+use crate::protocol::{self, DecodeError, Reader, Writer, CHUNK_COMPRESSION_LEVEL, CHUNK_VOLUME};
+use crate::save;
+use crate::world::ChunkCoord;
+use bevy::prelude::*;
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+pub const REGION_DIR: &str = "region";
+// Chunk columns along each side of a region
+pub const REGION_SIZE: i32 = 16;
+pub const REGION_MAGIC: [u8; 4] = *b"MCRR";
+// Bumped whenever the layout of region files or what chunks hold changes, see `migration`
+pub const REGION_FORMAT_VERSION: u32 = 1;
+pub const CHUNK_FORMAT_VERSION: u32 = 1;
+// Larger chunk data is treated as broken rather than inflated, as are chunks of more blocks
+// than 128 a side
+const MAX_CHUNK_DATA: usize = 16 << 20;
+const MAX_CHUNK_BLOCKS: usize = 1 << 21;
+
+/// A block state by name, so it means the same whatever ids the blocks have.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SavedBlock {
+    pub name: String,
+    pub properties: Vec<(String, u8)>,
+}
+
+/// A chunk as saved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedChunk {
+    pub version: u32,
+    pub palette: Vec<SavedBlock>,
+    /// Index into the palette of every block, in the order of `VoxelMap::chunk_blocks`.
+    pub blocks: Vec<usize>,
+}
+
+/// The saved chunks of a region.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Region {
+    pub chunks: HashMap<ChunkCoord, SavedChunk>,
+}
+
+/// Regions of the world being played, read when a chunk in them is first needed. Every chunk is
+/// only loaded once, so what is saved later need not be read back.
+pub struct WorldRegions {
+    dir: PathBuf,
+    regions: HashMap<(i32, i32), Region>,
+}
+
+impl SavedBlock {
+    pub fn new(state: BlockState) -> Self {
+        let block_type = state.block_type();
+        SavedBlock {
+            name: block_type.name.to_string(),
+            properties: block_type
+                .properties
+                .iter()
+                .map(|property| {
+                    let value = state.get(*property).unwrap_or(0);
+                    (property.name().to_string(), value)
+                })
+                .collect(),
+        }
+    }
+
+    /// The block state in this version, `None` if there is no block with the name. Properties
+    /// the block does not have or values out of their range are left at their default.
+    pub fn state(&self) -> Option<BlockState> {
+        let block = block_by_name(&self.name)?;
+        let mut state = BlockState::new(block);
+        for (name, value) in self.properties.iter() {
+            let property = BLOCKTYPES[block as usize]
+                .properties
+                .iter()
+                .find(|property| property.name() == name);
+            if let Some(property) = property {
+                if *value < property.value_count() {
+                    state = state.with(*property, *value);
+                }
+            }
+        }
+        Some(state)
+    }
+}
+
+impl SavedChunk {
+    pub fn new(blocks: &[BlockState]) -> Self {
+        let mut palette = Vec::new();
+        let mut palette_indices: HashMap<BlockState, usize> = HashMap::new();
+        let blocks = blocks
+            .iter()
+            .map(|block| {
+                *palette_indices.entry(*block).or_insert_with(|| {
+                    palette.push(SavedBlock::new(*block));
+                    palette.len() - 1
+                })
+            })
+            .collect();
+        SavedChunk {
+            version: CHUNK_FORMAT_VERSION,
+            palette,
+            blocks,
+        }
+    }
+
+    /// The blocks in this version, along with the names of blocks that no longer exist, which
+    /// become air.
+    pub fn states(&self) -> (Vec<BlockState>, BTreeSet<String>) {
+        let mut unknown = BTreeSet::new();
+        let palette: Vec<BlockState> = self
+            .palette
+            .iter()
+            .map(|block| {
+                block.state().unwrap_or_else(|| {
+                    unknown.insert(block.name.clone());
+                    BlockState::AIR
+                })
+            })
+            .collect();
+        let states = self.blocks.iter().map(|index| palette[*index]).collect();
+        (states, unknown)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.u32(self.palette.len() as u32);
+        for block in self.palette.iter() {
+            writer.string(&block.name);
+            writer.u8(block.properties.len() as u8);
+            for (name, value) in block.properties.iter() {
+                writer.string(name);
+                writer.u8(*value);
+            }
+        }
+        writer.u32(self.blocks.len() as u32);
+        writer.packed(
+            self.blocks.iter().copied(),
+            protocol::palette_bits(self.palette.len()),
+        );
+        compress_to_vec(&writer.0, CHUNK_COMPRESSION_LEVEL)
+    }
+
+    fn decode(version: u32, data: &[u8]) -> Result<Self, DecodeError> {
+        let raw = decompress_to_vec_with_limit(data, MAX_CHUNK_DATA)
+            .map_err(|_| DecodeError::InvalidChunk)?;
+        let mut reader = Reader::new(&raw);
+
+        let length = reader.u32()? as usize;
+        if length > reader.remaining() {
+            return Err(DecodeError::InvalidLength(length));
+        }
+        let palette = (0..length)
+            .map(|_| decode_block(&mut reader))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let count = reader.u32()? as usize;
+        if count > MAX_CHUNK_BLOCKS {
+            return Err(DecodeError::InvalidLength(count));
+        }
+        let blocks = reader.packed(count, protocol::palette_bits(length))?;
+        if reader.remaining() > 0 {
+            return Err(DecodeError::TrailingBytes(reader.remaining()));
+        }
+        if blocks.iter().any(|index| *index >= length) {
+            return Err(DecodeError::InvalidChunk);
+        }
+        Ok(SavedChunk {
+            version,
+            palette,
+            blocks,
+        })
+    }
+}
+
+impl Region {
+    /// Region holding the chunk, in units of `REGION_SIZE` chunks.
+    pub fn of_chunk(chunk_pos: ChunkCoord) -> (i32, i32) {
+        (
+            chunk_pos.x.div_euclid(REGION_SIZE),
+            chunk_pos.z.div_euclid(REGION_SIZE),
+        )
+    }
+
+    pub fn path(world_dir: &Path, (x, z): (i32, i32)) -> PathBuf {
+        world_dir
+            .join(REGION_DIR)
+            .join(format!("r.{}.{}.bin", x, z))
+    }
+
+    /// The region saved at `path`, upgraded to the current format. An empty region if there is
+    /// no file yet.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read(path) {
+            Ok(bytes) => {
+                Region::decode(&bytes).map_err(|error| format!("{}: {}", path.display(), error))
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(Region::default()),
+            Err(error) => Err(format!("{}: {}", path.display(), error)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        save::write_atomic(path, &self.encode())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer(REGION_MAGIC.to_vec());
+        writer.u32(REGION_FORMAT_VERSION);
+        writer.u32(self.chunks.len() as u32);
+        for (chunk_pos, chunk) in self.chunks.iter() {
+            writer.chunk_coord(*chunk_pos);
+            writer.u32(chunk.version);
+            writer.bytes(&chunk.encode());
+        }
+        writer.0
+    }
+
+    /// Reads a region file, running the migrations of the region and of every chunk in it.
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 8 || bytes[..4] != REGION_MAGIC {
+            return Err("not a region file".to_string());
+        }
+        let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        let mut body = bytes[8..].to_vec();
+        migration::migrate(&mut body, version, REGION_FORMAT_VERSION, REGION_MIGRATIONS)?;
+
+        let mut reader = Reader::new(&body);
+        let mut region = Region::default();
+        let count = reader.u32().map_err(|error| error.to_string())?;
+        for _ in 0..count {
+            let (chunk_pos, mut chunk) = decode_chunk_entry(&mut reader)
+                .map_err(|error| format!("invalid chunk: {}", error))?;
+            let version = chunk.version;
+            migration::migrate(&mut chunk, version, CHUNK_FORMAT_VERSION, CHUNK_MIGRATIONS)
+                .map_err(|error| {
+                    format!(
+                        "chunk ({}, {}, {}): {}",
+                        chunk_pos.x, chunk_pos.y, chunk_pos.z, error
+                    )
+                })?;
+            chunk.version = CHUNK_FORMAT_VERSION;
+            region.chunks.insert(chunk_pos, chunk);
+        }
+        if reader.remaining() > 0 {
+            return Err(DecodeError::TrailingBytes(reader.remaining()).to_string());
+        }
+        Ok(region)
+    }
+}
+
+fn decode_block(reader: &mut Reader) -> Result<SavedBlock, DecodeError> {
+    let name = reader.string()?;
+    let mut properties = Vec::new();
+    for _ in 0..reader.u8()? {
+        properties.push((reader.string()?, reader.u8()?));
+    }
+    Ok(SavedBlock { name, properties })
+}
+
+fn decode_chunk_entry(reader: &mut Reader) -> Result<(ChunkCoord, SavedChunk), DecodeError> {
+    let chunk_pos = reader.chunk_coord()?;
+    let version = reader.u32()?;
+    let chunk = SavedChunk::decode(version, reader.bytes()?)?;
+    Ok((chunk_pos, chunk))
+}
+
+impl WorldRegions {
+    pub fn new(world_dir: &Path) -> Self {
+        WorldRegions {
+            dir: world_dir.to_path_buf(),
+            regions: HashMap::new(),
+        }
+    }
+
+    /// Saved blocks of the chunk, `None` if it was never changed.
+    pub fn load_chunk(&mut self, chunk_pos: ChunkCoord) -> Option<Vec<BlockState>> {
+        let region = Region::of_chunk(chunk_pos);
+        let dir = &self.dir;
+        let chunk = self
+            .regions
+            .entry(region)
+            .or_insert_with(|| {
+                Region::load(&Region::path(dir, region)).unwrap_or_else(|error| {
+                    warn!("Could not load region: {}", error);
+                    Region::default()
+                })
+            })
+            .chunks
+            .get(&chunk_pos)?;
+
+        if chunk.blocks.len() != CHUNK_VOLUME {
+            warn!(
+                "Ignoring chunk ({}, {}, {}) saved with {} blocks",
+                chunk_pos.x,
+                chunk_pos.y,
+                chunk_pos.z,
+                chunk.blocks.len()
+            );
+            return None;
+        }
+        let (blocks, unknown) = chunk.states();
+        for name in unknown {
+            warn!("Replacing unknown block {} with air", name);
+        }
+        Some(blocks)
+    }
+}
+
+/// Writes the chunks into the region files of the world in `world_dir`, keeping the chunks
+/// already saved there. Only one thread may save a world at a time, see `save::SaveThread`.
+pub fn save_chunks(
+    world_dir: &Path,
+    chunks: &[(ChunkCoord, Vec<BlockState>)],
+) -> Result<(), String> {
+    let mut by_region = HashMap::new();
+    for (chunk_pos, blocks) in chunks {
+        by_region
+            .entry(Region::of_chunk(*chunk_pos))
+            .or_insert_with(Vec::new)
+            .push((*chunk_pos, blocks));
+    }
+
+    for (region_pos, chunks) in by_region {
+        let path = Region::path(world_dir, region_pos);
+        // Read again rather than taken from what was loaded, so a file that could not be read
+        // is never overwritten
+        let mut region = Region::load(&path)?;
+        for (chunk_pos, blocks) in chunks {
+            region.chunks.insert(chunk_pos, SavedChunk::new(blocks));
+        }
+        region.save(&path)?;
+    }
+    Ok(())
+}
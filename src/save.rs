@@ -0,0 +1,539 @@
+use crate::block_types::{block_by_name, BlockState, BLOCKTYPES};
+use crate::inventory::{Inventory, ItemStack, INVENTORY_SIZE, MAX_STACK_SIZE};
+use crate::level::{self, Level};
+use crate::network_client::NetworkClient;
+use crate::player::{GameMode, Health, PlayerLook, MAX_HEALTH};
+use crate::region;
+use crate::voxel_map::VoxelMap;
+use crate::world::ChunkCoord;
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+pub const SAVES_DIR: &str = "saves";
+pub const DEFAULT_WORLD: &str = "world";
+pub const PLAYER_FILE: &str = "player.toml";
+// Seconds between saves of the world while playing
+pub const AUTOSAVE_INTERVAL: f32 = 60.0;
+
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Directory the world is saved in.
+pub struct WorldSave {
+    pub dir: PathBuf,
+}
+
+/// Everything about the player kept between sessions. Blocks are stored by name, so saves
+/// survive blocks being added or reordered.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlayerData {
+    /// Camera position.
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub game_mode: GameMode,
+    pub health: f32,
+    pub selected_slot: usize,
+    pub inventory: Vec<SavedStack>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedStack {
+    pub slot: usize,
+    pub block: String,
+    pub count: u8,
+}
+
+/// Something for the save thread to write.
+pub enum SaveJob {
+    Chunks(Vec<(ChunkCoord, Vec<BlockState>)>),
+    Player(PlayerData),
+    Level(Level),
+}
+
+/// Writes the world on a thread of its own, so saving never holds up a frame. Jobs are written
+/// in the order they are sent.
+pub struct SaveThread {
+    jobs: Option<Sender<SaveJob>>,
+    thread: Option<JoinHandle<()>>,
+    timer: Timer,
+}
+
+impl WorldSave {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        WorldSave { dir: dir.into() }
+    }
+
+    pub fn player_path(&self) -> PathBuf {
+        self.dir.join(PLAYER_FILE)
+    }
+}
+
+impl SaveThread {
+    /// Starts the thread saving the world in `world_dir`.
+    pub fn spawn(world_dir: PathBuf) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("save".to_string())
+            .spawn(move || save_jobs(&world_dir, receiver))
+            .map_err(|error| warn!("Could not start the save thread: {}", error))
+            .ok();
+        SaveThread {
+            jobs: thread.as_ref().map(|_| sender),
+            thread,
+            timer: Timer::from_seconds(AUTOSAVE_INTERVAL, true),
+        }
+    }
+
+    pub fn send(&self, job: SaveJob) {
+        match &self.jobs {
+            Some(jobs) if jobs.send(job).is_ok() => {}
+            _ => warn!("Nothing is saved, the save thread is gone"),
+        }
+    }
+
+    /// Waits for the jobs sent so far to be written and stops the thread.
+    pub fn finish(&mut self) {
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("The save thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for SaveThread {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+// Runs on the save thread until the sender is dropped. Chunks that could not be written are
+// kept and tried again with the next ones
+fn save_jobs(world_dir: &Path, jobs: Receiver<SaveJob>) {
+    let mut unsaved = HashMap::new();
+    for job in jobs {
+        match job {
+            SaveJob::Chunks(chunks) => {
+                unsaved.extend(chunks);
+                let chunks: Vec<_> = unsaved.drain().collect();
+                match region::save_chunks(world_dir, &chunks) {
+                    Ok(()) => info!("Saved {} chunks", chunks.len()),
+                    Err(error) => {
+                        warn!("Could not save chunks: {}", error);
+                        unsaved.extend(chunks);
+                    }
+                }
+            }
+            SaveJob::Player(data) => {
+                let path = world_dir.join(PLAYER_FILE);
+                if let Err(error) = data.save(&path) {
+                    warn!("Could not save {}: {}", path.display(), error);
+                }
+            }
+            SaveJob::Level(level) => {
+                if let Err(error) = level.save(world_dir) {
+                    warn!(
+                        "Could not save the level of {}: {}",
+                        world_dir.display(),
+                        error
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Replaces the file at `path` with `bytes`. They are written to a temporary file next to it
+/// first, which is then renamed over it, so a crash while writing leaves the old file intact
+/// rather than half of the new one.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    write_atomic_with(path, |file| file.write_all(bytes))
+}
+
+// `write_atomic` with the temporary file filled in by `write`
+fn write_atomic_with(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> Result<(), String> {
+    let mut temp_name = path
+        .file_name()
+        .ok_or_else(|| format!("{} is not a file", path.display()))?
+        .to_os_string();
+    // Numbered, so threads writing the same file at once do not share a temporary one
+    temp_name.push(format!(
+        ".{}.tmp",
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp = path.with_file_name(temp_name);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|error| error.to_string())?;
+    }
+    write_and_rename(&temp, path, write).map_err(|error| {
+        let _ = fs::remove_file(&temp);
+        error.to_string()
+    })
+}
+
+fn write_and_rename(
+    temp: &Path,
+    path: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut file = File::create(temp)?;
+    write(&mut file)?;
+    // On disk before it replaces the old file, not only in the OS's cache
+    file.sync_all()?;
+    fs::rename(temp, path)?;
+    // The rename is only kept through a power cut once the directory is on disk too. Windows
+    // cannot open directories, and does not need to
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Removes the temporary files in `dir` that writes cut short by a crash left behind, see
+/// `write_atomic`. The files they were replacing are still whole.
+pub fn remove_temp_files(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        if name.to_str().is_some_and(is_temp_file) {
+            // Tried again on the next load if it cannot be removed now
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+// Whether the file is named like the temporary files of `write_atomic`, `<file>.<number>.tmp`
+fn is_temp_file(name: &str) -> bool {
+    match name
+        .strip_suffix(".tmp")
+        .and_then(|rest| rest.rsplit_once('.'))
+    {
+        Some((file, number)) => {
+            !file.is_empty()
+                && !number.is_empty()
+                && number.bytes().all(|byte| byte.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+impl PlayerData {
+    pub fn new(
+        transform: &Transform,
+        look: &PlayerLook,
+        game_mode: GameMode,
+        health: &Health,
+        inventory: &Inventory,
+    ) -> Self {
+        PlayerData {
+            position: transform.translation.to_array(),
+            yaw: look.yaw,
+            pitch: look.pitch,
+            game_mode,
+            health: health.0,
+            selected_slot: inventory.selected,
+            inventory: inventory
+                .slots
+                .iter()
+                .enumerate()
+                .filter_map(|(slot, stack)| {
+                    stack.map(|stack| SavedStack {
+                        slot,
+                        block: BLOCKTYPES[stack.block as usize].name.to_string(),
+                        count: stack.count,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// The saved player, or None if there is none yet or it cannot be read.
+    pub fn load(path: &Path) -> Option<Self> {
+        let text = fs::read_to_string(path).ok()?;
+        match toml::from_str(&text) {
+            Ok(data) => Some(data),
+            Err(error) => {
+                warn!("Ignoring invalid {}: {}", path.display(), error);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|error| error.to_string())?;
+        write_atomic(path, text.as_bytes())
+    }
+
+    pub fn position(&self) -> Vec3 {
+        Vec3::from(self.position)
+    }
+
+    pub fn look(&self) -> PlayerLook {
+        PlayerLook {
+            yaw: self.yaw,
+            pitch: self.pitch,
+        }
+    }
+
+    pub fn health(&self) -> Health {
+        Health(self.health.clamp(0.0, MAX_HEALTH))
+    }
+
+    /// The saved inventory. Stacks of blocks that no longer exist are dropped.
+    pub fn inventory(&self) -> Inventory {
+        let mut inventory = Inventory::new();
+        inventory.select(self.selected_slot);
+        for stack in self.inventory.iter() {
+            let block = match block_by_name(&stack.block) {
+                Some(block) => block,
+                None => {
+                    warn!("Dropping {} of unknown block {}", stack.count, stack.block);
+                    continue;
+                }
+            };
+            if stack.slot < INVENTORY_SIZE && stack.count > 0 {
+                inventory.slots[stack.slot] = Some(ItemStack {
+                    block,
+                    count: stack.count.min(MAX_STACK_SIZE),
+                });
+            }
+        }
+        inventory
+    }
+}
+
+/// Gathers the player and what changed in the world every `AUTOSAVE_INTERVAL` seconds and hands
+/// them to the save thread. Online, the world and the player are the server's to save.
+pub fn autosave(
+    time: Res<Time>,
+    mut save_thread: ResMut<SaveThread>,
+    mut voxel_map: ResMut<VoxelMap>,
+    level: Res<Level>,
+    network_client: Option<Res<NetworkClient>>,
+    query: Query<(&Transform, &PlayerLook, &GameMode, &Health, &Inventory), With<super::Player>>,
+) {
+    if !save_thread.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let online = network_client.is_some();
+    queue_save(
+        &save_thread,
+        &mut voxel_map,
+        &level,
+        player_data(&query),
+        online,
+    );
+}
+
+/// Saves the world when the game is closed, waiting for the save thread to write everything
+/// queued before the game exits.
+pub fn save_on_exit(
+    mut exits: EventReader<AppExit>,
+    mut save_thread: ResMut<SaveThread>,
+    mut voxel_map: ResMut<VoxelMap>,
+    level: Res<Level>,
+    network_client: Option<Res<NetworkClient>>,
+    query: Query<(&Transform, &PlayerLook, &GameMode, &Health, &Inventory), With<super::Player>>,
+) {
+    if exits.iter().last().is_none() {
+        return;
+    }
+    let online = network_client.is_some();
+    queue_save(
+        &save_thread,
+        &mut voxel_map,
+        &level,
+        player_data(&query),
+        online,
+    );
+    save_thread.finish();
+}
+
+fn queue_save(
+    save_thread: &SaveThread,
+    voxel_map: &mut VoxelMap,
+    level: &Level,
+    players: Vec<PlayerData>,
+    online: bool,
+) {
+    // Online the player is somewhere in the server's world, saving it would move the player
+    // of this world there
+    if online {
+        return;
+    }
+    for data in players {
+        save_thread.send(SaveJob::Player(data));
+    }
+    queue_world_save(save_thread, voxel_map, level);
+}
+
+/// Hands the chunks changed since the last save and the level to the save thread.
+pub fn queue_world_save(save_thread: &SaveThread, voxel_map: &mut VoxelMap, level: &Level) {
+    let modified: Vec<_> = voxel_map.modified.drain().collect();
+    if !modified.is_empty() {
+        let chunks = modified
+            .into_iter()
+            .map(|chunk_pos| (chunk_pos, voxel_map.chunk_blocks(chunk_pos)))
+            .collect();
+        save_thread.send(SaveJob::Chunks(chunks));
+    }
+    let mut level = level.clone();
+    level.last_played = level::unix_time();
+    save_thread.send(SaveJob::Level(level));
+}
+
+fn player_data(
+    query: &Query<(&Transform, &PlayerLook, &GameMode, &Health, &Inventory), With<super::Player>>,
+) -> Vec<PlayerData> {
+    query
+        .iter()
+        .map(|(transform, look, game_mode, health, inventory)| {
+            PlayerData::new(transform, look, *game_mode, health, inventory)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::LEVEL_FILE;
+    use std::env;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("minecrust-save-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn player() -> PlayerData {
+        PlayerData::new(
+            &Transform::from_xyz(1.0, 70.0, -3.0),
+            &PlayerLook {
+                yaw: 0.5,
+                pitch: 0.0,
+            },
+            GameMode::Survival,
+            &Health(MAX_HEALTH),
+            &Inventory::new(),
+        )
+    }
+
+    #[test]
+    fn saves_the_player_and_the_level_offline() {
+        let dir = temp_dir("offline");
+        let mut save_thread = SaveThread::spawn(dir.clone());
+        let mut voxel_map = VoxelMap::new(0, 4);
+        queue_save(
+            &save_thread,
+            &mut voxel_map,
+            &Level::new("Offline", 0),
+            vec![player()],
+            false,
+        );
+        save_thread.finish();
+        assert_eq!(PlayerData::load(&dir.join(PLAYER_FILE)), Some(player()));
+        assert!(dir.join(LEVEL_FILE).is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_leave_the_old_file() {
+        let dir = temp_dir("failed");
+        let path = dir.join(PLAYER_FILE);
+        player().save(&path).unwrap();
+
+        let error = write_atomic_with(&path, |file| {
+            file.write_all(b"position = [1.0, 7")?;
+            Err(io::Error::other("disk full"))
+        })
+        .unwrap_err();
+        assert_eq!(error, "disk full");
+        assert_eq!(PlayerData::load(&path), Some(player()));
+        // Nothing is left behind when the write fails cleanly
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interrupted_writes_leave_the_old_file() {
+        let dir = temp_dir("interrupted");
+        let path = dir.join(PLAYER_FILE);
+        player().save(&path).unwrap();
+
+        // A crash halfway through writing the next save, which skips all cleaning up
+        let crash = std::panic::catch_unwind(|| {
+            write_atomic_with(&path, |file| {
+                file.write_all(b"position = [1.0, 7")?;
+                panic!("crashed while writing");
+            })
+        });
+        assert!(crash.is_err());
+        assert_eq!(PlayerData::load(&path), Some(player()));
+        let temp_files = || {
+            fs::read_dir(&dir)
+                .unwrap()
+                .filter(|entry| {
+                    let name = entry.as_ref().unwrap().file_name();
+                    is_temp_file(name.to_str().unwrap())
+                })
+                .count()
+        };
+        assert_eq!(temp_files(), 1);
+
+        remove_temp_files(&dir);
+        assert_eq!(temp_files(), 0);
+        assert_eq!(PlayerData::load(&path), Some(player()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_temporary_files_are_removed() {
+        let dir = temp_dir("temp-files");
+        fs::create_dir_all(&dir).unwrap();
+        let names = [
+            "level.toml",
+            "level.toml.12.tmp",
+            "notes.tmp",
+            "r.0.0.bin.0.tmp",
+        ];
+        for name in names {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        remove_temp_files(&dir);
+        let kept: Vec<bool> = names.iter().map(|name| dir.join(name).exists()).collect();
+        assert_eq!(kept, [true, false, true, false]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saves_nothing_online() {
+        let dir = temp_dir("online");
+        let mut save_thread = SaveThread::spawn(dir.clone());
+        let mut voxel_map = VoxelMap::new(0, 4);
+        queue_save(
+            &save_thread,
+            &mut voxel_map,
+            &Level::new("Online", 0),
+            vec![player()],
+            true,
+        );
+        save_thread.finish();
+        assert!(!dir.exists());
+    }
+}
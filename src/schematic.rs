@@ -0,0 +1,582 @@
+//! Builds saved to and loaded from files other tools read, as Sponge schematics (`.schem`) or
+//! MagicaVoxel models (`.vox`).
+//!
+//! Sponge schematics are gzipped NBT, see `nbt`. Version 2 has a root compound `Schematic`
+//! holding the size as shorts `Width`, `Height` and `Length`, a `Palette` compound of Minecraft
+//! block states and their indices, and `BlockData`, the index of every block as an unsigned
+//! LEB128 varint, x fastest, then z, then y. Version 3 keeps the same in a `Schematic` compound
+//! under the root, with the palette and data in a `Blocks` compound as `Palette` and `Data`.
+//! Block states go through `block_mapping` both ways. Block entities and entities are not
+//! read or written.
+//!
+//! MagicaVoxel models are chunks of a 4 byte id, the u32 size of the content and of the
+//! children, all little endian, in a `MAIN` chunk after the bytes `VOX ` and the version. Only
+//! the first model is read, its `SIZE` and the `XYZI` of every voxel, with the colors of the
+//! `RGBA` palette or MagicaVoxel's default one, leaving out the transforms of the scene. Blocks are saved as their color in
+//! `BLOCK_COLORS`, and colors are read as the block of the nearest one, so block properties are
+//! lost. MagicaVoxel's z points up.
+
+use crate::block_mapping::{self, BlockMapping};
+use crate::block_types::{block_by_name, BlockState};
+use crate::nbt::{self, Tag};
+use crate::save;
+use crate::voxel_map::VoxelMap;
+use crate::world_edit::{Region, MAX_EDIT_VOLUME};
+use bevy::prelude::*;
+use itertools::iproduct;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const SCHEMATICS_DIR: &str = "schematics";
+pub const SPONGE_EXTENSION: &str = "schem";
+pub const VOX_EXTENSION: &str = "vox";
+// Minecraft 1.20.4, the blocks of `block_mapping::MINECRAFT_NAMES` are named as in it
+pub const DATA_VERSION: i32 = 3700;
+pub const VOX_VERSION: i32 = 150;
+// Largest MagicaVoxel model along each side
+pub const MAX_VOX_SIZE: i32 = 256;
+// Levels of the ramps of red, green, blue and gray ending MagicaVoxel's default palette
+const VOX_RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+/// Color each block is saved as in MagicaVoxel models, by name. Blocks not listed are left out.
+pub static BLOCK_COLORS: &[(&str, [u8; 3])] = &[
+    ("stone", [125, 125, 125]),
+    ("bedrock", [85, 85, 85]),
+    ("grass", [95, 159, 53]),
+    ("dirt", [134, 96, 67]),
+    ("water", [64, 96, 223]),
+    ("lava", [207, 92, 20]),
+    ("portal", [130, 50, 200]),
+    ("log", [102, 81, 51]),
+    ("furnace", [96, 96, 96]),
+    ("planks", [162, 130, 78]),
+    ("stone_slab", [160, 160, 160]),
+    ("planks_stairs", [180, 144, 88]),
+    ("tall_grass", [120, 180, 80]),
+    ("rose", [200, 30, 30]),
+    ("dandelion", [240, 220, 40]),
+    ("fence", [140, 110, 70]),
+    ("torch", [255, 200, 80]),
+];
+
+/// Format of a schematic file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchematicFormat {
+    Sponge { version: i32 },
+    Vox,
+}
+
+/// A box of blocks, x major and z minor like `Region::blocks`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schematic {
+    pub size: IVec3,
+    pub blocks: Vec<BlockState>,
+}
+
+impl SchematicFormat {
+    /// Format written for a file name, by its extension. Sponge schematics are written in the
+    /// latest version.
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(SPONGE_EXTENSION) => Ok(SchematicFormat::Sponge { version: 3 }),
+            Some(VOX_EXTENSION) => Ok(SchematicFormat::Vox),
+            _ => Err(format!(
+                "Unknown schematic format of {}, use .{} or .{}",
+                path.display(),
+                SPONGE_EXTENSION,
+                VOX_EXTENSION
+            )),
+        }
+    }
+}
+
+impl Schematic {
+    /// The blocks of `region`.
+    pub fn from_world(voxel_map: &VoxelMap, region: Region) -> Self {
+        Schematic {
+            size: region.size(),
+            blocks: region.blocks().map(|block| voxel_map.get(block)).collect(),
+        }
+    }
+
+    /// Changes placing the schematic with its lowest corner at `origin`, air included.
+    pub fn changes(&self, origin: IVec3) -> Vec<(IVec3, BlockState)> {
+        Region::new(origin, origin + self.size - IVec3::ONE)
+            .blocks()
+            .zip(self.blocks.iter().copied())
+            .collect()
+    }
+
+    pub fn get(&self, position: IVec3) -> BlockState {
+        self.blocks[self.index(position)]
+    }
+
+    fn index(&self, position: IVec3) -> usize {
+        ((position.x * self.size.y + position.y) * self.size.z + position.z) as usize
+    }
+
+    /// Reads the schematic at `path`, telling the format from the file. Returns the Minecraft
+    /// blocks replaced by the mapping's placeholder too.
+    pub fn load(path: &Path, mapping: &BlockMapping) -> Result<(Self, Vec<String>), String> {
+        let bytes = fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        let result = if bytes.starts_with(b"VOX ") {
+            Schematic::decode_vox(&bytes).map(|schematic| (schematic, Vec::new()))
+        } else {
+            Schematic::decode_sponge(&bytes, mapping)
+        };
+        result.map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    pub fn save(&self, path: &Path, format: SchematicFormat) -> Result<(), String> {
+        let bytes = match format {
+            SchematicFormat::Sponge { version } => self.encode_sponge(version)?,
+            SchematicFormat::Vox => self.encode_vox()?,
+        };
+        save::write_atomic(path, &bytes)
+    }
+
+    fn check_size(size: IVec3) -> Result<(), String> {
+        let volume = size.x as i64 * size.y as i64 * size.z as i64;
+        if size.min_element() <= 0 {
+            return Err("empty schematic".to_string());
+        }
+        if volume > MAX_EDIT_VOLUME as i64 {
+            return Err(format!(
+                "size {} by {} by {} is over the limit of {} blocks",
+                size.x, size.y, size.z, MAX_EDIT_VOLUME
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn encode_sponge(&self, version: i32) -> Result<Vec<u8>, String> {
+        if self.size.max_element() > u16::MAX as i32 {
+            return Err("too large for a Sponge schematic".to_string());
+        }
+        let mut palette = HashMap::new();
+        let mut data = Vec::new();
+        for position in sponge_positions(self.size) {
+            let state = block_mapping::minecraft_state(self.get(position));
+            let next = palette.len() as i32;
+            let index = *palette.entry(state).or_insert(next);
+            write_varint(&mut data, index as u32);
+        }
+        let palette_max = palette.len() as i32;
+        let palette = Tag::Compound(
+            palette
+                .into_iter()
+                .map(|(state, index)| (state, Tag::Int(index)))
+                .collect(),
+        );
+        let data = Tag::ByteArray(data.into_iter().map(|byte| byte as i8).collect());
+
+        let mut schematic = HashMap::from([
+            ("Version".to_string(), Tag::Int(version)),
+            ("DataVersion".to_string(), Tag::Int(DATA_VERSION)),
+            ("Width".to_string(), Tag::Short(self.size.x as u16 as i16)),
+            ("Height".to_string(), Tag::Short(self.size.y as u16 as i16)),
+            ("Length".to_string(), Tag::Short(self.size.z as u16 as i16)),
+            ("Offset".to_string(), Tag::IntArray(vec![0, 0, 0])),
+        ]);
+        let bytes = match version {
+            2 => {
+                schematic.insert("PaletteMax".to_string(), Tag::Int(palette_max));
+                schematic.insert("Palette".to_string(), palette);
+                schematic.insert("BlockData".to_string(), data);
+                nbt::write("Schematic", &schematic)
+            }
+            3 => {
+                let blocks =
+                    HashMap::from([("Palette".to_string(), palette), ("Data".to_string(), data)]);
+                schematic.insert("Blocks".to_string(), Tag::Compound(blocks));
+                let root = HashMap::from([("Schematic".to_string(), Tag::Compound(schematic))]);
+                nbt::write("", &root)
+            }
+            _ => return Err(format!("unknown Sponge schematic version {}", version)),
+        };
+        Ok(nbt::gzip(&bytes))
+    }
+
+    pub fn decode_sponge(
+        bytes: &[u8],
+        mapping: &BlockMapping,
+    ) -> Result<(Self, Vec<String>), String> {
+        let root = nbt::read(bytes)?;
+        // Version 3 nests the schematic in the root compound
+        let compound = root.get("Schematic").unwrap_or(&root);
+        let version = compound
+            .get("Version")
+            .and_then(Tag::as_i64)
+            .ok_or_else(|| "not a Sponge schematic".to_string())?;
+        let (palette, data) = match version {
+            1 | 2 => (compound.get("Palette"), compound.get("BlockData")),
+            3 => {
+                let blocks = compound.get("Blocks");
+                let get = |name| blocks.and_then(|blocks| blocks.get(name));
+                (get("Palette"), get("Data"))
+            }
+            _ => return Err(format!("unknown Sponge schematic version {}", version)),
+        };
+        let dimension = |name| {
+            compound
+                .get(name)
+                .and_then(Tag::as_i64)
+                .map(|value| value as u16 as i32)
+                .ok_or_else(|| format!("schematic without a {}", name))
+        };
+        let size = IVec3::new(
+            dimension("Width")?,
+            dimension("Height")?,
+            dimension("Length")?,
+        );
+        Schematic::check_size(size)?;
+
+        let palette = palette
+            .and_then(Tag::as_compound)
+            .ok_or_else(|| "schematic without a palette".to_string())?;
+        let mut states = HashMap::new();
+        let mut unknown = Vec::new();
+        for (text, index) in palette {
+            let index = index
+                .as_i64()
+                .ok_or_else(|| format!("invalid palette index of {}", text))?;
+            let (name, properties) = block_mapping::parse_minecraft_state(text);
+            let state = mapping.block(name, &properties).unwrap_or_else(|| {
+                unknown.push(name.to_string());
+                mapping.placeholder()
+            });
+            states.insert(index, state);
+        }
+
+        let data = data
+            .and_then(Tag::as_byte_array)
+            .ok_or_else(|| "schematic without block data".to_string())?;
+        let mut data = data.iter().map(|byte| *byte as u8);
+        let mut schematic = Schematic {
+            size,
+            blocks: vec![BlockState::AIR; size.x as usize * size.y as usize * size.z as usize],
+        };
+        for position in sponge_positions(size) {
+            let index = read_varint(&mut data)?;
+            let state = *states
+                .get(&(index as i64))
+                .ok_or_else(|| format!("block {} is not in the palette", index))?;
+            let index = schematic.index(position);
+            schematic.blocks[index] = state;
+        }
+        unknown.sort();
+        unknown.dedup();
+        Ok((schematic, unknown))
+    }
+
+    pub fn encode_vox(&self) -> Result<Vec<u8>, String> {
+        if self.size.max_element() > MAX_VOX_SIZE {
+            return Err(format!(
+                "too large for a MagicaVoxel model, which reach {} blocks a side",
+                MAX_VOX_SIZE
+            ));
+        }
+        // Color index of each block id, 0 for blocks left out
+        let mut color_indices = [0u8; 256];
+        let mut palette = [[0u8; 4]; 256];
+        for (index, (name, color)) in BLOCK_COLORS.iter().enumerate() {
+            if let Some(block) = block_by_name(name) {
+                color_indices[block as usize] = index as u8 + 1;
+                palette[index] = [color[0], color[1], color[2], 255];
+            }
+        }
+
+        let mut voxels = Vec::new();
+        for position in Region::new(IVec3::ZERO, self.size - IVec3::ONE).blocks() {
+            let color = color_indices[self.get(position).block() as usize];
+            if color != 0 {
+                let vox = to_vox(position, self.size);
+                voxels.extend([vox.x as u8, vox.y as u8, vox.z as u8, color]);
+            }
+        }
+        let vox_size = to_vox_size(self.size);
+        let mut size_chunk = Vec::new();
+        for side in vox_size.to_array() {
+            size_chunk.extend(side.to_le_bytes());
+        }
+        let mut xyzi = ((voxels.len() / 4) as u32).to_le_bytes().to_vec();
+        xyzi.extend(voxels);
+        let rgba: Vec<u8> = palette.iter().flatten().copied().collect();
+
+        let mut children = Vec::new();
+        write_vox_chunk(&mut children, b"SIZE", &size_chunk, &[]);
+        write_vox_chunk(&mut children, b"XYZI", &xyzi, &[]);
+        write_vox_chunk(&mut children, b"RGBA", &rgba, &[]);
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend(VOX_VERSION.to_le_bytes());
+        write_vox_chunk(&mut bytes, b"MAIN", &[], &children);
+        Ok(bytes)
+    }
+
+    pub fn decode_vox(bytes: &[u8]) -> Result<Self, String> {
+        let main = bytes
+            .get(8..)
+            .filter(|_| bytes.starts_with(b"VOX "))
+            .ok_or_else(|| "not a MagicaVoxel model".to_string())?;
+        let (id, _, children, _) = read_vox_chunk(main)?;
+        if id != *b"MAIN" {
+            return Err("MagicaVoxel model without a MAIN chunk".to_string());
+        }
+
+        let (mut size, mut voxels, mut palette) = (None, None, None);
+        let mut rest = children;
+        while !rest.is_empty() {
+            let (id, content, _, next) = read_vox_chunk(rest)?;
+            rest = next;
+            match &id {
+                b"SIZE" if size.is_none() => {
+                    let side = |index: usize| -> Result<i32, String> {
+                        let bytes = content
+                            .get(index * 4..index * 4 + 4)
+                            .ok_or_else(|| "invalid SIZE chunk".to_string())?;
+                        Ok(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    };
+                    size = Some(IVec3::new(side(0)?, side(1)?, side(2)?));
+                }
+                b"XYZI" if voxels.is_none() => voxels = Some(content),
+                b"RGBA" => palette = Some(content),
+                _ => (),
+            }
+        }
+        let vox_size = size.ok_or_else(|| "MagicaVoxel model without a size".to_string())?;
+        let voxels = voxels.ok_or_else(|| "MagicaVoxel model without voxels".to_string())?;
+        let size = IVec3::new(vox_size.x, vox_size.z, vox_size.y);
+        Schematic::check_size(size)?;
+
+        // Block for each color index, the nearest of `BLOCK_COLORS`
+        let mut blocks = [BlockState::AIR; 256];
+        for (color_index, block) in blocks.iter_mut().enumerate().skip(1) {
+            let color = match palette {
+                Some(palette) => palette
+                    .get((color_index - 1) * 4..color_index * 4)
+                    .map_or(BLOCK_COLORS[0].1, |rgba| [rgba[0], rgba[1], rgba[2]]),
+                None => default_vox_color(color_index),
+            };
+            *block = nearest_block(color);
+        }
+
+        let mut schematic = Schematic {
+            size,
+            blocks: vec![BlockState::AIR; size.x as usize * size.y as usize * size.z as usize],
+        };
+        let count = voxels
+            .get(..4)
+            .map(|count| u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize)
+            .ok_or_else(|| "invalid XYZI chunk".to_string())?;
+        let voxel_data = voxels
+            .get(4..4 + count.saturating_mul(4))
+            .ok_or_else(|| "invalid XYZI chunk".to_string())?;
+        for voxel in voxel_data.chunks_exact(4) {
+            let vox = IVec3::new(voxel[0] as i32, voxel[1] as i32, voxel[2] as i32);
+            if vox.cmpge(vox_size).any() {
+                return Err("voxel outside the model".to_string());
+            }
+            let position = from_vox(vox, size);
+            let index = schematic.index(position);
+            schematic.blocks[index] = blocks[voxel[3] as usize];
+        }
+        Ok(schematic)
+    }
+}
+
+// Positions in the order of Sponge block data, x fastest, then z, then y
+fn sponge_positions(size: IVec3) -> impl Iterator<Item = IVec3> {
+    iproduct!(0..size.y, 0..size.z, 0..size.x).map(|(y, z, x)| IVec3::new(x, y, z))
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Result<u32, String> {
+    let mut value = 0u32;
+    for shift in (0..32).step_by(7) {
+        let byte = bytes
+            .next()
+            .ok_or_else(|| "block data ends early".to_string())?;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("invalid block data".to_string())
+}
+
+fn write_vox_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    bytes.extend(id);
+    bytes.extend((content.len() as u32).to_le_bytes());
+    bytes.extend((children.len() as u32).to_le_bytes());
+    bytes.extend(content);
+    bytes.extend(children);
+}
+
+// Id, content and children of a MagicaVoxel chunk, and what follows it
+type VoxChunk<'a> = ([u8; 4], &'a [u8], &'a [u8], &'a [u8]);
+
+// The chunk `bytes` start with
+fn read_vox_chunk(bytes: &[u8]) -> Result<VoxChunk<'_>, String> {
+    let invalid = || "invalid MagicaVoxel chunk".to_string();
+    let header = bytes.get(..12).ok_or_else(invalid)?;
+    let id = [header[0], header[1], header[2], header[3]];
+    let content_size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let children_size = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    let content_end = 12usize.checked_add(content_size).ok_or_else(invalid)?;
+    let end = content_end.checked_add(children_size).ok_or_else(invalid)?;
+    if end > bytes.len() {
+        return Err(invalid());
+    }
+    Ok((
+        id,
+        &bytes[12..content_end],
+        &bytes[content_end..end],
+        &bytes[end..],
+    ))
+}
+
+// MagicaVoxel's z points up, and its y the other way from our z so nothing is mirrored
+fn to_vox(position: IVec3, size: IVec3) -> IVec3 {
+    IVec3::new(position.x, size.z - 1 - position.z, position.y)
+}
+
+fn from_vox(vox: IVec3, size: IVec3) -> IVec3 {
+    IVec3::new(vox.x, vox.z, size.z - 1 - vox.y)
+}
+
+fn to_vox_size(size: IVec3) -> IVec3 {
+    IVec3::new(size.x, size.z, size.y)
+}
+
+// Color of `color_index` in the palette of MagicaVoxel models without an `RGBA` chunk: a cube
+// of six levels of red, green and blue from white down, blue changing fastest and black left
+// out, then the ramps
+fn default_vox_color(color_index: usize) -> [u8; 3] {
+    let level = |step: usize| 0xff - 0x33 * step as u8;
+    match color_index {
+        0 => [0, 0, 0],
+        1..=215 => {
+            let cube = color_index - 1;
+            [level(cube / 36), level(cube / 6 % 6), level(cube % 6)]
+        }
+        _ => {
+            let value = VOX_RAMP[(color_index - 216) % VOX_RAMP.len()];
+            match (color_index - 216) / VOX_RAMP.len() {
+                0 => [value, 0, 0],
+                1 => [0, value, 0],
+                2 => [0, 0, value],
+                _ => [value, value, value],
+            }
+        }
+    }
+}
+
+fn nearest_block(color: [u8; 3]) -> BlockState {
+    let distance = |other: &[u8; 3]| -> i32 {
+        (0..3)
+            .map(|channel| (color[channel] as i32 - other[channel] as i32).pow(2))
+            .sum()
+    };
+    BLOCK_COLORS
+        .iter()
+        .min_by_key(|(_, other)| distance(other))
+        .and_then(|(name, _)| block_by_name(name))
+        .map_or(BlockState::AIR, BlockState::new)
+}
+
+/// Path of the schematic called `name` in `SCHEMATICS_DIR`, a Sponge schematic if the name
+/// has no extension.
+pub fn schematic_path(name: &str) -> PathBuf {
+    let path = Path::new(SCHEMATICS_DIR).join(name);
+    if path.extension().is_some() {
+        path
+    } else {
+        path.with_extension(SPONGE_EXTENSION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(name: &str) -> BlockState {
+        BlockState::new(block_by_name(name).unwrap())
+    }
+
+    // A 3 by 2 by 2 box of different blocks, air included
+    fn schematic() -> Schematic {
+        let names = ["stone", "air", "dirt", "planks", "grass", "log"];
+        Schematic {
+            size: IVec3::new(3, 2, 2),
+            blocks: (0..12)
+                .map(|index| block(names[index % names.len()]))
+                .collect(),
+        }
+    }
+
+    // A model of a single voxel of `color_index`, without a palette
+    fn vox_without_palette(color_index: u8) -> Vec<u8> {
+        let mut size = Vec::new();
+        for side in [1u32, 1, 1] {
+            size.extend(side.to_le_bytes());
+        }
+        let mut xyzi = 1u32.to_le_bytes().to_vec();
+        xyzi.extend([0, 0, 0, color_index]);
+        let mut children = Vec::new();
+        write_vox_chunk(&mut children, b"SIZE", &size, &[]);
+        write_vox_chunk(&mut children, b"XYZI", &xyzi, &[]);
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend(VOX_VERSION.to_le_bytes());
+        write_vox_chunk(&mut bytes, b"MAIN", &[], &children);
+        bytes
+    }
+
+    #[test]
+    fn sponge_schematics_round_trip() {
+        for version in [2, 3] {
+            let bytes = schematic().encode_sponge(version).unwrap();
+            let (decoded, unknown) =
+                Schematic::decode_sponge(&bytes, &BlockMapping::default()).unwrap();
+            assert_eq!(decoded, schematic(), "version {}", version);
+            assert!(unknown.is_empty());
+        }
+    }
+
+    #[test]
+    fn vox_models_round_trip() {
+        let bytes = schematic().encode_vox().unwrap();
+        assert_eq!(Schematic::decode_vox(&bytes), Ok(schematic()));
+    }
+
+    #[test]
+    fn vox_models_without_a_palette_use_the_default_one() {
+        assert_eq!(default_vox_color(1), [0xff, 0xff, 0xff]);
+        assert_eq!(default_vox_color(36), [0xff, 0, 0]);
+        assert_eq!(default_vox_color(215), [0, 0, 0x33]);
+        assert_eq!(default_vox_color(236), [0, 0, 0xee]);
+        assert_eq!(default_vox_color(255), [0x11, 0x11, 0x11]);
+
+        let red = Schematic::decode_vox(&vox_without_palette(36)).unwrap();
+        assert_eq!(red.blocks, vec![block("rose")]);
+        let blue = Schematic::decode_vox(&vox_without_palette(236)).unwrap();
+        assert_eq!(blue.blocks, vec![block("water")]);
+    }
+
+    #[test]
+    fn broken_files_fail() {
+        let bytes = schematic().encode_sponge(3).unwrap();
+        assert!(
+            Schematic::decode_sponge(&bytes[..bytes.len() / 2], &BlockMapping::default()).is_err()
+        );
+        let bytes = schematic().encode_vox().unwrap();
+        assert!(Schematic::decode_vox(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
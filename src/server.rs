@@ -0,0 +1,627 @@
+use crate::block_models::BlockModels;
+use crate::block_types::{BlockState, BLOCKTYPES};
+use crate::interaction::REACH;
+use crate::inventory::Inventory;
+use crate::level::{GameRules, Level};
+use crate::player::{self, GameMode, PlayerPhysics, WorldSpawn, MAX_HEALTH, MAX_STEP};
+use crate::protocol::{
+    ClientMessage, Connection, EntityKind, ServerMessage, KEEPALIVE_INTERVAL, PROTOCOL_VERSION,
+    TIMEOUT,
+};
+use crate::region::WorldRegions;
+use crate::save::{self, SaveThread, AUTOSAVE_INTERVAL};
+use crate::settings::MAX_RENDER_DISTANCE;
+use crate::voxel_data::WORLD_HEIGHT_IN_CHUNKS;
+use crate::voxel_map::VoxelMap;
+use crate::world::{get_chunk_from_player_pos, ChunkCoord};
+use bevy::prelude::*;
+use itertools::iproduct;
+use std::collections::HashSet;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const TICKS_PER_SECOND: u32 = 20;
+// Chunks sent to each player per tick, so joining does not stall everyone else
+pub const CHUNKS_PER_TICK: usize = 16;
+// Players can edit a little further than they reach, since their position lags behind
+pub const EDIT_RANGE: f32 = REACH + 2.0;
+// Fastest creative flight accepted from clients, in blocks per second
+pub const MAX_FLY_SPEED: f32 = 100.0;
+// Seconds of movement a player can save up while its inputs are held up on the way, more
+// arriving at once is cut short so a client cannot move faster than time passes
+pub const MAX_INPUT_BACKLOG: f32 = 1.0;
+// Seconds by which breaks can arrive closer together than their break times, since they can
+// be held up on the way by different amounts
+pub const BREAK_TIME_TOLERANCE: f32 = 0.25;
+
+/// A connected client. It becomes a player once it has sent `ClientMessage::Handshake`.
+struct ConnectedPlayer {
+    id: u32,
+    name: Option<String>,
+    connection: Connection,
+    address: SocketAddr,
+    position: Vec3,
+    physics: PlayerPhysics,
+    game_mode: GameMode,
+    /// Health as the server works it out from the player's falls, so it knows when a
+    /// respawn is due.
+    health: f32,
+    yaw: f32,
+    pitch: f32,
+    /// Seconds of movement the player has left, topped up with the time passed since
+    /// `last_input`.
+    input_time: f32,
+    last_input: Instant,
+    /// What the server counts the player as holding, filled by breaking blocks in survival and
+    /// used up by placing them.
+    inventory: Inventory,
+    last_break: Instant,
+    /// Latest input or teleport applied, and whether one was since the last tick.
+    last_sequence: u32,
+    moved: bool,
+    /// Chunks around the player to send, in chunks.
+    view_distance: i32,
+    sent_chunks: HashSet<ChunkCoord>,
+    last_heard: Instant,
+    last_keepalive: Instant,
+    next_keepalive_id: u32,
+    disconnected: bool,
+}
+
+impl ConnectedPlayer {
+    /// Unbreakable blocks stay, and survival players have to have spent the block's break time
+    /// since the last block they broke.
+    fn can_break(&self, state: BlockState) -> bool {
+        match (state.block_type().break_time(), self.game_mode) {
+            (None, _) => false,
+            (Some(_), GameMode::Creative) => true,
+            (Some(break_time), GameMode::Survival) => {
+                self.last_break.elapsed().as_secs_f32() + BREAK_TIME_TOLERANCE >= break_time
+            }
+        }
+    }
+
+    /// Survival players only place blocks they hold.
+    fn can_place(&self, state: BlockState) -> bool {
+        self.game_mode == GameMode::Creative || self.inventory.contains(state.block())
+    }
+}
+
+/// The authoritative world. Clients only send what their players want to do, the server
+/// applies it and tells every client what changed.
+pub struct Server {
+    listener: TcpListener,
+    players: Vec<ConnectedPlayer>,
+    voxel_map: VoxelMap,
+    block_models: BlockModels,
+    generated: HashSet<ChunkCoord>,
+    world_spawn: WorldSpawn,
+    game_rules: GameRules,
+    /// Game mode of players joining.
+    pub game_mode: GameMode,
+    next_player_id: u32,
+    storage: Option<WorldStorage>,
+}
+
+/// Where the world of a server started with `Server::open` is saved.
+struct WorldStorage {
+    level: Level,
+    regions: WorldRegions,
+    save_thread: SaveThread,
+    last_save: Instant,
+}
+
+impl Server {
+    /// Listens on `address` for players of a world generated from `seed`, `size_in_chunks`
+    /// chunks across. Nothing is saved.
+    pub fn bind(address: impl ToSocketAddrs, seed: u64, size_in_chunks: usize) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let voxel_map = VoxelMap::new(seed, size_in_chunks);
+        let world_spawn = WorldSpawn::new(&voxel_map);
+        Ok(Server {
+            listener,
+            players: Vec::new(),
+            voxel_map,
+            block_models: BlockModels::new(),
+            generated: HashSet::new(),
+            world_spawn,
+            game_rules: GameRules::default(),
+            game_mode: GameMode::Survival,
+            next_player_id: 1,
+            storage: None,
+        })
+    }
+
+    /// Listens on `address` for players of the world saved in `world_dir`. Chunks are loaded
+    /// from its save, and the ones players change are written back every `AUTOSAVE_INTERVAL`
+    /// seconds and on `shutdown`.
+    pub fn open(
+        address: impl ToSocketAddrs,
+        world_dir: &Path,
+        level: Level,
+        size_in_chunks: usize,
+    ) -> io::Result<Self> {
+        let mut server = Server::bind(address, level.seed, size_in_chunks)?;
+        if let Some(spawn) = level.spawn {
+            server.world_spawn = WorldSpawn(Vec3::from(spawn));
+        }
+        server.game_rules = level.game_rules.clone();
+        server.storage = Some(WorldStorage {
+            level,
+            regions: WorldRegions::new(world_dir),
+            save_thread: SaveThread::spawn(world_dir.to_path_buf()),
+            last_save: Instant::now(),
+        });
+        Ok(server)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Ticks at `TICKS_PER_SECOND` until `stop` is set, then shuts down.
+    pub fn run(&mut self, stop: &AtomicBool) {
+        let tick_length = Duration::from_secs(1) / TICKS_PER_SECOND;
+        let mut next_tick = Instant::now();
+        while !stop.load(Ordering::Relaxed) {
+            self.tick();
+            next_tick += tick_length;
+            match next_tick.checked_duration_since(Instant::now()) {
+                Some(wait) => thread::sleep(wait),
+                // Running behind, skip ahead instead of catching up
+                None => next_tick = Instant::now(),
+            }
+        }
+        self.shutdown();
+    }
+
+    /// Disconnects everyone and saves the world, waiting until it is written.
+    pub fn shutdown(&mut self) {
+        for index in 0..self.players.len() {
+            if !self.players[index].disconnected {
+                self.disconnect(index, "Server closed");
+            }
+        }
+        for player in self.players.iter_mut() {
+            // They are going anyway
+            let _ = player.connection.flush();
+        }
+        self.players.clear();
+        self.save();
+        if let Some(storage) = &mut self.storage {
+            storage.save_thread.finish();
+        }
+    }
+
+    /// Hands the chunks changed since the last save to the save thread.
+    pub fn save(&mut self) {
+        if let Some(storage) = &mut self.storage {
+            save::queue_world_save(&storage.save_thread, &mut self.voxel_map, &storage.level);
+            storage.last_save = Instant::now();
+        }
+    }
+
+    /// Accepts new clients, handles what they sent, streams chunks and drops clients that
+    /// went away.
+    pub fn tick(&mut self) {
+        self.accept_connections();
+
+        for index in 0..self.players.len() {
+            let messages = match self.players[index].connection.receive::<ClientMessage>() {
+                Ok(messages) => messages,
+                Err(error) => {
+                    if error.kind() != io::ErrorKind::UnexpectedEof {
+                        warn!("{} disconnected: {}", self.players[index].address, error);
+                    }
+                    self.players[index].disconnected = true;
+                    continue;
+                }
+            };
+            if !messages.is_empty() {
+                self.players[index].last_heard = Instant::now();
+            }
+            for message in messages {
+                self.handle_message(index, message);
+            }
+        }
+
+        for index in 0..self.players.len() {
+            self.send_movement(index);
+            self.keep_alive(index);
+        }
+
+        for index in 0..self.players.len() {
+            self.stream_chunks(index);
+        }
+
+        for player in self.players.iter_mut() {
+            if let Err(error) = player.connection.flush() {
+                warn!("{} disconnected: {}", player.address, error);
+                player.disconnected = true;
+            }
+        }
+        self.remove_disconnected();
+
+        let autosave = self
+            .storage
+            .as_ref()
+            .is_some_and(|storage| storage.last_save.elapsed().as_secs_f32() >= AUTOSAVE_INTERVAL);
+        if autosave {
+            self.save();
+        }
+    }
+
+    fn accept_connections(&mut self) {
+        loop {
+            let (stream, address) = match self.listener.accept() {
+                Ok(client) => client,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return,
+                Err(error) => {
+                    warn!("Could not accept a connection: {}", error);
+                    return;
+                }
+            };
+            let connection = match Connection::new(stream) {
+                Ok(connection) => connection,
+                Err(error) => {
+                    warn!("Could not set up the connection to {}: {}", address, error);
+                    continue;
+                }
+            };
+            self.players.push(ConnectedPlayer {
+                id: self.next_player_id,
+                name: None,
+                connection,
+                address,
+                position: self.world_spawn.0,
+                physics: PlayerPhysics::default(),
+                game_mode: self.game_mode,
+                health: MAX_HEALTH,
+                yaw: 0.0,
+                pitch: 0.0,
+                input_time: 0.0,
+                last_input: Instant::now(),
+                inventory: Inventory::starting(),
+                last_break: Instant::now(),
+                last_sequence: 0,
+                moved: false,
+                view_distance: 0,
+                sent_chunks: HashSet::new(),
+                last_heard: Instant::now(),
+                last_keepalive: Instant::now(),
+                next_keepalive_id: 0,
+                disconnected: false,
+            });
+            self.next_player_id += 1;
+        }
+    }
+
+    fn handle_message(&mut self, index: usize, message: ClientMessage) {
+        let player = &mut self.players[index];
+        if player.name.is_none() && !matches!(message, ClientMessage::Handshake { .. }) {
+            self.disconnect(index, "Expected a handshake");
+            return;
+        }
+
+        match message {
+            ClientMessage::Handshake {
+                protocol_version,
+                name,
+                view_distance,
+            } => {
+                if player.name.is_some() {
+                    return;
+                }
+                if protocol_version != PROTOCOL_VERSION {
+                    let reason = format!(
+                        "Incompatible versions, the server speaks protocol {} and the client {}",
+                        PROTOCOL_VERSION, protocol_version
+                    );
+                    self.disconnect(index, &reason);
+                    return;
+                }
+                info!("{} joined from {}", name, player.address);
+                player.name = Some(name.clone());
+                player.view_distance = view_distance.min(MAX_RENDER_DISTANCE as u32 + 1) as i32;
+                let (id, position) = (player.id, player.position);
+                player.connection.send(&ServerMessage::Welcome {
+                    player_id: id,
+                    position,
+                });
+                player.connection.send(&ServerMessage::WorldInfo {
+                    seed: self.voxel_map.seed,
+                    spawn: self.world_spawn.0,
+                });
+                player.connection.send(&ServerMessage::GameMode {
+                    game_mode: player.game_mode,
+                });
+
+                // The new player learns about everyone else, everyone else about the new player
+                let others: Vec<ServerMessage> = self
+                    .players
+                    .iter()
+                    .filter(|other| other.id != id && other.name.is_some())
+                    .map(|other| ServerMessage::SpawnEntity {
+                        entity_id: other.id,
+                        kind: EntityKind::Player {
+                            name: other.name.clone().unwrap_or_default(),
+                        },
+                        position: other.position,
+                    })
+                    .collect();
+                for other in others {
+                    self.players[index].connection.send(&other);
+                }
+                self.broadcast_except(
+                    id,
+                    &ServerMessage::SpawnEntity {
+                        entity_id: id,
+                        kind: EntityKind::Player { name: name.clone() },
+                        position,
+                    },
+                );
+                self.broadcast(&ServerMessage::Chat {
+                    text: format!("{} joined the game", name),
+                });
+            }
+            ClientMessage::Input {
+                sequence,
+                mut input,
+            } => {
+                player.last_sequence = sequence;
+                player.moved = true;
+                if !(input.delta.is_finite() && input.yaw.is_finite() && input.pitch.is_finite()) {
+                    return;
+                }
+                player.input_time = (player.input_time + player.last_input.elapsed().as_secs_f32())
+                    .min(MAX_INPUT_BACKLOG);
+                player.last_input = Instant::now();
+                input.delta = input.delta.clamp(0.0, MAX_STEP).min(player.input_time);
+                player.input_time -= input.delta;
+                input.flying = player.game_mode == GameMode::Creative;
+                input.fly_speed = input.fly_speed.clamp(0.0, MAX_FLY_SPEED);
+                player.yaw = input.yaw;
+                player.pitch = input.pitch;
+
+                // Like on the client, walking waits for the chunk the player is in
+                let chunk_pos = get_chunk_from_player_pos(player.position);
+                if !input.flying
+                    && self.voxel_map.contains_chunk(&chunk_pos)
+                    && !self.generated.contains(&chunk_pos)
+                {
+                    return;
+                }
+                let (voxel_map, block_models) = (&self.voxel_map, &self.block_models);
+                let damage = player::step_movement(
+                    &mut player.position,
+                    &mut player.physics,
+                    &input,
+                    block_models,
+                    |block| voxel_map.get(block),
+                );
+                if self.game_rules.fall_damage {
+                    player.health -= damage;
+                }
+            }
+            // Players only move themselves by respawning. Anything else is answered with where
+            // the player really is, which puts the client back there
+            ClientMessage::Teleport { sequence, position } => {
+                player.last_sequence = sequence;
+                player.moved = true;
+                if player.health <= 0.0 && position == self.world_spawn.0 {
+                    player.position = position;
+                    player.physics = PlayerPhysics::default();
+                    player.health = MAX_HEALTH;
+                }
+            }
+            // Air breaks the block, anything else is placed where there is air
+            ClientMessage::SetBlock { block, state } => {
+                let center = block.as_vec3() + Vec3::splat(0.5);
+                let current = self.voxel_map.get(block);
+                let breaking = state == BlockState::AIR;
+                let valid = is_valid_block(state)
+                    && center.distance(player.position) <= EDIT_RANGE
+                    && self
+                        .generated
+                        .contains(&get_chunk_from_player_pos(block.as_vec3()))
+                    && if breaking {
+                        player.can_break(current)
+                    } else {
+                        current == BlockState::AIR && player.can_place(state)
+                    };
+                if valid && self.voxel_map.set(block, state) {
+                    if player.game_mode == GameMode::Survival {
+                        if breaking {
+                            player.last_break = Instant::now();
+                            player.inventory.add(current.block(), 1);
+                        } else {
+                            player.inventory.take(state.block());
+                        }
+                    }
+                    self.broadcast(&ServerMessage::BlockChange { block, state });
+                } else {
+                    // Puts the client's copy of the block back the way it really is
+                    let state = self.voxel_map.get(block);
+                    self.players[index]
+                        .connection
+                        .send(&ServerMessage::BlockChange { block, state });
+                }
+            }
+            // Being heard from at all is what counts
+            ClientMessage::KeepAlive { .. } => (),
+            ClientMessage::Chat { text } => {
+                let name = player.name.clone().unwrap_or_default();
+                info!("<{}> {}", name, text);
+                self.broadcast(&ServerMessage::Chat {
+                    text: format!("<{}> {}", name, text),
+                });
+            }
+        }
+    }
+
+    /// Tells the player where its inputs got it, and everyone else where it is now.
+    fn send_movement(&mut self, index: usize) {
+        let player = &mut self.players[index];
+        if !player.moved || player.disconnected {
+            return;
+        }
+        player.moved = false;
+        player.connection.send(&ServerMessage::PlayerState {
+            sequence: player.last_sequence,
+            position: player.position,
+            physics: player.physics,
+        });
+        let message = ServerMessage::MoveEntity {
+            entity_id: player.id,
+            position: player.position,
+            yaw: player.yaw,
+            pitch: player.pitch,
+        };
+        self.broadcast_except(self.players[index].id, &message);
+    }
+
+    /// Sends a keepalive every `KEEPALIVE_INTERVAL`, and drops the client once nothing has
+    /// been heard from it for `TIMEOUT`.
+    fn keep_alive(&mut self, index: usize) {
+        let player = &mut self.players[index];
+        if player.disconnected {
+            return;
+        }
+        if player.last_heard.elapsed().as_secs_f32() > TIMEOUT {
+            self.disconnect(index, "Timed out");
+            return;
+        }
+        if player.name.is_some()
+            && player.last_keepalive.elapsed().as_secs_f32() >= KEEPALIVE_INTERVAL
+        {
+            player.connection.send(&ServerMessage::KeepAlive {
+                id: player.next_keepalive_id,
+            });
+            player.next_keepalive_id = player.next_keepalive_id.wrapping_add(1);
+            player.last_keepalive = Instant::now();
+        }
+    }
+
+    /// Tells the client why and closes the connection at the end of the tick.
+    fn disconnect(&mut self, index: usize, reason: &str) {
+        let player = &mut self.players[index];
+        info!("Disconnecting {}: {}", player.address, reason);
+        player.connection.send(&ServerMessage::Disconnect {
+            reason: reason.to_string(),
+        });
+        player.disconnected = true;
+    }
+
+    /// Sends the nearest chunks in view the player does not have yet, generating them first
+    /// if no one needed them before.
+    fn stream_chunks(&mut self, index: usize) {
+        let player = &mut self.players[index];
+        if player.name.is_none() || player.disconnected {
+            return;
+        }
+        let center = get_chunk_from_player_pos(player.position);
+        let distance = player.view_distance;
+        // Forgotten once a chunk beyond the view, so walking back and forth at its edge does
+        // not send it every time, and sent again when the player comes back
+        player.sent_chunks.retain(|chunk_pos| {
+            (chunk_pos.x - center.x).abs() <= distance + 1
+                && (chunk_pos.z - center.z).abs() <= distance + 1
+        });
+
+        let mut missing: Vec<ChunkCoord> = iproduct!(
+            center.x - distance..=center.x + distance,
+            0..WORLD_HEIGHT_IN_CHUNKS as i32,
+            center.z - distance..=center.z + distance
+        )
+        .map(|(x, y, z)| ChunkCoord { x, y, z })
+        .filter(|chunk_pos| {
+            self.voxel_map.contains_chunk(chunk_pos) && !player.sent_chunks.contains(chunk_pos)
+        })
+        .collect();
+        missing.sort_by_key(|chunk_pos| {
+            (chunk_pos.x - center.x).pow(2)
+                + (chunk_pos.y - center.y).pow(2)
+                + (chunk_pos.z - center.z).pow(2)
+        });
+
+        for chunk_pos in missing.into_iter().take(CHUNKS_PER_TICK) {
+            self.fill_chunk(chunk_pos);
+            let blocks = self.voxel_map.chunk_blocks(chunk_pos);
+            let player = &mut self.players[index];
+            player.connection.send(&ServerMessage::Chunk {
+                position: chunk_pos,
+                blocks,
+            });
+            player.sent_chunks.insert(chunk_pos);
+        }
+    }
+
+    /// Loads the chunk's blocks from the save, or generates them if it was never changed.
+    fn fill_chunk(&mut self, chunk_pos: ChunkCoord) {
+        if !self.generated.insert(chunk_pos) {
+            return;
+        }
+        let saved = self
+            .storage
+            .as_mut()
+            .and_then(|storage| storage.regions.load_chunk(chunk_pos));
+        match saved {
+            Some(blocks) => self.voxel_map.set_chunk_blocks(chunk_pos, &blocks),
+            None => {
+                self.voxel_map.generate_chunk_blocks(chunk_pos);
+            }
+        }
+    }
+
+    fn remove_disconnected(&mut self) {
+        let mut left = Vec::new();
+        self.players.retain(|player| {
+            if player.disconnected {
+                if let Some(name) = &player.name {
+                    left.push((player.id, name.clone()));
+                }
+            }
+            !player.disconnected
+        });
+        for (player_id, name) in left {
+            info!("{} left the game", name);
+            self.broadcast(&ServerMessage::DespawnEntity {
+                entity_id: player_id,
+            });
+            self.broadcast(&ServerMessage::Chat {
+                text: format!("{} left the game", name),
+            });
+        }
+    }
+
+    /// Sends the message to every player that has joined.
+    fn broadcast(&mut self, message: &ServerMessage) {
+        for player in self
+            .players
+            .iter_mut()
+            .filter(|player| player.name.is_some())
+        {
+            player.connection.send(message);
+        }
+    }
+
+    fn broadcast_except(&mut self, player_id: u32, message: &ServerMessage) {
+        for player in self
+            .players
+            .iter_mut()
+            .filter(|player| player.name.is_some() && player.id != player_id)
+        {
+            player.connection.send(message);
+        }
+    }
+}
+
+// Rejects block ids that do not exist
+fn is_valid_block(state: BlockState) -> bool {
+    (state.block() as usize) < BLOCKTYPES.len()
+}
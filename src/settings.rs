@@ -0,0 +1,436 @@
+use crate::input_map::{self, Action, Actions};
+use crate::lod::LodDistances;
+use crate::player::MovementSettings;
+use crate::save::write_atomic;
+use crate::voxel_data::{RENDER_DISTANCE, WORLD_SIZE_IN_CHUNKS};
+use bevy::prelude::*;
+use bevy::render::camera::Projection;
+use bevy::window::{PresentMode, WindowMode};
+use bevy_egui::egui::{self, Align2};
+use bevy_egui::EguiContext;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+pub const SETTINGS_PATH: &str = "settings.toml";
+pub const MIN_RENDER_DISTANCE: usize = 2;
+pub const MAX_RENDER_DISTANCE: usize = 32;
+// In chunks, the largest being `WORLD_SIZE_IN_CHUNKS`
+pub const MIN_WORLD_SIZE: usize = 4;
+// Sample counts wgpu supports on every backend
+pub const MSAA_SAMPLES: [u32; 2] = [1, 4];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowModeSetting {
+    Windowed,
+    Borderless,
+    Fullscreen,
+}
+
+/// Everything players can configure, read from `settings.toml` at startup. Missing entries keep
+/// their defaults, so old files keep working as settings are added.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// In chunks.
+    pub render_distance: usize,
+    /// Chunks across the loaded world, see `VoxelMap::size_in_chunks`. Takes effect on the next
+    /// start.
+    pub world_size: usize,
+    /// Vertical field of view in degrees.
+    pub fov: f32,
+    pub mouse_sensitivity: f32,
+    /// Blocks per second.
+    pub fly_speed: f32,
+    pub vsync: bool,
+    pub msaa: u32,
+    pub window_mode: WindowModeSetting,
+    pub window_width: f32,
+    pub window_height: f32,
+    /// Inputs bound to each action, see `input_map::InputMap`.
+    pub controls: BTreeMap<String, Vec<String>>,
+}
+
+/// Whether the settings menu is shown, and the file the settings are written back to.
+pub struct SettingsMenu {
+    pub open: bool,
+    pub path: PathBuf,
+    unsaved: bool,
+    /// Problems loading the settings and the controls in them, logged once the logger exists.
+    pub warnings: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            render_distance: RENDER_DISTANCE,
+            world_size: WORLD_SIZE_IN_CHUNKS,
+            fov: 45.0,
+            mouse_sensitivity: 0.00012,
+            fly_speed: 30.0,
+            vsync: true,
+            msaa: 4,
+            window_mode: WindowModeSetting::Windowed,
+            window_width: 1920.0,
+            window_height: 1080.0,
+            controls: input_map::default_controls(),
+        }
+    }
+}
+
+impl Settings {
+    /// Settings from the file, or the defaults if it does not exist or cannot be read. An
+    /// invalid file is moved aside to `<path>.invalid` rather than overwritten by the next
+    /// save, and the returned warning says so.
+    pub fn load(path: &Path) -> (Self, Option<String>) {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return (Settings::default(), None),
+        };
+        match toml::from_str(&text) {
+            Ok(settings) => (settings, None),
+            Err(error) => {
+                let mut backup = path.as_os_str().to_os_string();
+                backup.push(".invalid");
+                let backup = PathBuf::from(backup);
+                let warning = match fs::rename(path, &backup) {
+                    Ok(()) => format!(
+                        "Ignoring invalid {}, moved to {}: {}",
+                        path.display(),
+                        backup.display(),
+                        error
+                    ),
+                    Err(rename_error) => format!(
+                        "Ignoring invalid {}: {}, could not move it aside: {}",
+                        path.display(),
+                        error,
+                        rename_error
+                    ),
+                };
+                (Settings::default(), Some(warning))
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|error| error.to_string())?;
+        write_atomic(path, text.as_bytes())
+    }
+
+    /// Overrides settings with command line options such as `--render-distance 12` or
+    /// `--no-vsync`. Unknown options are left for others to handle.
+    pub fn apply_args(&mut self, args: &[String]) -> Result<(), String> {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--render-distance" => self.render_distance = parse(arg, value()?)?,
+                "--world-size" => self.world_size = parse(arg, value()?)?,
+                "--fov" => self.fov = parse(arg, value()?)?,
+                "--sensitivity" => self.mouse_sensitivity = parse(arg, value()?)?,
+                "--fly-speed" => self.fly_speed = parse(arg, value()?)?,
+                "--vsync" => self.vsync = true,
+                "--no-vsync" => self.vsync = false,
+                "--msaa" => self.msaa = parse(arg, value()?)?,
+                "--window-mode" => {
+                    self.window_mode = match value()?.as_str() {
+                        "windowed" => WindowModeSetting::Windowed,
+                        "borderless" => WindowModeSetting::Borderless,
+                        "fullscreen" => WindowModeSetting::Fullscreen,
+                        other => return Err(format!("Unknown window mode {}", other)),
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.clamp();
+        Ok(())
+    }
+
+    /// Brings out of range values back to something usable.
+    pub fn clamp(&mut self) {
+        self.render_distance = self
+            .render_distance
+            .clamp(MIN_RENDER_DISTANCE, MAX_RENDER_DISTANCE);
+        self.world_size = self.world_size.clamp(MIN_WORLD_SIZE, WORLD_SIZE_IN_CHUNKS);
+        self.fov = self.fov.clamp(30.0, 110.0);
+        if !MSAA_SAMPLES.contains(&self.msaa) {
+            self.msaa = 1;
+        }
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::Fifo
+        } else {
+            PresentMode::Immediate
+        }
+    }
+
+    pub fn window_mode(&self) -> WindowMode {
+        match self.window_mode {
+            WindowModeSetting::Windowed => WindowMode::Windowed,
+            WindowModeSetting::Borderless => WindowMode::BorderlessFullscreen,
+            WindowModeSetting::Fullscreen => WindowMode::Fullscreen,
+        }
+    }
+
+    pub fn movement(&self) -> MovementSettings {
+        MovementSettings {
+            sensitivity: self.mouse_sensitivity,
+            fly_speed: self.fly_speed,
+        }
+    }
+}
+
+fn parse<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {} for {}", value, arg))
+}
+
+/// Settings file named by `--settings`, or `SETTINGS_PATH`.
+pub fn settings_path(args: &[String]) -> PathBuf {
+    args.iter()
+        .position(|arg| arg == "--settings")
+        .and_then(|index| args.get(index + 1))
+        .map_or_else(|| PathBuf::from(SETTINGS_PATH), PathBuf::from)
+}
+
+/// Settings from the file overridden by the command line, and the menu writing them back to
+/// the file they came from.
+pub fn load_settings() -> (Settings, SettingsMenu) {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = settings_path(&args);
+    let (mut settings, warning) = Settings::load(&path);
+    settings.clamp();
+    let mut menu = SettingsMenu::new(path);
+    menu.warnings.extend(warning);
+    if let Err(error) = settings.apply_args(&args) {
+        menu.warnings.push(error);
+    }
+    (settings, menu)
+}
+
+impl SettingsMenu {
+    pub fn new(path: PathBuf) -> Self {
+        SettingsMenu {
+            open: false,
+            path,
+            unsaved: false,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// Logs the problems met loading the settings, which happens before the logger exists.
+pub fn log_settings_warnings(mut settings_menu: ResMut<SettingsMenu>) {
+    for warning in settings_menu.warnings.drain(..) {
+        warn!("{}", warning);
+    }
+}
+
+/// Applies the settings to the window, the camera and the world whenever they change.
+pub fn apply_settings(
+    settings: Res<Settings>,
+    mut windows: ResMut<Windows>,
+    mut msaa: ResMut<Msaa>,
+    mut movement_settings: ResMut<MovementSettings>,
+    mut lod_distances: ResMut<LodDistances>,
+    mut query: Query<&mut Projection, With<super::Player>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    if let Some(window) = windows.get_primary_mut() {
+        if window.present_mode() != settings.present_mode() {
+            window.set_present_mode(settings.present_mode());
+        }
+        if window.mode() != settings.window_mode() {
+            window.set_mode(settings.window_mode());
+        }
+    }
+    if msaa.samples != settings.msaa {
+        msaa.samples = settings.msaa;
+    }
+    *movement_settings = settings.movement();
+    let distances = LodDistances::new(settings.render_distance);
+    if *lod_distances != distances {
+        *lod_distances = distances;
+    }
+    for mut projection in query.iter_mut() {
+        if let Projection::Perspective(ref mut perspective) = *projection {
+            perspective.fov = settings.fov.to_radians();
+        }
+    }
+}
+
+/// Opens and closes the settings menu, and writes the settings back to their file once it is
+/// closed after a change.
+pub fn toggle_settings_menu(
+    actions: Res<Actions>,
+    settings: Res<Settings>,
+    mut settings_menu: ResMut<SettingsMenu>,
+    mut windows: ResMut<Windows>,
+) {
+    if !actions.just_pressed(Action::Settings) {
+        return;
+    }
+    settings_menu.open = !settings_menu.open;
+
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(!settings_menu.open);
+        window.set_cursor_visibility(settings_menu.open);
+    }
+    if !settings_menu.open && settings_menu.unsaved {
+        match settings.save(&settings_menu.path) {
+            Ok(()) => info!("Settings saved to {}", settings_menu.path.display()),
+            Err(error) => warn!("Could not save {}: {}", settings_menu.path.display(), error),
+        }
+        settings_menu.unsaved = false;
+    }
+}
+
+pub fn draw_settings_menu(
+    mut egui_context: ResMut<EguiContext>,
+    mut settings: ResMut<Settings>,
+    mut settings_menu: ResMut<SettingsMenu>,
+) {
+    if !settings_menu.open {
+        return;
+    }
+    // Edited on a copy, so the settings only count as changed when a value really is
+    let mut edited = settings.clone();
+
+    egui::Window::new("Settings")
+        .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.add(
+                egui::Slider::new(
+                    &mut edited.render_distance,
+                    MIN_RENDER_DISTANCE..=MAX_RENDER_DISTANCE,
+                )
+                .text("Render distance"),
+            );
+            ui.add(
+                egui::Slider::new(
+                    &mut edited.world_size,
+                    MIN_WORLD_SIZE..=WORLD_SIZE_IN_CHUNKS,
+                )
+                .text("World size in chunks, on the next start"),
+            );
+            ui.add(egui::Slider::new(&mut edited.fov, 30.0..=110.0).text("Field of view"));
+            ui.add(
+                egui::Slider::new(&mut edited.mouse_sensitivity, 0.00002..=0.0005)
+                    .text("Mouse sensitivity"),
+            );
+            ui.add(egui::Slider::new(&mut edited.fly_speed, 5.0..=100.0).text("Fly speed"));
+            ui.checkbox(&mut edited.vsync, "VSync");
+            egui::ComboBox::from_label("Anti-aliasing")
+                .selected_text(format!("{}x MSAA", edited.msaa))
+                .show_ui(ui, |ui| {
+                    for samples in MSAA_SAMPLES {
+                        ui.selectable_value(
+                            &mut edited.msaa,
+                            samples,
+                            format!("{}x MSAA", samples),
+                        );
+                    }
+                });
+            egui::ComboBox::from_label("Window mode")
+                .selected_text(format!("{:?}", edited.window_mode))
+                .show_ui(ui, |ui| {
+                    for mode in [
+                        WindowModeSetting::Windowed,
+                        WindowModeSetting::Borderless,
+                        WindowModeSetting::Fullscreen,
+                    ] {
+                        ui.selectable_value(&mut edited.window_mode, mode, format!("{:?}", mode));
+                    }
+                });
+            ui.label(format!(
+                "Saved to {} when closed",
+                settings_menu.path.display()
+            ));
+        });
+
+    if edited != *settings {
+        *settings = edited;
+        settings_menu.unsaved = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Empty directory of its own for a test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "minecrust-settings-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn saved_settings_load_back() {
+        let path = test_dir("round-trip").join(SETTINGS_PATH);
+        let settings = Settings {
+            world_size: 16,
+            fov: 70.0,
+            ..Default::default()
+        };
+        settings.save(&path).unwrap();
+        assert_eq!(Settings::load(&path), (settings, None));
+    }
+
+    #[test]
+    fn invalid_files_are_moved_aside() {
+        let dir = test_dir("invalid");
+        let path = dir.join(SETTINGS_PATH);
+        fs::write(&path, "fov = \"wide\"").unwrap();
+
+        let (settings, warning) = Settings::load(&path);
+        assert_eq!(settings, Settings::default());
+        assert!(warning.unwrap().contains("settings.toml.invalid"));
+        assert!(!path.exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("settings.toml.invalid")).unwrap(),
+            "fov = \"wide\""
+        );
+    }
+
+    #[test]
+    fn arguments_override_and_are_clamped() {
+        let mut settings = Settings::default();
+        let args = [
+            "--world-size",
+            "1000",
+            "--render-distance",
+            "1",
+            "--no-vsync",
+        ];
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        settings.apply_args(&args).unwrap();
+        assert_eq!(settings.world_size, WORLD_SIZE_IN_CHUNKS);
+        assert_eq!(settings.render_distance, MIN_RENDER_DISTANCE);
+        assert!(!settings.vsync);
+
+        let args = vec!["--fov".to_string(), "wide".to_string()];
+        assert_eq!(
+            settings.apply_args(&args),
+            Err("Invalid value wide for --fov".to_string())
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Shadows cast by meshes of packed voxel vertices.
+//!
+//! Bevy's shadow pass reads plain vertex positions, so voxel meshes are left out of it with
+//! `NotShadowCaster` and drawn into the sun's shadow map here instead, by a pipeline that unpacks
+//! their positions like `voxel.wgsl`. Casters are extracted whether the camera sees them or not,
+//! as long as they are within the box the shadow map covers.
+
+use crate::mesh::ATTRIBUTE_PACKED_VOXEL;
+use crate::world_time::{Sun, SHADOW_DISTANCE};
+use bevy::math::Mat3A;
+use bevy::pbr::{
+    DrawShadowMesh, LightEntity, MeshUniform, Shadow, ShadowPipeline, ShadowPipelineKey,
+    ViewLightEntities, SHADOW_FORMAT,
+};
+use bevy::prelude::*;
+use bevy::render::mesh::MeshVertexBufferLayout;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_phase::{DrawFunctions, RenderPhase};
+use bevy::render::render_resource::{
+    BindGroupLayout, CompareFunction, DepthBiasState, DepthStencilState, FrontFace,
+    MultisampleState, PipelineCache, PolygonMode, PrimitiveState, RenderPipelineDescriptor,
+    SpecializedMeshPipeline, SpecializedMeshPipelineError, SpecializedMeshPipelines,
+    StencilFaceState, StencilState, VertexState,
+};
+use bevy::render::{RenderApp, RenderStage};
+
+pub const VOXEL_SHADOW_SHADER: &str = "shaders/voxel_shadow.wgsl";
+// Bits of `MeshUniform::flags`, which Bevy keeps private
+const SHADOW_RECEIVER: u32 = 1;
+const SIGN_DETERMINANT_MODEL_3X3: u32 = 1 << 31;
+
+/// A voxel mesh casting shadows, with a sphere holding all of it in the mesh's own units.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct VoxelShadowCaster {
+    pub centre: Vec3,
+    pub radius: f32,
+}
+
+impl VoxelShadowCaster {
+    /// A caster for a mesh spanning from the origin to `size`.
+    pub fn covering(size: Vec3) -> Self {
+        VoxelShadowCaster {
+            centre: size / 2.0,
+            radius: size.length() / 2.0,
+        }
+    }
+}
+
+pub struct VoxelShadowsPlugin;
+
+impl Plugin for VoxelShadowsPlugin {
+    fn build(&self, app: &mut App) {
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<VoxelShadowPipeline>()
+                .init_resource::<SpecializedMeshPipelines<VoxelShadowPipeline>>()
+                .add_system_to_stage(RenderStage::Extract, extract_voxel_shadow_casters)
+                .add_system_to_stage(RenderStage::Queue, queue_voxel_shadows);
+        }
+    }
+}
+
+pub struct VoxelShadowPipeline {
+    view_layout: BindGroupLayout,
+    mesh_layout: BindGroupLayout,
+    shader: Handle<Shader>,
+}
+
+impl FromWorld for VoxelShadowPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let shadow_pipeline = world.resource::<ShadowPipeline>();
+        let view_layout = shadow_pipeline.view_layout.clone();
+        let mesh_layout = shadow_pipeline.mesh_layout.clone();
+        VoxelShadowPipeline {
+            view_layout,
+            mesh_layout,
+            shader: world.resource::<AssetServer>().load(VOXEL_SHADOW_SHADER),
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for VoxelShadowPipeline {
+    type Key = ShadowPipelineKey;
+
+    // Same as Bevy's shadow pipeline but for the vertex layout and shader
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let vertex_layout = layout.get_layout(&[ATTRIBUTE_PACKED_VOXEL.at_shader_location(0)])?;
+
+        Ok(RenderPipelineDescriptor {
+            vertex: VertexState {
+                shader: self.shader.clone(),
+                entry_point: "vertex".into(),
+                shader_defs: Vec::new(),
+                buffers: vec![vertex_layout],
+            },
+            fragment: None,
+            layout: Some(vec![self.view_layout.clone(), self.mesh_layout.clone()]),
+            primitive: PrimitiveState {
+                topology: key.primitive_topology(),
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: SHADOW_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState {
+                    front: StencilFaceState::IGNORE,
+                    back: StencilFaceState::IGNORE,
+                    read_mask: 0,
+                    write_mask: 0,
+                },
+                bias: DepthBiasState {
+                    constant: 0,
+                    slope_scale: 0.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: MultisampleState::default(),
+            label: Some("voxel_shadow_pipeline".into()),
+        })
+    }
+}
+
+/// Extracts the mesh and transform of every caster within the sun's shadow map. Casters the
+/// camera sees are extracted by Bevy as well, with the same values.
+fn extract_voxel_shadow_casters(
+    mut commands: Commands,
+    sun_query: Query<&GlobalTransform, With<Sun>>,
+    casters: Query<(Entity, &Handle<Mesh>, &GlobalTransform, &VoxelShadowCaster)>,
+) {
+    let to_light = match sun_query.get_single() {
+        Ok(sun_transform) => sun_transform.compute_matrix().inverse(),
+        Err(_) => return,
+    };
+
+    let mut extracted = Vec::new();
+    for (entity, mesh_handle, transform, caster) in casters.iter() {
+        let matrix = transform.compute_matrix();
+        let centre = to_light.transform_point3(matrix.transform_point3(caster.centre));
+        let radius = caster.radius * transform.compute_transform().scale.max_element();
+        let mut flags = SHADOW_RECEIVER;
+        if Mat3A::from_mat4(matrix).determinant().is_sign_positive() {
+            flags |= SIGN_DETERMINANT_MODEL_3X3;
+        }
+        // The shadow map's orthographic box, see `world_time::spawn_sun`
+        if centre.x.abs() > SHADOW_DISTANCE + radius
+            || centre.y.abs() > SHADOW_DISTANCE + radius
+            || centre.z.abs() > 4.0 * SHADOW_DISTANCE + radius
+        {
+            continue;
+        }
+
+        extracted.push((
+            entity,
+            (
+                mesh_handle.clone_weak(),
+                MeshUniform {
+                    transform: matrix,
+                    inverse_transpose_model: matrix.inverse().transpose(),
+                    flags,
+                },
+                *caster,
+            ),
+        ));
+    }
+    commands.insert_or_spawn_batch(extracted);
+}
+
+/// Adds the extracted casters to the sun's shadow phase.
+#[allow(clippy::too_many_arguments)]
+fn queue_voxel_shadows(
+    shadow_draw_functions: Res<DrawFunctions<Shadow>>,
+    voxel_shadow_pipeline: Res<VoxelShadowPipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<VoxelShadowPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    render_meshes: Res<RenderAssets<Mesh>>,
+    casters: Query<(Entity, &Handle<Mesh>), With<VoxelShadowCaster>>,
+    view_lights: Query<&ViewLightEntities>,
+    mut view_light_shadow_phases: Query<(&LightEntity, &mut RenderPhase<Shadow>)>,
+) {
+    let draw_function = match shadow_draw_functions.read().get_id::<DrawShadowMesh>() {
+        Some(draw_function) => draw_function,
+        None => return,
+    };
+
+    for view_lights in view_lights.iter() {
+        for view_light_entity in view_lights.lights.iter().copied() {
+            let (light_entity, mut shadow_phase) =
+                match view_light_shadow_phases.get_mut(view_light_entity) {
+                    Ok(phase) => phase,
+                    Err(_) => continue,
+                };
+            // Only the sun casts shadows of the terrain
+            if !matches!(light_entity, LightEntity::Directional { .. }) {
+                continue;
+            }
+
+            for (entity, mesh_handle) in casters.iter() {
+                let mesh = match render_meshes.get(mesh_handle) {
+                    Some(mesh) => mesh,
+                    None => continue,
+                };
+                let key = ShadowPipelineKey::from_primitive_topology(mesh.primitive_topology);
+                match pipelines.specialize(
+                    &mut pipeline_cache,
+                    &voxel_shadow_pipeline,
+                    key,
+                    &mesh.layout,
+                ) {
+                    Ok(pipeline) => shadow_phase.add(Shadow {
+                        distance: 0.0,
+                        entity,
+                        pipeline,
+                        draw_function,
+                    }),
+                    Err(error) => error!("{}", error),
+                }
+            }
+        }
+    }
+}
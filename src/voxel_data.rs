@@ -3,7 +3,15 @@ use bevy::prelude::Vec3;
 pub const CHUNK_SIZE: usize = 32;
 pub const WORLD_SIZE_IN_CHUNKS: usize = 128;
 pub const WORLD_HEIGHT_IN_CHUNKS: usize = 5;
+// Default render distance in chunks, see `settings.rs`
 pub const RENDER_DISTANCE: usize = 8;
+// Steps per block edge of vertex positions and texture coordinates
+pub const SUBVOXELS: u32 = 16;
+// Chebyshev distance in chunks up to which each LOD level is used, level 0 being full detail.
+// Level n downsamples voxels by 2^n. These are the defaults, see `lod::LodDistances`.
+pub const LOD_DISTANCES: [usize; 4] = [RENDER_DISTANCE, 24, 64, 192];
+pub const LOD_MESHES_PER_FRAME: usize = 4;
+pub const LOD_SKIRT_DEPTH: usize = 2;
 
 pub const VERTICES: [[Vec3; 4]; 6] = [
     [
@@ -54,12 +62,8 @@ pub const FACE_CHECKS: [Vec3; 6] = [
 ];
 
 pub const INDICES: [u32; 6] = [0, 2, 1, 0, 3, 2];
+// Same winding, split along the other diagonal
+pub const FLIPPED_INDICES: [u32; 6] = [1, 0, 3, 1, 3, 2];
 
-pub const NORMALS: [Vec3; 6] = [
-    Vec3::new(-1.0, 0.0, 0.0), // front face
-    Vec3::new(1.0, 0.0, 0.0),  // back face
-    Vec3::new(0.0, 1.0, 0.0),  // top face
-    Vec3::new(0.0, -1.0, 0.0), // bottom face
-    Vec3::new(0.0, 0.0, -1.0), // right face
-    Vec3::new(0.0, 0.0, 1.0),  // left face
-];
+// f32 position, normal and UV of a vertex before packing
+pub const UNPACKED_VERTEX_SIZE: usize = 32;
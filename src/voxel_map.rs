@@ -1,36 +1,239 @@
+use crate::block_types::BlockState;
 use crate::voxel_data::CHUNK_SIZE;
+use crate::voxel_data::WORLD_SIZE_IN_CHUNKS;
 use crate::world;
-use crate::world::{WORLD_HEIGHT, WORLD_SIZE};
+use crate::world::{is_chunk_in_world, WORLD_HEIGHT, WORLD_SIZE};
 use bevy::log::info_span;
+use bevy::prelude::IVec3;
 use bracket_noise::prelude::*;
-use itertools::Itertools;
-use ndarray::Array3;
+use itertools::{iproduct, Itertools};
+use ndarray::{s, Array3, Ix3, SliceInfo, SliceInfoElem};
 use splines::{Interpolation, Key, Spline};
 use std::cmp::{Ord, Ordering};
+use std::collections::HashSet;
+
+/// Chunk holding world block position `block`.
+pub fn block_chunk(block: IVec3) -> world::ChunkCoord {
+    let size = CHUNK_SIZE as i32;
+    world::ChunkCoord {
+        x: block.x.div_euclid(size),
+        y: block.y.div_euclid(size),
+        z: block.z.div_euclid(size),
+    }
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct VoxelMap {
-    pub voxels: Array3<u8>,
+    /// State of every block, see `BlockState`. Air is 0, so the map starts out as zeroed memory
+    /// the system only backs once blocks are written.
+    pub voxels: Array3<u16>,
+    /// Chunks across the map along x and z, centred on the origin. The terrain around it is
+    /// the same whatever the size.
+    pub size_in_chunks: usize,
+    /// Seed of the terrain generator filling in chunks.
+    pub seed: u64,
+    /// Chunks whose blocks are in place, generated or loaded. Generating a neighbour leaves
+    /// them alone.
+    pub filled: HashSet<world::ChunkCoord>,
+    /// Chunks changed since they were last saved.
+    pub modified: HashSet<world::ChunkCoord>,
 }
 
 impl VoxelMap {
-    pub fn new() -> Self {
+    /// Map of `size_in_chunks` chunks across, at most `WORLD_SIZE_IN_CHUNKS`.
+    pub fn new(seed: u64, size_in_chunks: usize) -> Self {
+        let size_in_chunks = size_in_chunks.min(WORLD_SIZE_IN_CHUNKS);
+        let size = size_in_chunks * CHUNK_SIZE;
         VoxelMap {
-            voxels: Array3::<u8>::from_elem((WORLD_SIZE, WORLD_HEIGHT, WORLD_SIZE), 0),
+            seed,
+            size_in_chunks,
+            filled: HashSet::new(),
+            modified: HashSet::new(),
+            voxels: Array3::<u16>::zeros((size, WORLD_HEIGHT, size)),
+        }
+    }
+
+    // Blocks from the edge of the map to the origin
+    fn half_size(&self) -> i32 {
+        (self.size_in_chunks / 2 * CHUNK_SIZE) as i32
+    }
+
+    /// Index into `voxels` of the block at world block position `block`, which is its world
+    /// position rounded down.
+    pub fn index(&self, block: IVec3) -> Option<[usize; 3]> {
+        let size = (self.size_in_chunks * CHUNK_SIZE) as i32;
+        let index = block + IVec3::new(self.half_size(), 0, self.half_size());
+
+        if index.cmpge(IVec3::ZERO).all()
+            && index
+                .cmplt(IVec3::new(size, WORLD_HEIGHT as i32, size))
+                .all()
+        {
+            Some([index.x as usize, index.y as usize, index.z as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Whether the chunk is in the world and within the map.
+    pub fn contains_chunk(&self, chunk_pos: &world::ChunkCoord) -> bool {
+        let half = (self.size_in_chunks / 2) as i32;
+        let size = self.size_in_chunks as i32;
+        is_chunk_in_world(chunk_pos)
+            && (0..size).contains(&(chunk_pos.x + half))
+            && (0..size).contains(&(chunk_pos.z + half))
+    }
+
+    // Voxels of a chunk within `voxels`
+    fn chunk_slice(&self, chunk_pos: world::ChunkCoord) -> SliceInfo<[SliceInfoElem; 3], Ix3, Ix3> {
+        let min = [
+            chunk_pos.x * CHUNK_SIZE as i32 + self.half_size(),
+            chunk_pos.y * CHUNK_SIZE as i32,
+            chunk_pos.z * CHUNK_SIZE as i32 + self.half_size(),
+        ]
+        .map(|min| min as usize);
+        s![
+            min[0]..min[0] + CHUNK_SIZE,
+            min[1]..min[1] + CHUNK_SIZE,
+            min[2]..min[2] + CHUNK_SIZE
+        ]
+    }
+
+    /// Block at world block position `block`, air outside the map.
+    pub fn get(&self, block: IVec3) -> BlockState {
+        match self.index(block) {
+            Some(index) => self.state(index),
+            None => BlockState::AIR,
+        }
+    }
+
+    /// Block at an index into `voxels`.
+    pub fn state(&self, index: [usize; 3]) -> BlockState {
+        BlockState(self.voxels[index])
+    }
+
+    /// Sets the block at world block position `block`, returning false outside the map.
+    pub fn set(&mut self, block: IVec3, state: BlockState) -> bool {
+        match self.index(block) {
+            Some(index) => {
+                self.voxels[index] = state.0;
+                self.modified.insert(block_chunk(block));
+                true
+            }
+            None => false,
         }
     }
 
     pub fn populate_voxel_map(&mut self, chunk_pos: world::ChunkCoord) -> bool {
+        self.populate(chunk_pos, 1)
+    }
+
+    /// Generates the blocks of the chunk only, leaving its neighbours alone even if they have
+    /// not been filled, so they can be filled later without overwriting edits next to them.
+    pub fn generate_chunk_blocks(&mut self, chunk_pos: world::ChunkCoord) -> bool {
+        self.populate(chunk_pos, 0)
+    }
+
+    // Generates the chunk and `border` blocks around it, leaving chunks already filled alone.
+    // The chunk itself may be one of them, then only its border is generated for meshing
+    fn populate(&mut self, chunk_pos: world::ChunkCoord, border: i32) -> bool {
         let _span = info_span!("VoxelMap population").entered();
-        let mut noise = FastNoise::new();
+        let generator = TerrainGenerator::new(self.seed);
+        let mut counter = 0;
+
+        // Terrain is sampled in global voxel coordinates, see `TerrainGenerator`
+        let half = (WORLD_SIZE / 2) as i32;
+        let min = IVec3::new(chunk_pos.x, chunk_pos.y, chunk_pos.z) * CHUNK_SIZE as i32;
+
+        for (x, z) in (-border..CHUNK_SIZE as i32 + border)
+            .cartesian_product(-border..CHUNK_SIZE as i32 + border)
+        {
+            let (block_x, block_z) = (min.x + x, min.z + z);
+
+            if self.index(IVec3::new(block_x, 0, block_z)).is_some() && x >= 0 && z >= 0 {
+                let threshold = generator.height_at(block_x + half, block_z + half);
+
+                for y in min.y - border..min.y + CHUNK_SIZE as i32 + border {
+                    if y < WORLD_HEIGHT as i32 && y >= 0 {
+                        if y < SEA_LEVEL as i32 {
+                            counter += 1;
+                        }
+                        if y as usize <= threshold {
+                            counter += 1;
+                        }
+                        let block = IVec3::new(block_x, y, block_z);
+                        let neighbour = block_chunk(block);
+                        if self.filled.contains(&neighbour) {
+                            continue;
+                        }
+                        if let Some(index) = self.index(block) {
+                            self.voxels[index] =
+                                BlockState::new(TerrainGenerator::block_at(y as usize, threshold))
+                                    .0;
+                        }
+                    }
+                }
+            }
+        }
+        self.filled.insert(chunk_pos);
+        counter == CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE
+    }
+
+    /// Blocks of a chunk, x major and z minor.
+    pub fn chunk_blocks(&self, chunk_pos: world::ChunkCoord) -> Vec<BlockState> {
+        if !self.contains_chunk(&chunk_pos) {
+            return vec![BlockState::AIR; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
+        }
+        self.voxels
+            .slice(self.chunk_slice(chunk_pos))
+            .iter()
+            .map(|state| BlockState(*state))
+            .collect()
+    }
+
+    /// Overwrites the blocks of a chunk with ones in the order of `chunk_blocks`, as they were
+    /// saved or sent, so the chunk does not count as modified. Chunks outside the map are
+    /// ignored.
+    pub fn set_chunk_blocks(&mut self, chunk_pos: world::ChunkCoord, blocks: &[BlockState]) {
+        if !self.contains_chunk(&chunk_pos) {
+            return;
+        }
+        self.filled.insert(chunk_pos);
+        let slice = self.chunk_slice(chunk_pos);
+        for (voxel, block) in self.voxels.slice_mut(slice).iter_mut().zip(blocks) {
+            *voxel = block.0;
+        }
+    }
+}
+
+// Seed of the terrain noise in worlds saved before they had a level file
+pub const WORLD_SEED: u64 = 1337;
+// Water fills everything below this height the terrain leaves open
+pub const SEA_LEVEL: usize = 50;
+// How far out from the origin to look for dry land to spawn on, and how many blocks apart
+// the columns looked at are
+const SPAWN_SEARCH_RADIUS: i32 = 1024;
+const SPAWN_SEARCH_STEP: i32 = 8;
+
+/// Heightmap terrain shared by the voxel map and the LOD meshes.
+///
+/// Coordinates are global voxel coordinates, i.e. already shifted by `WORLD_SIZE / 2`. The
+/// generator is purely procedural, so it can be sampled outside the voxel map as well.
+pub struct TerrainGenerator {
+    noise: FastNoise,
+    spline: Spline<f32, f32>,
+    scale: f32,
+}
+
+impl TerrainGenerator {
+    pub fn new(seed: u64) -> Self {
+        let mut noise = FastNoise::seeded(seed);
         noise.set_noise_type(NoiseType::SimplexFractal);
         noise.set_fractal_type(FractalType::FBM);
         noise.set_fractal_octaves(4);
         noise.set_fractal_gain(0.6);
         noise.set_fractal_lacunarity(2.0);
         noise.set_frequency(2.0);
-        let scale = 500.;
-        let mut counter = 0;
 
         let start = Key::new(-1., 5., Interpolation::Linear);
         let point1 = Key::new(-0.8, 10., Interpolation::Linear);
@@ -41,51 +244,61 @@ impl VoxelMap {
         let end = Key::new(1., 127., Interpolation::default());
         let spline = Spline::from_vec(vec![start, point1, point3, point4, point5, point6, end]);
 
-        let shifted_x = (chunk_pos.x * CHUNK_SIZE as i32 + (WORLD_SIZE / 2) as i32) as usize;
-        let shifted_y = chunk_pos.y as usize * CHUNK_SIZE;
-        let shifted_z = (chunk_pos.z * CHUNK_SIZE as i32 + (WORLD_SIZE / 2) as i32) as usize;
+        TerrainGenerator {
+            noise,
+            spline,
+            scale: 500.,
+        }
+    }
 
-        for (x, z) in (-1..CHUNK_SIZE as i32 + 1).cartesian_product(-1..CHUNK_SIZE as i32 + 1) {
-            let global_x = shifted_x as i32 + x;
-            let global_z = shifted_z as i32 + z;
+    /// Height of the grass block in the given column.
+    pub fn height_at(&self, global_x: i32, global_z: i32) -> usize {
+        let noise_value = self
+            .noise
+            .get_noise(global_x as f32 / self.scale, global_z as f32 / self.scale);
 
-            if global_x < WORLD_SIZE as i32 && global_z < WORLD_SIZE as i32 && x >= 0 && z >= 0 {
-                let noise_value = noise.get_noise(global_x as f32 / scale, global_z as f32 / scale);
+        self.spline.sample(noise_value).unwrap().floor() as usize
+    }
 
-                let threshold = (spline.sample(noise_value).unwrap() as f32).floor() as usize;
+    /// World block position of the surface of a column above sea level, searched in squares
+    /// growing out from the origin up to `radius` blocks away. The origin column if there is
+    /// only water around.
+    pub fn find_spawn(&self, radius: i32) -> IVec3 {
+        let half = (WORLD_SIZE / 2) as i32;
+        let surface = |x: i32, z: i32| IVec3::new(x, self.height_at(x + half, z + half) as i32, z);
 
-                for y in shifted_y as i32 - 1..(shifted_y + CHUNK_SIZE) as i32 + 1 {
-                    if y < WORLD_HEIGHT as i32 && y >= 0 {
-                        if y < 50 {
-                            self.voxels[[global_x as usize, y as usize, global_z as usize]] = 5;
-                            counter += 1;
-                        }
-                        match (y as usize).cmp(&threshold) {
-                            Ordering::Less => {
-                                if y == 0 {
-                                    self.voxels
-                                        [[global_x as usize, y as usize, global_z as usize]] = 2;
-                                    counter += 1;
-                                } else if (threshold - y as usize) == 1 {
-                                    self.voxels
-                                        [[global_x as usize, y as usize, global_z as usize]] = 4;
-                                    counter += 1;
-                                } else {
-                                    self.voxels
-                                        [[global_x as usize, y as usize, global_z as usize]] = 1;
-                                    counter += 1;
-                                }
-                            }
-                            Ordering::Greater => (),
-                            Ordering::Equal => {
-                                self.voxels[[global_x as usize, y as usize, global_z as usize]] = 3;
-                                counter += 1;
-                            }
-                        }
-                    }
+        for ring in 0..=radius.min(SPAWN_SEARCH_RADIUS) / SPAWN_SEARCH_STEP {
+            let dry = iproduct!(-ring..=ring, -ring..=ring)
+                .filter(|(i, j)| i.abs().max(j.abs()) == ring)
+                .map(|(i, j)| surface(i * SPAWN_SEARCH_STEP, j * SPAWN_SEARCH_STEP))
+                .find(|block| block.y >= SEA_LEVEL as i32);
+            if let Some(block) = dry {
+                return block;
+            }
+        }
+        surface(0, 0)
+    }
+
+    /// Block id at height `y` in a column whose surface is at `height`.
+    pub fn block_at(y: usize, height: usize) -> u8 {
+        match y.cmp(&height) {
+            Ordering::Less => {
+                if y == 0 {
+                    2
+                } else if height - y == 1 {
+                    4
+                } else {
+                    1
+                }
+            }
+            Ordering::Equal => 3,
+            Ordering::Greater => {
+                if y < SEA_LEVEL {
+                    5
+                } else {
+                    0
                 }
             }
         }
-        counter == CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE
     }
 }
@@ -0,0 +1,38 @@
+use crate::mesh::ATTRIBUTE_PACKED_VOXEL;
+use bevy::pbr::{MaterialPipeline, MaterialPipelineKey};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::mesh::MeshVertexBufferLayout;
+use bevy::render::render_resource::{
+    AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+};
+
+/// Material for chunk and LOD meshes built from `ATTRIBUTE_PACKED_VOXEL` vertices.
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "8c1d4a2e-5f7b-4e0c-9a3d-6b2f1e8c7d45"]
+pub struct VoxelMaterial {
+    #[texture(0, dimension = "2d_array")]
+    #[sampler(1)]
+    pub texture: Handle<Image>,
+}
+
+impl Material for VoxelMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/voxel.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/voxel.wgsl".into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.get_layout(&[ATTRIBUTE_PACKED_VOXEL.at_shader_location(0)])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        Ok(())
+    }
+}
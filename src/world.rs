@@ -1,6 +1,8 @@
 use crate::chunk::Chunk;
+use crate::lod;
+use crate::voxel_map::VoxelMap;
 use crate::voxel_data::{
-    CHUNK_SIZE, RENDER_DISTANCE, WORLD_HEIGHT_IN_CHUNKS, WORLD_SIZE_IN_CHUNKS,
+    CHUNK_SIZE, WORLD_HEIGHT_IN_CHUNKS, WORLD_SIZE_IN_CHUNKS,
 };
 use bevy::prelude::*;
 use itertools::iproduct;
@@ -8,24 +10,29 @@ use ndarray::Array3;
 
 pub const WORLD_SIZE: usize = WORLD_SIZE_IN_CHUNKS * CHUNK_SIZE;
 pub const WORLD_HEIGHT: usize = WORLD_HEIGHT_IN_CHUNKS * CHUNK_SIZE;
-pub const TEXTURE_ATLAS_SIZE_IN_BLOCKS: u8 = 16;
-pub const NORMALIZED_BLOCK_TEXTURE_SIZE: f32 = 1.0 / TEXTURE_ATLAS_SIZE_IN_BLOCKS as f32;
 
-pub fn spawn_world(mut chunk_queue: ResMut<ChunkToGenerateQueue>) {
+pub fn spawn_world(
+    mut chunk_queue: ResMut<ChunkToGenerateQueue>,
+    lod_distances: Res<lod::LodDistances>,
+    voxel_map: Res<VoxelMap>,
+) {
     // spawn chunks in spiral starting from 0.0 https://stackoverflow.com/a/398302
-    let render_square = (2 * RENDER_DISTANCE).pow(2);
+    // Full detail columns are aligned to LOD regions, so they can reach one chunk further
+    let render_square = (2 * lod_distances.render_distance() + 2).pow(2);
     let mut x = 0;
     let mut z = 0;
     let mut dx = 0;
     let mut dz = -1;
     let mut chunks = Vec::new();
+    let origin = ChunkCoord { x: 0, y: 0, z: 0 };
 
     for _ in 0..render_square {
-        if x > -1 * RENDER_DISTANCE as isize
-            && x <= RENDER_DISTANCE as isize
-            && z > -1 * RENDER_DISTANCE as isize
-            && x <= RENDER_DISTANCE as isize
-        {
+        let chunk_pos = ChunkCoord {
+            x,
+            y: 0,
+            z,
+        };
+        if lod::is_full_detail(chunk_pos, origin, &lod_distances) && voxel_map.contains_chunk(&chunk_pos) {
             chunks.push((x, z));
         }
 
@@ -38,15 +45,16 @@ pub fn spawn_world(mut chunk_queue: ResMut<ChunkToGenerateQueue>) {
     for (x, z) in chunks.iter().rev() {
         for y in 0..WORLD_HEIGHT_IN_CHUNKS {
             let chunk_pos = ChunkCoord {
-                x: *x as i32,
+                x: *x,
                 y: y as i32,
-                z: *z as i32,
+                z: *z,
             };
             chunk_queue.0.push(chunk_pos);
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn check_render_distance(
     query: Query<(&GlobalTransform, With<super::Player>)>,
     mut commands: Commands,
@@ -54,44 +62,46 @@ pub fn check_render_distance(
     mut chunk_queue: ResMut<ChunkToGenerateQueue>,
     mut active_chunks: ResMut<ActiveChunks>,
     mut player_last_chunk: ResMut<PlayerLastChunk>,
+    lod_distances: Res<lod::LodDistances>,
+    voxel_map: Res<VoxelMap>,
 ) {
     let player_pos = query.single().0.translation();
     let player_chunk_pos = get_chunk_from_player_pos(player_pos);
+    let render_distance = lod_distances.render_distance() as i32;
 
-    if !player_chunk_pos.equals2d(player_last_chunk.0) {
+    if !player_chunk_pos.equals2d(player_last_chunk.0) || lod_distances.is_changed() {
         for (x, y, z) in iproduct!(
-            (player_chunk_pos.x - RENDER_DISTANCE as i32..player_chunk_pos.x + RENDER_DISTANCE as i32),
+            (player_chunk_pos.x - render_distance - 1..=player_chunk_pos.x + render_distance + 1),
             (0..WORLD_HEIGHT_IN_CHUNKS as i32),
-            (player_chunk_pos.z - RENDER_DISTANCE as i32..player_chunk_pos.z + RENDER_DISTANCE as i32)
+            (player_chunk_pos.z - render_distance - 1..=player_chunk_pos.z + render_distance + 1)
         ) {
-            if is_chunk_in_world(&ChunkCoord { x, y, z }) {
-                if chunk_map.0[[
+            let chunk_pos = ChunkCoord { x, y, z };
+            if voxel_map.contains_chunk(&chunk_pos)
+                && lod::is_full_detail(chunk_pos, player_chunk_pos, &lod_distances)
+                && chunk_map.0[[
                     (x + WORLD_SIZE_IN_CHUNKS as i32 / 2) as usize,
                     y as usize,
                     (z + WORLD_SIZE_IN_CHUNKS as i32 / 2) as usize,
-                ]].1 == None
-                {
-                    chunk_queue.0.push(ChunkCoord { x, y, z });
-                }
+                ]]
+                .1
+                .is_none()
+            {
+                chunk_queue.0.push(ChunkCoord { x, y, z });
             }
         }
 
         for i in (0..active_chunks.0.len()).rev() {
             let chunk_coord = active_chunks.0[i];
 
-            if chunk_coord.x < player_chunk_pos.x - RENDER_DISTANCE as i32
-                || chunk_coord.x > player_chunk_pos.x + RENDER_DISTANCE as i32
-                || chunk_coord.z < player_chunk_pos.z - RENDER_DISTANCE as i32
-                || chunk_coord.z > player_chunk_pos.z + RENDER_DISTANCE as i32
-            {
+            if !lod::is_full_detail(chunk_coord, player_chunk_pos, &lod_distances) {
                 let chunk_entity = chunk_map.0[[
                     (chunk_coord.x + WORLD_SIZE_IN_CHUNKS as i32 / 2) as usize,
                     chunk_coord.y as usize,
                     (chunk_coord.z + WORLD_SIZE_IN_CHUNKS as i32 / 2) as usize,
                 ]].1;
 
-                if chunk_entity.is_some() {
-                    commands.entity(chunk_entity.unwrap()).despawn_recursive();
+                if let Some(chunk_entity) = chunk_entity {
+                    commands.entity(chunk_entity).despawn_recursive();
                     active_chunks.0.swap_remove(i);
                     chunk_map.0[[
                         (chunk_coord.x + WORLD_SIZE_IN_CHUNKS as i32 / 2) as usize,
@@ -105,7 +115,7 @@ pub fn check_render_distance(
     }
 }
 
-fn get_chunk_from_player_pos(mut pos: Vec3) -> ChunkCoord {
+pub fn get_chunk_from_player_pos(mut pos: Vec3) -> ChunkCoord {
     pos.x = (pos.x / CHUNK_SIZE as f32).floor();
     pos.y = (pos.y / CHUNK_SIZE as f32).floor();
     pos.z = (pos.z / CHUNK_SIZE as f32).floor();
@@ -117,13 +127,13 @@ fn get_chunk_from_player_pos(mut pos: Vec3) -> ChunkCoord {
     }
 }
 
-fn is_chunk_in_world(chunk_pos: &ChunkCoord) -> bool {
-    return chunk_pos.x + WORLD_SIZE_IN_CHUNKS as i32 / 2 >= 0
+pub fn is_chunk_in_world(chunk_pos: &ChunkCoord) -> bool {
+    chunk_pos.x + WORLD_SIZE_IN_CHUNKS as i32 / 2 >= 0
         && chunk_pos.x + WORLD_SIZE_IN_CHUNKS as i32 / 2 < WORLD_SIZE_IN_CHUNKS as i32
         && chunk_pos.y >= 0
         && chunk_pos.y < WORLD_HEIGHT_IN_CHUNKS as i32
         && chunk_pos.z + WORLD_SIZE_IN_CHUNKS as i32 / 2 >= 0
-        && chunk_pos.z + WORLD_SIZE_IN_CHUNKS as i32 / 2 < WORLD_SIZE_IN_CHUNKS as i32;
+        && chunk_pos.z + WORLD_SIZE_IN_CHUNKS as i32 / 2 < WORLD_SIZE_IN_CHUNKS as i32
 }
 
 #[derive(Clone, Debug)]
@@ -131,8 +141,9 @@ pub struct ActiveChunks(pub Vec<ChunkCoord>);
 pub struct PlayerLastChunk(ChunkCoord);
 pub struct ChunkToGenerateQueue(pub Vec<ChunkCoord>);
 pub struct ChunkToSpawnQueue(pub Vec<(ChunkCoord, bool)>);
+pub struct ChunkToRemeshQueue(pub Vec<ChunkCoord>);
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct ChunkCoord {
     pub x: i32,
     pub y: i32,
@@ -142,6 +153,12 @@ pub struct ChunkCoord {
 #[derive(Default, Debug)]
 pub struct ChunkMap(pub Array3<(Option<Chunk>, Option<Entity>)>);
 
+impl Default for PlayerLastChunk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PlayerLastChunk {
     pub fn new() -> Self {
         PlayerLastChunk(ChunkCoord { x: 0, y: 0, z: 0 })
@@ -150,11 +167,72 @@ impl PlayerLastChunk {
 
 impl ChunkCoord {
     pub fn equals2d(self, other: ChunkCoord) -> bool {
-        return other.x == self.x && other.z == self.z;
+        other.x == self.x && other.z == self.z
+    }
+}
+
+impl ChunkToRemeshQueue {
+    /// Queues the chunk holding world block position `block`, and the neighbouring chunks whose
+    /// faces or ambient occlusion it affects when it lies on the chunk border.
+    pub fn push_block(&mut self, block: IVec3) {
+        let chunk_pos = get_chunk_from_player_pos(block.as_vec3());
+        let local = block - IVec3::new(chunk_pos.x, chunk_pos.y, chunk_pos.z) * CHUNK_SIZE as i32;
+
+        for (dx, dy, dz) in iproduct!((-1..=1), (-1..=1), (-1..=1)) {
+            let offset = IVec3::new(dx, dy, dz);
+            let touches = (0..3).all(|axis| match offset[axis] {
+                -1 => local[axis] == 0,
+                1 => local[axis] == CHUNK_SIZE as i32 - 1,
+                _ => true,
+            });
+            let neighbour = ChunkCoord {
+                x: chunk_pos.x + dx,
+                y: chunk_pos.y + dy,
+                z: chunk_pos.z + dz,
+            };
+
+            if touches && is_chunk_in_world(&neighbour) && !self.0.contains(&neighbour) {
+                self.0.push(neighbour);
+            }
+        }
+    }
+
+    /// Queues every chunk holding a block between world block positions `min` and `max`, or
+    /// next to one, each once no matter how many blocks changed in it.
+    pub fn push_region(&mut self, min: IVec3, max: IVec3) {
+        let min_chunk = get_chunk_from_player_pos((min - IVec3::ONE).as_vec3());
+        let max_chunk = get_chunk_from_player_pos((max + IVec3::ONE).as_vec3());
+
+        for (x, y, z) in iproduct!(
+            min_chunk.x..=max_chunk.x,
+            min_chunk.y..=max_chunk.y,
+            min_chunk.z..=max_chunk.z
+        ) {
+            let chunk_pos = ChunkCoord { x, y, z };
+            if is_chunk_in_world(&chunk_pos) && !self.0.contains(&chunk_pos) {
+                self.0.push(chunk_pos);
+            }
+        }
     }
 }
 
 impl ChunkMap {
+    pub fn get(&self, chunk_pos: &ChunkCoord) -> &(Option<Chunk>, Option<Entity>) {
+        &self.0[[
+            (chunk_pos.x + WORLD_SIZE_IN_CHUNKS as i32 / 2) as usize,
+            chunk_pos.y as usize,
+            (chunk_pos.z + WORLD_SIZE_IN_CHUNKS as i32 / 2) as usize,
+        ]]
+    }
+
+    pub fn get_mut(&mut self, chunk_pos: &ChunkCoord) -> &mut (Option<Chunk>, Option<Entity>) {
+        &mut self.0[[
+            (chunk_pos.x + WORLD_SIZE_IN_CHUNKS as i32 / 2) as usize,
+            chunk_pos.y as usize,
+            (chunk_pos.z + WORLD_SIZE_IN_CHUNKS as i32 / 2) as usize,
+        ]]
+    }
+
     pub fn new() -> Self {
         ChunkMap(Array3::<(Option<Chunk>, Option<Entity>)>::from_elem(
             (
@@ -0,0 +1,604 @@
+use crate::block_mapping::BlockMapping;
+use crate::block_types::BlockState;
+use crate::console::{self, ArgKind, ArgSpec, ArgValue, CommandSpec};
+use crate::interaction::raycast;
+use crate::network_client::NetworkClient;
+use crate::region::WorldRegions;
+use crate::schematic::{self, Schematic, SchematicFormat};
+use crate::voxel_data::CHUNK_SIZE;
+use crate::voxel_map::{block_chunk, VoxelMap};
+use crate::world::{ChunkToRemeshQueue, WORLD_SIZE};
+use bevy::prelude::*;
+use itertools::iproduct;
+use std::collections::{HashSet, VecDeque};
+
+// Most blocks a single edit may change
+pub const MAX_EDIT_VOLUME: usize = 1 << 21;
+// Edits that can be undone, older ones are forgotten
+pub const MAX_UNDO_HISTORY: usize = 32;
+pub const FILL_MODES: [&str; 3] = ["replace", "hollow", "outline"];
+// Blocks away from the camera a schematic can be pasted on
+pub const PASTE_REACH: f32 = 64.0;
+
+/// Box of blocks between two corners, both included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub min: IVec3,
+    pub max: IVec3,
+}
+
+/// What `fill` does with the inside of the box: fill it too, clear it or leave it alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FillMode {
+    Replace,
+    Hollow,
+    Outline,
+}
+
+/// Blocks changed by one edit, with the states they had before, in the order they were set.
+#[derive(Clone, Debug)]
+pub struct Edit {
+    pub name: String,
+    pub previous: Vec<(IVec3, BlockState)>,
+}
+
+/// Edits that can be undone, the latest last.
+pub struct EditHistory {
+    edits: VecDeque<Edit>,
+}
+
+impl Region {
+    pub fn new(corner: IVec3, other_corner: IVec3) -> Self {
+        Region {
+            min: corner.min(other_corner),
+            max: corner.max(other_corner),
+        }
+    }
+
+    pub fn size(&self) -> IVec3 {
+        self.max - self.min + IVec3::ONE
+    }
+
+    pub fn volume(&self) -> usize {
+        let size = self.size();
+        size.x as usize * size.y as usize * size.z as usize
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = IVec3> {
+        let (min, max) = (self.min, self.max);
+        iproduct!(min.x..=max.x, min.y..=max.y, min.z..=max.z).map(|(x, y, z)| IVec3::new(x, y, z))
+    }
+
+    /// Whether `block` is on one of the faces of the box.
+    pub fn is_border(&self, block: IVec3) -> bool {
+        (0..3).any(|axis| block[axis] == self.min[axis] || block[axis] == self.max[axis])
+    }
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EditHistory {
+    pub fn new() -> Self {
+        EditHistory {
+            edits: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, edit: Edit) {
+        self.edits.push_back(edit);
+        while self.edits.len() > MAX_UNDO_HISTORY {
+            self.edits.pop_front();
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Edit> {
+        self.edits.pop_back()
+    }
+}
+
+/// Changes setting every block of `region` to `state`, or only its border depending on `mode`.
+pub fn fill(region: Region, state: BlockState, mode: FillMode) -> Vec<(IVec3, BlockState)> {
+    region
+        .blocks()
+        .filter_map(|block| match mode {
+            _ if region.is_border(block) => Some((block, state)),
+            FillMode::Replace => Some((block, state)),
+            FillMode::Hollow => Some((block, BlockState::AIR)),
+            FillMode::Outline => None,
+        })
+        .collect()
+}
+
+/// Changes turning every block of type `from` in `region` into `to`.
+pub fn replace(
+    voxel_map: &VoxelMap,
+    region: Region,
+    from: u8,
+    to: BlockState,
+) -> Vec<(IVec3, BlockState)> {
+    region
+        .blocks()
+        .filter(|block| voxel_map.get(*block).block() == from)
+        .map(|block| (block, to))
+        .collect()
+}
+
+/// Changes copying `region` to `offset` blocks away. The blocks are all read before any is
+/// set, so the copy may overlap the original.
+pub fn clone_region(
+    voxel_map: &VoxelMap,
+    region: Region,
+    offset: IVec3,
+) -> Vec<(IVec3, BlockState)> {
+    region
+        .blocks()
+        .map(|block| (block + offset, voxel_map.get(block)))
+        .collect()
+}
+
+/// Fills in the chunks of the blocks that are not yet, with their saved blocks or else
+/// generated ones. Otherwise they would read as air, and generating them later would overwrite
+/// what is set in them.
+pub fn fill_chunks(world: &mut World, blocks: impl IntoIterator<Item = IVec3>) {
+    let chunks: HashSet<_> = blocks.into_iter().map(block_chunk).collect();
+    for chunk_pos in chunks {
+        let voxel_map = world.resource::<VoxelMap>();
+        if !voxel_map.contains_chunk(&chunk_pos) || voxel_map.filled.contains(&chunk_pos) {
+            continue;
+        }
+        let saved = world
+            .get_resource_mut::<WorldRegions>()
+            .and_then(|mut world_regions| world_regions.load_chunk(chunk_pos));
+        match saved {
+            Some(blocks) => {
+                world
+                    .resource_mut::<VoxelMap>()
+                    .set_chunk_blocks(chunk_pos, &blocks);
+                // Neighbours already built were meshed against generated blocks
+                let min = IVec3::new(chunk_pos.x, chunk_pos.y, chunk_pos.z) * CHUNK_SIZE as i32;
+                world
+                    .resource_mut::<ChunkToRemeshQueue>()
+                    .push_region(min, min + IVec3::splat(CHUNK_SIZE as i32 - 1));
+            }
+            None => {
+                world
+                    .resource_mut::<VoxelMap>()
+                    .generate_chunk_blocks(chunk_pos);
+            }
+        }
+    }
+}
+
+/// Fails while connected to a server, which does not take edits this big. They would only
+/// change the local copy of the world.
+pub fn check_offline(world: &World) -> Result<(), String> {
+    if world.contains_resource::<NetworkClient>() {
+        Err("World edits only work in single player".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Sets the blocks, returning what they were before. The chunks around the changed blocks are
+/// queued for remeshing once for the whole edit.
+fn set_blocks(world: &mut World, changes: &[(IVec3, BlockState)]) -> Vec<(IVec3, BlockState)> {
+    fill_chunks(world, changes.iter().map(|(block, _)| *block));
+    let mut previous = Vec::new();
+    let mut min = IVec3::splat(i32::MAX);
+    let mut max = IVec3::splat(i32::MIN);
+    {
+        let mut voxel_map = world.resource_mut::<VoxelMap>();
+        for (block, state) in changes.iter() {
+            let old = voxel_map.get(*block);
+            if old != *state && voxel_map.set(*block, *state) {
+                previous.push((*block, old));
+                min = min.min(*block);
+                max = max.max(*block);
+            }
+        }
+    }
+    if !previous.is_empty() {
+        world
+            .resource_mut::<ChunkToRemeshQueue>()
+            .push_region(min, max);
+    }
+    previous
+}
+
+/// Applies the changes as one undoable edit and returns how many blocks changed.
+pub fn apply_edit(world: &mut World, name: &str, changes: &[(IVec3, BlockState)]) -> usize {
+    let previous = set_blocks(world, changes);
+    let changed = previous.len();
+    if changed > 0 {
+        world.resource_mut::<EditHistory>().push(Edit {
+            name: name.to_string(),
+            previous,
+        });
+    }
+    changed
+}
+
+/// Reverts the latest edit, returning it.
+pub fn undo(world: &mut World) -> Option<Edit> {
+    let edit = world.resource_mut::<EditHistory>().pop()?;
+    let reverted: Vec<(IVec3, BlockState)> = edit.previous.iter().rev().copied().collect();
+    set_blocks(world, &reverted);
+    Some(edit)
+}
+
+pub const COMMANDS: [CommandSpec; 7] = [
+    CommandSpec {
+        name: "setblock",
+        args: &[
+            ArgSpec::required("x", ArgKind::Coordinate),
+            ArgSpec::required("y", ArgKind::Coordinate),
+            ArgSpec::required("z", ArgKind::Coordinate),
+            ArgSpec::required("block", ArgKind::Block),
+        ],
+        help: "Sets a single block",
+        run: set_block_command,
+    },
+    CommandSpec {
+        name: "fill",
+        args: &[
+            ArgSpec::required("x1", ArgKind::Coordinate),
+            ArgSpec::required("y1", ArgKind::Coordinate),
+            ArgSpec::required("z1", ArgKind::Coordinate),
+            ArgSpec::required("x2", ArgKind::Coordinate),
+            ArgSpec::required("y2", ArgKind::Coordinate),
+            ArgSpec::required("z2", ArgKind::Coordinate),
+            ArgSpec::required("block", ArgKind::Block),
+            ArgSpec::optional("mode", ArgKind::Word(&FILL_MODES)),
+        ],
+        help: "Fills a box, or only its walls when hollow or outline",
+        run: fill_command,
+    },
+    CommandSpec {
+        name: "replace",
+        args: &[
+            ArgSpec::required("x1", ArgKind::Coordinate),
+            ArgSpec::required("y1", ArgKind::Coordinate),
+            ArgSpec::required("z1", ArgKind::Coordinate),
+            ArgSpec::required("x2", ArgKind::Coordinate),
+            ArgSpec::required("y2", ArgKind::Coordinate),
+            ArgSpec::required("z2", ArgKind::Coordinate),
+            ArgSpec::required("from", ArgKind::Block),
+            ArgSpec::required("to", ArgKind::Block),
+        ],
+        help: "Replaces one block with another in a box",
+        run: replace_command,
+    },
+    CommandSpec {
+        name: "clone",
+        args: &[
+            ArgSpec::required("x1", ArgKind::Coordinate),
+            ArgSpec::required("y1", ArgKind::Coordinate),
+            ArgSpec::required("z1", ArgKind::Coordinate),
+            ArgSpec::required("x2", ArgKind::Coordinate),
+            ArgSpec::required("y2", ArgKind::Coordinate),
+            ArgSpec::required("z2", ArgKind::Coordinate),
+            ArgSpec::required("dx", OFFSET),
+            ArgSpec::required("dy", OFFSET),
+            ArgSpec::required("dz", OFFSET),
+        ],
+        help: "Copies a box by an offset",
+        run: clone_command,
+    },
+    CommandSpec {
+        name: "schematic save",
+        args: &[
+            ArgSpec::required("x1", ArgKind::Coordinate),
+            ArgSpec::required("y1", ArgKind::Coordinate),
+            ArgSpec::required("z1", ArgKind::Coordinate),
+            ArgSpec::required("x2", ArgKind::Coordinate),
+            ArgSpec::required("y2", ArgKind::Coordinate),
+            ArgSpec::required("z2", ArgKind::Coordinate),
+            ArgSpec::required("file", ArgKind::FileName),
+        ],
+        help: "Saves a box as a .schem or .vox file in the schematics directory",
+        run: save_schematic_command,
+    },
+    CommandSpec {
+        name: "schematic paste",
+        args: &[ArgSpec::required("file", ArgKind::FileName)],
+        help: "Pastes a schematic with its lowest corner on the block looked at",
+        run: paste_schematic_command,
+    },
+    CommandSpec {
+        name: "undo",
+        args: &[],
+        help: "Reverts the latest world edit",
+        run: undo_command,
+    },
+];
+
+const OFFSET: ArgKind = ArgKind::Integer {
+    min: -(WORLD_SIZE as i64),
+    max: WORLD_SIZE as i64,
+};
+
+/// Region given by the first six arguments, relative coordinates resolved against the player.
+fn region_arg(world: &mut World, args: &[ArgValue]) -> Result<Region, String> {
+    let origin = console::player_feet(world).unwrap_or(Vec3::ZERO);
+    let region = Region::new(
+        console::block_position(&args[0..3], origin),
+        console::block_position(&args[3..6], origin),
+    );
+    if region.volume() > MAX_EDIT_VOLUME {
+        return Err(format!(
+            "Too many blocks, {} is over the limit of {}",
+            region.volume(),
+            MAX_EDIT_VOLUME
+        ));
+    }
+    Ok(region)
+}
+
+fn block_arg(arg: &ArgValue) -> BlockState {
+    match arg {
+        ArgValue::Block(block) => BlockState::new(*block),
+        _ => BlockState::AIR,
+    }
+}
+
+fn changed_message(changed: usize) -> Result<String, String> {
+    if changed == 0 {
+        Err("No blocks changed".to_string())
+    } else {
+        Ok(format!("Changed {} blocks", changed))
+    }
+}
+
+fn set_block_command(world: &mut World, args: &[ArgValue]) -> Result<String, String> {
+    check_offline(world)?;
+    let origin = console::player_feet(world).unwrap_or(Vec3::ZERO);
+    let block = console::block_position(&args[0..3], origin);
+    let changed = apply_edit(world, "setblock", &[(block, block_arg(&args[3]))]);
+    changed_message(changed)
+}
+
+fn fill_command(world: &mut World, args: &[ArgValue]) -> Result<String, String> {
+    check_offline(world)?;
+    let region = region_arg(world, args)?;
+    let mode = match args.get(7) {
+        Some(ArgValue::Word("hollow")) => FillMode::Hollow,
+        Some(ArgValue::Word("outline")) => FillMode::Outline,
+        _ => FillMode::Replace,
+    };
+    let changes = fill(region, block_arg(&args[6]), mode);
+    let changed = apply_edit(world, "fill", &changes);
+    changed_message(changed)
+}
+
+fn replace_command(world: &mut World, args: &[ArgValue]) -> Result<String, String> {
+    check_offline(world)?;
+    let region = region_arg(world, args)?;
+    let from = block_arg(&args[6]).block();
+    fill_chunks(world, region.blocks());
+    let changes = replace(
+        world.resource::<VoxelMap>(),
+        region,
+        from,
+        block_arg(&args[7]),
+    );
+    let changed = apply_edit(world, "replace", &changes);
+    changed_message(changed)
+}
+
+fn clone_command(world: &mut World, args: &[ArgValue]) -> Result<String, String> {
+    check_offline(world)?;
+    let region = region_arg(world, args)?;
+    let mut offset = IVec3::ZERO;
+    for axis in 0..3 {
+        if let ArgValue::Integer(value) = args[6 + axis] {
+            offset[axis] = value as i32;
+        }
+    }
+    fill_chunks(world, region.blocks());
+    let changes = clone_region(world.resource::<VoxelMap>(), region, offset);
+    let changed = apply_edit(world, "clone", &changes);
+    changed_message(changed)
+}
+
+fn file_arg(arg: &ArgValue) -> &str {
+    match arg {
+        ArgValue::FileName(name) => name,
+        _ => "",
+    }
+}
+
+fn save_schematic_command(world: &mut World, args: &[ArgValue]) -> Result<String, String> {
+    let region = region_arg(world, args)?;
+    let path = schematic::schematic_path(file_arg(&args[6]));
+    let format = SchematicFormat::from_path(&path)?;
+    fill_chunks(world, region.blocks());
+    Schematic::from_world(world.resource::<VoxelMap>(), region).save(&path, format)?;
+    Ok(format!(
+        "Saved {} blocks to {}",
+        region.volume(),
+        path.display()
+    ))
+}
+
+fn paste_schematic_command(world: &mut World, args: &[ArgValue]) -> Result<String, String> {
+    check_offline(world)?;
+    let path = schematic::schematic_path(file_arg(&args[0]));
+    let (schematic, unknown) = Schematic::load(&path, &BlockMapping::default())?;
+
+    let mut query = world.query_filtered::<&Transform, With<super::Player>>();
+    let transform = query
+        .get_single(world)
+        .map_err(|_| "There is no player".to_string())?;
+    let look = transform.rotation * -Vec3::Z;
+    let voxel_map = world.resource::<VoxelMap>();
+    let hit = raycast(transform.translation, look, PASTE_REACH, |block| {
+        voxel_map.get(block).block_type().textures.is_some()
+    })
+    .ok_or_else(|| "Look at a block to paste on".to_string())?;
+
+    let changes = schematic.changes(hit.adjacent());
+    let changed = apply_edit(world, "schematic paste", &changes);
+    let mut message = changed_message(changed)?;
+    if !unknown.is_empty() {
+        message += &format!(", unknown {} became placeholders", unknown.join(", "));
+    }
+    Ok(message)
+}
+
+fn undo_command(world: &mut World, _: &[ArgValue]) -> Result<String, String> {
+    check_offline(world)?;
+    match undo(world) {
+        Some(edit) => Ok(format!(
+            "Undid /{}, {} blocks",
+            edit.name,
+            edit.previous.len()
+        )),
+        None => Err("Nothing to undo".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_types::block_by_name;
+    use crate::console::{execute_command, CommandRegistry};
+    use crate::world::ChunkCoord;
+
+    // Chunk the tests build in, left empty rather than generated
+    const EMPTY_CHUNK: ChunkCoord = ChunkCoord { x: 0, y: 3, z: 0 };
+
+    fn world() -> World {
+        let mut voxel_map = VoxelMap::new(0, 4);
+        voxel_map.filled.insert(EMPTY_CHUNK);
+        let mut world = World::new();
+        world.insert_resource(voxel_map);
+        world.insert_resource(ChunkToRemeshQueue(Vec::new()));
+        world.insert_resource(EditHistory::new());
+        world.insert_resource(CommandRegistry::new());
+        world
+    }
+
+    fn state(name: &str) -> BlockState {
+        BlockState::new(block_by_name(name).unwrap())
+    }
+
+    fn count(world: &World, region: Region, name: &str) -> usize {
+        let voxel_map = world.resource::<VoxelMap>();
+        region
+            .blocks()
+            .filter(|block| voxel_map.get(*block) == state(name))
+            .count()
+    }
+
+    fn region(min: [i32; 3], max: [i32; 3]) -> Region {
+        Region::new(IVec3::from(min), IVec3::from(max))
+    }
+
+    #[test]
+    fn fill_modes_change_the_inside_differently() {
+        let cube = region([0, 96, 0], [2, 98, 2]);
+        let stone = state("stone");
+        assert_eq!(fill(cube, stone, FillMode::Replace).len(), 27);
+        let hollow = fill(cube, stone, FillMode::Hollow);
+        assert_eq!(hollow.len(), 27);
+        assert!(hollow.contains(&(IVec3::new(1, 97, 1), BlockState::AIR)));
+        let outline = fill(cube, stone, FillMode::Outline);
+        assert_eq!(outline.len(), 26);
+        assert!(outline.iter().all(|(_, state)| *state == stone));
+    }
+
+    #[test]
+    fn fill_and_replace_change_the_map() {
+        let mut world = world();
+        assert_eq!(
+            execute_command(&mut world, "/fill 0 96 0 2 98 2 stone"),
+            Ok("Changed 27 blocks".to_string())
+        );
+        assert_eq!(count(&world, region([0, 96, 0], [2, 98, 2]), "stone"), 27);
+        assert_eq!(
+            execute_command(&mut world, "/replace 0 96 0 2 96 2 stone dirt"),
+            Ok("Changed 9 blocks".to_string())
+        );
+        assert_eq!(count(&world, region([0, 96, 0], [2, 98, 2]), "dirt"), 9);
+        assert_eq!(
+            execute_command(&mut world, "/replace 0 96 0 2 96 2 stone dirt"),
+            Err("No blocks changed".to_string())
+        );
+        assert!(!world.resource::<ChunkToRemeshQueue>().0.is_empty());
+    }
+
+    #[test]
+    fn clone_reads_everything_before_writing() {
+        let mut world = world();
+        execute_command(&mut world, "/setblock 0 96 0 stone").unwrap();
+        execute_command(&mut world, "/setblock 1 96 0 dirt").unwrap();
+        // The copy overlaps the original
+        assert_eq!(
+            execute_command(&mut world, "/clone 0 96 0 1 96 0 1 0 0"),
+            Ok("Changed 2 blocks".to_string())
+        );
+        let voxel_map = world.resource::<VoxelMap>();
+        assert_eq!(voxel_map.get(IVec3::new(0, 96, 0)), state("stone"));
+        assert_eq!(voxel_map.get(IVec3::new(1, 96, 0)), state("stone"));
+        assert_eq!(voxel_map.get(IVec3::new(2, 96, 0)), state("dirt"));
+    }
+
+    #[test]
+    fn undo_reverts_the_latest_edit_first() {
+        let mut world = world();
+        execute_command(&mut world, "/fill 0 96 0 1 96 1 stone").unwrap();
+        execute_command(&mut world, "/fill 0 96 0 0 96 0 planks").unwrap();
+        assert_eq!(
+            execute_command(&mut world, "/undo"),
+            Ok("Undid /fill, 1 blocks".to_string())
+        );
+        assert_eq!(count(&world, region([0, 96, 0], [1, 96, 1]), "stone"), 4);
+        execute_command(&mut world, "/undo").unwrap();
+        assert_eq!(count(&world, region([0, 96, 0], [1, 96, 1]), "air"), 4);
+        assert_eq!(
+            execute_command(&mut world, "/undo"),
+            Err("Nothing to undo".to_string())
+        );
+    }
+
+    #[test]
+    fn edits_outside_the_map_change_nothing() {
+        let mut world = world();
+        assert_eq!(
+            execute_command(&mut world, "/setblock 500 96 0 stone"),
+            Err("No blocks changed".to_string())
+        );
+        assert_eq!(
+            execute_command(&mut world, "/fill 0 -2 0 0 -1 0 stone"),
+            Err("No blocks changed".to_string())
+        );
+    }
+
+    #[test]
+    fn edits_survive_generating_their_chunk() {
+        let mut world = world();
+        let block = IVec3::new(40, 100, 40);
+        let chunk_pos = block_chunk(block);
+        execute_command(&mut world, "/setblock 40 100 40 planks").unwrap();
+
+        let mut voxel_map = world.resource_mut::<VoxelMap>();
+        assert!(voxel_map.filled.contains(&chunk_pos));
+        // What `chunk::generate_chunk` does once the chunk comes into view
+        voxel_map.populate_voxel_map(chunk_pos);
+        assert_eq!(voxel_map.get(block), state("planks"));
+    }
+
+    #[test]
+    fn replace_sees_the_terrain_of_chunks_not_generated_yet() {
+        let mut world = world();
+        // Bedrock is generated at the bottom of every column
+        assert_eq!(
+            execute_command(&mut world, "/replace 40 0 40 41 0 41 bedrock stone"),
+            Ok("Changed 4 blocks".to_string())
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use crate::console::Console;
+use crate::input_map::{Action, Actions};
+use crate::level::{self, Level, WorldEntry};
+use crate::save::WorldSave;
+use crate::settings::SettingsMenu;
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Align2};
+use bevy_egui::EguiContext;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Whether the world selection screen is shown, the worlds it lists and what is typed into it.
+pub struct WorldSelectScreen {
+    pub open: bool,
+    saves: PathBuf,
+    worlds: Vec<WorldEntry>,
+    selected: Option<usize>,
+    rename: String,
+    new_name: String,
+    new_seed: String,
+    confirm_delete: bool,
+    message: String,
+    /// World to start the game again with once this one is saved and closed.
+    relaunch: Option<String>,
+}
+
+enum WorldAction {
+    Open(String),
+    Create,
+    Rename,
+    Duplicate,
+    Delete,
+}
+
+impl WorldSelectScreen {
+    pub fn new(saves: PathBuf) -> Self {
+        WorldSelectScreen {
+            open: false,
+            saves,
+            worlds: Vec::new(),
+            selected: None,
+            rename: String::new(),
+            new_name: String::new(),
+            new_seed: String::new(),
+            confirm_delete: false,
+            message: String::new(),
+            relaunch: None,
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.worlds = level::list_worlds(&self.saves);
+        self.selected = None;
+        self.confirm_delete = false;
+    }
+}
+
+/// Opens and closes the world selection screen, freeing the cursor while it is open.
+pub fn toggle_world_select_screen(
+    actions: Res<Actions>,
+    mut screen: ResMut<WorldSelectScreen>,
+    console: Res<Console>,
+    mut windows: ResMut<Windows>,
+) {
+    if !actions.just_pressed(Action::Worlds) || console.open {
+        return;
+    }
+    screen.open = !screen.open;
+    if screen.open {
+        screen.refresh();
+        screen.message.clear();
+    }
+
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(!screen.open);
+        window.set_cursor_visibility(screen.open);
+    }
+}
+
+/// Lists the saved worlds to open, rename, duplicate or delete, and creates new ones. The world
+/// being played cannot be opened again or deleted. Opening a world starts the game again in it,
+/// since the whole app is built around the world it started with.
+pub fn draw_world_select_screen(
+    mut egui_context: ResMut<EguiContext>,
+    mut screen: ResMut<WorldSelectScreen>,
+    mut playing_level: ResMut<Level>,
+    world_save: Res<WorldSave>,
+    mut exits: EventWriter<AppExit>,
+) {
+    if !screen.open {
+        return;
+    }
+    let screen = &mut *screen;
+    let mut action = None;
+
+    egui::Window::new("Worlds")
+        .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            if screen.worlds.is_empty() {
+                ui.label("There are no saved worlds");
+            }
+            for (index, entry) in screen.worlds.iter().enumerate() {
+                let playing = entry.dir == world_save.dir;
+                let label = format!(
+                    "{}{} - seed {}, last played {}",
+                    entry.level.name,
+                    if playing { " (playing)" } else { "" },
+                    entry.level.seed,
+                    level::format_time(entry.level.last_played)
+                );
+                if ui
+                    .selectable_label(screen.selected == Some(index), label)
+                    .clicked()
+                {
+                    screen.selected = Some(index);
+                    screen.rename = entry.level.name.clone();
+                    screen.confirm_delete = false;
+                }
+            }
+
+            if let Some(entry) = screen.selected.and_then(|index| screen.worlds.get(index)) {
+                let playing = entry.dir == world_save.dir;
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(!playing, egui::Button::new("Open"))
+                        .clicked()
+                    {
+                        action = Some(WorldAction::Open(entry.id()));
+                    }
+                    if ui.button("Duplicate").clicked() {
+                        action = Some(WorldAction::Duplicate);
+                    }
+                    let delete = if screen.confirm_delete {
+                        "Really delete?"
+                    } else {
+                        "Delete"
+                    };
+                    if ui
+                        .add_enabled(!playing, egui::Button::new(delete))
+                        .clicked()
+                    {
+                        action = Some(WorldAction::Delete);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut screen.rename);
+                    if ui.button("Rename").clicked() {
+                        action = Some(WorldAction::Rename);
+                    }
+                });
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Name");
+                ui.text_edit_singleline(&mut screen.new_name);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Seed");
+                ui.text_edit_singleline(&mut screen.new_seed);
+                if ui.button("Create").clicked() {
+                    action = Some(WorldAction::Create);
+                }
+            });
+            ui.label("Leave the seed empty for a random one");
+            if !screen.message.is_empty() {
+                ui.label(&screen.message);
+            }
+        });
+
+    let action = match action {
+        Some(action) => action,
+        None => return,
+    };
+    let selected = screen
+        .selected
+        .and_then(|index| screen.worlds.get(index))
+        .cloned();
+    let result = match (action, selected) {
+        (WorldAction::Open(world), _) => {
+            screen.relaunch = Some(world);
+            exits.send(AppExit);
+            Ok(())
+        }
+        (WorldAction::Create, _) => create_world(screen).map(|world| {
+            screen.relaunch = Some(world);
+            exits.send(AppExit);
+        }),
+        (WorldAction::Rename, Some(entry)) => {
+            let name = screen.rename.trim().to_string();
+            if name.is_empty() {
+                screen.message = "Worlds need a name".to_string();
+                return;
+            }
+            if entry.dir == world_save.dir {
+                playing_level.name = name.clone();
+            }
+            level::rename_world(&entry.dir, &name)
+        }
+        (WorldAction::Duplicate, Some(entry)) => {
+            let name = format!("{} copy", entry.level.name);
+            level::duplicate_world(&screen.saves, &entry.dir, &name).map(|_| ())
+        }
+        (WorldAction::Delete, Some(entry)) if screen.confirm_delete => {
+            level::delete_world(&entry.dir)
+        }
+        (WorldAction::Delete, Some(_)) => {
+            screen.confirm_delete = true;
+            return;
+        }
+        (_, None) => Ok(()),
+    };
+    match result {
+        Ok(()) => screen.message.clear(),
+        Err(error) => screen.message = error,
+    }
+    screen.refresh();
+}
+
+// Creates the world typed in, returning its directory name
+fn create_world(screen: &mut WorldSelectScreen) -> Result<String, String> {
+    let name = screen.new_name.trim();
+    if name.is_empty() {
+        return Err("The new world needs a name".to_string());
+    }
+    let seed = match screen.new_seed.trim() {
+        "" => level::random_seed(),
+        seed => level::parse_seed(seed)?,
+    };
+    let dir = level::create_world(&screen.saves, name, seed)?;
+    Ok(dir
+        .file_name()
+        .map_or_else(String::new, |world| world.to_string_lossy().into_owned()))
+}
+
+/// Starts the game again with the world picked on the selection screen, once the world played
+/// now is saved. Runs after `save_on_exit`, so the new game never reads a half written save and
+/// the two never hold a voxel map at the same time.
+pub fn relaunch_on_exit(
+    mut exits: EventReader<AppExit>,
+    screen: Res<WorldSelectScreen>,
+    settings_menu: Res<SettingsMenu>,
+) {
+    if exits.iter().last().is_none() {
+        return;
+    }
+    if let Some(world) = &screen.relaunch {
+        let error = relaunch(world, &settings_menu.path);
+        error!("Could not start the game again: {}", error);
+    }
+}
+
+// Replaces this game with one playing `world`, with the same settings file and the rest of
+// the command line. Only returns if that failed
+fn relaunch(world: &str, settings_path: &Path) -> io::Error {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(error) => return error,
+    };
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut command = Command::new(exe);
+    command
+        .args(forwarded_args(&args))
+        .arg("--world")
+        .arg(world)
+        .arg("--settings")
+        .arg(settings_path);
+    replace_process(&mut command)
+}
+
+// The command line without the options choosing the world or the settings file, which the
+// relaunched game is given anew
+fn forwarded_args(args: &[String]) -> Vec<String> {
+    let mut forwarded = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let values = level::WORLD_OPTIONS
+            .iter()
+            .chain([&("--settings", 1)])
+            .find(|(option, _)| option == arg)
+            .map(|(_, values)| *values);
+        match values {
+            Some(values) => {
+                for _ in 0..values {
+                    args.next();
+                }
+            }
+            None => forwarded.push(arg.clone()),
+        }
+    }
+    forwarded
+}
+
+#[cfg(unix)]
+fn replace_process(command: &mut Command) -> io::Error {
+    use std::os::unix::process::CommandExt;
+    command.exec()
+}
+
+// Without exec, the new game starts as this one exits
+#[cfg(not(unix))]
+fn replace_process(command: &mut Command) -> io::Error {
+    match command.spawn() {
+        Ok(_) => std::process::exit(0),
+        Err(error) => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relaunching_keeps_the_other_options() {
+        let args: Vec<String> = [
+            "--connect",
+            "127.0.0.1:25565",
+            "--new-world",
+            "Island",
+            "--seed",
+            "7",
+            "--render-distance",
+            "8",
+            "--settings",
+            "other.toml",
+            "--rename-world",
+            "island",
+            "Isle",
+            "--list-worlds",
+            "--name",
+            "Alice",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(
+            forwarded_args(&args),
+            [
+                "--connect",
+                "127.0.0.1:25565",
+                "--render-distance",
+                "8",
+                "--name",
+                "Alice"
+            ]
+        );
+    }
+}
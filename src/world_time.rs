@@ -0,0 +1,259 @@
+use crate::level::Level;
+use bevy::prelude::*;
+use bevy_atmosphere::prelude::*;
+use std::f32::consts::TAU;
+
+// Seconds in a full day and night cycle
+pub const DEFAULT_DAY_LENGTH: f32 = 1200.0;
+// Time of day the world starts at, a little after sunrise
+pub const START_TIME: f32 = 0.05;
+// Brightness of the sun in the units `voxel.wgsl` works in, there is no exposure
+pub const SUN_ILLUMINANCE: f32 = 4.0;
+pub const DAY_AMBIENT: f32 = 0.5;
+pub const NIGHT_AMBIENT: f32 = 0.05;
+// bevy_atmosphere's default sun intensity
+pub const DAY_SKY_INTENSITY: f32 = 22.0;
+pub const NIGHT_SKY_INTENSITY: f32 = 1.0;
+// The sky is rendered again whenever the atmosphere changes, so it is updated less often
+pub const SKY_UPDATE_INTERVAL: f32 = 0.1;
+// Half size in blocks of the area around the player covered by the sun's shadow map
+pub const SHADOW_DISTANCE: f32 = 96.0;
+
+/// Time of day as a fraction of a day: 0 is sunrise, 0.25 noon, 0.5 sunset and 0.75 midnight.
+pub struct WorldTime {
+    pub time_of_day: f32,
+    pub day_length: f32,
+    pub frozen: bool,
+    pub day: u32,
+    sky_timer: Timer,
+}
+
+/// Changes to the world time, sent as events by commands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeCommand {
+    /// Jump to a time of day, see `WorldTime`.
+    Set(f32),
+    /// Move the time forward by a fraction of a day.
+    Add(f32),
+    Freeze(bool),
+    /// Seconds a full day lasts.
+    SetDayLength(f32),
+}
+
+#[derive(Component)]
+pub struct Sun;
+
+impl Default for WorldTime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WorldTime {
+    pub fn new() -> Self {
+        WorldTime {
+            time_of_day: START_TIME,
+            day_length: DEFAULT_DAY_LENGTH,
+            frozen: false,
+            day: 0,
+            sky_timer: Timer::from_seconds(SKY_UPDATE_INTERVAL, true),
+        }
+    }
+
+    pub fn advance(&mut self, days: f32) {
+        let time = self.time_of_day + days;
+        self.day = (self.day as i64 + time.floor() as i64).max(0) as u32;
+        self.time_of_day = time.rem_euclid(1.0);
+    }
+
+    /// Carries out a time command. Times of day wrap into the day and days last at least a
+    /// second.
+    pub fn apply(&mut self, command: TimeCommand) {
+        match command {
+            TimeCommand::Set(time_of_day) => self.time_of_day = time_of_day.rem_euclid(1.0),
+            TimeCommand::Add(days) => self.advance(days),
+            TimeCommand::Freeze(frozen) => self.frozen = frozen,
+            TimeCommand::SetDayLength(day_length) => self.day_length = day_length.max(1.0),
+        }
+    }
+
+    /// Lets `seconds` pass unless the time is frozen or the world has no daylight cycle.
+    pub fn tick(&mut self, seconds: f32, daylight_cycle: bool) {
+        if !self.frozen && daylight_cycle {
+            self.advance(seconds / self.day_length);
+        }
+    }
+
+    /// Unit vector pointing at the sun, which rises in +Z and sets in -Z.
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = self.time_of_day * TAU;
+        Vec3::new(0.0, angle.sin(), angle.cos())
+    }
+
+    /// How much of the daylight reaches the ground, fading in and out around the horizon.
+    pub fn daylight(&self) -> f32 {
+        (self.sun_direction().y * 4.0 + 0.2).clamp(0.0, 1.0)
+    }
+}
+
+pub fn spawn_sun(mut commands: Commands) {
+    commands
+        .spawn_bundle(DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                illuminance: SUN_ILLUMINANCE,
+                shadows_enabled: true,
+                shadow_projection: OrthographicProjection {
+                    left: -SHADOW_DISTANCE,
+                    right: SHADOW_DISTANCE,
+                    bottom: -SHADOW_DISTANCE,
+                    top: SHADOW_DISTANCE,
+                    near: -4.0 * SHADOW_DISTANCE,
+                    far: 4.0 * SHADOW_DISTANCE,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Sun)
+        .insert(Name::new("Sun"));
+}
+
+/// Applies pending time commands, then lets the time run unless it is frozen or the world has
+/// its daylight cycle turned off.
+pub fn advance_time(
+    mut world_time: ResMut<WorldTime>,
+    mut time_commands: EventReader<TimeCommand>,
+    time: Res<Time>,
+    level: Res<Level>,
+) {
+    for command in time_commands.iter() {
+        world_time.apply(*command);
+    }
+    world_time.tick(time.delta_seconds(), level.game_rules.daylight_cycle);
+}
+
+/// Turns the sun towards the time of day, keeping its shadow map centred on the player, and
+/// scales the ambient light and the sky with the daylight.
+pub fn update_daylight(
+    mut world_time: ResMut<WorldTime>,
+    time: Res<Time>,
+    player_query: Query<&GlobalTransform, With<super::Player>>,
+    mut sun_query: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut atmosphere: ResMut<Atmosphere>,
+) {
+    let sun_direction = world_time.sun_direction();
+    let daylight = world_time.daylight();
+
+    for (mut transform, mut light) in sun_query.iter_mut() {
+        // The light shines along its forward axis, away from the sun
+        transform.rotation = Quat::from_rotation_x(-world_time.time_of_day * TAU);
+        if let Ok(player_transform) = player_query.get_single() {
+            transform.translation = player_transform.translation();
+        }
+        light.illuminance = SUN_ILLUMINANCE * daylight;
+    }
+
+    let ambient = NIGHT_AMBIENT + (DAY_AMBIENT - NIGHT_AMBIENT) * daylight;
+    if ambient_light.brightness != ambient {
+        ambient_light.brightness = ambient;
+    }
+
+    if world_time.sky_timer.tick(time.delta()).just_finished() {
+        atmosphere.sun_position = sun_direction;
+        atmosphere.sun_intensity =
+            NIGHT_SKY_INTENSITY + (DAY_SKY_INTENSITY - NIGHT_SKY_INTENSITY) * daylight;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time_of_day: f32) -> WorldTime {
+        WorldTime {
+            time_of_day,
+            ..WorldTime::new()
+        }
+    }
+
+    fn assert_near(found: f32, expected: f32) {
+        assert!(
+            (found - expected).abs() < 1e-5,
+            "{} is not {}",
+            found,
+            expected
+        );
+    }
+
+    #[test]
+    fn days_wrap_around() {
+        let mut world_time = at(0.9);
+        world_time.advance(0.2);
+        assert_near(world_time.time_of_day, 0.1);
+        assert_eq!(world_time.day, 1);
+
+        world_time.advance(2.5);
+        assert_near(world_time.time_of_day, 0.6);
+        assert_eq!(world_time.day, 3);
+
+        world_time.advance(-0.7);
+        assert_near(world_time.time_of_day, 0.9);
+        assert_eq!(world_time.day, 2);
+
+        // There is no day before the first
+        world_time.advance(-5.0);
+        assert_eq!(world_time.day, 0);
+    }
+
+    #[test]
+    fn the_sun_is_up_at_noon_and_down_at_midnight() {
+        assert!(at(0.25).sun_direction().distance(Vec3::Y) < 1e-5);
+        assert!(at(0.75).sun_direction().distance(-Vec3::Y) < 1e-5);
+        assert!(at(0.0).sun_direction().distance(Vec3::Z) < 1e-5);
+        assert!(at(0.5).sun_direction().distance(-Vec3::Z) < 1e-5);
+
+        assert_near(at(0.25).daylight(), 1.0);
+        assert_near(at(0.75).daylight(), 0.0);
+        // Still a little light while the sun is on the horizon
+        assert!(at(0.0).daylight() > 0.0 && at(0.0).daylight() < 1.0);
+    }
+
+    #[test]
+    fn frozen_time_stands_still() {
+        let mut world_time = at(0.3);
+        world_time.apply(TimeCommand::Freeze(true));
+        world_time.tick(DEFAULT_DAY_LENGTH / 4.0, true);
+        assert_near(world_time.time_of_day, 0.3);
+
+        // Commands still change it
+        world_time.apply(TimeCommand::Add(0.1));
+        assert_near(world_time.time_of_day, 0.4);
+
+        world_time.apply(TimeCommand::Freeze(false));
+        world_time.tick(DEFAULT_DAY_LENGTH / 4.0, true);
+        assert_near(world_time.time_of_day, 0.65);
+
+        // Worlds without a daylight cycle stand still too
+        world_time.tick(DEFAULT_DAY_LENGTH / 4.0, false);
+        assert_near(world_time.time_of_day, 0.65);
+    }
+
+    #[test]
+    fn setting_the_time() {
+        let mut world_time = at(0.3);
+        world_time.apply(TimeCommand::Set(1.25));
+        assert_near(world_time.time_of_day, 0.25);
+        world_time.apply(TimeCommand::Set(-0.25));
+        assert_near(world_time.time_of_day, 0.75);
+        assert_eq!(world_time.day, 0);
+
+        world_time.apply(TimeCommand::SetDayLength(0.0));
+        assert_eq!(world_time.day_length, 1.0);
+        world_time.apply(TimeCommand::SetDayLength(60.0));
+        world_time.tick(15.0, true);
+        assert_near(world_time.time_of_day, 0.0);
+        assert_eq!(world_time.day, 1);
+    }
+}
@@ -0,0 +1,239 @@
+use bevy::prelude::*;
+use minecrust::block_types::{block_by_name, BlockState};
+use minecrust::level::Level;
+use minecrust::network_client::NetworkClient;
+use minecrust::player::GameMode;
+use minecrust::protocol::{ClientMessage, ServerMessage};
+use minecrust::region::WorldRegions;
+use minecrust::server::Server;
+use minecrust::settings::MIN_WORLD_SIZE;
+use minecrust::voxel_map::VoxelMap;
+use minecrust::world::get_chunk_from_player_pos;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SEED: u64 = 42;
+// Long enough for a slow machine, short enough that a broken server fails the test
+const WAIT: Duration = Duration::from_secs(20);
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!(
+        "minecrust-multiplayer-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn connect(server: &Server, name: &str) -> NetworkClient {
+    let address = server.local_addr().unwrap().to_string();
+    NetworkClient::connect(&address, name, 1).unwrap()
+}
+
+/// Ticks the server and passes every message the clients receive to `until`, until it returns
+/// true for one of them.
+fn run_until(
+    server: &mut Server,
+    clients: &mut [&mut NetworkClient],
+    mut until: impl FnMut(usize, &ServerMessage) -> bool,
+) {
+    let start = Instant::now();
+    while start.elapsed() < WAIT {
+        server.tick();
+        let mut done = false;
+        for (index, client) in clients.iter_mut().enumerate() {
+            client.flush().unwrap();
+            for message in client.receive().unwrap() {
+                done |= until(index, &message);
+            }
+        }
+        if done {
+            return;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    panic!("Timed out waiting for the server");
+}
+
+/// Joins and waits for the chunk the player spawned in, returning the block at its eyes.
+fn join(server: &mut Server, client: &mut NetworkClient) -> IVec3 {
+    let mut spawn = None;
+    run_until(server, &mut [client], |_, message| {
+        if let ServerMessage::Welcome { position, .. } = message {
+            spawn = Some(position.floor().as_ivec3());
+        }
+        match (message, spawn) {
+            (ServerMessage::Chunk { position, .. }, Some(block)) => {
+                *position == get_chunk_from_player_pos(block.as_vec3())
+            }
+            _ => false,
+        }
+    });
+    spawn.unwrap()
+}
+
+/// Asks to set `block` to `state`, returning what the server says the block is now.
+fn edit(
+    server: &mut Server,
+    client: &mut NetworkClient,
+    block: IVec3,
+    state: BlockState,
+) -> BlockState {
+    client.send(&ClientMessage::SetBlock { block, state });
+    let mut answer = None;
+    run_until(server, &mut [client], |_, message| {
+        if let ServerMessage::BlockChange {
+            block: changed,
+            state,
+        } = message
+        {
+            if *changed == block {
+                answer = Some(*state);
+            }
+        }
+        answer.is_some()
+    });
+    answer.unwrap()
+}
+
+fn block_named(name: &str) -> BlockState {
+    BlockState::new(block_by_name(name).unwrap())
+}
+
+fn stone() -> BlockState {
+    block_named("stone")
+}
+
+#[test]
+fn block_edits_reach_the_other_players() {
+    let mut server = Server::bind("127.0.0.1:0", SEED, MIN_WORLD_SIZE).unwrap();
+    let mut alice = connect(&server, "Alice");
+    let mut bob = connect(&server, "Bob");
+    let block = join(&mut server, &mut alice);
+    join(&mut server, &mut bob);
+
+    alice.send(&ClientMessage::SetBlock {
+        block,
+        state: stone(),
+    });
+    let change = ServerMessage::BlockChange {
+        block,
+        state: stone(),
+    };
+    let mut received = [false; 2];
+    run_until(
+        &mut server,
+        &mut [&mut alice, &mut bob],
+        |index, message| {
+            received[index] |= *message == change;
+            received == [true, true]
+        },
+    );
+}
+
+#[test]
+fn edits_are_saved_on_shutdown() {
+    let dir = temp_dir("shutdown");
+    let level = Level::new("Saved", SEED);
+    let mut server = Server::open("127.0.0.1:0", &dir, level, MIN_WORLD_SIZE).unwrap();
+    let mut alice = connect(&server, "Alice");
+    let block = join(&mut server, &mut alice);
+
+    alice.send(&ClientMessage::SetBlock {
+        block,
+        state: stone(),
+    });
+    run_until(&mut server, &mut [&mut alice], |_, message| {
+        matches!(message, ServerMessage::BlockChange { .. })
+    });
+    server.shutdown();
+
+    let chunk_pos = get_chunk_from_player_pos(block.as_vec3());
+    let blocks = WorldRegions::new(&dir).load_chunk(chunk_pos).unwrap();
+    let mut voxel_map = VoxelMap::new(SEED, MIN_WORLD_SIZE);
+    voxel_map.set_chunk_blocks(chunk_pos, &blocks);
+    assert_eq!(voxel_map.get(block), stone());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn teleports_other_than_respawning_are_refused() {
+    let mut server = Server::bind("127.0.0.1:0", SEED, MIN_WORLD_SIZE).unwrap();
+    let mut alice = connect(&server, "Alice");
+    let spawn = join(&mut server, &mut alice);
+
+    alice.send(&ClientMessage::Teleport {
+        sequence: 1,
+        position: Vec3::new(0.0, 200.0, 0.0),
+    });
+    let mut state = None;
+    run_until(&mut server, &mut [&mut alice], |_, message| {
+        if let ServerMessage::PlayerState {
+            sequence, position, ..
+        } = message
+        {
+            state = Some((*sequence, position.floor().as_ivec3()));
+        }
+        state.is_some()
+    });
+    assert_eq!(state, Some((1, spawn)));
+}
+
+#[test]
+fn unbreakable_blocks_stay() {
+    let mut server = Server::bind("127.0.0.1:0", SEED, MIN_WORLD_SIZE).unwrap();
+    server.game_mode = GameMode::Creative;
+    let mut alice = connect(&server, "Alice");
+    let block = join(&mut server, &mut alice);
+
+    let bedrock = block_named("bedrock");
+    assert_eq!(edit(&mut server, &mut alice, block, bedrock), bedrock);
+    assert_eq!(
+        edit(&mut server, &mut alice, block, BlockState::AIR),
+        bedrock
+    );
+}
+
+#[test]
+fn survival_players_place_only_what_they_hold() {
+    let mut server = Server::bind("127.0.0.1:0", SEED, MIN_WORLD_SIZE).unwrap();
+    let mut alice = connect(&server, "Alice");
+    let block = join(&mut server, &mut alice);
+
+    let bedrock = block_named("bedrock");
+    assert_eq!(
+        edit(&mut server, &mut alice, block, bedrock),
+        BlockState::AIR
+    );
+    assert_eq!(edit(&mut server, &mut alice, block, stone()), stone());
+    // Only into air
+    let dirt = block_named("dirt");
+    assert_eq!(edit(&mut server, &mut alice, block, dirt), stone());
+}
+
+#[test]
+fn survival_players_take_the_break_time() {
+    let mut server = Server::bind("127.0.0.1:0", SEED, MIN_WORLD_SIZE).unwrap();
+    let mut alice = connect(&server, "Alice");
+    let block = join(&mut server, &mut alice);
+
+    // Torches break at once
+    let torch = block_named("torch");
+    let above = block + IVec3::Y;
+    assert_eq!(edit(&mut server, &mut alice, above, torch), torch);
+    assert_eq!(
+        edit(&mut server, &mut alice, above, BlockState::AIR),
+        BlockState::AIR
+    );
+
+    // Stone takes longer than the time since the torch was broken
+    assert_eq!(edit(&mut server, &mut alice, block, stone()), stone());
+    assert_eq!(
+        edit(&mut server, &mut alice, block, BlockState::AIR),
+        stone()
+    );
+}
@@ -0,0 +1,126 @@
+use bevy::prelude::*;
+use minecrust::block_types::{block_by_name, BlockState};
+use minecrust::level::{self, GameRules, Level, LEVEL_FORMAT_VERSION};
+use minecrust::player::GameMode;
+use minecrust::region::{Region, WorldRegions, CHUNK_FORMAT_VERSION, REGION_DIR};
+use minecrust::save::{PlayerData, SavedStack, PLAYER_FILE};
+use minecrust::settings::MIN_WORLD_SIZE;
+use minecrust::voxel_map::VoxelMap;
+use minecrust::world::ChunkCoord;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FIXTURES_DIR: &str = "fixtures/saves";
+// Every fixture is saved with the same seed, player and chunk
+const SEED: u64 = 1337;
+const CREATED: u64 = 1792300000;
+const CHUNK: ChunkCoord = ChunkCoord { x: 0, y: 3, z: 0 };
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(FIXTURES_DIR)
+        .join(name)
+}
+
+/// Copies a fixture somewhere it can be opened, as opening a world writes to it.
+fn copy_fixture(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!(
+        "minecrust-fixtures-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    copy_dir(&fixture(name), &dir);
+    dir
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let path = entry.path();
+        if path.is_dir() {
+            copy_dir(&path, &to.join(entry.file_name()));
+        } else {
+            fs::copy(&path, to.join(entry.file_name())).unwrap();
+        }
+    }
+}
+
+fn block(name: &str) -> BlockState {
+    BlockState::new(block_by_name(name).unwrap())
+}
+
+fn assert_level(level: &Level, name: &str) {
+    assert_eq!(level.name, name);
+    assert_eq!(level.seed, SEED);
+    assert_eq!(level.generator, "default");
+    assert_eq!(level.created, CREATED);
+    assert_eq!(level.format_version, LEVEL_FORMAT_VERSION);
+    assert_eq!(level.spawn, None);
+    assert!(level.generator_settings.is_empty());
+    assert_eq!(level.game_rules, GameRules::default());
+}
+
+fn assert_player(dir: &Path) {
+    let player = PlayerData::load(&dir.join(PLAYER_FILE)).unwrap();
+    assert_eq!(player.position, [8.5, 98.62, 8.5]);
+    assert_eq!(player.game_mode, GameMode::Creative);
+    assert_eq!(player.health, 20.0);
+    assert_eq!(player.selected_slot, 0);
+    assert_eq!(
+        player.inventory,
+        vec![
+            SavedStack {
+                slot: 0,
+                block: "planks".into(),
+                count: 64,
+            },
+            SavedStack {
+                slot: 1,
+                block: "log".into(),
+                count: 64,
+            },
+        ]
+    );
+}
+
+#[test]
+fn format_1_opens() {
+    let dir = fixture("format-1");
+    assert_level(&Level::load(&dir).unwrap(), "Format 1");
+    assert_player(&dir);
+    assert!(!dir.join(REGION_DIR).exists());
+    assert_eq!(WorldRegions::new(&dir).load_chunk(CHUNK), None);
+
+    let copy = copy_fixture("format-1");
+    assert_level(&level::open_world(&copy).unwrap(), "Format 1");
+    fs::remove_dir_all(&copy).unwrap();
+}
+
+#[test]
+fn format_2_opens() {
+    let dir = fixture("format-2");
+    assert_level(&Level::load(&dir).unwrap(), "Format 2");
+    assert_player(&dir);
+
+    let region = Region::load(&Region::path(&dir, (0, 0))).unwrap();
+    assert_eq!(region.chunks.len(), 1);
+    assert_eq!(region.chunks[&CHUNK].version, CHUNK_FORMAT_VERSION);
+
+    let blocks = WorldRegions::new(&dir).load_chunk(CHUNK).unwrap();
+    let mut voxel_map = VoxelMap::new(SEED, MIN_WORLD_SIZE);
+    voxel_map.set_chunk_blocks(CHUNK, &blocks);
+    // The floor the player stands on, and the pillar next to them
+    assert_eq!(voxel_map.get(IVec3::new(8, 96, 8)), block("planks"));
+    assert_eq!(voxel_map.get(IVec3::new(8, 97, 8)), BlockState::AIR);
+    for y in 96..104 {
+        assert_eq!(voxel_map.get(IVec3::new(12, y, 12)), block("log"));
+    }
+    assert_eq!(voxel_map.get(IVec3::new(12, 104, 12)), BlockState::AIR);
+
+    let copy = copy_fixture("format-2");
+    assert_level(&level::open_world(&copy).unwrap(), "Format 2");
+    fs::remove_dir_all(&copy).unwrap();
+}